@@ -1,20 +1,25 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use db::{DBTypes, Database};
+use hoya::interpreter::shared::SharedInterpreter;
+use hoya::parser::parse;
 use rayon::prelude::*;
-use std::sync::RwLock;
+use rayon::ThreadPoolBuilder;
 use std::time::Instant;
 
 pub fn db_benchmark(c: &mut Criterion) {
-    let db = RwLock::new(Database::new());
+    let db = Database::new();
 
     c.bench_function("put", |b| {
         b.iter_custom(|iters| {
             let start = Instant::now();
             (0..iters).collect::<Vec<u64>>().par_iter().for_each(|i| {
-                black_box(db.write().unwrap().put(
-                    i.to_string(),
-                    DBTypes::Number(i.to_string().parse::<isize>().unwrap()),
-                ));
+                black_box(
+                    db.put(
+                        i.to_string(),
+                        DBTypes::Number(i.to_string().parse::<isize>().unwrap()),
+                    )
+                    .unwrap(),
+                );
             });
             start.elapsed()
         })
@@ -22,11 +27,41 @@ pub fn db_benchmark(c: &mut Criterion) {
 
     println!("{:?}", db);
 
+    // The same keys as the `put` loop, written under a single lock
+    c.bench_function("put_many", |b| {
+        b.iter_custom(|iters| {
+            let entries = (0..iters)
+                .map(|i| (i.to_string(), DBTypes::Number(i as isize)))
+                .collect::<Vec<_>>();
+            let start = Instant::now();
+            black_box(db.put_many(entries).unwrap());
+            start.elapsed()
+        })
+    });
+
+    c.bench_function("get_many", |b| {
+        b.iter_custom(|iters| {
+            let keys = (0..iters).map(|i| i.to_string()).collect::<Vec<_>>();
+            let start = Instant::now();
+            black_box(db.get_many(&keys));
+            start.elapsed()
+        })
+    });
+
+    c.bench_function("remove_many", |b| {
+        b.iter_custom(|iters| {
+            let keys = (0..iters).map(|i| i.to_string()).collect::<Vec<_>>();
+            let start = Instant::now();
+            black_box(db.remove_many(&keys).unwrap());
+            start.elapsed()
+        })
+    });
+
     c.bench_function("remove", |b| {
         b.iter_custom(|iters| {
             let start = Instant::now();
             (0..iters).collect::<Vec<u64>>().par_iter().for_each(|i| {
-                black_box(db.write().unwrap().remove(&i.to_string()));
+                black_box(db.remove(&i.to_string()).unwrap());
             });
             start.elapsed()
         })
@@ -38,7 +73,7 @@ pub fn db_benchmark(c: &mut Criterion) {
         b.iter_custom(|iters| {
             let start = Instant::now();
             for i in 0..iters {
-                black_box(db.read().unwrap().get(&i.to_string()));
+                black_box(db.get(&i.to_string()));
             }
             start.elapsed()
         })
@@ -50,7 +85,7 @@ pub fn db_benchmark(c: &mut Criterion) {
         b.iter_custom(|iters| {
             let start = Instant::now();
             for i in 0..iters {
-                black_box(db.read().unwrap().exists(&i.to_string()));
+                black_box(db.exists(&i.to_string()));
             }
             start.elapsed()
         })
@@ -59,5 +94,67 @@ pub fn db_benchmark(c: &mut Criterion) {
     println!("{:?}", db);
 }
 
-criterion_group!(benches, db_benchmark);
+/// Parallel `put`s with more and more threads, into one lock and into lock-striped shards
+pub fn parallel_put_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parallel_put");
+
+    for shards in [1, 16] {
+        for threads in [1, 2, 4, 8] {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let db = Database::sharded(shards);
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("{shards} shards"), threads),
+                &threads,
+                |b, _| {
+                    b.iter_custom(|iters| {
+                        let start = Instant::now();
+                        pool.install(|| {
+                            (0..iters).into_par_iter().for_each(|i| {
+                                black_box(
+                                    db.put(i.to_string(), DBTypes::Number(i as isize)).unwrap(),
+                                );
+                            })
+                        });
+                        start.elapsed()
+                    })
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+/// Hoya programs evaluated from every rayon thread by one shared interpreter
+pub fn parallel_interpret_benchmark(c: &mut Criterion) {
+    let interpreter = SharedInterpreter::new(Database::sharded(16));
+
+    c.bench_function("parallel_interpret", |b| {
+        b.iter_custom(|iters| {
+            let programs = (0..iters)
+                .map(|i| {
+                    let key = format!("(concat \"key:\" \"{i}\")");
+                    format!("(unwrap-or (put (+ {i} 1) {key}) (get {key}))")
+                })
+                .collect::<Vec<_>>();
+            let start = Instant::now();
+            programs.par_iter().for_each(|program| {
+                let (expr, _) = parse(program).unwrap();
+                black_box(interpreter.eval_expr(&expr).unwrap());
+            });
+            start.elapsed()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    db_benchmark,
+    parallel_put_benchmark,
+    parallel_interpret_benchmark
+);
 criterion_main!(benches);
@@ -0,0 +1,67 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::Database;
+
+/// How often the save rules are checked
+const TICK: Duration = Duration::from_secs(1);
+
+/// Save once `after` has passed since the last save and there were at least `changes` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub after: Duration,
+    pub changes: u64,
+}
+
+/// Saves the database in the background whenever one of its rules is met, until dropped
+#[derive(Debug)]
+pub struct Autosave {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Database {
+    /// Starts saving to `name.hoya` in the data directory by `rules`. Does nothing without
+    /// rules
+    pub fn autosave(&self, name: impl Into<String>, rules: Vec<SaveRule>) -> Autosave {
+        if rules.is_empty() {
+            return Autosave {
+                stop: None,
+                thread: None,
+            };
+        }
+
+        let db = self.clone();
+        let name = name.into();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let mut last = Instant::now();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(TICK) {
+                let dirty = db.dirty();
+                let due = rules
+                    .iter()
+                    .any(|rule| dirty > 0 && dirty >= rule.changes && last.elapsed() >= rule.after);
+                // A save that's already running covers these changes, and a failed one
+                // counts them as unsaved again, so either way there's nothing else to do
+                if due && db.bgsave(&name).is_ok() {
+                    last = Instant::now();
+                }
+            }
+        });
+
+        Autosave {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Autosave {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::DatabaseError;
+
+const EXTENSION: &str = "hoya";
+
+/// The directory a database reads and writes its files in. Files are named relative to
+/// it, and a name can't reach outside it, whether by `..`, an absolute path or a
+/// symbolic link. Symbolic links in the directory are never followed, even ones that
+/// point inside it
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    root: PathBuf,
+}
+
+impl Default for DataDir {
+    /// The working directory
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("."),
+        }
+    }
+}
+
+impl DataDir {
+    /// Creates `root` if it doesn't exist yet
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path of the file called `name` in the directory. Only plain file names are
+    /// allowed, not paths
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, DatabaseError> {
+        let invalid = || DatabaseError::InvalidName {
+            name: name.to_owned(),
+        };
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if !name.contains(['/', '\\', '\0']) => {}
+            _ => return Err(invalid()),
+        }
+
+        let path = self.root.join(name);
+        // A link could point anywhere, including where nothing exists yet
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => Err(invalid()),
+            _ => Ok(path),
+        }
+    }
+
+    /// The path of the snapshot `name`, which is written as `name.hoya`
+    pub(crate) fn snapshot(&self, name: &str) -> Result<PathBuf, DatabaseError> {
+        let invalid = || DatabaseError::InvalidName {
+            name: name.to_owned(),
+        };
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        self.resolve(&format!("{name}.{EXTENSION}"))
+            .map_err(|_| invalid())
+    }
+
+    pub fn open(&self, name: &str) -> Result<File, Box<dyn Error>> {
+        no_follow()
+            .read(true)
+            .open(self.resolve(name)?)
+            .map_err(|e| file_error(name, e))
+    }
+
+    pub fn create(&self, name: &str) -> Result<File, Box<dyn Error>> {
+        no_follow()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.resolve(name)?)
+            .map_err(|e| file_error(name, e))
+    }
+
+    /// The names of the snapshots in the directory that can be loaded from it, in order
+    pub fn snapshots(&self) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|e| e == EXTENSION) {
+                match path.file_stem().and_then(|stem| stem.to_str()) {
+                    Some(name) if self.snapshot(name).is_ok() => names.push(name.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+/// Opens files without following a symbolic link put in place after `resolve` looked
+pub(crate) fn no_follow() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+    options
+}
+
+/// Names the file in the errors a caller can do something about
+pub(crate) fn file_error(name: &str, e: io::Error) -> Box<dyn Error> {
+    let name = name.to_owned();
+    match e.kind() {
+        io::ErrorKind::NotFound => Box::new(DatabaseError::NotFound { name }),
+        io::ErrorKind::PermissionDenied => Box::new(DatabaseError::PermissionDenied { name }),
+        _ => Box::new(e),
+    }
+}
@@ -1,9 +1,25 @@
+use chrono::{DateTime, Duration, Utc};
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::sync::{Arc, RwLock};
+use std::fs;
+use std::io;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::data_dir::{file_error, no_follow, DataDir};
+use crate::engine::{MemoryEngine, StorageEngine};
+use crate::error::DatabaseError;
+use crate::index::{range_across, Extractor, Index};
+use crate::replication::log::{Change, ChangeLog};
+use crate::replication::Replication;
+use crate::shard::{merge, shard_of};
+use crate::snapshot::{self, Freeze, Saves, SnapshotOptions};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum DBTypes {
@@ -13,60 +29,511 @@ pub enum DBTypes {
     Text(String),
     List(Vec<DBTypes>),
     Unit(()),
+    Set(BTreeSet<String>),
+    /// Members with their scores
+    SortedSet(BTreeMap<String, f64>),
+    Bytes(Vec<u8>),
+    Timestamp(DateTime<Utc>),
+    Duration(Duration),
+    BigInt(BigInt),
+    Decimal(Decimal),
+    /// An absent value, only ever stored inside a list
+    Nil,
 }
 
-type Collection = BTreeMap<String, DBTypes>;
-type Records = Arc<RwLock<Collection>>;
+impl DBTypes {
+    pub(crate) fn rank(&self) -> u8 {
+        match self {
+            DBTypes::Nil => 0,
+            DBTypes::Unit(_) => 1,
+            DBTypes::Boolean(_) => 2,
+            DBTypes::Number(_) | DBTypes::BigInt(_) | DBTypes::Decimal(_) | DBTypes::Float(_) => 3,
+            DBTypes::Text(_) => 4,
+            DBTypes::List(_) => 5,
+            DBTypes::Set(_) => 6,
+            DBTypes::SortedSet(_) => 7,
+            DBTypes::Bytes(_) => 8,
+            DBTypes::Timestamp(_) => 9,
+            DBTypes::Duration(_) => 10,
+        }
+    }
+
+    /// Orders numbers of the same value by kind, so `1` and `1.0` are still distinct
+    fn numeric_kind(&self) -> Option<u8> {
+        match self {
+            DBTypes::Number(_) => Some(0),
+            DBTypes::BigInt(_) => Some(1),
+            DBTypes::Decimal(_) => Some(2),
+            DBTypes::Float(_) => Some(3),
+            _ => None,
+        }
+    }
+
+    /// The value of a number of any kind as a float, as it's compared with floats
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            DBTypes::Number(n) => Some(*n as f64),
+            DBTypes::Float(f) => Some(*f),
+            DBTypes::BigInt(n) => Some(n.to_f64().unwrap_or(f64::NAN)),
+            DBTypes::Decimal(d) => Some(d.to_f64().unwrap_or(f64::NAN)),
+            _ => None,
+        }
+    }
+
+    /// Compares the values of two numbers of any kind, or `None` unless both are numbers.
+    /// Integers and decimals compare exactly, anything compared with a float is compared as
+    /// a float
+    pub(crate) fn numeric_cmp(&self, other: &DBTypes) -> Option<Ordering> {
+        use DBTypes::*;
+        self.numeric_kind().zip(other.numeric_kind())?;
+        let integer = |v: &DBTypes| match v {
+            Number(n) => Some(num_bigint::BigInt::from(*n)),
+            BigInt(n) => Some(n.clone()),
+            _ => None,
+        };
+        // An integer too large for a decimal is larger in magnitude than every decimal
+        let decimal = |v: &DBTypes| match v {
+            Decimal(d) => Ok(*d),
+            v => {
+                let n = integer(v).unwrap();
+                n.to_i128()
+                    .and_then(|n| rust_decimal::Decimal::try_from_i128_with_scale(n, 0).ok())
+                    .ok_or(if n.is_negative() {
+                        Ordering::Less
+                    } else {
+                        Ordering::Greater
+                    })
+            }
+        };
+
+        Some(match (self, other) {
+            (Float(_), _) | (_, Float(_)) => self.as_f64()?.total_cmp(&other.as_f64()?),
+            (Decimal(_), _) | (_, Decimal(_)) => match (decimal(self), decimal(other)) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Err(a), _) => a,
+                (_, Err(b)) => b.reverse(),
+            },
+            (a, b) => integer(a)?.cmp(&integer(b)?),
+        })
+    }
+
+    /// A total order over every value: numbers compare numerically with each other,
+    /// values of unrelated types are ordered by type
+    pub fn total_cmp(&self, other: &DBTypes) -> Ordering {
+        use DBTypes::*;
+        match (self, other) {
+            (Number(a), Number(b)) => a.cmp(b),
+            (Float(a), Float(b)) => a.total_cmp(b),
+            (a, b) if a.numeric_kind().is_some() && b.numeric_kind().is_some() => a
+                .numeric_cmp(b)
+                .unwrap()
+                .then(a.numeric_kind().cmp(&b.numeric_kind())),
+            (Boolean(a), Boolean(b)) => a.cmp(b),
+            (Text(a), Text(b)) => a.cmp(b),
+            (List(a), List(b)) => a
+                .iter()
+                .zip(b.iter())
+                .map(|(a, b)| a.total_cmp(b))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Set(a), Set(b)) => a.cmp(b),
+            (SortedSet(a), SortedSet(b)) => a
+                .iter()
+                .zip(b.iter())
+                .map(|((a, x), (b, y))| a.cmp(b).then(x.total_cmp(y)))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Bytes(a), Bytes(b)) => a.cmp(b),
+            (Timestamp(a), Timestamp(b)) => a.cmp(b),
+            (Duration(a), Duration(b)) => a.cmp(b),
+            (a, b) => a.rank().cmp(&b.rank()),
+        }
+    }
+}
+
+pub(crate) type Collection = BTreeMap<String, DBTypes>;
+pub(crate) type Indexes = BTreeMap<String, Index>;
+
+/// The records together with their secondary indexes, so both are guarded by one lock
+#[derive(Debug)]
+pub(crate) struct Store {
+    pub(crate) records: Box<dyn StorageEngine>,
+    pub(crate) indexes: Indexes,
+    /// Set while a save is writing this shard out
+    pub(crate) freeze: Option<Freeze>,
+    /// Changes since the last save began
+    pub(crate) dirty: u64,
+    /// Shared by every shard
+    changes: Arc<ChangeLog>,
+}
+
+impl Store {
+    fn new(engine: Box<dyn StorageEngine>, changes: Arc<ChangeLog>) -> Self {
+        Self {
+            records: engine,
+            indexes: Indexes::new(),
+            freeze: None,
+            dirty: 0,
+            changes,
+        }
+    }
+
+    /// Indexes and logs the entry once the engine has taken it, so a failed write changes
+    /// nothing
+    fn insert(&mut self, key: String, value: DBTypes) -> io::Result<Option<DBTypes>> {
+        let tracked =
+            (!self.indexes.is_empty() || self.freeze.is_some() || self.changes.is_active())
+                .then(|| (key.clone(), value.clone()));
+        let old = self.records.put(key, value)?;
+        self.dirty += 1;
+        if let Some((key, value)) = tracked {
+            self.changed(&key, old.as_ref(), Some(&value));
+        }
+        Ok(old)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<DBTypes>> {
+        let old = self.records.remove(key)?;
+        if old.is_some() {
+            self.dirty += 1;
+        }
+        self.changed(key, old.as_ref(), None);
+        Ok(old)
+    }
+
+    /// Brings the indexes, what a running save is to write and the change log up to date
+    /// with a change the engine has taken
+    fn changed(&mut self, key: &str, old: Option<&DBTypes>, new: Option<&DBTypes>) {
+        if let Some(freeze) = &mut self.freeze {
+            freeze.preserve(key, old);
+        }
+        for index in self.indexes.values_mut() {
+            if let Some(old) = old {
+                index.remove(key, old);
+            }
+            if let Some(new) = new {
+                index.insert(key, new);
+            }
+        }
+        match (old, new) {
+            (_, Some(value)) => self.changes.record(|| Change::Put {
+                key: key.to_owned(),
+                value: value.clone(),
+            }),
+            (Some(_), None) => self.changes.record(|| Change::Remove {
+                key: key.to_owned(),
+            }),
+            (None, None) => {}
+        }
+    }
+
+    /// Swaps every record for `records`, as far as indexes and saves are concerned too
+    fn replace(&mut self, records: Collection) -> io::Result<()> {
+        let Store {
+            records: engine,
+            indexes,
+            freeze,
+            dirty,
+            ..
+        } = self;
+        if let Some(freeze) = freeze {
+            for (key, value) in engine.scan(ALL) {
+                freeze.preserve(&key, Some(&value));
+            }
+            for key in records.keys() {
+                freeze.preserve(key, None);
+            }
+        }
+        *dirty += records.len() as u64;
+        engine.replace(records)?;
+        for index in indexes.values_mut() {
+            index.rebuild(engine.scan(ALL));
+        }
+        Ok(())
+    }
+}
+
+/// Hash-partitioned shards, each with its own lock and its own copy of every index.
+/// Operations spanning shards lock them in order, so they can't deadlock each other
+type Shards = Arc<[RwLock<Store>]>;
+
+/// Every key
+pub(crate) const ALL: (Bound<&str>, Bound<&str>) = (Bound::Unbounded, Bound::Unbounded);
 
 #[derive(Debug, Clone)]
 pub struct Database {
-    records: Records,
+    pub(crate) shards: Shards,
+    pub(crate) saves: Arc<Saves>,
+    pub(crate) snapshot_options: Arc<SnapshotOptions>,
+    pub(crate) data_dir: Arc<DataDir>,
+    pub(crate) changes: Arc<ChangeLog>,
+    pub(crate) replication: Arc<Replication>,
 }
 
-unsafe impl Sync for Database {}
-unsafe impl Send for Database {}
-
 impl Database {
     pub fn new() -> Self {
+        Self::sharded(1)
+    }
+
+    /// A database split into `shards` independently locked partitions, so writers to
+    /// different keys rarely wait on each other. Scans merge the shards back in order
+    pub fn sharded(shards: usize) -> Self {
+        Self::with_engines(
+            (0..shards.max(1))
+                .map(|_| Box::new(MemoryEngine::default()) as Box<dyn StorageEngine>)
+                .collect(),
+        )
+    }
+
+    /// A database keeping its records in `engine`
+    pub fn with_engine(engine: impl StorageEngine + 'static) -> Self {
+        Self::with_engines(vec![Box::new(engine)])
+    }
+
+    /// A database with one shard per engine. A database reopened over the same engines
+    /// has to list them in the same order, since keys are hashed to positions
+    pub fn with_engines(engines: Vec<Box<dyn StorageEngine>>) -> Self {
+        assert!(!engines.is_empty(), "a database needs at least one engine");
+        let changes = Arc::new(ChangeLog::default());
         Self {
-            records: Arc::new(RwLock::new(Collection::new())),
+            shards: engines
+                .into_iter()
+                .map(|engine| RwLock::new(Store::new(engine, changes.clone())))
+                .collect(),
+            saves: Arc::default(),
+            snapshot_options: Arc::default(),
+            data_dir: Arc::default(),
+            changes,
+            replication: Arc::default(),
         }
     }
 
+    /// Where snapshots are read and written. The working directory by default
+    pub fn with_data_dir(mut self, data_dir: DataDir) -> Self {
+        self.data_dir = Arc::new(data_dir);
+        self
+    }
+
+    pub fn data_dir(&self) -> &DataDir {
+        &self.data_dir
+    }
+
+    /// How `store` and the background saves write snapshots. `load` reads any snapshot
+    /// whose secret, if it has one, is the one given here
+    pub fn with_snapshot_options(mut self, options: SnapshotOptions) -> Self {
+        self.snapshot_options = Arc::new(options);
+        self
+    }
+
+    pub(crate) fn shard_index(&self, key: &str) -> usize {
+        shard_of(key, self.shards.len())
+    }
+
+    pub(crate) fn shard(&self, key: &str) -> &RwLock<Store> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    pub(crate) fn read_all(&self) -> Vec<RwLockReadGuard<'_, Store>> {
+        self.shards.iter().map(|s| s.read().unwrap()).collect()
+    }
+
+    pub(crate) fn write_all(&self) -> Vec<RwLockWriteGuard<'_, Store>> {
+        self.shards.iter().map(|s| s.write().unwrap()).collect()
+    }
+
     pub fn get(&self, key: &str) -> Option<DBTypes> {
-        self.records.read().unwrap().get(key).cloned()
+        self.shard(key)
+            .read()
+            .unwrap()
+            .records
+            .get(key)
+            .map(Cow::into_owned)
+    }
+
+    pub fn put(&self, key: String, value: DBTypes) -> Result<Option<DBTypes>, DatabaseError> {
+        Ok(self.shard(&key).write().unwrap().insert(key, value)?)
+    }
+
+    pub fn remove(&self, key: &str) -> Result<Option<DBTypes>, DatabaseError> {
+        Ok(self.shard(key).write().unwrap().remove(key)?)
+    }
+
+    /// The values at `keys`, in order, read under one lock
+    pub fn get_many(&self, keys: &[String]) -> Vec<Option<DBTypes>> {
+        let stores = self.read_all();
+        keys.iter()
+            .map(|key| {
+                stores[self.shard_index(key)]
+                    .records
+                    .get(key)
+                    .map(Cow::into_owned)
+            })
+            .collect()
+    }
+
+    /// Puts every pair under one write lock, so readers see all of them or none.
+    /// Returns the values they replaced, in order. A write the engine fails stops the
+    /// batch there, leaving the pairs before it written
+    pub fn put_many(
+        &self,
+        entries: impl IntoIterator<Item = (String, DBTypes)>,
+    ) -> Result<Vec<Option<DBTypes>>, DatabaseError> {
+        let mut stores = self.write_all();
+        Ok(entries
+            .into_iter()
+            .map(|(key, value)| stores[self.shard_index(&key)].insert(key, value))
+            .collect::<io::Result<_>>()?)
+    }
+
+    /// Removes every key under one write lock. Returns the removed values, in order.
+    /// A removal the engine fails stops the batch there
+    pub fn remove_many(&self, keys: &[String]) -> Result<Vec<Option<DBTypes>>, DatabaseError> {
+        let mut stores = self.write_all();
+        Ok(keys
+            .iter()
+            .map(|key| stores[self.shard_index(key)].remove(key))
+            .collect::<io::Result<_>>()?)
     }
 
-    pub fn put(&self, key: String, value: DBTypes) -> Option<DBTypes> {
-        self.records.write().unwrap().insert(key, value)
+    /// Reads, changes and writes back the entry at `key` under one write lock, keeping
+    /// the indexes up to date. `f` sees `None` for a missing key and can set it to
+    /// `None` to remove the entry. It must leave the entry untouched when it fails
+    pub(crate) fn modify<T, E: From<io::Error>>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Option<DBTypes>) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut store = self.shard(key).write().unwrap();
+
+        let old = store.records.get(key).map(Cow::into_owned);
+        let mut entry = old.clone();
+        // A failed `f` left the entry as it was, so there's nothing to write back
+        let result = f(&mut entry)?;
+
+        match &entry {
+            Some(new) => {
+                store.records.put(key.to_owned(), new.clone())?;
+            }
+            None if old.is_some() => {
+                store.records.remove(key)?;
+            }
+            None => return Ok(result),
+        }
+        store.dirty += 1;
+        store.changed(key, old.as_ref(), entry.as_ref());
+        Ok(result)
     }
 
-    pub fn remove(&self, key: &str) -> Option<DBTypes> {
-        self.records.write().unwrap().remove(key)
+    /// How many writes happened since the last save began, or since startup
+    pub fn dirty(&self) -> u64 {
+        self.read_all().iter().map(|store| store.dirty).sum()
     }
 
     pub fn exists(&self, key: &str) -> bool {
-        self.records.read().unwrap().contains_key(key)
+        self.shard(key).read().unwrap().records.contains(key)
+    }
+
+    /// Creates (or replaces) the secondary index `name`, built from the current records
+    pub fn create_index(&self, name: &str, extractor: Extractor) {
+        for mut store in self.write_all() {
+            let mut index = Index::new(extractor.clone());
+            index.rebuild(store.records.scan(ALL));
+            store.indexes.insert(name.to_owned(), index);
+        }
+    }
+
+    pub fn drop_index(&self, name: &str) -> bool {
+        let mut dropped = false;
+        for mut store in self.write_all() {
+            dropped = store.indexes.remove(name).is_some();
+        }
+        dropped
+    }
+
+    pub fn indexes(&self) -> Vec<String> {
+        self.shards[0]
+            .read()
+            .unwrap()
+            .indexes
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Keys indexed under `value`, or `None` if the index doesn't exist
+    pub fn find_by(&self, index: &str, value: &DBTypes) -> Option<Vec<String>> {
+        let stores = self.read_all();
+        let found = stores
+            .iter()
+            .map(|store| Some(store.indexes.get(index)?.find(value).into_iter()))
+            .collect::<Option<Vec<_>>>()?;
+        Some(merge(found, String::cmp).collect())
+    }
+
+    /// Keys indexed under a value within `lo..=hi`, or `None` if the index doesn't exist
+    pub fn find_range(&self, index: &str, lo: &DBTypes, hi: &DBTypes) -> Option<Vec<String>> {
+        let stores = self.read_all();
+        let indexes = stores
+            .iter()
+            .map(|store| store.indexes.get(index))
+            .collect::<Option<Vec<_>>>()?;
+        Some(range_across(
+            &indexes,
+            Bound::Included(lo),
+            Bound::Included(hi),
+        ))
     }
 
-    pub fn store(&self, filename: &str) -> Result<(), Box<dyn Error>> {
-        let serialized_records = bincode::serialize(&*self.records.read().unwrap())?;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(filename.to_owned() + ".hoya")?;
+    /// Writes a point-in-time image of every record to `name.hoya` in the data directory.
+    /// Writers only wait for one chunk of records at a time, not for the whole file
+    pub fn store(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let path = self.data_dir.snapshot(name)?;
+        self.start_save(format!("`{name}.hoya`"))?;
+        self.freeze_for_save();
+        self.save(&path)
+            .map_err(|e| file_error(&format!("{name}.hoya"), e))
+    }
+
+    pub fn load(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let file = no_follow()
+            .read(true)
+            .open(self.data_dir.snapshot(name)?)
+            .map_err(|e| file_error(&format!("{name}.hoya"), e))?;
+        let tree = snapshot::read(file, self.snapshot_options.secret())?;
+        Ok(self.restore(tree)?)
+    }
 
-        file.write_all(&serialized_records)?;
+    /// Replaces every record with `tree`
+    pub(crate) fn restore(&self, tree: Collection) -> io::Result<()> {
+        let mut shards = vec![Collection::new(); self.shards.len()];
+        for (key, value) in tree {
+            shards[self.shard_index(&key)].insert(key, value);
+        }
 
+        let mut stores = self.write_all();
+        for (store, records) in stores.iter_mut().zip(shards) {
+            store.replace(records)?;
+        }
+        // Replicas can't catch up on a change this big one record at a time
+        self.changes.restart();
         Ok(())
     }
 
-    pub fn load(&self, filename: &str) -> Result<(), Box<dyn Error>> {
-        let records = fs::read(filename.to_owned() + ".hoya")?;
-        let tree = bincode::deserialize::<Collection>(&records)?;
+    /// The snapshots in the data directory, by the names `store` and `load` take
+    pub fn snapshots(&self) -> io::Result<Vec<String>> {
+        self.data_dir.snapshots()
+    }
+
+    pub fn delete_snapshot(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        fs::remove_file(self.data_dir.snapshot(name)?)
+            .map_err(|e| file_error(&format!("{name}.hoya"), e))
+    }
 
-        let mut old_db = self.records.write().unwrap();
-        *old_db = tree;
+    /// Makes every write so far durable, for engines that persist them
+    pub fn flush(&self) -> io::Result<()> {
+        for mut store in self.write_all() {
+            store.records.flush()?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use super::{failed, record, Scan, StorageEngine};
+use crate::db::Collection;
+use crate::DBTypes;
+
+/// Keeps every record in memory and appends each change to a log file, which is replayed
+/// when the engine is opened again. `replace` rewrites the log with only the live records
+#[derive(Debug)]
+pub struct LogEngine {
+    path: PathBuf,
+    log: BufWriter<File>,
+    records: Collection,
+    failed: Option<io::Error>,
+}
+
+impl LogEngine {
+    /// Opens the log at `path`, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let log = fs::read(&path)?;
+        let (changes, complete) = record::read(&log)?;
+        let mut records = Collection::new();
+        for change in changes {
+            match change.value {
+                Some(at) => records.insert(change.key, record::value(&log, at)?),
+                None => records.remove(&change.key),
+            };
+        }
+        if complete < log.len() {
+            file.set_len(complete as u64)?;
+        }
+
+        Ok(Self {
+            path,
+            log: BufWriter::new(file),
+            records,
+            failed: None,
+        })
+    }
+
+    fn append(&mut self, record: io::Result<Vec<u8>>) -> io::Result<()> {
+        if let Some(e) = &self.failed {
+            return Err(failed(e));
+        }
+        // A record that can't be encoded leaves nothing behind
+        let record = record?;
+        self.log.write_all(&record).map_err(|e| self.fail(e))
+    }
+
+    /// Remembers `e`, which left the log with a gap, and returns it
+    fn fail(&mut self, e: io::Error) -> io::Error {
+        let reported = io::Error::new(e.kind(), e.to_string());
+        self.failed = Some(e);
+        reported
+    }
+}
+
+impl StorageEngine for LogEngine {
+    fn get(&self, key: &str) -> Option<Cow<'_, DBTypes>> {
+        self.records.get(key).map(Cow::Borrowed)
+    }
+
+    fn put(&mut self, key: String, value: DBTypes) -> io::Result<Option<DBTypes>> {
+        self.append(record::put(&key, &value))?;
+        Ok(self.records.insert(key, value))
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<DBTypes>> {
+        if !self.records.contains_key(key) {
+            return Ok(None);
+        }
+        self.append(Ok(record::remove(key)))?;
+        Ok(self.records.remove(key))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.records.contains_key(key)
+    }
+
+    fn scan(&self, range: (Bound<&str>, Bound<&str>)) -> Scan<'_> {
+        Box::new(
+            self.records
+                .range::<str, _>(range)
+                .map(|(key, value)| (Cow::Borrowed(key.as_str()), Cow::Borrowed(value))),
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = &self.failed {
+            return Err(failed(e));
+        }
+        self.log.flush().map_err(|e| self.fail(e))?;
+        self.log.get_ref().sync_data()
+    }
+
+    fn snapshot(&self) -> Collection {
+        self.records.clone()
+    }
+
+    /// Writes the new log beside the old one and swaps it in, so a crash leaves one of them
+    fn replace(&mut self, records: Collection) -> io::Result<()> {
+        let compacted = self.path.with_extension("compact");
+        let mut log = BufWriter::new(File::create(&compacted)?);
+        for (key, value) in &records {
+            log.write_all(&record::put(key, value)?)?;
+        }
+        log.flush()?;
+        log.get_ref().sync_all()?;
+        fs::rename(&compacted, &self.path)?;
+
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.log = BufWriter::new(file);
+        self.records = records;
+        self.failed = None;
+        Ok(())
+    }
+}
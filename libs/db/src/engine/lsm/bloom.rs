@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::shard::hash;
+
+const BITS_PER_KEY: usize = 10;
+const PROBES: u64 = 7;
+
+/// Answers whether a table may hold a key, wrongly saying yes about 1% of the time
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Bloom {
+    bits: Vec<u64>,
+}
+
+impl Bloom {
+    pub(super) fn new(keys: usize) -> Self {
+        Self {
+            bits: vec![0; (keys * BITS_PER_KEY).div_ceil(64).max(1)],
+        }
+    }
+
+    /// The two hashes every probe for `key` is derived from
+    pub(super) fn hashes(key: &str) -> (u64, u64) {
+        (hash(key.as_bytes(), 0), hash(key.as_bytes(), 1))
+    }
+
+    fn probes(&self, (a, b): (u64, u64)) -> impl Iterator<Item = usize> {
+        let len = self.bits.len() as u64 * 64;
+        (0..PROBES).map(move |i| (a.wrapping_add(i.wrapping_mul(b)) % len) as usize)
+    }
+
+    pub(super) fn insert(&mut self, hashes: (u64, u64)) {
+        for bit in self.probes(hashes).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub(super) fn may_contain(&self, key: &str) -> bool {
+        self.probes(Self::hashes(key))
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use super::table::{Block, TableId};
+
+/// A block of a table, by its position in the table's index
+type BlockId = (TableId, usize);
+
+/// Decoded blocks shared by every table of an engine, evicting the least recently used
+/// ones once they take more than `budget` bytes on disk
+#[derive(Debug)]
+pub(super) struct BlockCache {
+    budget: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    blocks: HashMap<BlockId, Cached>,
+    by_use: BTreeMap<u64, BlockId>,
+    used: usize,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Cached {
+    block: Arc<Block>,
+    size: usize,
+    last_used: u64,
+}
+
+impl BlockCache {
+    pub(super) fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            state: Mutex::default(),
+        }
+    }
+
+    /// The block `id`, loaded with `load` if it isn't cached. Loading doesn't hold the
+    /// cache's lock, so two readers may both load a block the first time it's read
+    pub(super) fn get_or_load(
+        &self,
+        id: BlockId,
+        load: impl FnOnce() -> io::Result<(Block, usize)>,
+    ) -> io::Result<Arc<Block>> {
+        if let Some(block) = self.state.lock().unwrap().touch(id) {
+            return Ok(block);
+        }
+
+        let (block, size) = load()?;
+        let block = Arc::new(block);
+        if size <= self.budget {
+            let mut state = self.state.lock().unwrap();
+            state.insert(id, block.clone(), size);
+            while state.used > self.budget {
+                state.evict_oldest();
+            }
+        }
+        Ok(block)
+    }
+}
+
+impl CacheState {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn touch(&mut self, id: BlockId) -> Option<Arc<Block>> {
+        let now = self.tick();
+        let cached = self.blocks.get_mut(&id)?;
+        self.by_use.remove(&cached.last_used);
+        cached.last_used = now;
+        self.by_use.insert(now, id);
+        Some(cached.block.clone())
+    }
+
+    fn insert(&mut self, id: BlockId, block: Arc<Block>, size: usize) {
+        let now = self.tick();
+        if let Some(old) = self.blocks.insert(
+            id,
+            Cached {
+                block,
+                size,
+                last_used: now,
+            },
+        ) {
+            self.by_use.remove(&old.last_used);
+            self.used -= old.size;
+        }
+        self.by_use.insert(now, id);
+        self.used += size;
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, id)) = self.by_use.pop_first() {
+            if let Some(evicted) = self.blocks.remove(&id) {
+                self.used -= evicted.size;
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+mod bloom;
+mod cache;
+mod table;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+use self::cache::BlockCache;
+use self::table::{parse_file_name, Table, TableId};
+use super::{failed, record, Scan, StorageEngine};
+use crate::db::{Collection, ALL};
+use crate::shard::merge;
+use crate::DBTypes;
+
+#[derive(Debug, Clone)]
+pub struct LsmOptions {
+    /// Roughly how many bytes of writes are buffered in memory before they're written
+    /// out as a table
+    pub memtable_size: usize,
+    /// How many bytes of table blocks are kept in memory for reads
+    pub block_cache_size: usize,
+    /// How many tables pile up before they're merged into one in the background
+    pub compaction_threshold: usize,
+}
+
+impl Default for LsmOptions {
+    fn default() -> Self {
+        Self {
+            memtable_size: 4 << 20,
+            block_cache_size: 64 << 20,
+            compaction_threshold: 4,
+        }
+    }
+}
+
+/// Writes go to a write-ahead log and a sorted in-memory memtable, which is written out
+/// as an immutable sorted table once it grows past `memtable_size`. Reads look through
+/// the memtable and then the tables from newest to oldest, skipping tables whose bloom
+/// filter rules the key out. Tables are merged in the background, so that reads look at
+/// few of them and space taken by overwritten and removed values is given back
+#[derive(Debug)]
+pub struct LsmEngine {
+    shared: Arc<Shared>,
+    options: LsmOptions,
+    /// `None` for keys removed since the last flush, which may still be in a table
+    memtable: BTreeMap<String, Option<DBTypes>>,
+    memtable_size: usize,
+    wal: BufWriter<File>,
+    next_sequence: u64,
+    compactor: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+/// What the engine shares with its compaction thread
+#[derive(Debug)]
+struct Shared {
+    dir: PathBuf,
+    /// Newest first
+    tables: RwLock<Vec<Arc<Table>>>,
+    cache: BlockCache,
+    /// The second half of the id of the next table a compaction or `replace` writes.
+    /// Both draw from it, so whichever finishes later shadows the other's inputs
+    generation: AtomicU64,
+    /// The first write, read or compaction error. A failed read leaves the old value of
+    /// a write unknown, so no more writes are made until `replace` rewrites the records
+    failed: Mutex<Option<io::Error>>,
+}
+
+fn remember(failed: &Mutex<Option<io::Error>>, e: io::Error) {
+    failed.lock().unwrap().get_or_insert(e);
+}
+
+/// Refuses a write once anything has failed
+fn check(state: &Mutex<Option<io::Error>>) -> io::Result<()> {
+    match &*state.lock().unwrap() {
+        Some(e) => Err(failed(e)),
+        None => Ok(()),
+    }
+}
+
+/// Merges sources sorted by key, newest first, keeping only the newest entry for each key
+fn newest<'a, K, V>(
+    sources: Vec<Box<dyn Iterator<Item = (K, Option<V>)> + 'a>>,
+) -> impl Iterator<Item = (K, Option<V>)> + 'a
+where
+    K: Ord + 'a,
+    V: 'a,
+{
+    let mut merged = merge(sources, |(a, _), (b, _)| a.cmp(b)).peekable();
+    std::iter::from_fn(move || {
+        let (key, value) = merged.next()?;
+        while merged.next_if(|(next, _)| *next == key).is_some() {}
+        Some((key, value))
+    })
+}
+
+impl LsmEngine {
+    /// Opens the engine kept in `dir`, creating it if needed
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with(dir, LsmOptions::default())
+    }
+
+    pub fn open_with(dir: impl AsRef<Path>, options: LsmOptions) -> io::Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir)?;
+
+        let mut tables = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if name.ends_with(".tmp") {
+                // A table that was still being written
+                fs::remove_file(&path)?;
+            } else if let Some(id) = parse_file_name(name) {
+                tables.push(Table::open(path, id)?);
+            }
+        }
+        tables.sort_by_key(|t| std::cmp::Reverse(t.id));
+        // Inputs of a compaction whose files weren't all deleted
+        let ids = tables.iter().map(|t| t.id).collect::<Vec<_>>();
+        let shadowed = |t: &Table| ids.iter().any(|id| id.0 >= t.id.0 && id.1 > t.id.1);
+        let (tables, stale) = tables.into_iter().partition::<Vec<_>, _>(|t| !shadowed(t));
+        for table in stale {
+            table.retire();
+        }
+        let next_sequence = tables.first().map_or(0, |t| t.id.0) + 1;
+        let generation = tables.iter().map(|t| t.id.1).max().unwrap_or_default() + 1;
+
+        let wal_path = dir.join("wal.log");
+        let wal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&wal_path)?;
+        let log = fs::read(&wal_path)?;
+        let (changes, complete) = record::read(&log)?;
+        let mut memtable = BTreeMap::new();
+        let mut memtable_size = 0;
+        for change in changes {
+            memtable_size += change.key.len();
+            let value = match change.value {
+                Some(at) => {
+                    memtable_size += at.len();
+                    Some(record::value(&log, at)?)
+                }
+                None => None,
+            };
+            memtable.insert(change.key, value);
+        }
+        if complete < log.len() {
+            wal.set_len(complete as u64)?;
+        }
+
+        let shared = Arc::new(Shared {
+            dir,
+            tables: RwLock::new(tables.into_iter().map(Arc::new).collect()),
+            cache: BlockCache::new(options.block_cache_size),
+            generation: AtomicU64::new(generation),
+            failed: Mutex::default(),
+        });
+
+        let (compact, requests) = mpsc::channel::<()>();
+        let compactor = {
+            let shared = shared.clone();
+            let threshold = options.compaction_threshold;
+            thread::spawn(move || {
+                while requests.recv().is_ok() {
+                    if let Err(e) = shared.compact(threshold) {
+                        remember(&shared.failed, e);
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            shared,
+            options,
+            memtable,
+            memtable_size,
+            wal: BufWriter::new(wal),
+            next_sequence,
+            compactor: Some((compact, compactor)),
+        })
+    }
+
+    fn tables(&self) -> Vec<Arc<Table>> {
+        self.shared.tables.read().unwrap().clone()
+    }
+
+    /// Remembers `e` so that later writes are refused, and returns a copy to report
+    fn fail(&self, e: io::Error) -> io::Error {
+        let reported = io::Error::new(e.kind(), e.to_string());
+        remember(&self.shared.failed, e);
+        reported
+    }
+
+    /// Logs a change and applies it to the memtable, or neither if the log write fails
+    fn write(&mut self, key: String, value: Option<DBTypes>) -> io::Result<()> {
+        check(&self.shared.failed)?;
+        // A record that can't be encoded leaves nothing behind
+        let record = match &value {
+            Some(value) => record::put(&key, value)?,
+            None => record::remove(&key),
+        };
+        self.wal.write_all(&record).map_err(|e| self.fail(e))?;
+        self.memtable_size += key.len();
+        if let Some(value) = &value {
+            self.memtable_size += bincode::serialized_size(value).unwrap_or_default() as usize;
+        }
+        self.memtable.insert(key, value);
+
+        // The change is in the log by now, so a failed flush only stops later writes
+        if self.memtable_size >= self.options.memtable_size {
+            if let Err(e) = self.flush_memtable() {
+                remember(&self.shared.failed, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the memtable out as the newest table and starts over with an empty log
+    fn flush_memtable(&mut self) -> io::Result<()> {
+        let id = (self.next_sequence, 0);
+        let entries = self.memtable.iter().map(|(k, v)| (k.clone(), v.clone()));
+        if let Some(table) = Table::write(&self.shared.dir, id, entries)? {
+            let mut tables = self.shared.tables.write().unwrap();
+            tables.insert(0, Arc::new(table));
+            if tables.len() >= self.options.compaction_threshold {
+                if let Some((compact, _)) = &self.compactor {
+                    let _ = compact.send(());
+                }
+            }
+        }
+        self.next_sequence += 1;
+        self.memtable.clear();
+        self.memtable_size = 0;
+        self.truncate_wal()
+    }
+
+    fn truncate_wal(&mut self) -> io::Result<()> {
+        self.wal.flush()?;
+        self.wal.get_ref().set_len(0)?;
+        self.wal.get_ref().sync_data()
+    }
+}
+
+impl Shared {
+    /// Merges every table into one. Removals can be dropped since no older table is left
+    /// for them to hide anything in
+    fn compact(&self, threshold: usize) -> io::Result<()> {
+        let inputs = self.tables.read().unwrap().clone();
+        if inputs.len() < threshold.max(2) {
+            return Ok(());
+        }
+        let id: TableId = (
+            inputs.iter().map(|t| t.id.0).max().unwrap_or_default(),
+            self.generation.fetch_add(1, Ordering::SeqCst),
+        );
+
+        let sources = inputs
+            .iter()
+            .map(|t| Box::new(t.range(ALL, None, &self.failed)) as Box<dyn Iterator<Item = _>>)
+            .collect();
+        let live = newest(sources).filter_map(|(key, value)| Some((key, Some(value?))));
+        let merged = Table::write(&self.dir, id, live)?;
+
+        let mut tables = self.tables.write().unwrap();
+        // `replace` may have swapped the tables out in the meantime
+        if !inputs
+            .iter()
+            .all(|input| tables.iter().any(|t| Arc::ptr_eq(t, input)))
+        {
+            if let Some(merged) = merged {
+                merged.retire();
+            }
+            return Ok(());
+        }
+        tables.retain(|t| !inputs.iter().any(|input| Arc::ptr_eq(t, input)));
+        tables.extend(merged.map(Arc::new));
+        for input in inputs {
+            input.retire();
+        }
+        Ok(())
+    }
+}
+
+impl StorageEngine for LsmEngine {
+    fn get(&self, key: &str) -> Option<Cow<'_, DBTypes>> {
+        if let Some(value) = self.memtable.get(key) {
+            return value.as_ref().map(Cow::Borrowed);
+        }
+        for table in self.tables() {
+            match table.get(key, &self.shared.cache) {
+                Ok(Some(value)) => return value.map(Cow::Owned),
+                Ok(None) => continue,
+                Err(e) => {
+                    remember(&self.shared.failed, e);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    fn put(&mut self, key: String, value: DBTypes) -> io::Result<Option<DBTypes>> {
+        let old = self.get(&key).map(Cow::into_owned);
+        self.write(key, Some(value))?;
+        Ok(old)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<DBTypes>> {
+        let old = self.get(key).map(Cow::into_owned);
+        match old {
+            Some(_) => self.write(key.to_owned(), None)?,
+            // The key may only look missing because reading it failed
+            None => check(&self.shared.failed)?,
+        }
+        Ok(old)
+    }
+
+    fn scan(&self, range: (Bound<&str>, Bound<&str>)) -> Scan<'_> {
+        let memtable = self.memtable.range::<str, _>(range).map(|(key, value)| {
+            (
+                Cow::Borrowed(key.as_str()),
+                value.as_ref().map(Cow::Borrowed),
+            )
+        });
+        let tables = self.tables().into_iter().map(|table| {
+            let entries = table
+                .range(range, Some(&self.shared.cache), &self.shared.failed)
+                .map(|(key, value)| (Cow::Owned(key), value.map(Cow::Owned)));
+            Box::new(entries) as Box<dyn Iterator<Item = _>>
+        });
+
+        let sources = std::iter::once(Box::new(memtable) as Box<dyn Iterator<Item = _>>)
+            .chain(tables)
+            .collect::<Vec<_>>();
+        Box::new(newest(sources).filter_map(|(key, value)| Some((key, value?))))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        check(&self.shared.failed)?;
+        self.wal.flush().map_err(|e| self.fail(e))?;
+        self.wal.get_ref().sync_data()
+    }
+
+    /// Writes `records` as one table that shadows every existing one
+    fn replace(&mut self, records: Collection) -> io::Result<()> {
+        let mut tables = self.shared.tables.write().unwrap();
+        let id = (
+            self.next_sequence,
+            self.shared.generation.fetch_add(1, Ordering::SeqCst),
+        );
+        let entries = records.into_iter().map(|(k, v)| (k, Some(v)));
+        let table = Table::write(&self.shared.dir, id, entries)?;
+        for old in tables.drain(..) {
+            old.retire();
+        }
+        tables.extend(table.map(Arc::new));
+        drop(tables);
+
+        self.next_sequence += 1;
+        self.memtable.clear();
+        self.memtable_size = 0;
+        self.truncate_wal()?;
+        *self.shared.failed.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+impl Drop for LsmEngine {
+    fn drop(&mut self) {
+        let _ = self.wal.flush();
+        if let Some((compact, compactor)) = self.compactor.take() {
+            drop(compact);
+            let _ = compactor.join();
+        }
+    }
+}
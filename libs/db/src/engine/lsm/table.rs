@@ -0,0 +1,282 @@
+//! Immutable sorted tables, written once by a memtable flush or a compaction. A table is laid
+//! out as `[blocks][index][bloom][index offset: u64][bloom offset: u64][magic: u64]`. Blocks
+//! hold entries `[key length: u32][key][value length: u32][value]` in key order, where a value
+//! length of `u32::MAX` marks a removed key, and the index holds the last key of every block
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::bloom::Bloom;
+use super::cache::BlockCache;
+use super::remember;
+use crate::DBTypes;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"HOYALSM1");
+const FOOTER: u64 = 24;
+const BLOCK_SIZE: usize = 4096;
+const REMOVED: u32 = u32::MAX;
+
+/// `(sequence, generation)`. Flushes take the next sequence number at generation 0, and
+/// compactions keep the newest sequence they merged at a higher generation than any input,
+/// so a table shadows every table with a lower id
+pub(super) type TableId = (u64, u64);
+
+/// Entries in key order, `None` for removed keys
+pub(super) type Block = Vec<(String, Option<DBTypes>)>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BlockHandle {
+    last_key: String,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug)]
+pub(super) struct Table {
+    pub(super) id: TableId,
+    path: PathBuf,
+    file: Mutex<File>,
+    index: Vec<BlockHandle>,
+    bloom: Bloom,
+    /// Set once the table has been merged into another, to delete its file when the
+    /// last reader lets go of it
+    retired: AtomicBool,
+}
+
+pub(super) fn file_name(id: TableId) -> String {
+    format!("{:010}-{:04}.sst", id.0, id.1)
+}
+
+pub(super) fn parse_file_name(name: &str) -> Option<TableId> {
+    let (sequence, generation) = name.strip_suffix(".sst")?.split_once('-')?;
+    Some((sequence.parse().ok()?, generation.parse().ok()?))
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Table {
+    /// Writes `entries`, which have to be in key order, as table `id` in `dir`.
+    /// The file only appears once it's complete. Writes nothing if there are no entries
+    pub(super) fn write(
+        dir: &Path,
+        id: TableId,
+        entries: impl IntoIterator<Item = (String, Option<DBTypes>)>,
+    ) -> io::Result<Option<Table>> {
+        let path = dir.join(file_name(id));
+        let partial = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&partial)?);
+
+        let mut index = vec![];
+        let mut hashes = vec![];
+        let mut block = vec![];
+        let mut offset = 0;
+        let mut entries = entries.into_iter().peekable();
+        while let Some((key, value)) = entries.next() {
+            block.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            block.extend_from_slice(key.as_bytes());
+            match value {
+                Some(value) => {
+                    let value = bincode::serialize(&value).map_err(io::Error::other)?;
+                    block.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    block.extend_from_slice(&value);
+                }
+                None => block.extend_from_slice(&REMOVED.to_le_bytes()),
+            }
+            hashes.push(Bloom::hashes(&key));
+
+            if block.len() >= BLOCK_SIZE || entries.peek().is_none() {
+                out.write_all(&block)?;
+                index.push(BlockHandle {
+                    last_key: key,
+                    offset,
+                    len: block.len() as u64,
+                });
+                offset += block.len() as u64;
+                block.clear();
+            }
+        }
+
+        if index.is_empty() {
+            drop(out);
+            fs::remove_file(&partial)?;
+            return Ok(None);
+        }
+
+        let mut bloom = Bloom::new(hashes.len());
+        for hashes in hashes {
+            bloom.insert(hashes);
+        }
+        let index_bytes = bincode::serialize(&index).map_err(io::Error::other)?;
+        let bloom_bytes = bincode::serialize(&bloom).map_err(io::Error::other)?;
+        let bloom_offset = offset + index_bytes.len() as u64;
+        out.write_all(&index_bytes)?;
+        out.write_all(&bloom_bytes)?;
+        out.write_all(&offset.to_le_bytes())?;
+        out.write_all(&bloom_offset.to_le_bytes())?;
+        out.write_all(&MAGIC.to_le_bytes())?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        drop(out);
+        fs::rename(&partial, &path)?;
+
+        Ok(Some(Table {
+            id,
+            file: Mutex::new(File::open(&path)?),
+            path,
+            index,
+            bloom,
+            retired: AtomicBool::new(false),
+        }))
+    }
+
+    pub(super) fn open(path: PathBuf, id: TableId) -> io::Result<Table> {
+        let mut file = File::open(&path)?;
+        let len = file.metadata()?.len();
+        if len < FOOTER {
+            return Err(invalid(format!("{} is too short", path.display())));
+        }
+
+        let mut footer = [0; FOOTER as usize];
+        file.seek(SeekFrom::Start(len - FOOTER))?;
+        file.read_exact(&mut footer)?;
+        let word = |i: usize| u64::from_le_bytes(footer[i * 8..i * 8 + 8].try_into().unwrap());
+        let (index_offset, bloom_offset) = (word(0), word(1));
+        if word(2) != MAGIC || index_offset > bloom_offset || bloom_offset > len - FOOTER {
+            return Err(invalid(format!("{} isn't a table", path.display())));
+        }
+
+        let mut meta = vec![0; (len - FOOTER - index_offset) as usize];
+        file.seek(SeekFrom::Start(index_offset))?;
+        file.read_exact(&mut meta)?;
+        let (index, bloom) = meta.split_at((bloom_offset - index_offset) as usize);
+
+        Ok(Table {
+            id,
+            path,
+            file: Mutex::new(file),
+            index: bincode::deserialize(index).map_err(invalid)?,
+            bloom: bincode::deserialize(bloom).map_err(invalid)?,
+            retired: AtomicBool::new(false),
+        })
+    }
+
+    pub(super) fn retire(&self) {
+        self.retired.store(true, Ordering::SeqCst);
+    }
+
+    /// The block and its size on disk
+    fn read_block(&self, i: usize) -> io::Result<(Block, usize)> {
+        let handle = &self.index[i];
+        let mut bytes = vec![0; handle.len as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(handle.offset))?;
+            file.read_exact(&mut bytes)?;
+        }
+        Ok((decode_block(&bytes)?, bytes.len()))
+    }
+
+    fn block(&self, i: usize, cache: Option<&BlockCache>) -> io::Result<Arc<Block>> {
+        match cache {
+            Some(cache) => cache.get_or_load((self.id, i), || self.read_block(i)),
+            None => Ok(Arc::new(self.read_block(i)?.0)),
+        }
+    }
+
+    /// `None` if the table doesn't mention `key`, `Some(None)` if it records its removal
+    pub(super) fn get(&self, key: &str, cache: &BlockCache) -> io::Result<Option<Option<DBTypes>>> {
+        if !self.bloom.may_contain(key) {
+            return Ok(None);
+        }
+        let i = self.index.partition_point(|h| h.last_key.as_str() < key);
+        if i == self.index.len() {
+            return Ok(None);
+        }
+        let block = self.block(i, Some(cache))?;
+        Ok(block
+            .binary_search_by(|(k, _)| k.as_str().cmp(key))
+            .ok()
+            .map(|j| block[j].1.clone()))
+    }
+
+    /// The entries within `range`, in key order, read through `cache` if there is one.
+    /// A block that can't be read ends the iteration, and its error goes to `failed`
+    pub(super) fn range<'a>(
+        self: &Arc<Self>,
+        (start, end): (Bound<&str>, Bound<&str>),
+        cache: Option<&'a BlockCache>,
+        failed: &'a Mutex<Option<io::Error>>,
+    ) -> impl Iterator<Item = (String, Option<DBTypes>)> + 'a {
+        let first = match start {
+            Bound::Included(s) | Bound::Excluded(s) => {
+                self.index.partition_point(|h| h.last_key.as_str() < s)
+            }
+            Bound::Unbounded => 0,
+        };
+        let (start, end) = (start.map(str::to_owned), end.map(str::to_owned));
+        let table = self.clone();
+
+        (first..self.index.len())
+            .map_while(move |i| match table.block(i, cache) {
+                Ok(block) => Some(block),
+                Err(e) => {
+                    remember(failed, e);
+                    None
+                }
+            })
+            .flat_map(|block| (0..block.len()).map(move |j| block[j].clone()))
+            .skip_while(move |(key, _)| match &start {
+                Bound::Included(s) => key < s,
+                Bound::Excluded(s) => key <= s,
+                Bound::Unbounded => false,
+            })
+            .take_while(move |(key, _)| match &end {
+                Bound::Included(e) => key <= e,
+                Bound::Excluded(e) => key < e,
+                Bound::Unbounded => true,
+            })
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if self.retired.load(Ordering::SeqCst) {
+            // Left behind if this fails, and shadowed by the table that replaced it
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn decode_block(mut bytes: &[u8]) -> io::Result<Block> {
+    fn take<'a>(bytes: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+        if bytes.len() < n {
+            return Err(invalid("truncated block"));
+        }
+        let (taken, rest) = bytes.split_at(n);
+        *bytes = rest;
+        Ok(taken)
+    }
+    fn length(bytes: &mut &[u8]) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(take(bytes, 4)?.try_into().unwrap()))
+    }
+
+    let mut block = vec![];
+    while !bytes.is_empty() {
+        let key_len = length(&mut bytes)? as usize;
+        let key = String::from_utf8(take(&mut bytes, key_len)?.to_vec()).map_err(invalid)?;
+        let value = match length(&mut bytes)? {
+            REMOVED => None,
+            len => Some(bincode::deserialize(take(&mut bytes, len as usize)?).map_err(invalid)?),
+        };
+        block.push((key, value));
+    }
+    Ok(block)
+}
@@ -0,0 +1,52 @@
+use std::borrow::Cow;
+use std::io;
+use std::ops::Bound;
+
+use super::{Scan, StorageEngine};
+use crate::db::Collection;
+use crate::DBTypes;
+
+/// Keeps everything in a `BTreeMap`, persisted only by `Database::store`
+#[derive(Debug, Default)]
+pub struct MemoryEngine {
+    records: Collection,
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, key: &str) -> Option<Cow<'_, DBTypes>> {
+        self.records.get(key).map(Cow::Borrowed)
+    }
+
+    fn put(&mut self, key: String, value: DBTypes) -> io::Result<Option<DBTypes>> {
+        Ok(self.records.insert(key, value))
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<DBTypes>> {
+        Ok(self.records.remove(key))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.records.contains_key(key)
+    }
+
+    fn scan(&self, range: (Bound<&str>, Bound<&str>)) -> Scan<'_> {
+        Box::new(
+            self.records
+                .range::<str, _>(range)
+                .map(|(key, value)| (Cow::Borrowed(key.as_str()), Cow::Borrowed(value))),
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Collection {
+        self.records.clone()
+    }
+
+    fn replace(&mut self, records: Collection) -> io::Result<()> {
+        self.records = records;
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use super::{failed, record, Scan, StorageEngine};
+use crate::db::Collection;
+use crate::DBTypes;
+
+/// Appends changes to a log like `LogEngine`, but keeps only keys in memory and decodes
+/// values from a memory map of the log when they're read. Suits data larger than memory
+/// that's read much more than it's written, since every write remaps the log
+#[derive(Debug)]
+pub struct MmapEngine {
+    path: PathBuf,
+    file: File,
+    map: Option<Mmap>,
+    /// Where each live value lies in the log
+    values: BTreeMap<String, Range<usize>>,
+    failed: Option<io::Error>,
+}
+
+impl MmapEngine {
+    /// Opens the log at `path`, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut engine = Self {
+            path,
+            file,
+            map: None,
+            values: BTreeMap::new(),
+            failed: None,
+        };
+        engine.remap()?;
+
+        let log = engine.log();
+        let (changes, complete) = record::read(log)?;
+        let mut values = BTreeMap::new();
+        for change in changes {
+            match change.value {
+                Some(at) => {
+                    // Checked once here, so reads can rely on every value decoding
+                    record::value(log, at.clone())?;
+                    values.insert(change.key, at);
+                }
+                None => {
+                    values.remove(&change.key);
+                }
+            }
+        }
+        let torn = complete < log.len();
+        engine.values = values;
+        if torn {
+            engine.file.set_len(complete as u64)?;
+            engine.remap()?;
+        }
+        Ok(engine)
+    }
+
+    fn log(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or_default()
+    }
+
+    fn remap(&mut self) -> io::Result<()> {
+        self.map = if self.file.metadata()?.len() == 0 {
+            None
+        } else {
+            // SAFETY: the log is only written through this engine, which appends past the
+            // mapped part and remaps before reading it. Another process changing the
+            // file underneath would make reads see torn values
+            Some(unsafe { Mmap::map(&self.file)? })
+        };
+        Ok(())
+    }
+
+    fn decode(&self, at: &Range<usize>) -> Option<DBTypes> {
+        record::value(self.log(), at.clone()).ok()
+    }
+
+    /// Appends `record`, returning where in the log it lies
+    fn append(&mut self, record: io::Result<Vec<u8>>) -> io::Result<Range<usize>> {
+        if let Some(e) = &self.failed {
+            return Err(failed(e));
+        }
+        // A record that can't be encoded leaves nothing behind
+        let record = record?;
+        let start = self.log().len();
+        // Once the write starts, a failure may leave part of the record in the log, or
+        // the map not covering it
+        match self.file.write_all(&record).and_then(|()| self.remap()) {
+            Ok(()) => Ok(start..start + record.len()),
+            Err(e) => {
+                let reported = io::Error::new(e.kind(), e.to_string());
+                self.failed = Some(e);
+                Err(reported)
+            }
+        }
+    }
+}
+
+impl StorageEngine for MmapEngine {
+    fn get(&self, key: &str) -> Option<Cow<'_, DBTypes>> {
+        self.values
+            .get(key)
+            .and_then(|at| self.decode(at))
+            .map(Cow::Owned)
+    }
+
+    fn put(&mut self, key: String, value: DBTypes) -> io::Result<Option<DBTypes>> {
+        let old = self.values.get(&key).and_then(|at| self.decode(at));
+        let at = self.append(record::put(&key, &value))?;
+        let at = record::value_of_put(&key, at);
+        self.values.insert(key, at);
+        Ok(old)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<DBTypes>> {
+        let old = self.values.get(key).and_then(|at| self.decode(at));
+        if old.is_some() {
+            self.append(Ok(record::remove(key)))?;
+            self.values.remove(key);
+        }
+        Ok(old)
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    fn scan(&self, range: (Bound<&str>, Bound<&str>)) -> Scan<'_> {
+        Box::new(self.values.range::<str, _>(range).filter_map(|(key, at)| {
+            Some((Cow::Borrowed(key.as_str()), Cow::Owned(self.decode(at)?)))
+        }))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = &self.failed {
+            return Err(failed(e));
+        }
+        self.file.sync_data()
+    }
+
+    /// Writes the new log beside the old one and swaps it in, so a crash leaves one of them
+    fn replace(&mut self, records: Collection) -> io::Result<()> {
+        let compacted = self.path.with_extension("compact");
+        let mut file = File::create(&compacted)?;
+        let mut values = BTreeMap::new();
+        let mut length = 0;
+        for (key, value) in records {
+            let record = record::put(&key, &value)?;
+            file.write_all(&record)?;
+            let at = record::value_of_put(&key, length..length + record.len());
+            length += record.len();
+            values.insert(key, at);
+        }
+        file.sync_all()?;
+        fs::rename(&compacted, &self.path)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.remap()?;
+        self.values = values;
+        self.failed = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+mod log;
+mod lsm;
+mod memory;
+mod mmap;
+mod record;
+
+pub use log::LogEngine;
+pub use lsm::{LsmEngine, LsmOptions};
+pub use memory::MemoryEngine;
+pub use mmap::MmapEngine;
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::ops::Bound;
+
+use crate::db::Collection;
+use crate::DBTypes;
+
+/// Reports the write that failed earlier, which leaves the engine refusing writes
+pub(crate) fn failed(e: &io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!(
+            "An earlier write failed, and writes are lost until the records are rewritten: {e}"
+        ),
+    )
+}
+
+/// The records within a key range, in key order
+pub type Scan<'a> = Box<dyn Iterator<Item = (Cow<'a, str>, Cow<'a, DBTypes>)> + 'a>;
+
+/// Where a shard keeps its records. `Database` guards each engine with a lock and keeps
+/// the secondary indexes itself, so engines only map keys to values.
+///
+/// A write that can't be persisted returns the error and isn't applied. A failed write
+/// may leave part of a record behind, so engines that persist writes refuse every later
+/// one, and report the error from `flush`, until `replace` rewrites them
+pub trait StorageEngine: fmt::Debug + Send + Sync {
+    /// Engines that don't keep values in memory decode them, so they hand out owned values
+    fn get(&self, key: &str) -> Option<Cow<'_, DBTypes>>;
+
+    fn put(&mut self, key: String, value: DBTypes) -> io::Result<Option<DBTypes>>;
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<DBTypes>>;
+
+    fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn scan(&self, range: (Bound<&str>, Bound<&str>)) -> Scan<'_>;
+
+    /// Makes every write so far durable
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// A copy of every record as of now
+    fn snapshot(&self) -> Collection {
+        self.scan((Bound::Unbounded, Bound::Unbounded))
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect()
+    }
+
+    /// Replaces every record with `records`
+    fn replace(&mut self, records: Collection) -> io::Result<()> {
+        let keys = self
+            .scan((Bound::Unbounded, Bound::Unbounded))
+            .map(|(key, _)| key.into_owned())
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.remove(&key)?;
+        }
+        for (key, value) in records {
+            self.put(key, value)?;
+        }
+        self.flush()
+    }
+}
@@ -0,0 +1,155 @@
+//! The append-only log both file engines write: one record per change, laid out as
+//! `[checksum: u32][tag: u8][key length: u32][key][value length: u32][value]` with
+//! little-endian numbers and bincode values. Removals have no value part. The checksum
+//! is the CRC-32 of the rest of the record
+
+use std::io;
+use std::ops::Range;
+
+use crate::DBTypes;
+
+const PUT: u8 = 1;
+const REMOVE: u8 = 2;
+
+pub(super) fn put(key: &str, value: &DBTypes) -> io::Result<Vec<u8>> {
+    let value = bincode::serialize(value).map_err(io::Error::other)?;
+    let mut record = header(PUT, key);
+    record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    record.extend_from_slice(&value);
+    Ok(sealed(record))
+}
+
+/// Where the value of the put of `key` at `record` lies
+pub(super) fn value_of_put(key: &str, record: Range<usize>) -> Range<usize> {
+    record.start + 4 + 1 + 4 + key.len() + 4..record.end
+}
+
+pub(super) fn remove(key: &str) -> Vec<u8> {
+    sealed(header(REMOVE, key))
+}
+
+/// Starts a record with room for its checksum
+fn header(tag: u8, key: &str) -> Vec<u8> {
+    let mut header = vec![0; 4];
+    header.push(tag);
+    header.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    header.extend_from_slice(key.as_bytes());
+    header
+}
+
+fn sealed(mut record: Vec<u8>) -> Vec<u8> {
+    let checksum = crc32fast::hash(&record[4..]);
+    record[..4].copy_from_slice(&checksum.to_le_bytes());
+    record
+}
+
+/// A change read back from a log, with the position of its value in the log
+pub(super) struct Change {
+    pub(super) key: String,
+    pub(super) value: Option<Range<usize>>,
+}
+
+enum Read {
+    Complete(Change, usize),
+    /// The record runs past the end of the log
+    Torn,
+    Corrupt,
+}
+
+/// The changes in `log`, and the length of the part holding them. A crash mid-write
+/// leaves a torn record at the end, or zeroes where the file grew but the record
+/// never landed, which are left out. A record that doesn't check out before the end
+/// means the log is damaged, which is an error rather than data to drop. That includes
+/// a damaged length, which makes a record look like it runs past the end even though
+/// complete records follow it all the way to the end
+pub(super) fn read(log: &[u8]) -> io::Result<(Vec<Change>, usize)> {
+    let mut changes = vec![];
+    let mut at = 0;
+    while at < log.len() {
+        match read_one(log, at) {
+            Read::Complete(change, end) => {
+                changes.push(change);
+                at = end;
+            }
+            Read::Torn if !followed_by_records(log, at) => break,
+            Read::Corrupt if log[at..].iter().all(|b| *b == 0) => break,
+            Read::Torn | Read::Corrupt => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("The log is corrupt at byte {at}"),
+                ))
+            }
+        }
+    }
+    Ok((changes, at))
+}
+
+/// Whether complete records starting somewhere after `at` run on to exactly the end of
+/// the log. A torn record is the start of a single write, so while its bytes might hold
+/// something that reads as a record, they don't chain into records up to the end
+fn followed_by_records(log: &[u8], at: usize) -> bool {
+    // Whether the records from each position after `at` reach the end, so each
+    // position is read at most once
+    let mut reaches_end = vec![None; log.len() - at];
+    (at + 1..log.len()).any(|start| {
+        let mut path = vec![];
+        let mut position = start;
+        let reaches = loop {
+            if position == log.len() {
+                break true;
+            }
+            if let Some(reaches) = reaches_end[position - at] {
+                break reaches;
+            }
+            path.push(position);
+            match read_one(log, position) {
+                Read::Complete(_, end) => position = end,
+                Read::Torn | Read::Corrupt => break false,
+            }
+        };
+        for position in path {
+            reaches_end[position - at] = Some(reaches);
+        }
+        reaches
+    })
+}
+
+fn read_one(log: &[u8], at: usize) -> Read {
+    let number = |at: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(log.get(at..at + 4)?.try_into().ok()?))
+    };
+    let (Some(checksum), Some(tag), Some(key_len)) = (number(at), log.get(at + 4), number(at + 5))
+    else {
+        return Read::Torn;
+    };
+    let key = at + 9..at + 9 + key_len as usize;
+    let end = match *tag {
+        PUT => match number(key.end) {
+            Some(value_len) => key.end + 4 + value_len as usize,
+            None => return Read::Torn,
+        },
+        REMOVE => key.end,
+        _ => return Read::Corrupt,
+    };
+    let Some(record) = log.get(at + 4..end) else {
+        return Read::Torn;
+    };
+    if crc32fast::hash(record) != checksum {
+        return Read::Corrupt;
+    }
+    let Ok(key) = std::str::from_utf8(&log[key]) else {
+        return Read::Corrupt;
+    };
+    let value = (*tag == PUT).then(|| value_of_put(key, at..end));
+    Read::Complete(
+        Change {
+            key: key.to_owned(),
+            value,
+        },
+        end,
+    )
+}
+
+pub(super) fn value(log: &[u8], at: Range<usize>) -> io::Result<DBTypes> {
+    bincode::deserialize(&log[at]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatabaseError {
+    /// The key holds a value of another type than the operation works on
+    WrongType {
+        key: String,
+        expected: &'static str,
+    },
+    /// The storage engine couldn't persist a write, which wasn't applied
+    Storage(String),
+    /// Only one save runs or waits at a time, and an export runs only while nothing else is
+    /// frozen
+    SaveInProgress {
+        target: String,
+    },
+    /// A file name that's empty, a path, or reaches outside the data directory
+    InvalidName {
+        name: String,
+    },
+    NotFound {
+        name: String,
+    },
+    PermissionDenied {
+        name: String,
+    },
+    /// A sorted set score that isn't finite, which would leave its members without an order
+    NonFiniteScore {
+        key: String,
+        score: f64,
+    },
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::WrongType { key, expected } => {
+                write!(f, "Key `{key}` doesn't hold a {expected}")
+            }
+            DatabaseError::Storage(e) => write!(f, "Couldn't write to storage: {e}"),
+            DatabaseError::SaveInProgress { target } => {
+                write!(f, "Can't start saving while writing {target}")
+            }
+            DatabaseError::InvalidName { name } => {
+                write!(f, "`{name}` isn't a file name in the data directory")
+            }
+            DatabaseError::NotFound { name } => write!(f, "There's no file `{name}`"),
+            DatabaseError::PermissionDenied { name } => {
+                write!(f, "Not allowed to access `{name}`")
+            }
+            DatabaseError::NonFiniteScore { key, score } => {
+                write!(
+                    f,
+                    "Scores in `{key}` must be finite, but one would be {score}"
+                )
+            }
+        }
+    }
+}
+
+impl Error for DatabaseError {}
+
+impl From<io::Error> for DatabaseError {
+    fn from(e: io::Error) -> Self {
+        DatabaseError::Storage(e.to_string())
+    }
+}
+
+/// Why a snapshot couldn't be read
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// Written by a newer HoyaDB, or not a snapshot
+    UnknownFormat(String),
+    KeyRequired,
+    /// Encrypted with a key file where a passphrase was given, or the other way around
+    WrongSecret {
+        passphrase: bool,
+    },
+    /// The key doesn't match, or the header was changed
+    WrongKey,
+    /// The contents don't match what was written
+    Corrupted,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::UnknownFormat(what) => write!(f, "Unknown snapshot format: {what}"),
+            SnapshotError::KeyRequired => {
+                write!(
+                    f,
+                    "The snapshot is encrypted, but no key or passphrase was given"
+                )
+            }
+            SnapshotError::WrongSecret { passphrase: true } => {
+                write!(f, "The snapshot was encrypted with a passphrase, not a key")
+            }
+            SnapshotError::WrongSecret { passphrase: false } => {
+                write!(f, "The snapshot was encrypted with a key, not a passphrase")
+            }
+            SnapshotError::WrongKey => write!(
+                f,
+                "Wrong key or passphrase for the snapshot, or its header was tampered with"
+            ),
+            SnapshotError::Corrupted => {
+                write!(
+                    f,
+                    "The snapshot is truncated, corrupted or was tampered with"
+                )
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Why a cluster couldn't carry out a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClusterError {
+    /// Only the leader takes writes and confirms reads. It's given with its address
+    /// when this node knows it
+    NotLeader {
+        leader: Option<(u64, String)>,
+    },
+    /// Leadership was lost before the write was committed, so it may or may not have
+    /// been made
+    Uncertain,
+    Stopped,
+    /// Most members couldn't be reached in time
+    Timeout,
+    /// Members change one at a time
+    ChangeInProgress,
+    NotMember(u64),
+    /// A cluster needs at least one member
+    LastMember,
+    /// The write was committed, but failed on the records
+    Database(DatabaseError),
+}
+
+impl fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClusterError::NotLeader {
+                leader: Some((id, addr)),
+            } => write!(f, "This node isn't the leader; node {id} at {addr} is"),
+            ClusterError::NotLeader { leader: None } => {
+                write!(f, "This node isn't the leader, and doesn't know of one")
+            }
+            ClusterError::Uncertain => write!(
+                f,
+                "Leadership changed before the write was committed, so it may not have been made"
+            ),
+            ClusterError::Stopped => write!(f, "The cluster node has stopped"),
+            ClusterError::Timeout => write!(f, "Timed out waiting for the cluster"),
+            ClusterError::ChangeInProgress => {
+                write!(f, "Another change of members is still being committed")
+            }
+            ClusterError::NotMember(id) => write!(f, "Node {id} isn't a member"),
+            ClusterError::LastMember => write!(f, "Can't remove the last member"),
+            ClusterError::Database(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ClusterError {}
+
+/// Why a shard router couldn't carry out a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShardError {
+    /// The shard server couldn't be reached, or stopped answering. A write sent to it
+    /// may or may not have been made
+    Unreachable {
+        addr: String,
+        reason: String,
+    },
+    Refused {
+        addr: String,
+        reason: String,
+    },
+    /// The shard made the request, but it failed on the records
+    Database(String),
+    /// The shards kept sending the request for `key` elsewhere
+    Redirected {
+        key: String,
+    },
+    /// A ring needs at least one shard
+    NoShards,
+    NotAShard(String),
+    AlreadyAShard(String),
+}
+
+impl fmt::Display for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardError::Unreachable { addr, reason } => {
+                write!(f, "Couldn't reach the shard at {addr}: {reason}")
+            }
+            ShardError::Refused { addr, reason } => {
+                write!(f, "The shard at {addr} refused: {reason}")
+            }
+            ShardError::Database(message) => write!(f, "{message}"),
+            ShardError::Redirected { key } => write!(
+                f,
+                "The shards kept redirecting `{key}`; they may be resharding"
+            ),
+            ShardError::NoShards => write!(f, "There are no shards"),
+            ShardError::NotAShard(addr) => write!(f, "{addr} isn't a shard"),
+            ShardError::AlreadyAShard(addr) => write!(f, "{addr} is a shard already"),
+        }
+    }
+}
+
+impl Error for ShardError {}
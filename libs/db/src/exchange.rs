@@ -0,0 +1,411 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde::de::{self, MapAccess, Visitor};
+use serde::Deserializer;
+use serde_json::{json, Map, Value};
+
+use crate::db::ALL;
+use crate::{DBTypes, Database, DatabaseError};
+
+/// How many imported records are put under one lock
+const BATCH: usize = 1024;
+
+/// A text format the keyspace can be exported to and imported from. Values are written
+/// as objects naming their type, like `{"number": 1}` or `{"float": 1.0}`, so that every
+/// value comes back as exactly what it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One object mapping every key to its value
+    Json,
+    /// One `{"key": ..., "value": ...}` object per line
+    Ndjson,
+    /// `key,type,value` rows, with text, numbers and other scalars written plainly and
+    /// lists, sets and durations written as JSON
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "Expected the format json, ndjson or csv, but found `{format}`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+        })
+    }
+}
+
+/// What happens to records that an import doesn't mention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// They're kept
+    Merge,
+    /// They're removed once the import has been read completely, so a file that fails
+    /// to parse halfway leaves them in place
+    Replace,
+}
+
+impl FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "merge" => Ok(ImportMode::Merge),
+            "replace" => Ok(ImportMode::Replace),
+            _ => Err(format!(
+                "Expected the mode merge or replace, but found `{mode}`"
+            )),
+        }
+    }
+}
+
+fn invalid(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn float(f: f64) -> Value {
+    match serde_json::Number::from_f64(f) {
+        Some(n) => Value::Number(n),
+        // JSON has no NaN or infinities
+        None => Value::String(f.to_string()),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The tagged form of `value`
+pub(crate) fn encode(value: &DBTypes) -> Value {
+    let (tag, payload) = match value {
+        DBTypes::Number(n) => ("number", json!(n)),
+        DBTypes::Float(f) => ("float", float(*f)),
+        DBTypes::Boolean(b) => ("boolean", json!(b)),
+        DBTypes::Text(t) => ("text", json!(t)),
+        DBTypes::List(l) => ("list", Value::Array(l.iter().map(encode).collect())),
+        DBTypes::Unit(()) => ("unit", Value::Null),
+        DBTypes::Set(s) => ("set", json!(s)),
+        DBTypes::SortedSet(s) => (
+            "sorted-set",
+            Value::Object(
+                s.iter()
+                    .map(|(m, score)| (m.clone(), float(*score)))
+                    .collect(),
+            ),
+        ),
+        DBTypes::Bytes(b) => ("bytes", json!(hex(b))),
+        DBTypes::Timestamp(t) => (
+            "timestamp",
+            json!(t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        ),
+        DBTypes::Duration(d) => (
+            "duration",
+            json!({ "seconds": d.num_seconds(), "nanoseconds": d.subsec_nanos() }),
+        ),
+        DBTypes::BigInt(n) => ("bigint", json!(n.to_string())),
+        DBTypes::Decimal(d) => ("decimal", json!(d.to_string())),
+        DBTypes::Nil => ("nil", Value::Null),
+    };
+    let mut tagged = Map::new();
+    tagged.insert(tag.to_owned(), payload);
+    Value::Object(tagged)
+}
+
+/// Reads back what `encode` wrote
+pub(crate) fn decode(value: Value) -> Result<DBTypes, String> {
+    let Value::Object(tagged) = value else {
+        return Err(format!(
+            "Expected a value like {{\"text\": \"…\"}}, but found {value}"
+        ));
+    };
+    if tagged.len() != 1 {
+        return Err(format!(
+            "Expected a value with exactly one type, but found {}",
+            Value::Object(tagged)
+        ));
+    }
+    let (tag, payload) = tagged.into_iter().next().unwrap();
+    decode_payload(&tag, payload)
+}
+
+fn decode_float(payload: &Value) -> Option<f64> {
+    match payload {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Some(f64::NAN),
+            "inf" => Some(f64::INFINITY),
+            "-inf" => Some(f64::NEG_INFINITY),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn decode_payload(tag: &str, payload: Value) -> Result<DBTypes, String> {
+    let wrong = |payload: &Value| format!("`{payload}` isn't a valid {tag}");
+    let text = |payload: &Value| {
+        payload
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| wrong(payload))
+    };
+
+    Ok(match tag {
+        "number" => DBTypes::Number(
+            payload
+                .as_i64()
+                .and_then(|n| isize::try_from(n).ok())
+                .ok_or_else(|| wrong(&payload))?,
+        ),
+        "float" => DBTypes::Float(decode_float(&payload).ok_or_else(|| wrong(&payload))?),
+        "boolean" => DBTypes::Boolean(payload.as_bool().ok_or_else(|| wrong(&payload))?),
+        "text" => DBTypes::Text(text(&payload)?),
+        "list" => match payload {
+            Value::Array(items) => {
+                DBTypes::List(items.into_iter().map(decode).collect::<Result<_, _>>()?)
+            }
+            payload => return Err(wrong(&payload)),
+        },
+        "unit" if payload.is_null() => DBTypes::Unit(()),
+        "nil" if payload.is_null() => DBTypes::Nil,
+        "set" => DBTypes::Set(
+            serde_json::from_value::<BTreeSet<String>>(payload.clone())
+                .map_err(|_| wrong(&payload))?,
+        ),
+        "sorted-set" => match &payload {
+            Value::Object(members) => DBTypes::SortedSet(
+                members
+                    .iter()
+                    .map(|(member, score)| Some((member.clone(), decode_float(score)?)))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| wrong(&payload))?,
+            ),
+            _ => return Err(wrong(&payload)),
+        },
+        "bytes" => {
+            let hex = text(&payload)?;
+            DBTypes::Bytes(
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| wrong(&payload))?,
+            )
+        }
+        "timestamp" => DBTypes::Timestamp(
+            DateTime::parse_from_rfc3339(&text(&payload)?)
+                .map_err(|_| wrong(&payload))?
+                .with_timezone(&Utc),
+        ),
+        "duration" => {
+            let seconds = payload.get("seconds").and_then(Value::as_i64);
+            let nanoseconds = payload.get("nanoseconds").and_then(Value::as_i64);
+            let duration = seconds
+                .zip(nanoseconds.filter(|n| n.abs() < 1_000_000_000))
+                .and_then(|(s, n)| {
+                    Duration::try_seconds(s)?.checked_add(&Duration::nanoseconds(n))
+                });
+            DBTypes::Duration(duration.ok_or_else(|| wrong(&payload))?)
+        }
+        "bigint" => DBTypes::BigInt(text(&payload)?.parse().map_err(|_| wrong(&payload))?),
+        "decimal" => DBTypes::Decimal(text(&payload)?.parse().map_err(|_| wrong(&payload))?),
+        _ => return Err(format!("Unknown type `{tag}` with {payload}")),
+    })
+}
+
+/// Types whose CSV cell is the text of their JSON string, rather than JSON
+const PLAIN_TEXT: [&str; 5] = ["text", "bytes", "timestamp", "bigint", "decimal"];
+
+fn csv_row(key: &str, value: &DBTypes) -> [String; 3] {
+    let Value::Object(tagged) = encode(value) else {
+        unreachable!("values are encoded as objects")
+    };
+    let (tag, payload) = tagged.into_iter().next().unwrap();
+    let cell = match payload {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        payload => payload.to_string(),
+    };
+    [key.to_owned(), tag, cell]
+}
+
+fn csv_value(tag: &str, cell: &str) -> Result<DBTypes, String> {
+    let payload = if PLAIN_TEXT.contains(&tag) {
+        Value::String(cell.to_owned())
+    } else if cell.is_empty() {
+        Value::Null
+    } else {
+        // NaN and the infinities are written plainly too
+        serde_json::from_str(cell).unwrap_or_else(|_| Value::String(cell.to_owned()))
+    };
+    decode_payload(tag, payload)
+}
+
+/// Calls a function with every entry of a JSON object as it's read
+struct Entries<F>(F);
+
+impl<'de, F: FnMut(String, Value) -> Result<(), String>> Visitor<'de> for Entries<F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object mapping keys to values")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        while let Some((key, value)) = map.next_entry::<String, Value>()? {
+            (self.0)(key, value).map_err(de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Streams a point-in-time image of every record to `out`, in key order. Writes carry on
+    /// meanwhile. Returns how many records were written
+    pub fn export(&self, out: impl Write, format: Format) -> Result<u64, Box<dyn Error>> {
+        self.freeze(format!("a {format} export"))?;
+        let result = self.write_export(BufWriter::new(out), format);
+        self.thaw(None);
+        Ok(result?)
+    }
+
+    fn write_export(&self, mut out: impl Write, format: Format) -> io::Result<u64> {
+        let count = match format {
+            Format::Json => {
+                out.write_all(b"{")?;
+                let mut first = true;
+                let count = self.write_frozen(|key, value| {
+                    let separator = if first { "\n" } else { ",\n" };
+                    first = false;
+                    write!(out, "{separator}{}: {}", json!(key), encode(value))
+                })?;
+                out.write_all(b"\n}\n")?;
+                count
+            }
+            Format::Ndjson => self.write_frozen(|key, value| {
+                writeln!(out, "{}", json!({ "key": key, "value": encode(value) }))
+            })?,
+            Format::Csv => {
+                let mut csv = csv::Writer::from_writer(&mut out);
+                csv.write_record(["key", "type", "value"])?;
+                let count = self.write_frozen(|key, value| {
+                    csv.write_record(csv_row(key, value))
+                        .map_err(io::Error::from)
+                })?;
+                csv.flush()?;
+                count
+            }
+        };
+        out.flush()?;
+        Ok(count)
+    }
+
+    /// Streams records from `input` into the database, a batch at a time. Returns how many
+    /// records were read. Imports aren't atomic: each batch is visible as soon as it's
+    /// put, and input that fails to parse or a write that fails partway through leaves
+    /// the batches before it in place
+    pub fn import(
+        &self,
+        input: impl Read,
+        format: Format,
+        mode: ImportMode,
+    ) -> Result<u64, Box<dyn Error>> {
+        let mut input = BufReader::new(input);
+        let mut batch = Vec::with_capacity(BATCH);
+        let mut imported = BTreeSet::new();
+        let mut count = 0u64;
+        let mut add = |key: String, value: DBTypes| -> Result<(), DatabaseError> {
+            if mode == ImportMode::Replace {
+                imported.insert(key.clone());
+            }
+            batch.push((key, value));
+            count += 1;
+            if batch.len() == BATCH {
+                self.put_many(batch.drain(..))?;
+            }
+            Ok(())
+        };
+
+        match format {
+            Format::Json => {
+                let mut json = serde_json::Deserializer::from_reader(&mut input);
+                json.deserialize_map(Entries(|key, value| {
+                    add(key, decode(value)?).map_err(|e| e.to_string())
+                }))?;
+                json.end()?;
+            }
+            Format::Ndjson => {
+                for (n, line) in input.lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let record = (|| {
+                        let mut record = serde_json::from_str::<Map<String, Value>>(&line)
+                            .map_err(|e| e.to_string())?;
+                        let key = match record.remove("key") {
+                            Some(Value::String(key)) => key,
+                            _ => return Err(String::from("Expected a `key` holding text")),
+                        };
+                        let value = record
+                            .remove("value")
+                            .ok_or_else(|| String::from("Expected a `value`"))?;
+                        Ok((key, decode(value)?))
+                    })()
+                    .map_err(|e| invalid(format!("Line {}: {e}", n + 1)))?;
+                    add(record.0, record.1)?;
+                }
+            }
+            Format::Csv => {
+                let mut csv = csv::Reader::from_reader(input);
+                if csv.headers()? != vec!["key", "type", "value"] {
+                    return Err(invalid("Expected the header `key,type,value`").into());
+                }
+                for row in csv.records() {
+                    let row = row?;
+                    let line = row.position().map_or(0, |p| p.line());
+                    let value = csv_value(&row[1], &row[2])
+                        .map_err(|e| invalid(format!("Line {line}: {e}")))?;
+                    add(row[0].to_owned(), value)?;
+                }
+            }
+        }
+        self.put_many(batch)?;
+
+        if mode == ImportMode::Replace {
+            let mut stale = vec![];
+            for store in self.read_all() {
+                stale.extend(
+                    store
+                        .records
+                        .scan(ALL)
+                        .map(|(key, _)| key)
+                        .filter(|key| !imported.contains(key.as_ref()))
+                        .map(Cow::into_owned),
+                );
+            }
+            self.remove_many(&stale)?;
+        }
+        Ok(count)
+    }
+}
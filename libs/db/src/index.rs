@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::shard::merge;
+use crate::DBTypes;
+
+/// Maps a stored value to the values it should be indexed under.
+/// Returning an empty `Vec` leaves the record out of the index.
+pub type Extractor = Arc<dyn Fn(&DBTypes) -> Vec<DBTypes> + Send + Sync>;
+
+/// Commonly used extractors
+pub mod extract {
+    use super::Extractor;
+    use crate::DBTypes;
+    use std::sync::Arc;
+
+    /// Indexes a record under its whole value
+    pub fn value() -> Extractor {
+        Arc::new(|value| vec![value.clone()])
+    }
+
+    /// Indexes a list under every one of its elements
+    pub fn elements() -> Extractor {
+        Arc::new(|value| match value {
+            DBTypes::List(l) => l.clone(),
+            _ => vec![],
+        })
+    }
+
+    /// Indexes a list under its `n`th element
+    pub fn nth(n: usize) -> Extractor {
+        Arc::new(move |value| match value {
+            DBTypes::List(l) => l.get(n).cloned().into_iter().collect(),
+            _ => vec![],
+        })
+    }
+}
+
+/// A `DBTypes` wrapper with a total order, used as the key of an index
+#[derive(Debug, Clone)]
+pub struct IndexKey(pub DBTypes);
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A sorted secondary index from extracted values to the keys holding them
+#[derive(Clone)]
+pub struct Index {
+    extractor: Extractor,
+    entries: BTreeMap<IndexKey, BTreeSet<String>>,
+}
+
+impl Index {
+    pub fn new(extractor: Extractor) -> Self {
+        Self {
+            extractor,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, key: &str, value: &DBTypes) {
+        for extracted in (self.extractor)(value) {
+            self.entries
+                .entry(IndexKey(extracted))
+                .or_default()
+                .insert(key.to_owned());
+        }
+    }
+
+    pub fn remove(&mut self, key: &str, value: &DBTypes) {
+        for extracted in (self.extractor)(value) {
+            let extracted = IndexKey(extracted);
+            if let Some(keys) = self.entries.get_mut(&extracted) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.entries.remove(&extracted);
+                }
+            }
+        }
+    }
+
+    pub fn rebuild<'a>(
+        &mut self,
+        records: impl IntoIterator<Item = (Cow<'a, str>, Cow<'a, DBTypes>)>,
+    ) {
+        self.entries.clear();
+        for (key, value) in records {
+            self.insert(&key, &value);
+        }
+    }
+
+    /// Keys whose extracted values equal `value`. Numbers equal it whatever their kind
+    pub fn find(&self, value: &DBTypes) -> Vec<String> {
+        if value.as_f64().is_some() {
+            let mut keys = self.range(value, value);
+            keys.sort_unstable();
+            return keys;
+        }
+        self.entries
+            .get(&IndexKey(value.clone()))
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Keys whose extracted values lie within `lo..=hi`, in index order
+    pub fn range(&self, lo: &DBTypes, hi: &DBTypes) -> Vec<String> {
+        self.range_bounds(Bound::Included(lo), Bound::Included(hi))
+    }
+
+    /// Keys whose extracted values lie within the given bounds, in index order
+    pub fn range_bounds(&self, start: Bound<&DBTypes>, end: Bound<&DBTypes>) -> Vec<String> {
+        range_across(&[self], start, end)
+    }
+
+    /// The values `value` would be indexed under
+    pub fn extract(&self, value: &DBTypes) -> Vec<DBTypes> {
+        (self.extractor)(value)
+    }
+}
+
+/// Keys whose extracted values lie within the given bounds in any of `indexes`, which
+/// hold disjoint keys, in index order. Keys indexed under several values come up once
+pub(crate) fn range_across(
+    indexes: &[&Index],
+    start: Bound<&DBTypes>,
+    end: Bound<&DBTypes>,
+) -> Vec<String> {
+    if is_empty_range(start, end, by_value) {
+        return vec![];
+    }
+    let (wide_start, wide_end) = (widen(start, f64::next_down), widen(end, f64::next_up));
+    if is_empty_range(wide_start.as_ref(), wide_end.as_ref(), DBTypes::total_cmp) {
+        return vec![];
+    }
+    let key = |b: Bound<&DBTypes>| b.map(|v| IndexKey(v.clone()));
+    let within = |value: &IndexKey| {
+        let value = &value.0;
+        let after = match start {
+            Bound::Included(s) => by_value(value, s).is_ge(),
+            Bound::Excluded(s) => by_value(value, s).is_gt(),
+            Bound::Unbounded => true,
+        };
+        let before = match end {
+            Bound::Included(e) => by_value(value, e).is_le(),
+            Bound::Excluded(e) => by_value(value, e).is_lt(),
+            Bound::Unbounded => true,
+        };
+        after && before
+    };
+
+    let entries = indexes.iter().map(|index| {
+        index
+            .entries
+            .range((key(wide_start.as_ref()), key(wide_end.as_ref())))
+            .filter(|(value, _)| within(value))
+            .flat_map(|(value, keys)| keys.iter().map(move |key| (value, key)))
+    });
+
+    let mut found = BTreeSet::new();
+    let mut ordered = vec![];
+    for (_, key) in merge(entries, |a, b| a.cmp(b)) {
+        if found.insert(key) {
+            ordered.push(key.clone());
+        }
+    }
+    ordered
+}
+
+/// Orders numbers by value alone, and anything else as the index does
+fn by_value(a: &DBTypes, b: &DBTypes) -> Ordering {
+    a.numeric_cmp(b).unwrap_or_else(|| a.total_cmp(b))
+}
+
+/// Numbers of the same value sit apart in an index, which orders them by kind as well,
+/// and floats only approximate the other kinds. A bound on a number moves out by a
+/// float's step to take in all of them, and `range_across` drops what's past the bound
+fn widen(bound: Bound<&DBTypes>, step: fn(f64) -> f64) -> Bound<DBTypes> {
+    match bound {
+        Bound::Included(v) | Bound::Excluded(v) => match v.as_f64().map(step) {
+            Some(f) if f.is_finite() => Bound::Included(DBTypes::Float(f)),
+            Some(_) => Bound::Unbounded,
+            None => bound.cloned(),
+        },
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Whether no value can lie within `start..end`.
+/// `BTreeMap::range` panics on such ranges instead of returning nothing
+pub(crate) fn is_empty_range<T: ?Sized>(
+    start: Bound<&T>,
+    end: Bound<&T>,
+    cmp: impl Fn(&T, &T) -> Ordering,
+) -> bool {
+    use Bound::*;
+    match (start, end) {
+        (Included(s), Included(e)) => cmp(s, e).is_gt(),
+        (Included(s), Excluded(e)) | (Excluded(s), Included(e)) | (Excluded(s), Excluded(e)) => {
+            cmp(s, e).is_ge()
+        }
+        _ => false,
+    }
+}
+
+impl fmt::Debug for Index {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Index")
+            .field("entries", &self.entries)
+            .finish_non_exhaustive()
+    }
+}
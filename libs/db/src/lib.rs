@@ -1,2 +1,30 @@
+mod autosave;
+mod data_dir;
 mod db;
+mod engine;
+mod error;
+mod exchange;
+mod index;
+mod list;
+mod query;
+mod raft;
+mod replication;
+mod set;
+mod shard;
+mod sharding;
+mod snapshot;
+pub use autosave::{Autosave, SaveRule};
+pub use data_dir::DataDir;
 pub use db::*;
+pub use engine::*;
+pub use error::*;
+pub use exchange::{Format, ImportMode};
+pub use index::*;
+pub use query::*;
+pub use raft::{
+    Cluster, ClusterClient, ClusterConfig, ClusterStatus, LocalNetwork, Mutation, NodeId, Outcome,
+    Role,
+};
+pub use replication::{Primary, Replica, ReplicaInfo, ReplicaStatus, ReplicationStatus};
+pub use sharding::{Ring, ShardRouter, ShardServer};
+pub use snapshot::{migrate, Cipher, Compression, Encryption, SaveStatus, Secret, SnapshotOptions};
@@ -0,0 +1,78 @@
+use crate::{DBTypes, Database, DatabaseError};
+
+fn not_a_list(key: &str) -> DatabaseError {
+    DatabaseError::WrongType {
+        key: key.to_owned(),
+        expected: "list",
+    }
+}
+
+/// Atomic operations on keys holding lists. A missing key behaves like an empty list
+impl Database {
+    fn push(&self, key: &str, value: DBTypes, front: bool) -> Result<usize, DatabaseError> {
+        self.modify(key, |entry| {
+            let list = match entry.get_or_insert_with(|| DBTypes::List(vec![])) {
+                DBTypes::List(list) => list,
+                _ => return Err(not_a_list(key)),
+            };
+            if front {
+                list.insert(0, value);
+            } else {
+                list.push(value);
+            }
+            Ok(list.len())
+        })
+    }
+
+    fn pop(&self, key: &str, front: bool) -> Result<Option<DBTypes>, DatabaseError> {
+        self.modify(key, |entry| match entry {
+            Some(DBTypes::List(list)) if front => Ok((!list.is_empty()).then(|| list.remove(0))),
+            Some(DBTypes::List(list)) => Ok(list.pop()),
+            Some(_) => Err(not_a_list(key)),
+            None => Ok(None),
+        })
+    }
+
+    /// Prepends `value`, returning the new length
+    pub fn lpush(&self, key: &str, value: DBTypes) -> Result<usize, DatabaseError> {
+        self.push(key, value, true)
+    }
+
+    /// Appends `value`, returning the new length
+    pub fn rpush(&self, key: &str, value: DBTypes) -> Result<usize, DatabaseError> {
+        self.push(key, value, false)
+    }
+
+    pub fn lpop(&self, key: &str) -> Result<Option<DBTypes>, DatabaseError> {
+        self.pop(key, true)
+    }
+
+    pub fn rpop(&self, key: &str) -> Result<Option<DBTypes>, DatabaseError> {
+        self.pop(key, false)
+    }
+
+    /// The elements from `start` to `stop`, both inclusive.
+    /// Negative positions count from the end, so `-1` is the last element
+    pub fn lrange(
+        &self,
+        key: &str,
+        start: isize,
+        stop: isize,
+    ) -> Result<Vec<DBTypes>, DatabaseError> {
+        match self.shard(key).read().unwrap().records.get(key).as_deref() {
+            Some(DBTypes::List(list)) => {
+                let len = list.len() as isize;
+                let position = |i: isize| if i < 0 { len + i } else { i };
+                let (start, stop) = (position(start).max(0), position(stop).min(len - 1));
+
+                if start > stop {
+                    Ok(vec![])
+                } else {
+                    Ok(list[start as usize..=stop as usize].to_vec())
+                }
+            }
+            Some(_) => Err(not_a_list(key)),
+            None => Ok(vec![]),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Bound, ControlFlow};
+
+use crate::db::{Indexes, ALL};
+use crate::index::{is_empty_range, range_across};
+use crate::shard::merge;
+use crate::{DBTypes, Database};
+
+/// The part of an entry a predicate or projection looks at
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Key,
+    Value,
+    /// The values extracted by the secondary index with this name
+    Index(String),
+}
+
+impl From<&str> for Field {
+    /// `"key"` and `"value"` name the entry itself, anything else names an index
+    fn from(field: &str) -> Self {
+        match field {
+            "key" => Field::Key,
+            "value" => Field::Value,
+            index => Field::Index(index.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn parse(op: &str) -> Option<Self> {
+        match op {
+            "=" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            "<" => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            _ => None,
+        }
+    }
+
+    /// Whether `ordering` (of the entry's side against the predicate's value) satisfies this comparison
+    pub fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Eq => ordering.is_eq(),
+            Comparison::Ne => ordering.is_ne(),
+            Comparison::Lt => ordering.is_lt(),
+            Comparison::Le => ordering.is_le(),
+            Comparison::Gt => ordering.is_gt(),
+            Comparison::Ge => ordering.is_ge(),
+        }
+    }
+
+    /// Compares two values. Numbers of any kind compare by value alone, so `1` equals `1.0`.
+    /// Other values of different types are only ever unequal, even though `total_cmp`
+    /// orders them
+    pub fn compare(&self, a: &DBTypes, b: &DBTypes) -> bool {
+        match a.numeric_cmp(b) {
+            Some(ordering) => self.holds(ordering),
+            None if a.rank() == b.rank() => self.holds(a.total_cmp(b)),
+            None => *self == Comparison::Ne,
+        }
+    }
+}
+
+/// `field op value`. Index fields hold if any extracted value satisfies the comparison
+#[derive(Debug, Clone)]
+pub struct Predicate {
+    pub field: Field,
+    pub op: Comparison,
+    pub value: DBTypes,
+}
+
+impl Predicate {
+    pub fn new(field: Field, op: Comparison, value: DBTypes) -> Self {
+        Self { field, op, value }
+    }
+
+    pub fn matches(&self, entry: &Entry<'_>) -> bool {
+        match &self.field {
+            Field::Key => match &self.value {
+                DBTypes::Text(key) => self.op.holds(entry.key.cmp(key.as_str())),
+                _ => self.op == Comparison::Ne,
+            },
+            Field::Value => self.op.compare(entry.value, &self.value),
+            Field::Index(_) => entry
+                .field(&self.field)
+                .iter()
+                .any(|v| self.op.compare(v, &self.value)),
+        }
+    }
+}
+
+/// A selection of entries, optionally restricted to keys starting with `prefix`
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub prefix: Option<String>,
+    pub predicates: Vec<Predicate>,
+}
+
+impl Query {
+    pub fn new(prefix: Option<String>) -> Self {
+        Self {
+            prefix,
+            predicates: vec![],
+        }
+    }
+
+    pub fn with(mut self, predicate: Predicate) -> Self {
+        self.predicates.push(predicate);
+        self
+    }
+
+    pub fn matches(&self, entry: &Entry<'_>) -> bool {
+        self.prefix
+            .as_ref()
+            .is_none_or(|prefix| entry.key.starts_with(prefix.as_str()))
+            && self.predicates.iter().all(|p| p.matches(entry))
+    }
+}
+
+/// How a `Query` finds its candidate entries.
+/// Every candidate is still checked against the whole query
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    FullScan,
+    KeyRange {
+        start: Bound<String>,
+        end: Bound<String>,
+    },
+    IndexLookup {
+        index: String,
+        value: DBTypes,
+    },
+    IndexRange {
+        index: String,
+        start: Bound<DBTypes>,
+        end: Bound<DBTypes>,
+    },
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn bounds<T: fmt::Debug>(start: &Bound<T>, end: &Bound<T>) -> String {
+            let start = match start {
+                Bound::Included(s) => format!("[{:?}", s),
+                Bound::Excluded(s) => format!("({:?}", s),
+                Bound::Unbounded => String::from("(-inf"),
+            };
+            let end = match end {
+                Bound::Included(e) => format!("{:?}]", e),
+                Bound::Excluded(e) => format!("{:?})", e),
+                Bound::Unbounded => String::from("+inf)"),
+            };
+            format!("{start}, {end}")
+        }
+
+        match self {
+            Plan::FullScan => write!(f, "full scan"),
+            Plan::KeyRange { start, end } => write!(f, "key range {}", bounds(start, end)),
+            Plan::IndexLookup { index, value } => {
+                write!(f, "index `{index}` lookup of {:?}", value)
+            }
+            Plan::IndexRange { index, start, end } => {
+                write!(f, "index `{index}` range {}", bounds(start, end))
+            }
+        }
+    }
+}
+
+/// An entry visited by `Database::scan`
+pub struct Entry<'a> {
+    pub key: &'a str,
+    pub value: &'a DBTypes,
+    pub(crate) indexes: &'a Indexes,
+}
+
+impl Entry<'_> {
+    /// The values of `field` for this entry; an unknown index yields nothing
+    pub fn field(&self, field: &Field) -> Vec<DBTypes> {
+        match field {
+            Field::Key => vec![DBTypes::Text(self.key.to_owned())],
+            Field::Value => vec![self.value.clone()],
+            Field::Index(name) => self
+                .indexes
+                .get(name)
+                .map(|index| index.extract(self.value))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Narrows `current` to `new` if `new` excludes more values
+fn tighter<T: Clone>(
+    current: Bound<T>,
+    new: Bound<T>,
+    cmp: impl Fn(&T, &T) -> Ordering,
+    lower: bool,
+) -> Bound<T> {
+    use Bound::*;
+    let value = |b: &Bound<T>| match b {
+        Included(v) | Excluded(v) => Some(v.clone()),
+        Unbounded => None,
+    };
+    match (value(&current), value(&new)) {
+        (None, _) => new,
+        (_, None) => current,
+        (Some(c), Some(n)) => match (cmp(&n, &c), lower) {
+            (Ordering::Greater, true) | (Ordering::Less, false) => new,
+            (Ordering::Equal, _) if matches!(new, Excluded(_)) => new,
+            _ => current,
+        },
+    }
+}
+
+/// Narrows `(start, end)` by `op value`
+fn narrow<T: Clone>(
+    (start, end): (Bound<T>, Bound<T>),
+    op: Comparison,
+    value: T,
+    cmp: impl Fn(&T, &T) -> Ordering + Copy,
+) -> (Bound<T>, Bound<T>) {
+    match op {
+        Comparison::Eq => (
+            tighter(start, Bound::Included(value.clone()), cmp, true),
+            tighter(end, Bound::Included(value), cmp, false),
+        ),
+        Comparison::Gt => (tighter(start, Bound::Excluded(value), cmp, true), end),
+        Comparison::Ge => (tighter(start, Bound::Included(value), cmp, true), end),
+        Comparison::Lt => (start, tighter(end, Bound::Excluded(value), cmp, false)),
+        Comparison::Le => (start, tighter(end, Bound::Included(value), cmp, false)),
+        Comparison::Ne => (start, end),
+    }
+}
+
+/// The smallest string greater than every string starting with `prefix`, if any
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars = prefix.chars().collect::<Vec<_>>();
+    while let Some(last) = chars.pop() {
+        if let Some(next) = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+fn plan(indexes: &Indexes, query: &Query) -> Plan {
+    let indexed =
+        |p: &&Predicate| matches!(&p.field, Field::Index(name) if indexes.contains_key(name));
+
+    if let Some(p) = query
+        .predicates
+        .iter()
+        .filter(indexed)
+        .find(|p| p.op == Comparison::Eq)
+    {
+        if let Field::Index(index) = &p.field {
+            return Plan::IndexLookup {
+                index: index.clone(),
+                value: p.value.clone(),
+            };
+        }
+    }
+
+    if let Some(Field::Index(index)) = query
+        .predicates
+        .iter()
+        .filter(indexed)
+        .find(|p| p.op != Comparison::Ne)
+        .map(|p| &p.field)
+    {
+        let (start, end) = query
+            .predicates
+            .iter()
+            .filter(|p| matches!(&p.field, Field::Index(name) if name == index))
+            .fold((Bound::Unbounded, Bound::Unbounded), |bounds, p| {
+                narrow(bounds, p.op, p.value.clone(), DBTypes::total_cmp)
+            });
+        return Plan::IndexRange {
+            index: index.clone(),
+            start,
+            end,
+        };
+    }
+
+    let mut bounds = (Bound::Unbounded, Bound::Unbounded);
+    if let Some(prefix) = &query.prefix {
+        bounds = narrow(bounds, Comparison::Ge, prefix.clone(), String::cmp);
+        if let Some(end) = prefix_end(prefix) {
+            bounds = narrow(bounds, Comparison::Lt, end, String::cmp);
+        }
+    }
+    for p in query.predicates.iter().filter(|p| p.field == Field::Key) {
+        if let DBTypes::Text(key) = &p.value {
+            bounds = narrow(bounds, p.op, key.clone(), String::cmp);
+        }
+    }
+
+    match bounds {
+        (Bound::Unbounded, Bound::Unbounded) => Plan::FullScan,
+        (start, end) => Plan::KeyRange { start, end },
+    }
+}
+
+impl Database {
+    /// The access path `scan` would use for `query`
+    pub fn plan(&self, query: &Query) -> Plan {
+        // Every shard has the same indexes
+        plan(&self.shards[0].read().unwrap().indexes, query)
+    }
+
+    /// Visits every entry matching `query` in plan order, holding the read locks throughout.
+    /// Entries are borrowed, so callers only clone what they keep
+    pub fn scan<F>(&self, query: &Query, mut f: F)
+    where
+        F: FnMut(Entry<'_>) -> ControlFlow<()>,
+    {
+        let stores = self.read_all();
+        let indexes = &stores[0].indexes;
+        let record = |key: &str| stores[self.shard_index(key)].records.get(key);
+
+        let mut visit = |key: &str, value: &DBTypes| {
+            let entry = Entry {
+                key,
+                value,
+                indexes,
+            };
+            if query.matches(&entry) {
+                f(entry)
+            } else {
+                ControlFlow::Continue(())
+            }
+        };
+
+        match plan(indexes, query) {
+            Plan::FullScan => {
+                let records = stores.iter().map(|store| store.records.scan(ALL));
+                for (key, value) in merge(records, |(a, _), (b, _)| a.cmp(b)) {
+                    if visit(&key, &value).is_break() {
+                        return;
+                    }
+                }
+            }
+            Plan::KeyRange { start, end } => {
+                if is_empty_range(start.as_ref(), end.as_ref(), String::cmp) {
+                    return;
+                }
+                let range = (
+                    start.as_ref().map(String::as_str),
+                    end.as_ref().map(String::as_str),
+                );
+                let records = stores.iter().map(|store| store.records.scan(range));
+                for (key, value) in merge(records, |(a, _), (b, _)| a.cmp(b)) {
+                    if visit(&key, &value).is_break() {
+                        return;
+                    }
+                }
+            }
+            Plan::IndexLookup { index, value } => {
+                let found = stores
+                    .iter()
+                    .map(|store| store.indexes[&index].find(&value));
+                for key in merge(found.map(Vec::into_iter), String::cmp) {
+                    let Some(value) = record(&key) else { continue };
+                    if visit(&key, &value).is_break() {
+                        return;
+                    }
+                }
+            }
+            Plan::IndexRange { index, start, end } => {
+                let indexes = stores
+                    .iter()
+                    .map(|store| &store.indexes[&index])
+                    .collect::<Vec<_>>();
+                for key in range_across(&indexes, start.as_ref(), end.as_ref()) {
+                    let Some(value) = record(&key) else { continue };
+                    if visit(&key, &value).is_break() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The number of entries matching `query`
+    pub fn count(&self, query: &Query) -> usize {
+        let mut count = 0;
+        self.scan(query, |_| {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        count
+    }
+}
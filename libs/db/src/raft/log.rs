@@ -0,0 +1,352 @@
+//! What a node keeps on disk, in `raft-<id>` in the data directory: its term and vote
+//! with the latest snapshot's position in `state`, the entries after the snapshot in
+//! `log`, and the snapshot itself in `snapshot-<index>.hoya`, in the format `store`
+//! writes. The log is only appended to, and rewritten when a leader overwrites entries
+//! or a snapshot makes the ones before it unnecessary
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use super::mutation::{read_mutation, write_mutation};
+use super::{Mutation, NodeId};
+use crate::snapshot::replace_file;
+use crate::snapshot::schema::{read_text, read_u64, read_u8, write_text};
+use crate::DataDir;
+
+const MAGIC: &[u8; 8] = b"HOYARAFT";
+const VERSION: u8 = 1;
+
+const NOOP: u8 = 0;
+const MUTATION: u8 = 1;
+const MEMBERS: u8 = 2;
+
+/// The nodes of a cluster, by id, with the addresses they listen on
+pub(crate) type Members = BTreeMap<NodeId, String>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    /// Appended by each new leader, to commit what earlier terms left
+    Noop,
+    Mutation(Mutation),
+    /// The members from this entry on, whether it's committed or not
+    Members(Members),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) term: u64,
+    pub(crate) command: Command,
+}
+
+/// The last entry a snapshot covers
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SnapshotMeta {
+    pub(crate) index: u64,
+    pub(crate) term: u64,
+    pub(crate) members: Members,
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_owned())
+}
+
+pub(crate) fn write_members(out: &mut dyn Write, members: &Members) -> io::Result<()> {
+    out.write_all(&(members.len() as u64).to_le_bytes())?;
+    members.iter().try_for_each(|(id, addr)| {
+        out.write_all(&id.to_le_bytes())?;
+        write_text(out, addr)
+    })
+}
+
+pub(crate) fn read_members(input: &mut dyn Read) -> io::Result<Members> {
+    (0..read_u64(input)?)
+        .map(|_| Ok((read_u64(input)?, read_text(input)?)))
+        .collect()
+}
+
+pub(crate) fn write_entry(out: &mut dyn Write, entry: &Entry) -> io::Result<()> {
+    out.write_all(&entry.term.to_le_bytes())?;
+    match &entry.command {
+        Command::Noop => out.write_all(&[NOOP]),
+        Command::Mutation(mutation) => {
+            out.write_all(&[MUTATION])?;
+            write_mutation(out, mutation)
+        }
+        Command::Members(members) => {
+            out.write_all(&[MEMBERS])?;
+            write_members(out, members)
+        }
+    }
+}
+
+pub(crate) fn read_entry(input: &mut dyn Read) -> io::Result<Entry> {
+    let term = read_u64(input)?;
+    let command = match read_u8(input)? {
+        NOOP => Command::Noop,
+        MUTATION => Command::Mutation(read_mutation(input)?),
+        MEMBERS => Command::Members(read_members(input)?),
+        _ => return Err(invalid("Unknown log entry")),
+    };
+    Ok(Entry { term, command })
+}
+
+#[derive(Debug)]
+pub(crate) struct Log {
+    dir: DataDir,
+    file: BufWriter<File>,
+    /// The latest term this node knows of
+    pub(crate) term: u64,
+    /// Who it voted for in that term
+    pub(crate) vote: Option<NodeId>,
+    pub(crate) snapshot: SnapshotMeta,
+    /// The entries after the snapshot
+    entries: Vec<Entry>,
+}
+
+impl Log {
+    /// Reads what the node kept, or starts it out with `members` on its first run
+    pub(crate) fn open(dir: DataDir, members: &Members) -> io::Result<Self> {
+        let state = dir.resolve("state").map_err(io::Error::other)?;
+        let path = dir.resolve("log").map_err(io::Error::other)?;
+        if !state.exists() {
+            let log = Self {
+                file: BufWriter::new(File::create(&path)?),
+                dir,
+                term: 0,
+                vote: None,
+                snapshot: SnapshotMeta {
+                    index: 0,
+                    term: 0,
+                    members: members.clone(),
+                },
+                entries: vec![],
+            };
+            log.write_state()?;
+            return Ok(log);
+        }
+
+        let mut input = BufReader::new(File::open(&state)?);
+        let mut magic = [0; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u8(&mut input)? != VERSION {
+            return Err(invalid("Not the state of a HoyaDB cluster node"));
+        }
+        let term = read_u64(&mut input)?;
+        let vote = match read_u8(&mut input)? {
+            0 => None,
+            _ => Some(read_u64(&mut input)?),
+        };
+        let snapshot = SnapshotMeta {
+            index: read_u64(&mut input)?,
+            term: read_u64(&mut input)?,
+            members: read_members(&mut input)?,
+        };
+
+        let mut entries = vec![];
+        let mut input = BufReader::new(File::open(&path)?);
+        let mut torn = false;
+        loop {
+            let mut index = [0; 8];
+            match input.read_exact(&mut index) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let index = u64::from_le_bytes(index);
+            match read_entry(&mut input) {
+                Ok(entry) if index <= snapshot.index => drop(entry),
+                Ok(entry) if index == snapshot.index + entries.len() as u64 + 1 => {
+                    entries.push(entry)
+                }
+                Ok(_) => return Err(invalid("The cluster log skips entries")),
+                // Cut off by a crash while appending, so it was never acknowledged
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    torn = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut log = Self {
+            file: BufWriter::new(OpenOptions::new().append(true).open(&path)?),
+            dir,
+            term,
+            vote,
+            snapshot,
+            entries,
+        };
+        if torn {
+            log.rewrite()?;
+        }
+        Ok(log)
+    }
+
+    pub(crate) fn snapshot_path(&self, index: u64) -> io::Result<PathBuf> {
+        self.dir
+            .snapshot(&format!("snapshot-{index}"))
+            .map_err(io::Error::other)
+    }
+
+    pub(crate) fn last_index(&self) -> u64 {
+        self.snapshot.index + self.entries.len() as u64
+    }
+
+    pub(crate) fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.snapshot.term, |entry| entry.term)
+    }
+
+    /// The term of the entry at `index`, unless it's gone into the snapshot or doesn't
+    /// exist yet
+    pub(crate) fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot.index {
+            return Some(self.snapshot.term);
+        }
+        let offset = index.checked_sub(self.snapshot.index + 1)?;
+        self.entries.get(offset as usize).map(|entry| entry.term)
+    }
+
+    pub(crate) fn entry(&self, index: u64) -> &Entry {
+        &self.entries[(index - self.snapshot.index - 1) as usize]
+    }
+
+    /// Up to `max` entries from `index` on, which has to be after the snapshot
+    pub(crate) fn entries_from(&self, index: u64, max: usize) -> Vec<Entry> {
+        let offset = (index - self.snapshot.index - 1) as usize;
+        self.entries
+            .iter()
+            .skip(offset)
+            .take(max)
+            .cloned()
+            .collect()
+    }
+
+    /// The latest members, even if the entry naming them isn't committed yet
+    pub(crate) fn members(&self) -> &Members {
+        self.entries
+            .iter()
+            .rev()
+            .find_map(|entry| match &entry.command {
+                Command::Members(members) => Some(members),
+                _ => None,
+            })
+            .unwrap_or(&self.snapshot.members)
+    }
+
+    /// The members as of the entry at `index`
+    pub(crate) fn members_at(&self, index: u64) -> Members {
+        let end = index.saturating_sub(self.snapshot.index) as usize;
+        self.entries[..end]
+            .iter()
+            .rev()
+            .find_map(|entry| match &entry.command {
+                Command::Members(members) => Some(members.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.snapshot.members.clone())
+    }
+
+    /// The index of the entry that named the latest members
+    pub(crate) fn members_index(&self) -> u64 {
+        self.entries
+            .iter()
+            .rposition(|entry| matches!(entry.command, Command::Members(_)))
+            .map_or(self.snapshot.index, |offset| {
+                self.snapshot.index + offset as u64 + 1
+            })
+    }
+
+    /// Makes the entries durable before they're acknowledged
+    pub(crate) fn append(&mut self, entries: Vec<Entry>) -> io::Result<()> {
+        for (entry, index) in entries.iter().zip(self.last_index() + 1..) {
+            self.file.write_all(&index.to_le_bytes())?;
+            write_entry(&mut self.file, entry)?;
+        }
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.entries.extend(entries);
+        Ok(())
+    }
+
+    /// Drops the entries from `index` on, which a new leader doesn't have
+    pub(crate) fn truncate(&mut self, index: u64) -> io::Result<()> {
+        self.entries
+            .truncate((index - self.snapshot.index - 1) as usize);
+        self.rewrite()
+    }
+
+    pub(crate) fn set_vote(&mut self, term: u64, vote: Option<NodeId>) -> io::Result<()> {
+        self.term = term;
+        self.vote = vote;
+        self.write_state()
+    }
+
+    /// Moves the start of the log up to `snapshot`, which has been written. The entries
+    /// after it are kept if they follow on from it, as they do when the snapshot was
+    /// taken here, and older snapshots are deleted
+    pub(crate) fn compact(&mut self, snapshot: SnapshotMeta) -> io::Result<()> {
+        if snapshot.index < self.snapshot.index {
+            // Overtaken by one the leader sent while this one was written
+            return fs::remove_file(self.snapshot_path(snapshot.index)?);
+        }
+        if snapshot.index == self.snapshot.index {
+            return Ok(());
+        }
+        if self.term_at(snapshot.index) == Some(snapshot.term) {
+            self.entries
+                .drain(..(snapshot.index - self.snapshot.index) as usize);
+        } else {
+            self.entries.clear();
+        }
+        let old = std::mem::replace(&mut self.snapshot, snapshot);
+        self.write_state()?;
+        self.rewrite()?;
+        if old.index > 0 {
+            let _ = fs::remove_file(self.snapshot_path(old.index)?);
+        }
+        Ok(())
+    }
+
+    fn write_state(&self) -> io::Result<()> {
+        replace_file(
+            &self.dir.resolve("state").map_err(io::Error::other)?,
+            |file| {
+                let mut out = BufWriter::new(file);
+                out.write_all(MAGIC)?;
+                out.write_all(&[VERSION])?;
+                out.write_all(&self.term.to_le_bytes())?;
+                match self.vote {
+                    Some(id) => {
+                        out.write_all(&[1])?;
+                        out.write_all(&id.to_le_bytes())?;
+                    }
+                    None => out.write_all(&[0])?,
+                }
+                out.write_all(&self.snapshot.index.to_le_bytes())?;
+                out.write_all(&self.snapshot.term.to_le_bytes())?;
+                write_members(&mut out, &self.snapshot.members)?;
+                out.flush()?;
+                out.get_ref().sync_all()
+            },
+        )
+    }
+
+    fn rewrite(&mut self) -> io::Result<()> {
+        let path = self.dir.resolve("log").map_err(io::Error::other)?;
+        replace_file(&path, |file| {
+            let mut out = BufWriter::new(file);
+            for (entry, index) in self.entries.iter().zip(self.snapshot.index + 1..) {
+                out.write_all(&index.to_le_bytes())?;
+                write_entry(&mut out, entry)?;
+            }
+            out.flush()?;
+            out.get_ref().sync_all()
+        })?;
+        self.file = BufWriter::new(OpenOptions::new().append(true).open(&path)?);
+        Ok(())
+    }
+}
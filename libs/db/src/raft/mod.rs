@@ -0,0 +1,279 @@
+//! Clustered mode, where several nodes keep the same records by agreeing on every write
+//! through Raft. Writes go to the leader, which appends them to its log and applies them
+//! once most members have them, as do the followers after it. Nodes that miss too much
+//! catch up from a snapshot instead, and members are added and removed one at a time, so
+//! any two majorities overlap throughout
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::{ClusterError, Database};
+
+mod log;
+mod mutation;
+mod node;
+mod transport;
+mod wire;
+
+pub(crate) use mutation::{read_mutation, write_mutation};
+pub use mutation::{Mutation, Outcome};
+pub use transport::LocalNetwork;
+
+use node::{Event, MemberChange, Node};
+use transport::{TcpTransport, Transport};
+
+pub type NodeId = u64;
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub id: NodeId,
+    /// Where this node listens for the others
+    pub addr: String,
+    /// The members to start a new cluster with, by id and address, this node included.
+    /// Empty for a node that joins an existing cluster once a member adds it. Only read
+    /// the first time the node starts, after which it keeps its members itself
+    pub members: BTreeMap<NodeId, String>,
+    /// How often the leader lets the followers know it's still there
+    pub heartbeat: Duration,
+    /// A follower that hears nothing from a leader for between this and twice this
+    /// stands for election
+    pub election_timeout: Duration,
+    /// How many entries are applied between snapshots. The log before a snapshot is
+    /// dropped once it's written
+    pub snapshot_every: u64,
+    /// How long writes and reads wait for the cluster
+    pub timeout: Duration,
+    /// Makes `read_index` part of every read through the shell, so nothing it reads is
+    /// older than the last write the cluster committed. Reads then only work on the
+    /// leader
+    pub linearizable_reads: bool,
+}
+
+impl ClusterConfig {
+    pub fn new(id: NodeId, addr: impl Into<String>) -> Self {
+        Self {
+            id,
+            addr: addr.into(),
+            members: BTreeMap::new(),
+            heartbeat: Duration::from_millis(50),
+            election_timeout: Duration::from_millis(300),
+            snapshot_every: 10_000,
+            timeout: Duration::from_secs(5),
+            linearizable_reads: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+    /// Dropped, or stopped by an error it couldn't recover from
+    Stopped,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Follower => "follower",
+            Role::Candidate => "candidate",
+            Role::Leader => "leader",
+            Role::Stopped => "stopped",
+        })
+    }
+}
+
+/// Where a node stands in its cluster
+#[derive(Debug, Clone)]
+pub struct ClusterStatus {
+    pub id: NodeId,
+    pub role: Role,
+    pub term: u64,
+    pub leader: Option<NodeId>,
+    pub members: BTreeMap<NodeId, String>,
+    /// The last entry in the log
+    pub last_index: u64,
+    /// The last entry known to be on most members
+    pub commit: u64,
+    /// The last entry applied to the records
+    pub applied: u64,
+    /// The last entry the latest snapshot covers
+    pub snapshot_index: u64,
+    pub last_error: Option<String>,
+}
+
+impl fmt::Display for ClusterStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Node {}, {} in term {}", self.id, self.role, self.term)?;
+        if let Some(leader) = self.leader.filter(|leader| *leader != self.id) {
+            write!(f, " following {leader}")?;
+        }
+        write!(f, "; members")?;
+        for (i, (id, addr)) in self.members.iter().enumerate() {
+            write!(f, "{} {id} at {addr}", if i == 0 { "" } else { "," })?;
+        }
+        write!(
+            f,
+            "; log at {}, committed {}, applied {}, snapshot at {}",
+            self.last_index, self.commit, self.applied, self.snapshot_index
+        )?;
+        if let Some(e) = &self.last_error {
+            write!(f, "; last error: {e}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Makes requests of a running node. Clones talk to the same node, and fail with
+/// `ClusterError::Stopped` once it's dropped
+#[derive(Debug, Clone)]
+pub struct ClusterClient {
+    events: Sender<Event>,
+    status: Arc<Mutex<ClusterStatus>>,
+    timeout: Duration,
+    linearizable_reads: bool,
+}
+
+impl ClusterClient {
+    fn request<T>(
+        &self,
+        event: impl FnOnce(Sender<Result<T, ClusterError>>) -> Event,
+    ) -> Result<T, ClusterError> {
+        let (reply, response) = mpsc::channel();
+        self.events
+            .send(event(reply))
+            .map_err(|_| ClusterError::Stopped)?;
+        match response.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(ClusterError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(ClusterError::Stopped),
+        }
+    }
+
+    /// Makes the write on every node, returning once it's committed and applied here.
+    /// Only the leader takes writes
+    pub fn write(&self, mutation: Mutation) -> Result<Outcome, ClusterError> {
+        self.request(|reply| Event::Write { mutation, reply })
+    }
+
+    /// Waits until this node has applied every write committed before the call, after
+    /// confirming with most members that it's still the leader. Reads made after it
+    /// returns see everything the cluster acknowledged until then
+    pub fn read_index(&self) -> Result<(), ClusterError> {
+        self.request(|reply| Event::Read { reply })
+    }
+
+    /// Whether the shell calls `read_index` before reading
+    pub fn linearizable_reads(&self) -> bool {
+        self.linearizable_reads
+    }
+
+    /// Adds the node `id` listening on `addr`, or moves it there if it's a member
+    /// already. It's sent everything it needs to catch up
+    pub fn add_member(&self, id: NodeId, addr: impl Into<String>) -> Result<(), ClusterError> {
+        let change = MemberChange::Add {
+            id,
+            addr: addr.into(),
+        };
+        self.request(|reply| Event::ChangeMembers { change, reply })
+    }
+
+    /// Removes the node `id`. A leader that removes itself hands over once that's
+    /// committed
+    pub fn remove_member(&self, id: NodeId) -> Result<(), ClusterError> {
+        let change = MemberChange::Remove { id };
+        self.request(|reply| Event::ChangeMembers { change, reply })
+    }
+
+    pub fn status(&self) -> ClusterStatus {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+/// Runs a node until dropped. It derefs to a client for making requests of it
+#[derive(Debug)]
+pub struct Cluster {
+    client: ClusterClient,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Cluster {
+    /// A client that can outlive this borrow, to hand to other threads
+    pub fn client(&self) -> ClusterClient {
+        self.client.clone()
+    }
+}
+
+impl Deref for Cluster {
+    type Target = ClusterClient;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = self.client.events.send(Event::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Database {
+    /// Runs this database as the node `config.id` of a cluster, talking to the other
+    /// nodes over TCP. The cluster owns the records from then on: they're replaced with
+    /// what the node kept in `raft-<id>` in the data directory, or emptied on its first
+    /// run, and should only be written through the returned cluster
+    pub fn join_cluster(&self, config: ClusterConfig) -> io::Result<Cluster> {
+        let (events, inbox) = mpsc::channel();
+        let transport = TcpTransport::bind(config.id, &config.addr, events.clone())?;
+        self.start_node(config, Box::new(transport), events, inbox)
+    }
+
+    /// Like `join_cluster`, over `network` instead of TCP
+    pub fn join_local_cluster(
+        &self,
+        config: ClusterConfig,
+        network: &LocalNetwork,
+    ) -> io::Result<Cluster> {
+        let (events, inbox) = mpsc::channel();
+        let transport = network.connect(config.id, events.clone());
+        self.start_node(config, Box::new(transport), events, inbox)
+    }
+
+    fn start_node(
+        &self,
+        config: ClusterConfig,
+        transport: Box<dyn Transport>,
+        events: Sender<Event>,
+        inbox: mpsc::Receiver<Event>,
+    ) -> io::Result<Cluster> {
+        if !config.members.is_empty() && !config.members.contains_key(&config.id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Node {} isn't among the members to start with", config.id),
+            ));
+        }
+        let (timeout, linearizable_reads) = (config.timeout, config.linearizable_reads);
+        let (thread, status) = Node::start(self.clone(), config, transport, events.clone(), inbox)?;
+        let client = ClusterClient {
+            events,
+            status,
+            timeout,
+            linearizable_reads,
+        };
+        Ok(Cluster {
+            client,
+            thread: Some(thread),
+        })
+    }
+}
@@ -0,0 +1,247 @@
+//! The writes a cluster agrees on. Each is replayed on every node in log order, so it
+//! has to do the same thing everywhere given the same records
+
+use std::io::{self, Read, Write};
+
+use crate::snapshot::schema::{
+    read_f64, read_text, read_u64, read_u8, read_value, write_text, write_value,
+};
+use crate::{DBTypes, Database, DatabaseError};
+
+const PUT: u8 = 1;
+const REMOVE: u8 = 2;
+const PUT_MANY: u8 = 3;
+const REMOVE_MANY: u8 = 4;
+const PUSH: u8 = 5;
+const POP: u8 = 6;
+const SADD: u8 = 7;
+const SREM: u8 = 8;
+const ZADD: u8 = 9;
+const ZINCR: u8 = 10;
+
+/// A write, as a cluster replicates it. Each stands for the `Database` method of the
+/// same name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mutation {
+    Put {
+        key: String,
+        value: DBTypes,
+    },
+    Remove {
+        key: String,
+    },
+    PutMany(Vec<(String, DBTypes)>),
+    RemoveMany(Vec<String>),
+    /// `lpush` at the front, `rpush` at the back
+    Push {
+        key: String,
+        value: DBTypes,
+        front: bool,
+    },
+    /// `lpop` at the front, `rpop` at the back
+    Pop {
+        key: String,
+        front: bool,
+    },
+    Sadd {
+        key: String,
+        members: Vec<String>,
+    },
+    Srem {
+        key: String,
+        members: Vec<String>,
+    },
+    Zadd {
+        key: String,
+        member: String,
+        score: f64,
+    },
+    Zincr {
+        key: String,
+        member: String,
+        by: f64,
+    },
+}
+
+/// What the method a mutation stands for returned
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// From `put`, `remove` and the pops
+    Value(Option<DBTypes>),
+    /// From `put_many` and `remove_many`
+    Values(Vec<Option<DBTypes>>),
+    /// From the pushes, `sadd` and `srem`
+    Count(usize),
+    /// From `zadd`
+    Added(bool),
+    /// From `zincr`
+    Score(f64),
+}
+
+impl Mutation {
+    /// The key the write changes, unless it changes several
+    pub(crate) fn key(&self) -> Option<&str> {
+        match self {
+            Mutation::Put { key, .. }
+            | Mutation::Remove { key }
+            | Mutation::Push { key, .. }
+            | Mutation::Pop { key, .. }
+            | Mutation::Sadd { key, .. }
+            | Mutation::Srem { key, .. }
+            | Mutation::Zadd { key, .. }
+            | Mutation::Zincr { key, .. } => Some(key),
+            Mutation::PutMany(_) | Mutation::RemoveMany(_) => None,
+        }
+    }
+
+    /// Every key the write changes
+    pub(crate) fn keys(&self) -> Vec<String> {
+        match self {
+            Mutation::PutMany(entries) => entries.iter().map(|(key, _)| key.clone()).collect(),
+            Mutation::RemoveMany(keys) => keys.clone(),
+            mutation => mutation.key().map(str::to_owned).into_iter().collect(),
+        }
+    }
+
+    /// Makes the write on `db` alone, as every node does once the cluster committed it
+    pub fn apply(self, db: &Database) -> Result<Outcome, DatabaseError> {
+        Ok(match self {
+            Mutation::Put { key, value } => Outcome::Value(db.put(key, value)?),
+            Mutation::Remove { key } => Outcome::Value(db.remove(&key)?),
+            Mutation::PutMany(entries) => Outcome::Values(db.put_many(entries)?),
+            Mutation::RemoveMany(keys) => Outcome::Values(db.remove_many(&keys)?),
+            Mutation::Push { key, value, front } => Outcome::Count(if front {
+                db.lpush(&key, value)?
+            } else {
+                db.rpush(&key, value)?
+            }),
+            Mutation::Pop { key, front } => Outcome::Value(if front {
+                db.lpop(&key)?
+            } else {
+                db.rpop(&key)?
+            }),
+            Mutation::Sadd { key, members } => Outcome::Count(db.sadd(&key, members)?),
+            Mutation::Srem { key, members } => Outcome::Count(db.srem(&key, &members)?),
+            Mutation::Zadd { key, member, score } => Outcome::Added(db.zadd(&key, member, score)?),
+            Mutation::Zincr { key, member, by } => Outcome::Score(db.zincr(&key, member, by)?),
+        })
+    }
+}
+
+fn write_texts(out: &mut dyn Write, texts: &[String]) -> io::Result<()> {
+    out.write_all(&(texts.len() as u64).to_le_bytes())?;
+    texts.iter().try_for_each(|text| write_text(out, text))
+}
+
+fn read_texts(input: &mut dyn Read) -> io::Result<Vec<String>> {
+    (0..read_u64(input)?).map(|_| read_text(input)).collect()
+}
+
+/// Lays a mutation out for the log and the network, with values as in snapshots
+pub(crate) fn write_mutation(out: &mut dyn Write, mutation: &Mutation) -> io::Result<()> {
+    match mutation {
+        Mutation::Put { key, value } => {
+            out.write_all(&[PUT])?;
+            write_text(out, key)?;
+            write_value(out, value)
+        }
+        Mutation::Remove { key } => {
+            out.write_all(&[REMOVE])?;
+            write_text(out, key)
+        }
+        Mutation::PutMany(entries) => {
+            out.write_all(&[PUT_MANY])?;
+            out.write_all(&(entries.len() as u64).to_le_bytes())?;
+            entries.iter().try_for_each(|(key, value)| {
+                write_text(out, key)?;
+                write_value(out, value)
+            })
+        }
+        Mutation::RemoveMany(keys) => {
+            out.write_all(&[REMOVE_MANY])?;
+            write_texts(out, keys)
+        }
+        Mutation::Push { key, value, front } => {
+            out.write_all(&[PUSH, u8::from(*front)])?;
+            write_text(out, key)?;
+            write_value(out, value)
+        }
+        Mutation::Pop { key, front } => {
+            out.write_all(&[POP, u8::from(*front)])?;
+            write_text(out, key)
+        }
+        Mutation::Sadd { key, members } => {
+            out.write_all(&[SADD])?;
+            write_text(out, key)?;
+            write_texts(out, members)
+        }
+        Mutation::Srem { key, members } => {
+            out.write_all(&[SREM])?;
+            write_text(out, key)?;
+            write_texts(out, members)
+        }
+        Mutation::Zadd { key, member, score } => {
+            out.write_all(&[ZADD])?;
+            write_text(out, key)?;
+            write_text(out, member)?;
+            out.write_all(&score.to_bits().to_le_bytes())
+        }
+        Mutation::Zincr { key, member, by } => {
+            out.write_all(&[ZINCR])?;
+            write_text(out, key)?;
+            write_text(out, member)?;
+            out.write_all(&by.to_bits().to_le_bytes())
+        }
+    }
+}
+
+pub(crate) fn read_mutation(input: &mut dyn Read) -> io::Result<Mutation> {
+    Ok(match read_u8(input)? {
+        PUT => Mutation::Put {
+            key: read_text(input)?,
+            value: read_value(input)?,
+        },
+        REMOVE => Mutation::Remove {
+            key: read_text(input)?,
+        },
+        PUT_MANY => Mutation::PutMany(
+            (0..read_u64(input)?)
+                .map(|_| Ok((read_text(input)?, read_value(input)?)))
+                .collect::<io::Result<_>>()?,
+        ),
+        REMOVE_MANY => Mutation::RemoveMany(read_texts(input)?),
+        PUSH => Mutation::Push {
+            front: read_u8(input)? != 0,
+            key: read_text(input)?,
+            value: read_value(input)?,
+        },
+        POP => Mutation::Pop {
+            front: read_u8(input)? != 0,
+            key: read_text(input)?,
+        },
+        SADD => Mutation::Sadd {
+            key: read_text(input)?,
+            members: read_texts(input)?,
+        },
+        SREM => Mutation::Srem {
+            key: read_text(input)?,
+            members: read_texts(input)?,
+        },
+        ZADD => Mutation::Zadd {
+            key: read_text(input)?,
+            member: read_text(input)?,
+            score: read_f64(input)?,
+        },
+        ZINCR => Mutation::Zincr {
+            key: read_text(input)?,
+            member: read_text(input)?,
+            by: read_f64(input)?,
+        },
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unknown mutation",
+            ))
+        }
+    })
+}
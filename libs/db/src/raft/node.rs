@@ -0,0 +1,929 @@
+//! A node of a cluster, run by one thread that handles every message and request in
+//! turn, so none of its state needs a lock
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::mem;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use super::log::{Command, Entry, Log, Members, SnapshotMeta};
+use super::transport::Transport;
+use super::wire::Message;
+use super::{ClusterConfig, ClusterStatus, Mutation, NodeId, Outcome, Role};
+use crate::db::Collection;
+use crate::snapshot::{self, replace_file};
+use crate::{ClusterError, DataDir, Database, DatabaseError};
+
+/// The most entries sent in one message
+const BATCH: usize = 512;
+
+#[derive(Debug)]
+pub(crate) enum Event {
+    Message {
+        from: NodeId,
+        message: Message,
+    },
+    Write {
+        mutation: Mutation,
+        reply: Sender<Result<Outcome, ClusterError>>,
+    },
+    Read {
+        reply: Sender<Result<(), ClusterError>>,
+    },
+    ChangeMembers {
+        change: MemberChange,
+        reply: Sender<Result<(), ClusterError>>,
+    },
+    /// A snapshot was written in the background
+    Snapshotted {
+        snapshot: SnapshotMeta,
+        result: io::Result<()>,
+    },
+    Stop,
+}
+
+#[derive(Debug)]
+pub(crate) enum MemberChange {
+    Add { id: NodeId, addr: String },
+    Remove { id: NodeId },
+}
+
+/// A request waiting for its entry to be applied
+enum Pending {
+    Write(Sender<Result<Outcome, ClusterError>>),
+    Members(Sender<Result<(), ClusterError>>),
+}
+
+impl Pending {
+    fn fail(self, e: ClusterError) {
+        match self {
+            Pending::Write(reply) => drop(reply.send(Err(e))),
+            Pending::Members(reply) => drop(reply.send(Err(e))),
+        }
+    }
+}
+
+enum State {
+    Follower,
+    Candidate { votes: BTreeSet<NodeId> },
+    Leader(Leadership),
+}
+
+struct Leadership {
+    followers: BTreeMap<NodeId, Progress>,
+    /// The entry this leader started its term with. Until it's committed, the leader
+    /// can't tell what earlier terms committed
+    start: u64,
+    /// The latest heartbeat round, which reads wait for most members to answer
+    round: u64,
+    reads: Vec<Read>,
+}
+
+struct Progress {
+    /// The next entry to send
+    next: u64,
+    /// The last entry known to be on the follower
+    matched: u64,
+    /// The latest heartbeat round the follower answered
+    round: u64,
+    /// When it last answered, for telling whether most members can still be reached
+    contact: Instant,
+    /// When a snapshot was last sent to it, so a large one isn't sent on every heartbeat
+    snapshot_sent: Option<Instant>,
+}
+
+struct Read {
+    round: u64,
+    /// The entry that has to be applied first
+    index: u64,
+    reply: Sender<Result<(), ClusterError>>,
+}
+
+pub(crate) struct Node {
+    id: NodeId,
+    config: ClusterConfig,
+    db: Database,
+    log: Log,
+    transport: Box<dyn Transport>,
+    /// For reporting back from background snapshots
+    events: Sender<Event>,
+    status: Arc<Mutex<ClusterStatus>>,
+    state: State,
+    leader: Option<NodeId>,
+    commit: u64,
+    applied: u64,
+    /// When a follower stands for election, or when a leader next sends heartbeats
+    deadline: Instant,
+    /// When this node last heard from a leader, or was one that most members answered
+    heard_from_leader: Option<Instant>,
+    pending: BTreeMap<u64, Pending>,
+    snapshotting: bool,
+}
+
+impl Node {
+    /// Replaces the records with what the node kept and runs it on a thread of its own
+    pub(crate) fn start(
+        db: Database,
+        config: ClusterConfig,
+        transport: Box<dyn Transport>,
+        events: Sender<Event>,
+        inbox: Receiver<Event>,
+    ) -> io::Result<(JoinHandle<()>, Arc<Mutex<ClusterStatus>>)> {
+        let dir = DataDir::new(db.data_dir().root().join(format!("raft-{}", config.id)))?;
+        let log = Log::open(dir, &config.members)?;
+        let records = match log.snapshot.index {
+            0 => Collection::new(),
+            index => snapshot::read(
+                File::open(log.snapshot_path(index)?)?,
+                db.snapshot_options.secret(),
+            )
+            .map_err(|e| io::Error::other(e.to_string()))?,
+        };
+        db.restore(records)?;
+
+        let status = Arc::new(Mutex::new(ClusterStatus {
+            id: config.id,
+            role: Role::Follower,
+            term: log.term,
+            leader: None,
+            members: log.members().clone(),
+            last_index: log.last_index(),
+            commit: log.snapshot.index,
+            applied: log.snapshot.index,
+            snapshot_index: log.snapshot.index,
+            last_error: None,
+        }));
+        let mut node = Node {
+            id: config.id,
+            commit: log.snapshot.index,
+            applied: log.snapshot.index,
+            deadline: Instant::now(),
+            config,
+            db,
+            log,
+            transport,
+            events,
+            status: status.clone(),
+            state: State::Follower,
+            leader: None,
+            heard_from_leader: None,
+            pending: BTreeMap::new(),
+            snapshotting: false,
+        };
+        node.deadline = node.election_deadline();
+        node.transport.update(node.log.members());
+        Ok((thread::spawn(move || node.run(inbox)), status))
+    }
+
+    fn run(mut self, inbox: Receiver<Event>) {
+        let result = self.serve(&inbox);
+        // A node that can't keep its log or vote can't take part safely
+        let mut status = self.status.lock().unwrap();
+        status.role = Role::Stopped;
+        if let Err(e) = result {
+            status.last_error = Some(e.to_string());
+        }
+        drop(status);
+        self.stop_leading();
+        for (_, pending) in mem::take(&mut self.pending) {
+            pending.fail(ClusterError::Stopped);
+        }
+    }
+
+    fn serve(&mut self, inbox: &Receiver<Event>) -> io::Result<()> {
+        loop {
+            self.publish();
+            let wait = self.deadline.saturating_duration_since(Instant::now());
+            match inbox.recv_timeout(wait) {
+                Ok(Event::Stop) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Ok(event) => self.handle(event)?,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if Instant::now() >= self.deadline {
+                self.tick()?;
+            }
+            self.transport.update(self.log.members());
+        }
+    }
+
+    fn publish(&self) {
+        let mut status = self.status.lock().unwrap();
+        *status = ClusterStatus {
+            id: self.id,
+            role: match self.state {
+                State::Follower => Role::Follower,
+                State::Candidate { .. } => Role::Candidate,
+                State::Leader(_) => Role::Leader,
+            },
+            term: self.log.term,
+            leader: self.leader,
+            members: self.log.members().clone(),
+            last_index: self.log.last_index(),
+            commit: self.commit,
+            applied: self.applied,
+            snapshot_index: self.log.snapshot.index,
+            last_error: status.last_error.take(),
+        };
+    }
+
+    fn election_deadline(&self) -> Instant {
+        let timeout = self.config.election_timeout;
+        let jitter = OsRng.next_u64() % (timeout.as_nanos() as u64).max(1);
+        Instant::now() + timeout + Duration::from_nanos(jitter)
+    }
+
+    fn send(&mut self, to: NodeId, message: Message) {
+        self.transport.send(to, message);
+    }
+
+    /// The other members
+    fn peers(&self) -> Vec<NodeId> {
+        self.log
+            .members()
+            .keys()
+            .copied()
+            .filter(|id| *id != self.id)
+            .collect()
+    }
+
+    /// Whether most members are among those `has` picks
+    fn quorum(&self, has: impl Fn(NodeId) -> bool) -> bool {
+        let members = self.log.members();
+        members.keys().filter(|id| has(**id)).count() * 2 > members.len()
+    }
+
+    fn not_leader(&self) -> ClusterError {
+        ClusterError::NotLeader {
+            leader: self.leader.and_then(|id| {
+                let addr = self.log.members().get(&id)?;
+                Some((id, addr.clone()))
+            }),
+        }
+    }
+
+    fn handle(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Message { from, message } => self.receive(from, message),
+            Event::Write { mutation, reply } => {
+                self.propose(Command::Mutation(mutation), Pending::Write(reply))
+            }
+            Event::Read { reply } => {
+                self.read(reply);
+                Ok(())
+            }
+            Event::ChangeMembers { change, reply } => self.change_members(change, reply),
+            Event::Snapshotted { snapshot, result } => {
+                self.snapshotting = false;
+                match result {
+                    Ok(()) => self.log.compact(snapshot),
+                    Err(e) => {
+                        self.status.lock().unwrap().last_error =
+                            Some(format!("Couldn't write a snapshot: {e}"));
+                        Ok(())
+                    }
+                }
+            }
+            Event::Stop => Ok(()),
+        }
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        if let State::Leader(leadership) = &self.state {
+            // A leader cut off from most members steps down, failing what waits on it,
+            // rather than taking writes that can't commit
+            let timeout = self.config.election_timeout;
+            let reachable = |id: NodeId| {
+                id == self.id
+                    || leadership
+                        .followers
+                        .get(&id)
+                        .is_some_and(|progress| progress.contact.elapsed() < timeout)
+            };
+            if !self.quorum(reachable) {
+                return self.step_down(self.log.term);
+            }
+            self.heard_from_leader = Some(Instant::now());
+            self.broadcast()?;
+            self.deadline = Instant::now() + self.config.heartbeat;
+        } else if self.log.members().contains_key(&self.id) {
+            self.stand()?;
+        } else {
+            // Not a member yet, or not anymore
+            self.deadline = self.election_deadline();
+        }
+        Ok(())
+    }
+
+    fn stand(&mut self) -> io::Result<()> {
+        self.log.set_vote(self.log.term + 1, Some(self.id))?;
+        self.state = State::Candidate {
+            votes: BTreeSet::from([self.id]),
+        };
+        self.leader = None;
+        self.deadline = self.election_deadline();
+        let vote = Message::Vote {
+            term: self.log.term,
+            last_index: self.log.last_index(),
+            last_term: self.log.last_term(),
+        };
+        for id in self.peers() {
+            self.send(id, vote.clone());
+        }
+        self.count_votes()
+    }
+
+    fn count_votes(&mut self) -> io::Result<()> {
+        if let State::Candidate { votes } = &self.state {
+            if self.quorum(|id| votes.contains(&id)) {
+                return self.lead();
+            }
+        }
+        Ok(())
+    }
+
+    fn lead(&mut self) -> io::Result<()> {
+        self.state = State::Leader(Leadership {
+            followers: BTreeMap::new(),
+            start: self.log.last_index() + 1,
+            round: 0,
+            reads: vec![],
+        });
+        self.leader = Some(self.id);
+        self.heard_from_leader = Some(Instant::now());
+        self.sync_followers();
+        self.log.append(vec![Entry {
+            term: self.log.term,
+            command: Command::Noop,
+        }])?;
+        self.broadcast()?;
+        self.deadline = Instant::now() + self.config.heartbeat;
+        self.advance_commit()
+    }
+
+    /// Tracks every member but this one, as of the latest members
+    fn sync_followers(&mut self) {
+        let peers = self.peers();
+        let next = self.log.last_index() + 1;
+        if let State::Leader(leadership) = &mut self.state {
+            leadership.followers.retain(|id, _| peers.contains(id));
+            for id in peers {
+                leadership.followers.entry(id).or_insert_with(|| Progress {
+                    next,
+                    matched: 0,
+                    round: 0,
+                    contact: Instant::now(),
+                    snapshot_sent: None,
+                });
+            }
+        }
+    }
+
+    /// Follows whoever leads `term`, which is newer than this node's or the same
+    fn step_down(&mut self, term: u64) -> io::Result<()> {
+        if term > self.log.term {
+            self.log.set_vote(term, None)?;
+            self.leader = None;
+        }
+        self.stop_leading();
+        self.state = State::Follower;
+        self.deadline = self.election_deadline();
+        Ok(())
+    }
+
+    fn stop_leading(&mut self) {
+        if let State::Leader(leadership) = &mut self.state {
+            for read in leadership.reads.drain(..) {
+                let _ = read
+                    .reply
+                    .send(Err(ClusterError::NotLeader { leader: None }));
+            }
+            self.leader = None;
+            // Another leader may still commit them, or may not
+            for (_, pending) in mem::take(&mut self.pending) {
+                pending.fail(ClusterError::Uncertain);
+            }
+        }
+    }
+
+    fn receive(&mut self, from: NodeId, message: Message) -> io::Result<()> {
+        let term = message.term();
+        if let Message::Vote { .. } = message {
+            // Ignoring candidates while a leader is around keeps a node that was cut off,
+            // or removed, from deposing it when it comes back
+            if self
+                .heard_from_leader
+                .is_some_and(|at| at.elapsed() < self.config.election_timeout)
+            {
+                return Ok(());
+            }
+        }
+        if term > self.log.term {
+            self.step_down(term)?;
+        }
+        match message {
+            Message::Vote {
+                term,
+                last_index,
+                last_term,
+            } => self.vote(from, term, last_index, last_term),
+            Message::Voted { term, granted } => {
+                if let State::Candidate { votes } = &mut self.state {
+                    if granted && term == self.log.term {
+                        votes.insert(from);
+                    }
+                }
+                self.count_votes()
+            }
+            Message::Append {
+                term,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+                round,
+            } => self.append(from, term, (prev_index, prev_term), entries, commit, round),
+            Message::Appended {
+                term,
+                success,
+                index,
+                round,
+            } => self.appended(from, term, success, index, round),
+            Message::Snapshot {
+                term,
+                index,
+                last_term,
+                members,
+                data,
+            } => self.install(
+                from,
+                term,
+                SnapshotMeta {
+                    index,
+                    term: last_term,
+                    members,
+                },
+                data,
+            ),
+            Message::Installed { term, index } => self.installed(from, term, index),
+        }
+    }
+
+    fn vote(&mut self, from: NodeId, term: u64, last_index: u64, last_term: u64) -> io::Result<()> {
+        let up_to_date = (last_term, last_index) >= (self.log.last_term(), self.log.last_index());
+        let granted =
+            term == self.log.term && self.log.vote.is_none_or(|vote| vote == from) && up_to_date;
+        if granted {
+            if self.log.vote.is_none() {
+                self.log.set_vote(term, Some(from))?;
+            }
+            self.deadline = self.election_deadline();
+        }
+        self.send(
+            from,
+            Message::Voted {
+                term: self.log.term,
+                granted,
+            },
+        );
+        Ok(())
+    }
+
+    /// Takes what the leader of `term` says, unless it's from an earlier term
+    fn follow(&mut self, from: NodeId, term: u64) -> bool {
+        if term < self.log.term {
+            return false;
+        }
+        self.state = State::Follower;
+        self.leader = Some(from);
+        self.heard_from_leader = Some(Instant::now());
+        self.deadline = self.election_deadline();
+        true
+    }
+
+    fn append(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        (mut prev_index, mut prev_term): (u64, u64),
+        mut entries: Vec<Entry>,
+        commit: u64,
+        round: u64,
+    ) -> io::Result<()> {
+        let reply = |term, success, index| Message::Appended {
+            term,
+            success,
+            index,
+            round,
+        };
+        if !self.follow(from, term) {
+            let message = reply(self.log.term, false, self.log.last_index());
+            self.send(from, message);
+            return Ok(());
+        }
+
+        let last = prev_index + entries.len() as u64;
+        // Entries up to the snapshot are committed, so they're here already
+        if prev_index < self.log.snapshot.index {
+            let known = (self.log.snapshot.index - prev_index) as usize;
+            entries.drain(..known.min(entries.len()));
+            prev_index = self.log.snapshot.index;
+            prev_term = self.log.snapshot.term;
+        }
+        if self.log.term_at(prev_index) != Some(prev_term) {
+            let message = reply(
+                term,
+                false,
+                prev_index.saturating_sub(1).min(self.log.last_index()),
+            );
+            self.send(from, message);
+            return Ok(());
+        }
+
+        // Entries this node has already are skipped, and from the first it has
+        // differently on, the leader's replace its own
+        let new = entries
+            .iter()
+            .zip(prev_index + 1..)
+            .position(|(entry, index)| self.log.term_at(index) != Some(entry.term));
+        if let Some(new) = new {
+            let index = prev_index + 1 + new as u64;
+            if index <= self.log.last_index() {
+                self.log.truncate(index)?;
+            }
+            self.log.append(entries.split_off(new))?;
+        }
+
+        let last = last.max(self.log.snapshot.index);
+        if commit > self.commit {
+            self.commit = self.commit.max(commit.min(last));
+            self.apply()?;
+        }
+        let message = reply(term, true, last);
+        self.send(from, message);
+        Ok(())
+    }
+
+    fn appended(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        success: bool,
+        index: u64,
+        round: u64,
+    ) -> io::Result<()> {
+        let State::Leader(leadership) = &mut self.state else {
+            return Ok(());
+        };
+        if term < self.log.term {
+            return Ok(());
+        }
+        let Some(progress) = leadership.followers.get_mut(&from) else {
+            return Ok(());
+        };
+        progress.contact = Instant::now();
+        progress.round = progress.round.max(round);
+        if success {
+            progress.matched = progress.matched.max(index);
+            progress.next = progress.next.max(index + 1);
+        } else {
+            progress.next = (progress.next - 1).min(index + 1).max(1);
+        }
+        let behind = progress.next <= self.log.last_index();
+
+        if success {
+            self.advance_commit()?;
+        }
+        self.confirm_reads();
+        if behind {
+            self.send_append(from)?;
+        }
+        Ok(())
+    }
+
+    fn install(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        snapshot: SnapshotMeta,
+        data: Vec<u8>,
+    ) -> io::Result<()> {
+        if !self.follow(from, term) {
+            let message = Message::Installed {
+                term: self.log.term,
+                index: 0,
+            };
+            self.send(from, message);
+            return Ok(());
+        }
+        let index = snapshot.index;
+        if index > self.commit {
+            let path = self.log.snapshot_path(index)?;
+            replace_file(&path, |mut file| file.write_all(&data))?;
+            let records = snapshot::read(File::open(&path)?, self.db.snapshot_options.secret())
+                .map_err(|e| {
+                    io::Error::other(format!("Couldn't read the leader's snapshot: {e}"))
+                })?;
+            self.db.restore(records)?;
+            self.log.compact(snapshot)?;
+            self.commit = index;
+            self.applied = index;
+        }
+        let message = Message::Installed {
+            term: self.log.term,
+            index,
+        };
+        self.send(from, message);
+        Ok(())
+    }
+
+    fn installed(&mut self, from: NodeId, term: u64, index: u64) -> io::Result<()> {
+        let State::Leader(leadership) = &mut self.state else {
+            return Ok(());
+        };
+        if term < self.log.term {
+            return Ok(());
+        }
+        let Some(progress) = leadership.followers.get_mut(&from) else {
+            return Ok(());
+        };
+        progress.contact = Instant::now();
+        progress.snapshot_sent = None;
+        progress.matched = progress.matched.max(index);
+        progress.next = progress.next.max(index + 1);
+        self.advance_commit()?;
+        self.send_append(from)
+    }
+
+    /// Sends the follower the entries it's missing, or a snapshot if the log doesn't
+    /// go back far enough anymore
+    fn send_append(&mut self, id: NodeId) -> io::Result<()> {
+        let State::Leader(leadership) = &mut self.state else {
+            return Ok(());
+        };
+        let round = leadership.round;
+        let Some(progress) = leadership.followers.get_mut(&id) else {
+            return Ok(());
+        };
+
+        let message = if progress.next <= self.log.snapshot.index {
+            if progress
+                .snapshot_sent
+                .is_some_and(|at| at.elapsed() < self.config.election_timeout)
+            {
+                return Ok(());
+            }
+            progress.snapshot_sent = Some(Instant::now());
+            let snapshot = &self.log.snapshot;
+            Message::Snapshot {
+                term: self.log.term,
+                index: snapshot.index,
+                last_term: snapshot.term,
+                members: snapshot.members.clone(),
+                data: fs::read(self.log.snapshot_path(snapshot.index)?)?,
+            }
+        } else {
+            let prev_index = progress.next - 1;
+            let entries = self.log.entries_from(progress.next, BATCH);
+            // Sent on, assuming they arrive. If they don't, the follower says so
+            progress.next += entries.len() as u64;
+            Message::Append {
+                term: self.log.term,
+                prev_index,
+                prev_term: self
+                    .log
+                    .term_at(prev_index)
+                    .expect("entries after the snapshot are kept"),
+                entries,
+                commit: self.commit,
+                round,
+            }
+        };
+        self.send(id, message);
+        Ok(())
+    }
+
+    fn broadcast(&mut self) -> io::Result<()> {
+        for id in self.peers() {
+            self.send_append(id)?;
+        }
+        Ok(())
+    }
+
+    /// Commits the latest entry of this term that most members have
+    fn advance_commit(&mut self) -> io::Result<()> {
+        let State::Leader(leadership) = &self.state else {
+            return Ok(());
+        };
+        let mut matched = self
+            .log
+            .members()
+            .keys()
+            .map(|id| match leadership.followers.get(id) {
+                Some(progress) => progress.matched,
+                None if *id == self.id => self.log.last_index(),
+                None => 0,
+            })
+            .collect::<Vec<_>>();
+        matched.sort_unstable();
+        // Most members have at least the entry that the one in the middle has
+        let Some(&index) = matched.get(matched.len().saturating_sub(1) / 2) else {
+            return Ok(());
+        };
+        // Entries of earlier terms are only committed along with one of this term
+        if index > self.commit && self.log.term_at(index) == Some(self.log.term) {
+            self.commit = index;
+            self.apply()?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self) -> io::Result<()> {
+        while self.applied < self.commit {
+            let index = self.applied + 1;
+            let outcome = match &self.log.entry(index).command {
+                Command::Mutation(mutation) => match mutation.clone().apply(&self.db) {
+                    // Going on without it would leave this node's records unlike the others'
+                    Err(DatabaseError::Storage(e)) => {
+                        return Err(io::Error::other(format!(
+                            "Couldn't apply entry {index}: {e}"
+                        )))
+                    }
+                    outcome => Some(outcome),
+                },
+                Command::Noop | Command::Members(_) => None,
+            };
+            self.applied = index;
+            match (self.pending.remove(&index), outcome) {
+                (Some(Pending::Write(reply)), Some(outcome)) => {
+                    let _ = reply.send(outcome.map_err(ClusterError::Database));
+                }
+                (Some(Pending::Members(reply)), None) => {
+                    let _ = reply.send(Ok(()));
+                }
+                (Some(pending), _) => pending.fail(ClusterError::Uncertain),
+                (None, _) => {}
+            }
+        }
+
+        // A leader that removed itself hands over once the others know
+        if matches!(self.state, State::Leader(_))
+            && !self.log.members().contains_key(&self.id)
+            && self.log.members_index() <= self.commit
+        {
+            self.step_down(self.log.term)?;
+        }
+        self.confirm_reads();
+        self.snapshot()
+    }
+
+    /// Writes a snapshot in the background once enough entries were applied since
+    /// the last one, freezing the records as of the last applied entry
+    fn snapshot(&mut self) -> io::Result<()> {
+        if self.snapshotting || self.applied - self.log.snapshot.index < self.config.snapshot_every
+        {
+            return Ok(());
+        }
+        let path = self.log.snapshot_path(self.applied)?;
+        // Saving the database's own snapshot freezes it too, so this waits until after
+        if self.db.freeze(String::from("a cluster snapshot")).is_err() {
+            return Ok(());
+        }
+        let snapshot = SnapshotMeta {
+            index: self.applied,
+            term: self
+                .log
+                .term_at(self.applied)
+                .expect("applied entries are kept until a snapshot has them"),
+            members: self.log.members_at(self.applied),
+        };
+        self.snapshotting = true;
+        let (db, events) = (self.db.clone(), self.events.clone());
+        thread::spawn(move || {
+            let result = db.write_image(&path);
+            db.thaw(None);
+            let _ = events.send(Event::Snapshotted { snapshot, result });
+        });
+        Ok(())
+    }
+
+    fn propose(&mut self, command: Command, pending: Pending) -> io::Result<()> {
+        if !matches!(self.state, State::Leader(_)) {
+            pending.fail(self.not_leader());
+            return Ok(());
+        }
+        let index = self.log.last_index() + 1;
+        self.log.append(vec![Entry {
+            term: self.log.term,
+            command,
+        }])?;
+        self.pending.insert(index, pending);
+        self.sync_followers();
+        // Followers that are caught up get it now, the others with their next reply
+        let caught_up = match &self.state {
+            State::Leader(leadership) => leadership
+                .followers
+                .iter()
+                .filter(|(_, progress)| progress.next == index)
+                .map(|(id, _)| *id)
+                .collect(),
+            _ => vec![],
+        };
+        for id in caught_up {
+            self.send_append(id)?;
+        }
+        self.advance_commit()
+    }
+
+    fn read(&mut self, reply: Sender<Result<(), ClusterError>>) {
+        let not_leader = self.not_leader();
+        let State::Leader(leadership) = &mut self.state else {
+            let _ = reply.send(Err(not_leader));
+            return;
+        };
+        leadership.round += 1;
+        leadership.reads.push(Read {
+            round: leadership.round,
+            index: self.commit.max(leadership.start),
+            reply,
+        });
+        // Heartbeats now rather than at the next tick, to confirm the read sooner
+        let _ = self.broadcast();
+        self.confirm_reads();
+    }
+
+    /// Answers the reads that most members confirmed this leader for, once what was
+    /// committed when they came in is applied
+    fn confirm_reads(&mut self) {
+        let members = self.log.members().clone();
+        let (id, applied) = (self.id, self.applied);
+        let State::Leader(Leadership {
+            followers, reads, ..
+        }) = &mut self.state
+        else {
+            return;
+        };
+        reads.retain(|read| {
+            let confirmed = members
+                .keys()
+                .filter(|member| {
+                    **member == id
+                        || followers
+                            .get(member)
+                            .is_some_and(|progress| progress.round >= read.round)
+                })
+                .count()
+                * 2
+                > members.len();
+            let done = confirmed && applied >= read.index;
+            if done {
+                let _ = read.reply.send(Ok(()));
+            }
+            !done
+        });
+    }
+
+    fn change_members(
+        &mut self,
+        change: MemberChange,
+        reply: Sender<Result<(), ClusterError>>,
+    ) -> io::Result<()> {
+        let State::Leader(leadership) = &self.state else {
+            let _ = reply.send(Err(self.not_leader()));
+            return Ok(());
+        };
+        // Each change has to be committed before the next, and a new leader has to know
+        // which was the last
+        if self.log.members_index() > self.commit || leadership.start > self.commit {
+            let _ = reply.send(Err(ClusterError::ChangeInProgress));
+            return Ok(());
+        }
+        let mut members: Members = self.log.members().clone();
+        match change {
+            MemberChange::Add { id, addr } => {
+                if members.get(&id) == Some(&addr) {
+                    let _ = reply.send(Ok(()));
+                    return Ok(());
+                }
+                members.insert(id, addr);
+            }
+            MemberChange::Remove { id } => {
+                if members.remove(&id).is_none() {
+                    let _ = reply.send(Err(ClusterError::NotMember(id)));
+                    return Ok(());
+                }
+                if members.is_empty() {
+                    let _ = reply.send(Err(ClusterError::LastMember));
+                    return Ok(());
+                }
+            }
+        }
+        self.propose(Command::Members(members), Pending::Members(reply))
+    }
+}
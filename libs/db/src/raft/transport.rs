@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::log::Members;
+use super::node::Event;
+use super::wire::{self, Message};
+use super::NodeId;
+
+/// How often the listener checks whether it should stop
+const POLL: Duration = Duration::from_millis(50);
+/// How long connecting to another node may take, and how long to wait before trying
+/// again after it failed. Messages to it are dropped meanwhile, as Raft resends them
+const CONNECT: Duration = Duration::from_secs(1);
+/// Messages waiting to go to a node that's slow to take them. More are dropped
+const QUEUE: usize = 1024;
+
+/// Carries messages between the nodes of a cluster. Delivering them is best effort:
+/// they can be lost, but not reordered between two nodes
+pub(crate) trait Transport: Send {
+    /// Where to reach the members, whenever they change
+    fn update(&mut self, members: &Members);
+    fn send(&mut self, to: NodeId, message: Message);
+}
+
+/// Connects nodes in one process, to try out a cluster without a network. Links
+/// between them can be cut to see how it copes with partitions
+#[derive(Debug, Clone, Default)]
+pub struct LocalNetwork {
+    links: Arc<Mutex<Links>>,
+}
+
+#[derive(Debug, Default)]
+struct Links {
+    inboxes: BTreeMap<NodeId, Sender<Event>>,
+    /// Links that are down, in both directions
+    cut: BTreeSet<(NodeId, NodeId)>,
+    /// Nodes that can't reach any other
+    isolated: BTreeSet<NodeId>,
+}
+
+impl LocalNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cuts every link between a node in `a` and one in `b`
+    pub fn partition(&self, a: &[NodeId], b: &[NodeId]) {
+        let mut links = self.links.lock().unwrap();
+        for &x in a {
+            for &y in b {
+                links.cut.insert((x, y));
+                links.cut.insert((y, x));
+            }
+        }
+    }
+
+    /// Cuts every link to and from `id`
+    pub fn isolate(&self, id: NodeId) {
+        self.links.lock().unwrap().isolated.insert(id);
+    }
+
+    /// Brings every link back up
+    pub fn heal(&self) {
+        let mut links = self.links.lock().unwrap();
+        links.cut.clear();
+        links.isolated.clear();
+    }
+
+    pub(crate) fn connect(&self, id: NodeId, inbox: Sender<Event>) -> LocalTransport {
+        self.links.lock().unwrap().inboxes.insert(id, inbox);
+        LocalTransport {
+            id,
+            network: self.clone(),
+        }
+    }
+}
+
+pub(crate) struct LocalTransport {
+    id: NodeId,
+    network: LocalNetwork,
+}
+
+impl Transport for LocalTransport {
+    fn update(&mut self, _: &Members) {}
+
+    fn send(&mut self, to: NodeId, message: Message) {
+        let links = self.network.links.lock().unwrap();
+        if links.cut.contains(&(self.id, to))
+            || links.isolated.contains(&self.id)
+            || links.isolated.contains(&to)
+        {
+            return;
+        }
+        if let Some(inbox) = links.inboxes.get(&to) {
+            let _ = inbox.send(Event::Message {
+                from: self.id,
+                message,
+            });
+        }
+    }
+}
+
+impl Drop for LocalTransport {
+    fn drop(&mut self) {
+        self.network.links.lock().unwrap().inboxes.remove(&self.id);
+    }
+}
+
+/// Sends to each node over a connection of its own, from a thread of its own, so a slow
+/// or unreachable node holds nothing up
+pub(crate) struct TcpTransport {
+    id: NodeId,
+    addr: String,
+    peers: BTreeMap<NodeId, Peer>,
+    /// The addresses of nodes that connected to this one, which may not be members yet
+    /// or not know it is one
+    learned: Arc<Mutex<Members>>,
+    stop: Arc<AtomicBool>,
+    /// Shut down on drop, so their readers end
+    accepted: Arc<Mutex<Vec<TcpStream>>>,
+    listener: Option<JoinHandle<()>>,
+}
+
+struct Peer {
+    addr: String,
+    /// Dropping it ends the sending thread
+    queue: SyncSender<Message>,
+}
+
+impl TcpTransport {
+    /// Listens on `addr`, passing what arrives to `inbox`
+    pub(crate) fn bind(id: NodeId, addr: &str, inbox: Sender<Event>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let learned = Arc::new(Mutex::new(Members::new()));
+        let accepted = Arc::new(Mutex::new(vec![]));
+
+        let thread = {
+            let (stop, learned, accepted) = (stop.clone(), learned.clone(), accepted.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(_) => {
+                            thread::sleep(POLL);
+                            continue;
+                        }
+                    };
+                    let Ok(clone) = stream.try_clone() else {
+                        continue;
+                    };
+                    accepted.lock().unwrap().push(clone);
+                    let (learned, inbox) = (learned.clone(), inbox.clone());
+                    thread::spawn(move || receive(stream, &learned, &inbox));
+                }
+            })
+        };
+
+        Ok(Self {
+            id,
+            addr: addr.to_owned(),
+            peers: BTreeMap::new(),
+            learned,
+            stop,
+            accepted,
+            listener: Some(thread),
+        })
+    }
+
+    fn connect(&mut self, to: NodeId, addr: &str) {
+        let (queue, outbox) = mpsc::sync_channel(QUEUE);
+        let (id, own) = (self.id, self.addr.clone());
+        let target = addr.to_owned();
+        thread::spawn(move || {
+            let mut out = None;
+            let mut retry = Instant::now();
+            while let Ok(message) = outbox.recv() {
+                if out.is_none() && Instant::now() >= retry {
+                    match dial(&target, id, &own) {
+                        Ok(stream) => out = Some(stream),
+                        Err(_) => retry = Instant::now() + CONNECT,
+                    }
+                }
+                let Some(stream) = &mut out else {
+                    continue;
+                };
+                let mut sent = wire::write_message(stream, &message);
+                while sent.is_ok() {
+                    match outbox.try_recv() {
+                        Ok(message) => sent = wire::write_message(stream, &message),
+                        Err(_) => break,
+                    }
+                }
+                if sent.and_then(|()| stream.flush()).is_err() {
+                    out = None;
+                }
+            }
+        });
+        self.peers.insert(
+            to,
+            Peer {
+                addr: addr.to_owned(),
+                queue,
+            },
+        );
+    }
+}
+
+fn dial(addr: &str, id: NodeId, own: &str) -> io::Result<BufWriter<TcpStream>> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other(format!("`{addr}` isn't an address")))?;
+    let stream = TcpStream::connect_timeout(&addr, CONNECT)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(CONNECT))?;
+    let mut out = BufWriter::new(stream);
+    wire::write_hello(&mut out, id, own)?;
+    Ok(out)
+}
+
+/// Passes on what another node sends until it disconnects
+fn receive(stream: TcpStream, learned: &Mutex<Members>, inbox: &Sender<Event>) {
+    let mut input = BufReader::new(stream);
+    let Ok((from, addr)) = wire::read_hello(&mut input) else {
+        return;
+    };
+    learned.lock().unwrap().insert(from, addr);
+    while let Ok(message) = wire::read_message(&mut input) {
+        if inbox.send(Event::Message { from, message }).is_err() {
+            return;
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn update(&mut self, members: &Members) {
+        for (&id, addr) in members {
+            if id != self.id && self.peers.get(&id).is_none_or(|peer| peer.addr != *addr) {
+                self.connect(id, addr);
+            }
+        }
+    }
+
+    fn send(&mut self, to: NodeId, message: Message) {
+        if !self.peers.contains_key(&to) {
+            let Some(addr) = self.learned.lock().unwrap().get(&to).cloned() else {
+                return;
+            };
+            self.connect(to, &addr);
+        }
+        let _ = self.peers[&to].queue.try_send(message);
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.listener.take() {
+            let _ = thread.join();
+        }
+        for stream in self.accepted.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
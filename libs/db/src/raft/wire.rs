@@ -0,0 +1,220 @@
+//! What the nodes of a cluster send each other. Over TCP each node opens a connection to
+//! each other one and only ever writes to it: a hello naming itself, then messages, with
+//! replies coming back over the other node's connection
+
+use std::io::{self, Read, Write};
+
+use super::log::{read_entry, read_members, write_entry, write_members, Entry, Members};
+use super::NodeId;
+use crate::snapshot::schema::{read_bytes, read_text, read_u64, read_u8, write_bytes, write_text};
+
+const HELLO: &[u8; 8] = b"HOYARAFT";
+
+const VOTE: u8 = 1;
+const VOTED: u8 = 2;
+const APPEND: u8 = 3;
+const APPENDED: u8 = 4;
+const SNAPSHOT: u8 = 5;
+const INSTALLED: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Message {
+    /// Asks for a vote in `term`, from a candidate whose log ends as given
+    Vote {
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+    },
+    Voted {
+        term: u64,
+        granted: bool,
+    },
+    /// Entries following the one at `prev_index`, or none as a heartbeat. `round` numbers
+    /// the heartbeats, so the leader can tell which ones confirmed it's still leading
+    Append {
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+        round: u64,
+    },
+    /// On success `index` is the last entry the follower now shares with the leader,
+    /// and otherwise the latest one it might
+    Appended {
+        term: u64,
+        success: bool,
+        index: u64,
+        round: u64,
+    },
+    /// A snapshot up to the entry at `index`, for a follower that needs entries the
+    /// leader no longer has
+    Snapshot {
+        term: u64,
+        index: u64,
+        last_term: u64,
+        members: Members,
+        data: Vec<u8>,
+    },
+    Installed {
+        term: u64,
+        index: u64,
+    },
+}
+
+impl Message {
+    pub(crate) fn term(&self) -> u64 {
+        match self {
+            Message::Vote { term, .. }
+            | Message::Voted { term, .. }
+            | Message::Append { term, .. }
+            | Message::Appended { term, .. }
+            | Message::Snapshot { term, .. }
+            | Message::Installed { term, .. } => *term,
+        }
+    }
+}
+
+fn write_u64s(out: &mut dyn Write, numbers: &[u64]) -> io::Result<()> {
+    numbers
+        .iter()
+        .try_for_each(|number| out.write_all(&number.to_le_bytes()))
+}
+
+/// Opens a connection from the node `id`, listening on `addr`
+pub(crate) fn write_hello(out: &mut dyn Write, id: NodeId, addr: &str) -> io::Result<()> {
+    out.write_all(HELLO)?;
+    out.write_all(&id.to_le_bytes())?;
+    write_text(out, addr)
+}
+
+pub(crate) fn read_hello(input: &mut dyn Read) -> io::Result<(NodeId, String)> {
+    let mut hello = [0; HELLO.len()];
+    input.read_exact(&mut hello)?;
+    if &hello != HELLO {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a HoyaDB cluster node",
+        ));
+    }
+    Ok((read_u64(input)?, read_text(input)?))
+}
+
+pub(crate) fn write_message(out: &mut dyn Write, message: &Message) -> io::Result<()> {
+    match message {
+        Message::Vote {
+            term,
+            last_index,
+            last_term,
+        } => {
+            out.write_all(&[VOTE])?;
+            write_u64s(out, &[*term, *last_index, *last_term])
+        }
+        Message::Voted { term, granted } => {
+            out.write_all(&[VOTED, u8::from(*granted)])?;
+            write_u64s(out, &[*term])
+        }
+        Message::Append {
+            term,
+            prev_index,
+            prev_term,
+            entries,
+            commit,
+            round,
+        } => {
+            out.write_all(&[APPEND])?;
+            write_u64s(
+                out,
+                &[
+                    *term,
+                    *prev_index,
+                    *prev_term,
+                    *commit,
+                    *round,
+                    entries.len() as u64,
+                ],
+            )?;
+            entries.iter().try_for_each(|entry| write_entry(out, entry))
+        }
+        Message::Appended {
+            term,
+            success,
+            index,
+            round,
+        } => {
+            out.write_all(&[APPENDED, u8::from(*success)])?;
+            write_u64s(out, &[*term, *index, *round])
+        }
+        Message::Snapshot {
+            term,
+            index,
+            last_term,
+            members,
+            data,
+        } => {
+            out.write_all(&[SNAPSHOT])?;
+            write_u64s(out, &[*term, *index, *last_term])?;
+            write_members(out, members)?;
+            write_bytes(out, data)
+        }
+        Message::Installed { term, index } => {
+            out.write_all(&[INSTALLED])?;
+            write_u64s(out, &[*term, *index])
+        }
+    }
+}
+
+pub(crate) fn read_message(input: &mut dyn Read) -> io::Result<Message> {
+    Ok(match read_u8(input)? {
+        VOTE => Message::Vote {
+            term: read_u64(input)?,
+            last_index: read_u64(input)?,
+            last_term: read_u64(input)?,
+        },
+        VOTED => Message::Voted {
+            granted: read_u8(input)? != 0,
+            term: read_u64(input)?,
+        },
+        APPEND => {
+            let term = read_u64(input)?;
+            let prev_index = read_u64(input)?;
+            let prev_term = read_u64(input)?;
+            let commit = read_u64(input)?;
+            let round = read_u64(input)?;
+            let entries = (0..read_u64(input)?)
+                .map(|_| read_entry(input))
+                .collect::<io::Result<_>>()?;
+            Message::Append {
+                term,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+                round,
+            }
+        }
+        APPENDED => Message::Appended {
+            success: read_u8(input)? != 0,
+            term: read_u64(input)?,
+            index: read_u64(input)?,
+            round: read_u64(input)?,
+        },
+        SNAPSHOT => Message::Snapshot {
+            term: read_u64(input)?,
+            index: read_u64(input)?,
+            last_term: read_u64(input)?,
+            members: read_members(input)?,
+            data: read_bytes(input)?,
+        },
+        INSTALLED => Message::Installed {
+            term: read_u64(input)?,
+            index: read_u64(input)?,
+        },
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unknown cluster message",
+            ))
+        }
+    })
+}
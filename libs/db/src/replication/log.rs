@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::DBTypes;
+
+/// How many changes a primary keeps for replicas that reconnect. One that missed more
+/// has to sync from scratch
+pub(crate) const BACKLOG: usize = 1 << 16;
+
+/// A write, as replicas replay it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Change {
+    Put { key: String, value: DBTypes },
+    Remove { key: String },
+}
+
+/// Every write in the order it happened, numbered by its offset. Writes are recorded
+/// under their shard's lock, so changes to a key are in the order they were made
+#[derive(Debug)]
+pub(crate) struct ChangeLog {
+    /// Nothing is recorded until the database starts serving replicas
+    active: AtomicBool,
+    backlog: Mutex<Backlog>,
+    appended: Condvar,
+}
+
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    /// Names this history of changes. Loading a snapshot starts another one, which
+    /// replicas have to sync from scratch
+    pub(crate) id: String,
+    /// The offset of the latest change
+    pub(crate) offset: u64,
+    /// The latest changes, up to `offset`
+    changes: VecDeque<Change>,
+}
+
+fn new_id() -> String {
+    let mut bytes = [0; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            backlog: Mutex::new(Backlog {
+                id: new_id(),
+                offset: 0,
+                changes: VecDeque::new(),
+            }),
+            appended: Condvar::new(),
+        }
+    }
+}
+
+impl Backlog {
+    /// The changes after `offset` with their offsets, if they're all still kept
+    pub(crate) fn since(&self, offset: u64) -> Option<Vec<(u64, Change)>> {
+        let missing = self.offset.checked_sub(offset)?;
+        let skip = (self.changes.len() as u64).checked_sub(missing)?;
+        Some(
+            self.changes
+                .iter()
+                .skip(skip as usize)
+                .cloned()
+                .zip(offset + 1..)
+                .map(|(change, offset)| (offset, change))
+                .collect(),
+        )
+    }
+}
+
+impl ChangeLog {
+    pub(crate) fn activate(&self) {
+        self.active.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether changes are recorded
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Appends the change `change` builds, which it only does when replicas could need it
+    pub(crate) fn record(&self, change: impl FnOnce() -> Change) {
+        if !self.is_active() {
+            return;
+        }
+        let mut backlog = self.backlog.lock().unwrap();
+        backlog.offset += 1;
+        backlog.changes.push_back(change());
+        if backlog.changes.len() > BACKLOG {
+            backlog.changes.pop_front();
+        }
+        drop(backlog);
+        self.appended.notify_all();
+    }
+
+    /// Starts a new history, after every record was replaced at once
+    pub(crate) fn restart(&self) {
+        let mut backlog = self.backlog.lock().unwrap();
+        backlog.id = new_id();
+        backlog.offset = 0;
+        backlog.changes.clear();
+        drop(backlog);
+        self.appended.notify_all();
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Backlog> {
+        self.backlog.lock().unwrap()
+    }
+
+    /// Waits up to `timeout` for a change after `offset`, or for a new history
+    pub(crate) fn wait<'a>(
+        &self,
+        backlog: MutexGuard<'a, Backlog>,
+        id: &str,
+        offset: u64,
+        timeout: Duration,
+    ) -> MutexGuard<'a, Backlog> {
+        self.appended
+            .wait_timeout_while(backlog, timeout, |backlog| {
+                backlog.id == id && backlog.offset == offset
+            })
+            .unwrap()
+            .0
+    }
+}
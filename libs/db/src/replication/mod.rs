@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::Database;
+
+pub(crate) mod log;
+mod primary;
+mod replica;
+mod wire;
+
+pub use primary::Primary;
+pub use replica::Replica;
+
+/// Where a database stands in replication, shared by its clones
+#[derive(Debug, Default)]
+pub(crate) struct Replication {
+    /// The offset sent to each connected replica, while serving them
+    serving: Mutex<Option<BTreeMap<SocketAddr, u64>>>,
+    following: Mutex<Option<ReplicaStatus>>,
+}
+
+/// How a replica is keeping up with its primary
+#[derive(Debug, Clone)]
+pub struct ReplicaStatus {
+    pub primary: String,
+    pub connected: bool,
+    /// The primary's history the replica follows, empty before the first sync
+    pub id: String,
+    /// The offset of the last change applied
+    pub offset: u64,
+    /// The primary's offset, as of the last message from it
+    pub primary_offset: u64,
+    pub last_contact: Option<Instant>,
+    pub full_syncs: u64,
+    pub partial_syncs: u64,
+    pub last_error: Option<String>,
+}
+
+impl ReplicaStatus {
+    /// How many changes the replica is behind, as far as it knows
+    pub fn lag(&self) -> u64 {
+        self.primary_offset.saturating_sub(self.offset)
+    }
+}
+
+/// A replica connected to this database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaInfo {
+    pub addr: SocketAddr,
+    /// The offset of the last change sent to it
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplicationStatus {
+    Standalone,
+    Primary {
+        offset: u64,
+        replicas: Vec<ReplicaInfo>,
+    },
+    Replica(ReplicaStatus),
+}
+
+impl fmt::Display for ReplicationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplicationStatus::Standalone => write!(f, "Not replicating"),
+            ReplicationStatus::Primary { offset, replicas } => {
+                write!(
+                    f,
+                    "Primary at offset {offset} with {} replicas",
+                    replicas.len()
+                )?;
+                for replica in replicas {
+                    write!(
+                        f,
+                        "; {} at {}, {} behind",
+                        replica.addr,
+                        replica.offset,
+                        offset.saturating_sub(replica.offset)
+                    )?;
+                }
+                Ok(())
+            }
+            ReplicationStatus::Replica(status) => {
+                write!(
+                    f,
+                    "Replica of {}, {}; at offset {}, {} behind",
+                    status.primary,
+                    if status.connected {
+                        "connected"
+                    } else {
+                        "disconnected"
+                    },
+                    status.offset,
+                    status.lag()
+                )?;
+                if let Some(at) = status.last_contact {
+                    write!(f, "; last heard from {:.1?} ago", at.elapsed())?;
+                }
+                write!(
+                    f,
+                    "; {} full and {} partial syncs",
+                    status.full_syncs, status.partial_syncs
+                )?;
+                if let Some(e) = &status.last_error {
+                    write!(f, "; last error: {e}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Database {
+    pub fn replication_status(&self) -> ReplicationStatus {
+        if let Some(status) = &*self.replication.following.lock().unwrap() {
+            return ReplicationStatus::Replica(status.clone());
+        }
+        match &*self.replication.serving.lock().unwrap() {
+            Some(replicas) => ReplicationStatus::Primary {
+                offset: self.changes.lock().offset,
+                replicas: replicas
+                    .iter()
+                    .map(|(addr, offset)| ReplicaInfo {
+                        addr: *addr,
+                        offset: *offset,
+                    })
+                    .collect(),
+            },
+            None => ReplicationStatus::Standalone,
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::wire::{self, ChunkWriter, Message, Reply};
+use crate::snapshot::{self, SnapshotOptions};
+use crate::Database;
+
+/// How long a feed waits for changes before pinging the replica instead
+const PING: Duration = Duration::from_secs(1);
+/// How often the listener checks whether it should stop
+const POLL: Duration = Duration::from_millis(50);
+/// A replica that takes longer than this to say hello or to take what's sent is dropped
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves replicas until dropped
+#[derive(Debug)]
+pub struct Primary {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Primary {
+    /// Where replicas connect, with the port the system picked if it was 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Primary {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Database {
+    /// Accepts replicas on `addr`. Each is sent the changes it missed if they're still in
+    /// the backlog, or else a snapshot, and then every change as it's made
+    pub fn serve_replicas(&self, addr: impl ToSocketAddrs) -> io::Result<Primary> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        self.changes.activate();
+        *self.replication.serving.lock().unwrap() = Some(BTreeMap::new());
+
+        let db = self.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut feeds: Vec<JoinHandle<()>> = vec![];
+                while !stop.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, peer)) => {
+                            let (db, stop) = (db.clone(), stop.clone());
+                            feeds.push(thread::spawn(move || db.feed(stream, peer, &stop)));
+                        }
+                        Err(_) => thread::sleep(POLL),
+                    }
+                    feeds.retain(|feed| !feed.is_finished());
+                }
+                for feed in feeds {
+                    let _ = feed.join();
+                }
+                *db.replication.serving.lock().unwrap() = None;
+            })
+        };
+
+        Ok(Primary {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Serves one replica until it goes away or the primary stops. The replica reconnects
+    /// by itself after errors, so they only end the connection
+    fn feed(&self, stream: TcpStream, peer: SocketAddr, stop: &AtomicBool) {
+        let _ = self.try_feed(stream, peer, stop);
+        if let Some(replicas) = &mut *self.replication.serving.lock().unwrap() {
+            replicas.remove(&peer);
+        }
+    }
+
+    fn try_feed(&self, stream: TcpStream, peer: SocketAddr, stop: &AtomicBool) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let (id, offset) = wire::read_hello(&mut &stream)?;
+        let mut out = BufWriter::new(stream.try_clone()?);
+
+        let partial = {
+            let backlog = self.changes.lock();
+            (backlog.id == id && backlog.since(offset).is_some()).then(|| backlog.id.clone())
+        };
+        let (id, mut sent) = match partial {
+            Some(id) => {
+                wire::write_reply(&mut out, &Reply::Partial { id: id.clone() })?;
+                (id, offset)
+            }
+            None => match self.freeze(format!("a full sync of {peer}")) {
+                Ok((id, offset)) => {
+                    wire::write_reply(
+                        &mut out,
+                        &Reply::Full {
+                            id: id.clone(),
+                            offset,
+                        },
+                    )?;
+                    out.flush()?;
+                    let sent = self.send_snapshot(&stream);
+                    self.thaw(None);
+                    sent?;
+                    (id, offset)
+                }
+                Err(e) => {
+                    wire::write_reply(&mut out, &Reply::Refused(e.to_string()))?;
+                    return out.flush();
+                }
+            },
+        };
+        out.flush()?;
+
+        while !stop.load(Ordering::SeqCst) {
+            if let Some(replicas) = &mut *self.replication.serving.lock().unwrap() {
+                replicas.insert(peer, sent);
+            }
+            let changes = {
+                let backlog = self.changes.wait(self.changes.lock(), &id, sent, PING);
+                if backlog.id != id {
+                    return Err(io::Error::other("A snapshot was loaded"));
+                }
+                backlog
+                    .since(sent)
+                    .ok_or_else(|| io::Error::other("The replica fell behind the backlog"))?
+            };
+            if changes.is_empty() {
+                wire::write_message(&mut out, &Message::Ping { offset: sent })?;
+            }
+            for (offset, change) in changes {
+                wire::write_message(&mut out, &Message::Change { offset, change })?;
+                sent = offset;
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the frozen records in the format `store` writes, uncompressed since the
+    /// replica reads them as they come
+    fn send_snapshot(&self, stream: &TcpStream) -> io::Result<()> {
+        let mut out = snapshot::create(
+            Box::new(ChunkWriter::new(stream.try_clone()?)),
+            &SnapshotOptions::default(),
+        )?;
+        let count = self.write_frozen(|key, value| snapshot::write_record(&mut out, key, value))?;
+        snapshot::write_end(&mut out, count)?;
+        out.finish()
+    }
+}
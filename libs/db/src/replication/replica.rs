@@ -0,0 +1,181 @@
+use std::io::{self, BufReader};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::log::Change;
+use super::wire::{self, ChunkReader, Message, Reply};
+use super::ReplicaStatus;
+use crate::snapshot;
+use crate::Database;
+
+/// How long a replica waits before reconnecting
+const RETRY: Duration = Duration::from_secs(1);
+/// How often a replica waiting to reconnect checks whether it should stop
+const POLL: Duration = Duration::from_millis(50);
+/// A primary that sends nothing for this long, not even a ping, is given up on
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Follows a primary until dropped. The database then stands on its own again, with the
+/// records it had, so dropping this promotes a replica
+#[derive(Debug)]
+pub struct Replica {
+    db: Database,
+    stop: Arc<AtomicBool>,
+    /// The connection, so dropping doesn't wait for the primary to send something
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Replica {
+    pub fn status(&self) -> ReplicaStatus {
+        self.db
+            .replication
+            .following
+            .lock()
+            .unwrap()
+            .clone()
+            .expect("following until dropped")
+    }
+}
+
+impl Drop for Replica {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(stream) = self.stream.lock().unwrap().take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        *self.db.replication.following.lock().unwrap() = None;
+    }
+}
+
+impl Database {
+    /// Makes this database a copy of the one serving replicas at `primary`, like
+    /// `host:port`, and keeps it up to date. Whatever it held is replaced by the first
+    /// sync. After a dropped connection it reconnects, and only gets the changes it
+    /// missed if the primary still has them
+    pub fn replicate_from(&self, primary: impl Into<String>) -> Replica {
+        let primary = primary.into();
+        *self.replication.following.lock().unwrap() = Some(ReplicaStatus {
+            primary: primary.clone(),
+            connected: false,
+            id: String::new(),
+            offset: 0,
+            primary_offset: 0,
+            last_contact: None,
+            full_syncs: 0,
+            partial_syncs: 0,
+            last_error: None,
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stream = Arc::new(Mutex::new(None));
+        let thread = {
+            let (db, stop, stream) = (self.clone(), stop.clone(), stream.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    let result = db.follow(&primary, &stream, &stop);
+                    db.update_following(|status| {
+                        status.connected = false;
+                        if let Err(e) = result {
+                            status.last_error = Some(e.to_string());
+                        }
+                    });
+                    let retry = Instant::now();
+                    while retry.elapsed() < RETRY && !stop.load(Ordering::SeqCst) {
+                        thread::sleep(POLL);
+                    }
+                }
+            })
+        };
+
+        Replica {
+            db: self.clone(),
+            stop,
+            stream,
+            thread: Some(thread),
+        }
+    }
+
+    fn update_following(&self, update: impl FnOnce(&mut ReplicaStatus)) {
+        if let Some(status) = &mut *self.replication.following.lock().unwrap() {
+            update(status);
+        }
+    }
+
+    /// Syncs with the primary and applies its changes until the connection drops
+    fn follow(
+        &self,
+        primary: &str,
+        current: &Mutex<Option<TcpStream>>,
+        stop: &AtomicBool,
+    ) -> io::Result<()> {
+        let addr = primary
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("`{primary}` isn't an address")))?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        *current.lock().unwrap() = Some(stream.try_clone()?);
+        // Dropped while connecting, after the stream to shut down was taken
+        if stop.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let (id, offset) = {
+            let following = self.replication.following.lock().unwrap();
+            let status = following.as_ref().expect("following until dropped");
+            (status.id.clone(), status.offset)
+        };
+        wire::write_hello(&mut &stream, &id, offset)?;
+        let mut input = BufReader::new(&stream);
+        match wire::read_reply(&mut input)? {
+            Reply::Full { id, offset } => {
+                let records = snapshot::read(ChunkReader::new(&mut input), None)
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                self.restore(records)?;
+                self.update_following(|status| {
+                    status.id = id;
+                    status.offset = offset;
+                    status.primary_offset = offset;
+                    status.full_syncs += 1;
+                });
+            }
+            Reply::Partial { .. } => self.update_following(|status| status.partial_syncs += 1),
+            Reply::Refused(reason) => return Err(io::Error::other(reason)),
+        }
+        self.update_following(|status| {
+            status.connected = true;
+            status.last_contact = Some(Instant::now());
+            status.last_error = None;
+        });
+
+        loop {
+            let message = wire::read_message(&mut input)?;
+            let primary_offset = match message {
+                Message::Change { offset, change } => {
+                    let applied = match change {
+                        Change::Put { key, value } => self.put(key, value),
+                        Change::Remove { key } => self.remove(&key),
+                    };
+                    // Without counting it, so the primary sends it again on reconnecting
+                    applied.map_err(|e| io::Error::other(e.to_string()))?;
+                    self.update_following(|status| status.offset = offset);
+                    offset
+                }
+                Message::Ping { offset } => offset,
+            };
+            self.update_following(|status| {
+                status.primary_offset = status.primary_offset.max(primary_offset);
+                status.last_contact = Some(Instant::now());
+            });
+        }
+    }
+}
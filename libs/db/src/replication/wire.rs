@@ -0,0 +1,233 @@
+//! What primaries and replicas send each other. A replica opens with a hello naming the
+//! history and offset it has, and the primary answers with either the changes since or a
+//! full snapshot first, in the format `store` writes, split into length-prefixed chunks.
+//! Values are laid out as in snapshots, so both sides agree on them across versions
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use super::log::Change;
+use crate::snapshot::schema::{read_text, read_u64, read_u8, read_value, write_text, write_value};
+use crate::snapshot::Finish;
+
+const HELLO: &[u8; 8] = b"HOYAREPL";
+
+const FULL: u8 = 1;
+const PARTIAL: u8 = 2;
+const REFUSED: u8 = 3;
+
+const PUT: u8 = 1;
+const REMOVE: u8 = 2;
+const PING: u8 = 3;
+
+/// Bytes per snapshot chunk
+const CHUNK: usize = 64 * 1024;
+
+pub(crate) enum Reply {
+    /// A snapshot of the records as of `offset` follows
+    Full {
+        id: String,
+        offset: u64,
+    },
+    /// The changes after the replica's offset follow
+    Partial {
+        id: String,
+    },
+    Refused(String),
+}
+
+pub(crate) enum Message {
+    Change {
+        offset: u64,
+        change: Change,
+    },
+    /// Sent when nothing changed for a while, with the primary's offset
+    Ping {
+        offset: u64,
+    },
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_owned())
+}
+
+/// A replica without a history sends an empty id
+pub(crate) fn write_hello(out: &mut dyn Write, id: &str, offset: u64) -> io::Result<()> {
+    out.write_all(HELLO)?;
+    write_text(out, id)?;
+    out.write_all(&offset.to_le_bytes())
+}
+
+pub(crate) fn read_hello(input: &mut dyn Read) -> io::Result<(String, u64)> {
+    let mut hello = [0; HELLO.len()];
+    input.read_exact(&mut hello)?;
+    if &hello != HELLO {
+        return Err(invalid("Not a HoyaDB replica"));
+    }
+    Ok((read_text(input)?, read_u64(input)?))
+}
+
+pub(crate) fn write_reply(out: &mut dyn Write, reply: &Reply) -> io::Result<()> {
+    match reply {
+        Reply::Full { id, offset } => {
+            out.write_all(&[FULL])?;
+            write_text(out, id)?;
+            out.write_all(&offset.to_le_bytes())
+        }
+        Reply::Partial { id } => {
+            out.write_all(&[PARTIAL])?;
+            write_text(out, id)
+        }
+        Reply::Refused(reason) => {
+            out.write_all(&[REFUSED])?;
+            write_text(out, reason)
+        }
+    }
+}
+
+pub(crate) fn read_reply(input: &mut dyn Read) -> io::Result<Reply> {
+    match read_u8(input)? {
+        FULL => Ok(Reply::Full {
+            id: read_text(input)?,
+            offset: read_u64(input)?,
+        }),
+        PARTIAL => Ok(Reply::Partial {
+            id: read_text(input)?,
+        }),
+        REFUSED => Ok(Reply::Refused(read_text(input)?)),
+        _ => Err(invalid("Not a HoyaDB primary")),
+    }
+}
+
+pub(crate) fn write_message(out: &mut dyn Write, message: &Message) -> io::Result<()> {
+    match message {
+        Message::Change {
+            offset,
+            change: Change::Put { key, value },
+        } => {
+            out.write_all(&[PUT])?;
+            out.write_all(&offset.to_le_bytes())?;
+            write_text(out, key)?;
+            write_value(out, value)
+        }
+        Message::Change {
+            offset,
+            change: Change::Remove { key },
+        } => {
+            out.write_all(&[REMOVE])?;
+            out.write_all(&offset.to_le_bytes())?;
+            write_text(out, key)
+        }
+        Message::Ping { offset } => {
+            out.write_all(&[PING])?;
+            out.write_all(&offset.to_le_bytes())
+        }
+    }
+}
+
+pub(crate) fn read_message(input: &mut dyn Read) -> io::Result<Message> {
+    match read_u8(input)? {
+        PUT => Ok(Message::Change {
+            offset: read_u64(input)?,
+            change: Change::Put {
+                key: read_text(input)?,
+                value: read_value(input)?,
+            },
+        }),
+        REMOVE => Ok(Message::Change {
+            offset: read_u64(input)?,
+            change: Change::Remove {
+                key: read_text(input)?,
+            },
+        }),
+        PING => Ok(Message::Ping {
+            offset: read_u64(input)?,
+        }),
+        _ => Err(invalid("Unknown replication message")),
+    }
+}
+
+/// Splits a snapshot into chunks, so the replica knows where it ends without the primary
+/// knowing its size up front
+pub(crate) struct ChunkWriter {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub(crate) fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::with_capacity(CHUNK),
+        }
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        let mut frame = (self.buffer.len() as u32).to_le_bytes().to_vec();
+        frame.append(&mut self.buffer);
+        self.stream.write_all(&frame)
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK {
+            self.write_chunk()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Finish for ChunkWriter {
+    /// Writes the rest, then an empty chunk to mark the end
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_chunk()?;
+        }
+        self.write_chunk()?;
+        self.stream.flush()
+    }
+}
+
+/// Reads what `ChunkWriter` wrote, up to the empty chunk
+pub(crate) struct ChunkReader<R> {
+    inner: R,
+    left: usize,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            left: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for ChunkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.left == 0 && !self.done {
+            let mut len = [0; 4];
+            self.inner.read_exact(&mut len)?;
+            self.left = u32::from_le_bytes(len) as usize;
+            self.done = self.left == 0;
+        }
+        if self.done {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.left);
+        let n = self.inner.read(&mut buf[..n])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.left -= n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{DBTypes, Database, DatabaseError};
+
+fn wrong_type(key: &str, expected: &'static str) -> DatabaseError {
+    DatabaseError::WrongType {
+        key: key.to_owned(),
+        expected,
+    }
+}
+
+fn finite(key: &str, score: f64) -> Result<f64, DatabaseError> {
+    if score.is_finite() {
+        Ok(score)
+    } else {
+        Err(DatabaseError::NonFiniteScore {
+            key: key.to_owned(),
+            score,
+        })
+    }
+}
+
+/// Atomic operations on keys holding sets and sorted sets.
+/// A missing key behaves like an empty set
+impl Database {
+    fn modify_set<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut BTreeSet<String>) -> T,
+    ) -> Result<T, DatabaseError> {
+        self.modify(key, |entry| {
+            match entry.get_or_insert_with(|| DBTypes::Set(BTreeSet::new())) {
+                DBTypes::Set(set) => Ok(f(set)),
+                _ => Err(wrong_type(key, "set")),
+            }
+        })
+    }
+
+    fn modify_sorted_set<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut BTreeMap<String, f64>) -> Result<T, DatabaseError>,
+    ) -> Result<T, DatabaseError> {
+        self.modify(key, |entry| {
+            match entry.get_or_insert_with(|| DBTypes::SortedSet(BTreeMap::new())) {
+                DBTypes::SortedSet(set) => f(set),
+                _ => Err(wrong_type(key, "sorted set")),
+            }
+        })
+    }
+
+    /// Adds `members`, returning how many weren't already in the set
+    pub fn sadd(&self, key: &str, members: Vec<String>) -> Result<usize, DatabaseError> {
+        self.modify_set(key, |set| {
+            members
+                .into_iter()
+                .filter(|member| set.insert(member.clone()))
+                .count()
+        })
+    }
+
+    /// Removes `members`, returning how many were in the set
+    pub fn srem(&self, key: &str, members: &[String]) -> Result<usize, DatabaseError> {
+        self.modify(key, |entry| match entry {
+            Some(DBTypes::Set(set)) => Ok(members.iter().filter(|m| set.remove(*m)).count()),
+            Some(_) => Err(wrong_type(key, "set")),
+            None => Ok(0),
+        })
+    }
+
+    pub fn smembers(&self, key: &str) -> Result<BTreeSet<String>, DatabaseError> {
+        match self.shard(key).read().unwrap().records.get(key).as_deref() {
+            Some(DBTypes::Set(set)) => Ok(set.clone()),
+            Some(_) => Err(wrong_type(key, "set")),
+            None => Ok(BTreeSet::new()),
+        }
+    }
+
+    /// Combines the sets at `keys` with `op`, reading all of them under the same locks
+    fn combine_sets(
+        &self,
+        keys: &[String],
+        op: impl Fn(BTreeSet<String>, &BTreeSet<String>) -> BTreeSet<String>,
+    ) -> Result<BTreeSet<String>, DatabaseError> {
+        let stores = self.read_all();
+        let mut sets =
+            keys.iter()
+                .map(|key| match stores[self.shard_index(key)].records.get(key) {
+                    Some(Cow::Borrowed(DBTypes::Set(set))) => Ok(Cow::Borrowed(set)),
+                    Some(Cow::Owned(DBTypes::Set(set))) => Ok(Cow::Owned(set)),
+                    Some(_) => Err(wrong_type(key, "set")),
+                    None => Ok(Cow::Owned(BTreeSet::new())),
+                });
+
+        let first = match sets.next() {
+            Some(set) => set?.into_owned(),
+            None => return Ok(BTreeSet::new()),
+        };
+        sets.try_fold(first, |acc, set| set.map(|set| op(acc, &set)))
+    }
+
+    pub fn sinter(&self, keys: &[String]) -> Result<BTreeSet<String>, DatabaseError> {
+        self.combine_sets(keys, |acc, set| acc.intersection(set).cloned().collect())
+    }
+
+    pub fn sunion(&self, keys: &[String]) -> Result<BTreeSet<String>, DatabaseError> {
+        self.combine_sets(keys, |mut acc, set| {
+            acc.extend(set.iter().cloned());
+            acc
+        })
+    }
+
+    /// Sets the score of `member`, returning whether it is a new member. Scores must be
+    /// finite
+    pub fn zadd(&self, key: &str, member: String, score: f64) -> Result<bool, DatabaseError> {
+        let score = finite(key, score)?;
+        self.modify_sorted_set(key, |set| Ok(set.insert(member, score).is_none()))
+    }
+
+    /// Adds `by` to the score of `member`, starting from 0, and returns the new score.
+    /// A score that would stop being finite is left as it was
+    pub fn zincr(&self, key: &str, member: String, by: f64) -> Result<f64, DatabaseError> {
+        self.modify_sorted_set(key, |set| {
+            let score = set.entry(member).or_insert(0.0);
+            *score = finite(key, *score + by)?;
+            Ok(*score)
+        })
+    }
+
+    /// Members in ascending order of score, ties broken by member
+    fn ranked(set: &BTreeMap<String, f64>) -> Vec<(&String, &f64)> {
+        let mut ranked = set.iter().collect::<Vec<_>>();
+        ranked.sort_by(|(a, x), (b, y)| x.total_cmp(y).then_with(|| a.cmp(b)));
+        ranked
+    }
+
+    /// Members scoring within `min..=max`, in ascending order of score
+    pub fn zrange_by_score(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(String, f64)>, DatabaseError> {
+        match self.shard(key).read().unwrap().records.get(key).as_deref() {
+            Some(DBTypes::SortedSet(set)) => Ok(Self::ranked(set)
+                .into_iter()
+                .filter(|(_, score)| {
+                    score.total_cmp(&min) != Ordering::Less
+                        && score.total_cmp(&max) != Ordering::Greater
+                })
+                .map(|(member, score)| (member.clone(), *score))
+                .collect()),
+            Some(_) => Err(wrong_type(key, "sorted set")),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// The position of `member` in ascending order of score, starting at 0
+    pub fn zrank(&self, key: &str, member: &str) -> Result<Option<usize>, DatabaseError> {
+        match self.shard(key).read().unwrap().records.get(key).as_deref() {
+            Some(DBTypes::SortedSet(set)) => {
+                Ok(Self::ranked(set).into_iter().position(|(m, _)| m == member))
+            }
+            Some(_) => Err(wrong_type(key, "sorted set")),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::cmp::Ordering;
+
+/// FNV-1a, which unlike `DefaultHasher` is guaranteed not to change between Rust releases.
+/// Anything persisted, like shard assignments and bloom filters, depends on that
+pub(crate) fn hash(bytes: &[u8], seed: u64) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325 ^ seed, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The shard out of `count` that holds `key`
+pub(crate) fn shard_of(key: &str, count: usize) -> usize {
+    (hash(key.as_bytes(), 0) % count as u64) as usize
+}
+
+/// Merges iterators that are each sorted by `cmp` into one sorted iterator.
+/// Equal items come out in the order of the iterators they came from
+pub(crate) fn merge<I, T>(
+    sorted: impl IntoIterator<Item = I>,
+    cmp: impl Fn(&T, &T) -> Ordering,
+) -> impl Iterator<Item = T>
+where
+    I: Iterator<Item = T>,
+{
+    let mut sorted = sorted
+        .into_iter()
+        .map(Iterator::peekable)
+        .collect::<Vec<_>>();
+    std::iter::from_fn(move || {
+        let next = sorted
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, items)| items.peek().map(|item| (i, item)))
+            .min_by(|(_, a), (_, b)| cmp(a, b))
+            .map(|(i, _)| i)?;
+        sorted[next].next()
+    })
+}
@@ -0,0 +1,14 @@
+//! Sharded mode, where each shard server holds the records for its share of the keys
+//! on a consistent-hash ring. Routers send each read and write to the shard that owns
+//! its key and ask every shard for queries. Resharding moves keys while the shards keep
+//! serving: a shard that no longer holds a key sends its router to the new owner, and a
+//! router on an old ring is sent the newer one
+
+mod ring;
+mod router;
+mod server;
+mod wire;
+
+pub use ring::Ring;
+pub use router::ShardRouter;
+pub use server::ShardServer;
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use crate::shard::hash;
+
+/// Points each shard gets on the ring. More spread the keys more evenly, and make
+/// a shard that's added take a little from every other instead of a lot from one
+const POINTS: u64 = 64;
+/// Keeps where keys land on the ring independent of which in-process shard holds them
+const KEY_SEED: u64 = 0x0053_4841_5244;
+
+/// Which shard server owns each key: the one whose point on the ring comes first at
+/// or after the key's hash, wrapping around. Shards are named by the address they're
+/// reached at, and each change to them makes a ring with a higher version
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ring {
+    version: u64,
+    shards: Vec<String>,
+    /// Sorted by hash, with the index of the shard each belongs to
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    pub(crate) fn new(version: u64, shards: impl IntoIterator<Item = String>) -> Self {
+        let mut shards = shards.into_iter().collect::<Vec<_>>();
+        shards.sort();
+        shards.dedup();
+        let mut points = shards
+            .iter()
+            .enumerate()
+            .flat_map(|(i, shard)| {
+                (0..POINTS).map(move |point| (spread(shard.as_bytes(), point), i))
+            })
+            .collect::<Vec<_>>();
+        points.sort_unstable();
+        Self {
+            version,
+            shards,
+            points,
+        }
+    }
+
+    /// Zero for a shard server that isn't part of a ring yet
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn shards(&self) -> &[String] {
+        &self.shards
+    }
+
+    pub fn contains(&self, shard: &str) -> bool {
+        self.shards.iter().any(|s| s == shard)
+    }
+
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        let hash = spread(key.as_bytes(), KEY_SEED);
+        let at = self.points.partition_point(|(point, _)| *point < hash);
+        let (_, shard) = self.points.get(at).or(self.points.first())?;
+        Some(&self.shards[*shard])
+    }
+
+    /// The next version, with `shard` added
+    pub(crate) fn with(&self, shard: &str) -> Self {
+        Self::new(
+            self.version + 1,
+            self.shards.iter().cloned().chain([shard.to_owned()]),
+        )
+    }
+
+    /// The next version, without `shard`
+    pub(crate) fn without(&self, shard: &str) -> Self {
+        Self::new(
+            self.version + 1,
+            self.shards.iter().filter(|s| *s != shard).cloned(),
+        )
+    }
+}
+
+/// `hash`, with its bits mixed like SplitMix64 does. FNV-1a alone barely moves the high
+/// bits for names that differ in their last bytes, like addresses on consecutive ports,
+/// which would bunch their points together on the ring
+fn spread(bytes: &[u8], seed: u64) -> u64 {
+    let mut x = hash(bytes, seed);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl fmt::Display for Ring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.shards.is_empty() {
+            return write!(f, "No shards");
+        }
+        write!(
+            f,
+            "Ring version {}: {}",
+            self.version,
+            self.shards.join(", ")
+        )
+    }
+}
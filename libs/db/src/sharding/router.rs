@@ -0,0 +1,569 @@
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use super::wire::{Connection, Request, Response, Route};
+use super::Ring;
+use crate::db::Indexes;
+use crate::shard::merge;
+use crate::{DBTypes, Entry, Mutation, Outcome, Query, ShardError};
+
+/// How many times a request for a key follows shards sending it elsewhere
+const REDIRECTS: usize = 50;
+/// How long to wait before trying again when a shard sends a request back to where
+/// it came from, as happens while the shards switch to a new ring
+const RETRY: Duration = Duration::from_millis(20);
+
+/// Sends reads and writes to the shard servers that own their keys, and queries to
+/// every shard, merging what they find. Routers learn of each other's resharding from
+/// the shards, so any number can share the same shards
+#[derive(Debug)]
+pub struct ShardRouter {
+    ring: RwLock<Arc<Ring>>,
+    /// One per shard, for one request at a time
+    connections: Mutex<BTreeMap<String, Arc<Mutex<Option<Connection>>>>>,
+}
+
+impl ShardRouter {
+    /// Routes by the ring the shards at `seeds` have, or starts one with just them if
+    /// none of them have a ring yet
+    pub fn connect<S: AsRef<str>>(seeds: &[S]) -> Result<Self, ShardError> {
+        if seeds.is_empty() {
+            return Err(ShardError::NoShards);
+        }
+        let router = Self {
+            ring: RwLock::default(),
+            connections: Mutex::default(),
+        };
+        let mut newest = Ring::default();
+        for seed in seeds {
+            let seed = seed.as_ref();
+            match router.call(seed, &Request::Ring)? {
+                Response::Ring { ring, .. } if ring.version() > newest.version() => newest = ring,
+                Response::Ring { .. } => {}
+                response => return Err(unexpected(seed, response)),
+            }
+        }
+        if newest.version() == 0 {
+            newest = Ring::new(1, seeds.iter().map(|seed| seed.as_ref().to_owned()));
+            router.set_ring(&newest, newest.shards())?;
+        }
+        *router.ring.write().unwrap() = Arc::new(newest);
+        Ok(router)
+    }
+
+    /// The ring requests are routed by
+    pub fn ring(&self) -> Ring {
+        (**self.ring.read().unwrap()).clone()
+    }
+
+    /// The shards that are part way through moving to a new ring, with it
+    pub fn resharding(&self) -> Result<Vec<(String, Ring)>, ShardError> {
+        let mut resharding = vec![];
+        for shard in self.ring().shards() {
+            match self.call(shard, &Request::Ring)? {
+                Response::Ring {
+                    migrating: Some(target),
+                    ..
+                } => resharding.push((shard.clone(), target)),
+                Response::Ring { .. } => {}
+                response => return Err(unexpected(shard, response)),
+            }
+        }
+        Ok(resharding)
+    }
+
+    fn adopt(&self, ring: Ring) -> bool {
+        let mut current = self.ring.write().unwrap();
+        let newer = ring.version() > current.version();
+        if newer {
+            *current = Arc::new(ring);
+        }
+        newer
+    }
+
+    fn call(&self, addr: &str, request: &Request) -> Result<Response, ShardError> {
+        self.call_with(addr, request, true)
+    }
+
+    fn call_with(
+        &self,
+        addr: &str,
+        request: &Request,
+        timeout: bool,
+    ) -> Result<Response, ShardError> {
+        let slot = self
+            .connections
+            .lock()
+            .unwrap()
+            .entry(addr.to_owned())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().unwrap();
+        let unreachable = |e: std::io::Error| ShardError::Unreachable {
+            addr: addr.to_owned(),
+            reason: e.to_string(),
+        };
+        let connection = match &mut *slot {
+            Some(connection) if connection.is_open() => connection,
+            _ => slot.insert(Connection::open(addr).map_err(unreachable)?),
+        };
+        match connection.call(request, timeout) {
+            Ok(Response::Failed {
+                database: true,
+                message,
+            }) => Err(ShardError::Database(message)),
+            Ok(Response::Failed {
+                database: false,
+                message,
+            }) => Err(ShardError::Refused {
+                addr: addr.to_owned(),
+                reason: message,
+            }),
+            Ok(response) => Ok(response),
+            Err(e) => {
+                // What was sent may or may not have been carried out
+                *slot = None;
+                Err(unreachable(e))
+            }
+        }
+    }
+
+    /// Sends the request for `key` to its shard, and on to wherever that sends it
+    fn route(&self, key: &str, request: impl Fn(Route) -> Request) -> Result<Response, ShardError> {
+        let mut sent_on = None;
+        for _ in 0..REDIRECTS {
+            let ring = self.ring.read().unwrap().clone();
+            let (shard, asking) = match sent_on.take() {
+                Some(shard) => (shard, true),
+                None => (
+                    ring.owner(key).ok_or(ShardError::NoShards)?.to_owned(),
+                    false,
+                ),
+            };
+            let route = Route {
+                version: ring.version(),
+                asking,
+            };
+            match self.call(&shard, &request(route))? {
+                Response::Moved(moved) => {
+                    if !self.adopt(moved) {
+                        thread::sleep(RETRY);
+                    }
+                }
+                Response::Ask(shard) => sent_on = Some(shard),
+                response => return Ok(response),
+            }
+        }
+        Err(ShardError::Redirected {
+            key: key.to_owned(),
+        })
+    }
+
+    /// Sends one request per shard for the keys each owns, taking the keys of any shard
+    /// that sends its request elsewhere one at a time. `results` turns each response into
+    /// one result per key
+    fn route_many<T>(
+        &self,
+        keys: &[String],
+        request: impl Fn(&[usize], Route) -> Request,
+        results: impl Fn(&str, Response) -> Result<Vec<T>, ShardError>,
+    ) -> Result<Vec<T>, ShardError> {
+        let ring = self.ring.read().unwrap().clone();
+        let mut by_shard = BTreeMap::<&str, Vec<usize>>::new();
+        for (at, key) in keys.iter().enumerate() {
+            let shard = ring.owner(key).ok_or(ShardError::NoShards)?;
+            by_shard.entry(shard).or_default().push(at);
+        }
+        let route = Route {
+            version: ring.version(),
+            asking: false,
+        };
+
+        let mut found = (0..keys.len()).map(|_| None).collect::<Vec<_>>();
+        for (shard, positions) in by_shard {
+            let response = self.call(shard, &request(&positions, route))?;
+            let positions = match response {
+                Response::Moved(_) | Response::Ask(_) => {
+                    for at in positions {
+                        let response = self.route(&keys[at], |route| request(&[at], route))?;
+                        let mut result = results(shard, response)?;
+                        found[at] = result.pop();
+                    }
+                    continue;
+                }
+                response => positions.into_iter().zip(results(shard, response)?),
+            };
+            for (at, result) in positions {
+                found[at] = Some(result);
+            }
+        }
+        Ok(found
+            .into_iter()
+            .map(|result| result.expect("every key gets a result"))
+            .collect())
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<DBTypes>, ShardError> {
+        Ok(self.get_many(&[key.to_owned()])?.pop().flatten())
+    }
+
+    pub fn get_many(&self, keys: &[String]) -> Result<Vec<Option<DBTypes>>, ShardError> {
+        self.route_many(
+            keys,
+            |positions, route| Request::Get {
+                keys: positions.iter().map(|at| keys[*at].clone()).collect(),
+                route,
+            },
+            |shard, response| match response {
+                Response::Values(values) => Ok(values),
+                response => Err(unexpected(shard, response)),
+            },
+        )
+    }
+
+    pub fn exists(&self, key: &str) -> Result<bool, ShardError> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Makes the write on the shard that owns its key, or for `PutMany` and `RemoveMany`
+    /// on each shard that owns some of them
+    pub fn write(&self, mutation: Mutation) -> Result<Outcome, ShardError> {
+        let outcome = |shard: &str, response| match response {
+            Response::Outcome(outcome) => Ok(outcome),
+            response => Err(unexpected(shard, response)),
+        };
+        let values = |shard: &str, response| match outcome(shard, response)? {
+            Outcome::Values(values) => Ok(values),
+            other => Err(unexpected(shard, Response::Outcome(other))),
+        };
+        match mutation {
+            Mutation::PutMany(entries) => {
+                let keys = entries
+                    .iter()
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<_>>();
+                let previous = self.route_many(
+                    &keys,
+                    |positions, route| Request::Write {
+                        mutation: Mutation::PutMany(
+                            positions.iter().map(|at| entries[*at].clone()).collect(),
+                        ),
+                        route,
+                    },
+                    values,
+                )?;
+                Ok(Outcome::Values(previous))
+            }
+            Mutation::RemoveMany(keys) => {
+                let removed = self.route_many(
+                    &keys,
+                    |positions, route| Request::Write {
+                        mutation: Mutation::RemoveMany(
+                            positions.iter().map(|at| keys[*at].clone()).collect(),
+                        ),
+                        route,
+                    },
+                    values,
+                )?;
+                Ok(Outcome::Values(removed))
+            }
+            mutation => {
+                let key = mutation
+                    .key()
+                    .expect("other writes have one key")
+                    .to_owned();
+                let response = self.route(&key, |route| Request::Write {
+                    mutation: mutation.clone(),
+                    route,
+                })?;
+                outcome(&key, response)
+            }
+        }
+    }
+
+    /// Asks every shard at once, in no particular order
+    fn scatter<T: Send>(
+        &self,
+        request: &Request,
+        results: impl Fn(&str, Response) -> Result<T, ShardError> + Sync,
+    ) -> Result<Vec<T>, ShardError> {
+        let ring = self.ring();
+        thread::scope(|scope| {
+            let asked = ring
+                .shards()
+                .iter()
+                .map(|shard| {
+                    let results = &results;
+                    scope.spawn(move || results(shard, self.call(shard, request)?))
+                })
+                .collect::<Vec<_>>();
+            asked
+                .into_iter()
+                .map(|thread| thread.join().expect("asking a shard doesn't panic"))
+                .collect()
+        })
+    }
+
+    /// Every key starting with `prefix`, in order. A key that moves while this runs may
+    /// be missed
+    pub fn keys(&self, prefix: &str) -> Result<Vec<String>, ShardError> {
+        let request = Request::Keys {
+            prefix: prefix.to_owned(),
+        };
+        let found = self.scatter(&request, |shard, response| match response {
+            Response::Keys(keys) => Ok(keys),
+            response => Err(unexpected(shard, response)),
+        })?;
+        let mut keys =
+            merge(found.into_iter().map(Vec::into_iter), String::cmp).collect::<Vec<_>>();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Visits every entry matching `query` on any shard, in key order. Each shard runs
+    /// the query with its own indexes, which the entries don't carry here. A key found
+    /// on two shards while it moves between them is taken from its owner on the ring
+    pub fn scan<F>(&self, query: &Query, mut f: F) -> Result<(), ShardError>
+    where
+        F: FnMut(Entry<'_>) -> ControlFlow<()>,
+    {
+        let request = Request::Scan {
+            query: query.clone(),
+        };
+        let ring = self.ring();
+        let found = self.scatter(&request, |shard, response| match response {
+            Response::Entries(entries) => Ok(entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let owned = ring.owner(&key) == Some(shard);
+                    (key, value, owned)
+                })
+                .collect::<Vec<_>>()),
+            response => Err(unexpected(shard, response)),
+        })?;
+        let indexes = Indexes::new();
+        let mut entries = merge(found.into_iter().map(Vec::into_iter), |(a, ..), (b, ..)| {
+            a.cmp(b)
+        })
+        .peekable();
+        while let Some((key, mut value, mut owned)) = entries.next() {
+            while let Some((_, other, other_owned)) = entries.next_if(|(next, ..)| *next == key) {
+                if other_owned && !owned {
+                    (value, owned) = (other, true);
+                }
+            }
+            let entry = Entry {
+                key: &key,
+                value: &value,
+                indexes: &indexes,
+            };
+            if f(entry).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the shard server at `addr` and moves it the keys it owns on the new ring,
+    /// returning how many moved. Reads and writes carry on meanwhile
+    pub fn add_shard(&self, addr: &str) -> Result<u64, ShardError> {
+        let ring = self.ring();
+        if ring.contains(addr) {
+            return Err(ShardError::AlreadyAShard(addr.to_owned()));
+        }
+        self.reshard(&ring, ring.with(addr))
+    }
+
+    /// Moves the keys of the shard server at `addr` to the others and drops it from
+    /// the ring, returning how many moved
+    pub fn remove_shard(&self, addr: &str) -> Result<u64, ShardError> {
+        let ring = self.ring();
+        if !ring.contains(addr) {
+            return Err(ShardError::NotAShard(addr.to_owned()));
+        }
+        let target = ring.without(addr);
+        if target.shards().is_empty() {
+            return Err(ShardError::NoShards);
+        }
+        self.reshard(&ring, target)
+    }
+
+    /// Tells every shard of either ring to move to `target`, has the ones that lose keys
+    /// send them on, and then has every shard switch. If it fails part way, doing it
+    /// again carries on where it stopped
+    fn reshard(&self, ring: &Ring, target: Ring) -> Result<u64, ShardError> {
+        // Shards that gain keys are ready for them before any are moved
+        let mut shards = target
+            .shards()
+            .iter()
+            .filter(|shard| !ring.contains(shard))
+            .chain(target.shards().iter().filter(|shard| ring.contains(shard)))
+            .chain(ring.shards().iter().filter(|shard| !target.contains(shard)))
+            .cloned()
+            .collect::<Vec<_>>();
+        for shard in &shards {
+            let prepare = Request::Prepare {
+                ring: target.clone(),
+                addr: shard.clone(),
+            };
+            match self.call(shard, &prepare)? {
+                Response::Done => {}
+                response => return Err(unexpected(shard, response)),
+            }
+        }
+        let mut moved = 0;
+        for shard in ring.shards() {
+            match self.call_with(shard, &Request::Migrate, false)? {
+                Response::Migrated(count) => moved += count,
+                response => return Err(unexpected(shard, response)),
+            }
+        }
+        shards.sort();
+        self.set_ring(&target, &shards)?;
+        self.adopt(target);
+        Ok(moved)
+    }
+
+    fn set_ring(&self, ring: &Ring, shards: &[String]) -> Result<(), ShardError> {
+        for shard in shards {
+            let set = Request::SetRing {
+                ring: ring.clone(),
+                addr: shard.clone(),
+            };
+            match self.call(shard, &set)? {
+                Response::Done => {}
+                response => return Err(unexpected(shard, response)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unexpected(shard: &str, response: Response) -> ShardError {
+    ShardError::Refused {
+        addr: shard.to_owned(),
+        reason: format!("Unexpected response {response:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataDir, Database, ShardServer};
+
+    fn shards(test: &str, count: usize) -> Vec<(Database, ShardServer)> {
+        let dir = std::env::temp_dir().join(format!("hoya-router-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        (0..count)
+            .map(|i| {
+                let db = Database::default()
+                    .with_data_dir(DataDir::new(dir.join(i.to_string())).unwrap());
+                let server = db.serve_shard("127.0.0.1:0").unwrap();
+                (db, server)
+            })
+            .collect()
+    }
+
+    fn addr(server: &ShardServer) -> String {
+        server.local_addr().to_string()
+    }
+
+    fn put(router: &ShardRouter, key: &str, value: isize) {
+        router
+            .write(Mutation::Put {
+                key: key.to_owned(),
+                value: DBTypes::Number(value),
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn scan_takes_a_key_found_twice_from_its_owner() {
+        let shards = shards("scan", 3);
+        let addrs = shards.iter().map(|(_, s)| addr(s)).collect::<Vec<_>>();
+        let router = ShardRouter::connect(&addrs).unwrap();
+        for i in 0..100 {
+            put(&router, &format!("key{i:03}"), i);
+        }
+        // As a move part way through would leave them, with stale copies elsewhere
+        let ring = router.ring();
+        for i in 0..100 {
+            let key = format!("key{i:03}");
+            for shard in ring.shards() {
+                if ring.owner(&key) != Some(shard) {
+                    let entries = vec![(key.clone(), DBTypes::Number(-1))];
+                    router.call(shard, &Request::Import { entries }).unwrap();
+                }
+            }
+        }
+
+        let mut found = vec![];
+        router
+            .scan(&Query::default(), |entry| {
+                found.push((entry.key.to_owned(), entry.value.clone()));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        let expected = (0..100)
+            .map(|i| (format!("key{i:03}"), DBTypes::Number(i)))
+            .collect::<Vec<_>>();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn a_half_finished_reshard_carries_on_when_run_again() {
+        let shards = shards("resume", 3);
+        let addrs = shards.iter().map(|(_, s)| addr(s)).collect::<Vec<_>>();
+        let router = ShardRouter::connect(&addrs[..2]).unwrap();
+        for i in 0..2000 {
+            put(&router, &format!("key{i:04}"), i);
+        }
+
+        // Stop as if the router failed after the first shard sent its keys on
+        let ring = router.ring();
+        let target = ring.with(&addrs[2]);
+        for shard in [&addrs[2], &addrs[0], &addrs[1]] {
+            let prepare = Request::Prepare {
+                ring: target.clone(),
+                addr: shard.clone(),
+            };
+            assert!(matches!(router.call(shard, &prepare), Ok(Response::Done)));
+        }
+        let first = match router.call_with(&addrs[0], &Request::Migrate, false) {
+            Ok(Response::Migrated(moved)) => moved,
+            response => panic!("expected the keys to move, found {response:?}"),
+        };
+        assert!(first > 0);
+        let resharding = router.resharding().unwrap();
+        assert_eq!(resharding.len(), ring.shards().len());
+        assert!(resharding.iter().all(|(_, ring)| *ring == target));
+
+        // Keys are still served while it's stuck, wherever they are
+        put(&router, "during", -1);
+        for i in (0..2000).step_by(7) {
+            let key = format!("key{i:04}");
+            assert_eq!(router.get(&key).unwrap(), Some(DBTypes::Number(i)), "{key}");
+        }
+
+        let second = router.add_shard(&addrs[2]).unwrap();
+        assert!(router.resharding().unwrap().is_empty());
+        assert_eq!(router.ring(), target);
+        let moved = shards[2].0.count(&Query::default()) as u64;
+        let during = u64::from(target.owner("during") == Some(addrs[2].as_str()));
+        assert_eq!(first + second + during, moved);
+        for (i, (db, _)) in shards.iter().enumerate() {
+            db.scan(&Query::default(), |entry| {
+                assert_eq!(target.owner(entry.key), Some(addrs[i].as_str()));
+                ControlFlow::Continue(())
+            });
+        }
+        for i in 0..2000 {
+            let key = format!("key{i:04}");
+            assert_eq!(router.get(&key).unwrap(), Some(DBTypes::Number(i)), "{key}");
+        }
+        assert_eq!(router.get("during").unwrap(), Some(DBTypes::Number(-1)));
+    }
+}
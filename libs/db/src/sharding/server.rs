@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::wire::{self, Connection, Request, Response, Route};
+use super::Ring;
+use crate::snapshot::replace_file;
+use crate::snapshot::schema::{read_text, read_u8, write_text};
+use crate::{DBTypes, Database, Query};
+
+/// How often the listener checks whether it should stop
+const POLL: Duration = Duration::from_millis(50);
+/// Records moved to another shard at a time. Writes to them wait meanwhile
+const BATCH: usize = 1024;
+
+/// Kept in the data directory, so a restarted shard knows which keys are its own
+const PLACEMENT: &str = "shard-ring";
+const MAGIC: &[u8; 8] = b"HOYARING";
+const VERSION: u8 = 1;
+
+/// Serves a share of the records to routers until dropped
+#[derive(Debug)]
+pub struct ShardServer {
+    addr: SocketAddr,
+    serving: Arc<Serving>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ShardServer {
+    /// Where routers connect, with the port the system picked if it was 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The ring this shard serves keys by
+    pub fn ring(&self) -> Ring {
+        self.serving.placement.lock().unwrap().ring.clone()
+    }
+}
+
+impl Drop for ShardServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Placement {
+    /// This shard's name on the ring, as the routers reach it
+    me: Option<String>,
+    ring: Ring,
+    /// The ring records are being moved to, while resharding
+    migrating: Option<Ring>,
+}
+
+/// Whether a key is served here
+enum Disposition {
+    Serve,
+    Ask(String),
+    Moved,
+}
+
+#[derive(Debug)]
+struct Serving {
+    db: Database,
+    placement: Mutex<Placement>,
+    /// Held to serve keys, and taken exclusively to move them, so a key is only ever
+    /// served by one shard
+    gate: RwLock<()>,
+    path: PathBuf,
+}
+
+impl Database {
+    /// Serves the records to shard routers on `addr`, for the keys the ring gives this
+    /// shard. A new shard has no ring until a router starts one or adds it to one
+    pub fn serve_shard(&self, addr: impl ToSocketAddrs) -> io::Result<ShardServer> {
+        let path = self
+            .data_dir()
+            .resolve(PLACEMENT)
+            .map_err(io::Error::other)?;
+        let placement = match File::open(&path) {
+            Ok(file) => read_placement(&mut BufReader::new(file))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Placement::default(),
+            Err(e) => return Err(e),
+        };
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+
+        let serving = Arc::new(Serving {
+            db: self.clone(),
+            placement: Mutex::new(placement),
+            gate: RwLock::new(()),
+            path,
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (serving, stop) = (serving.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut connections: Vec<(TcpStream, JoinHandle<()>)> = vec![];
+                while !stop.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let Ok(clone) = stream.try_clone() else {
+                                continue;
+                            };
+                            let serving = serving.clone();
+                            let thread = thread::spawn(move || {
+                                let _ = serving.serve(stream);
+                            });
+                            connections.push((clone, thread));
+                        }
+                        Err(_) => thread::sleep(POLL),
+                    }
+                    connections.retain(|(_, thread)| !thread.is_finished());
+                }
+                // Routers keep connections open, so they're closed from this end
+                for (stream, thread) in connections {
+                    let _ = stream.shutdown(Shutdown::Both);
+                    let _ = thread.join();
+                }
+            })
+        };
+
+        Ok(ShardServer {
+            addr,
+            serving,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+fn read_placement(input: &mut dyn Read) -> io::Result<Placement> {
+    let mut magic = [0; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u8(input)? != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("`{PLACEMENT}` isn't a HoyaDB shard ring"),
+        ));
+    }
+    Ok(Placement {
+        me: Some(read_text(input)?),
+        ring: wire::read_ring(input)?,
+        migrating: None,
+    })
+}
+
+impl Serving {
+    /// Answers one router's requests until it disconnects
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        let mut input = BufReader::new(stream.try_clone()?);
+        let mut out = BufWriter::new(stream);
+        wire::read_hello(&mut input)?;
+        loop {
+            let request = match wire::read_request(&mut input) {
+                Ok(request) => request,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            wire::write_response(&mut out, &self.handle(request))?;
+            out.flush()?;
+        }
+    }
+
+    fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Ring => {
+                let placement = self.placement.lock().unwrap();
+                Response::Ring {
+                    ring: placement.ring.clone(),
+                    migrating: placement.migrating.clone(),
+                }
+            }
+            Request::Get { keys, route } => {
+                let _gate = self.gate.read().unwrap();
+                match self.route(&keys, route) {
+                    Some(redirect) => redirect,
+                    None => Response::Values(self.db.get_many(&keys)),
+                }
+            }
+            Request::Write { mutation, route } => {
+                let keys = mutation.keys();
+                let _gate = self.gate.read().unwrap();
+                if let Some(redirect) = self.route(&keys, route) {
+                    return redirect;
+                }
+                match mutation.apply(&self.db) {
+                    Ok(outcome) => Response::Outcome(outcome),
+                    Err(e) => Response::Failed {
+                        database: true,
+                        message: e.to_string(),
+                    },
+                }
+            }
+            Request::Keys { prefix } => {
+                let mut keys = vec![];
+                self.db.scan(&Query::new(Some(prefix)), |entry| {
+                    keys.push(entry.key.to_owned());
+                    ControlFlow::Continue(())
+                });
+                Response::Keys(keys)
+            }
+            Request::Scan { query } => {
+                let mut entries = vec![];
+                self.db.scan(&query, |entry| {
+                    entries.push((entry.key.to_owned(), entry.value.clone()));
+                    ControlFlow::Continue(())
+                });
+                Response::Entries(entries)
+            }
+            Request::Prepare { ring, addr } => self.prepare(ring, addr),
+            Request::Migrate => match self.migrate() {
+                Ok(moved) => Response::Migrated(moved),
+                Err(message) => Response::Failed {
+                    database: false,
+                    message,
+                },
+            },
+            Request::Import { entries } => match self.db.put_many(entries) {
+                Ok(_) => Response::Done,
+                Err(e) => Response::Failed {
+                    database: true,
+                    message: e.to_string(),
+                },
+            },
+            Request::SetRing { ring, addr } => self.set_ring(ring, addr),
+        }
+    }
+
+    /// Where to send a request for `keys` instead, unless every one is served here
+    fn route(&self, keys: &[String], route: Route) -> Option<Response> {
+        let placement = self.placement.lock().unwrap();
+        for key in keys {
+            match self.disposition(&placement, key, route) {
+                Disposition::Serve => {}
+                Disposition::Ask(addr) => return Some(Response::Ask(addr)),
+                Disposition::Moved => return Some(Response::Moved(placement.ring.clone())),
+            }
+        }
+        None
+    }
+
+    fn disposition(&self, placement: &Placement, key: &str, route: Route) -> Disposition {
+        let Some(me) = &placement.me else {
+            return Disposition::Moved;
+        };
+        if let Some(target) = &placement.migrating {
+            // Routers only use the new ring once every key has moved
+            if (route.asking || route.version == target.version()) && target.owner(key) == Some(me)
+            {
+                return Disposition::Serve;
+            }
+        }
+        if placement.ring.owner(key) != Some(me) {
+            return Disposition::Moved;
+        }
+        match placement
+            .migrating
+            .as_ref()
+            .and_then(|target| target.owner(key))
+        {
+            // Moved already, or written there since it doesn't exist here
+            Some(owner) if owner != me && !self.db.exists(key) => {
+                Disposition::Ask(owner.to_owned())
+            }
+            _ => Disposition::Serve,
+        }
+    }
+
+    fn prepare(&self, ring: Ring, addr: String) -> Response {
+        let mut placement = self.placement.lock().unwrap();
+        let refuse = |message: String| Response::Failed {
+            database: false,
+            message,
+        };
+        if ring.version() <= placement.ring.version() {
+            return refuse(format!(
+                "This shard is at ring version {} already",
+                placement.ring.version()
+            ));
+        }
+        match &placement.migrating {
+            Some(target) if *target != ring => refuse(format!(
+                "This shard is resharding to ring version {} already",
+                target.version()
+            )),
+            _ => {
+                placement.me = Some(addr);
+                placement.migrating = Some(ring);
+                Response::Done
+            }
+        }
+    }
+
+    /// Sends every record the new ring gives to another shard there, a batch at a time
+    fn migrate(&self) -> Result<u64, String> {
+        let (me, target) = {
+            let placement = self.placement.lock().unwrap();
+            match (&placement.me, &placement.migrating) {
+                (Some(me), Some(target)) => (me.clone(), target.clone()),
+                _ => return Err(String::from("This shard isn't resharding")),
+            }
+        };
+        // Keys that move and don't exist yet are written at their new owner from now
+        // on, so they can only be among these
+        let mut moving = vec![];
+        self.db.scan(&Query::default(), |entry| {
+            if target.owner(entry.key) != Some(&me) {
+                moving.push(entry.key.to_owned());
+            }
+            ControlFlow::Continue(())
+        });
+
+        let mut connections = BTreeMap::<String, Connection>::new();
+        let mut moved = 0;
+        for keys in moving.chunks(BATCH) {
+            let _gate = self.gate.write().unwrap();
+            let mut batches = BTreeMap::<&str, Vec<(String, DBTypes)>>::new();
+            for (key, value) in keys.iter().zip(self.db.get_many(keys)) {
+                let (Some(value), Some(owner)) = (value, target.owner(key)) else {
+                    continue;
+                };
+                batches.entry(owner).or_default().push((key.clone(), value));
+            }
+            for (owner, entries) in batches {
+                let count = entries.len() as u64;
+                let connection = match connections.get_mut(owner) {
+                    Some(connection) => connection,
+                    None => {
+                        let connection = Connection::open(owner)
+                            .map_err(|e| format!("Couldn't reach `{owner}`: {e}"))?;
+                        connections.entry(owner.to_owned()).or_insert(connection)
+                    }
+                };
+                match connection.call(&Request::Import { entries }, true) {
+                    Ok(Response::Done) => moved += count,
+                    Ok(_) => return Err(format!("`{owner}` didn't take the records")),
+                    Err(e) => return Err(format!("Couldn't send records to `{owner}`: {e}")),
+                }
+            }
+            self.db
+                .remove_many(keys)
+                .map_err(|e| format!("Couldn't drop the records that moved: {e}"))?;
+        }
+        Ok(moved)
+    }
+
+    fn set_ring(&self, ring: Ring, addr: String) -> Response {
+        let mut placement = self.placement.lock().unwrap();
+        if ring.version() >= placement.ring.version() {
+            placement.me = Some(addr);
+            placement.ring = ring;
+            if placement
+                .migrating
+                .as_ref()
+                .is_some_and(|target| target.version() <= placement.ring.version())
+            {
+                placement.migrating = None;
+            }
+        }
+        match self.keep(&placement) {
+            Ok(()) => Response::Done,
+            Err(e) => Response::Failed {
+                database: false,
+                message: format!("Couldn't keep the ring: {e}"),
+            },
+        }
+    }
+
+    fn keep(&self, placement: &Placement) -> io::Result<()> {
+        replace_file(&self.path, |file| {
+            let mut out = BufWriter::new(file);
+            out.write_all(MAGIC)?;
+            out.write_all(&[VERSION])?;
+            write_text(&mut out, placement.me.as_deref().unwrap_or_default())?;
+            wire::write_ring(&mut out, &placement.ring)?;
+            out.flush()?;
+            out.get_ref().sync_all()
+        })
+    }
+}
@@ -0,0 +1,487 @@
+//! What routers and shard servers send each other. A router opens a connection with a
+//! hello and then makes one request at a time over it, each answered by one response.
+//! Shard servers use the same requests to hand each other records while resharding
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::Ring;
+use crate::raft::{read_mutation, write_mutation};
+use crate::snapshot::schema::{
+    read_f64, read_text, read_u64, read_u8, read_value, write_text, write_value,
+};
+use crate::{Comparison, DBTypes, Field, Mutation, Outcome, Predicate, Query};
+
+const HELLO: &[u8; 8] = b"HOYASHRD";
+
+/// How long connecting may take, and how long a request may wait for its response
+/// unless it says otherwise
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which ring a request for keys was routed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Route {
+    pub(crate) version: u64,
+    /// Sent where a shard said to ask while it moves the keys there
+    pub(crate) asking: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Request {
+    Ring,
+    Get {
+        keys: Vec<String>,
+        route: Route,
+    },
+    Write {
+        mutation: Mutation,
+        route: Route,
+    },
+    Keys {
+        prefix: String,
+    },
+    Scan {
+        query: Query,
+    },
+    /// Starts moving to `ring`, telling the shard its name there
+    Prepare {
+        ring: Ring,
+        addr: String,
+    },
+    /// Sends on the records that `ring` from `Prepare` gives to other shards
+    Migrate,
+    /// Records sent on by another shard
+    Import {
+        entries: Vec<(String, DBTypes)>,
+    },
+    /// Finishes moving to `ring`
+    SetRing {
+        ring: Ring,
+        addr: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Response {
+    Ring {
+        ring: Ring,
+        /// The ring the shard is moving records to, while resharding
+        migrating: Option<Ring>,
+    },
+    Values(Vec<Option<DBTypes>>),
+    Outcome(Outcome),
+    Keys(Vec<String>),
+    Entries(Vec<(String, DBTypes)>),
+    Done,
+    Migrated(u64),
+    /// The keys belong to another shard, as of this ring
+    Moved(Ring),
+    /// The keys are being moved to the shard at this address, and aren't here anymore
+    Ask(String),
+    Failed {
+        /// Whether the records refused the request, rather than the shard
+        database: bool,
+        message: String,
+    },
+}
+
+// Request tags
+const RING: u8 = 1;
+const GET: u8 = 2;
+const WRITE: u8 = 3;
+const KEYS: u8 = 4;
+const SCAN: u8 = 5;
+const PREPARE: u8 = 6;
+const MIGRATE: u8 = 7;
+const IMPORT: u8 = 8;
+const SET_RING: u8 = 9;
+
+// Response tags
+const VALUES: u8 = 2;
+const OUTCOME: u8 = 3;
+const ENTRIES: u8 = 5;
+const DONE: u8 = 6;
+const MIGRATED: u8 = 7;
+const MOVED: u8 = 10;
+const ASK: u8 = 11;
+const FAILED: u8 = 12;
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_owned())
+}
+
+fn write_u64(out: &mut dyn Write, number: u64) -> io::Result<()> {
+    out.write_all(&number.to_le_bytes())
+}
+
+fn write_texts(out: &mut dyn Write, texts: &[String]) -> io::Result<()> {
+    write_u64(out, texts.len() as u64)?;
+    texts.iter().try_for_each(|text| write_text(out, text))
+}
+
+fn read_texts(input: &mut dyn Read) -> io::Result<Vec<String>> {
+    (0..read_u64(input)?).map(|_| read_text(input)).collect()
+}
+
+fn write_option(out: &mut dyn Write, value: Option<&DBTypes>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            out.write_all(&[1])?;
+            write_value(out, value)
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+fn read_option(input: &mut dyn Read) -> io::Result<Option<DBTypes>> {
+    Ok(match read_u8(input)? {
+        0 => None,
+        _ => Some(read_value(input)?),
+    })
+}
+
+fn write_values(out: &mut dyn Write, values: &[Option<DBTypes>]) -> io::Result<()> {
+    write_u64(out, values.len() as u64)?;
+    values
+        .iter()
+        .try_for_each(|value| write_option(out, value.as_ref()))
+}
+
+fn read_values(input: &mut dyn Read) -> io::Result<Vec<Option<DBTypes>>> {
+    (0..read_u64(input)?).map(|_| read_option(input)).collect()
+}
+
+fn write_entries(out: &mut dyn Write, entries: &[(String, DBTypes)]) -> io::Result<()> {
+    write_u64(out, entries.len() as u64)?;
+    entries.iter().try_for_each(|(key, value)| {
+        write_text(out, key)?;
+        write_value(out, value)
+    })
+}
+
+fn read_entries(input: &mut dyn Read) -> io::Result<Vec<(String, DBTypes)>> {
+    (0..read_u64(input)?)
+        .map(|_| Ok((read_text(input)?, read_value(input)?)))
+        .collect()
+}
+
+pub(crate) fn write_ring(out: &mut dyn Write, ring: &Ring) -> io::Result<()> {
+    write_u64(out, ring.version())?;
+    write_texts(out, ring.shards())
+}
+
+pub(crate) fn read_ring(input: &mut dyn Read) -> io::Result<Ring> {
+    let version = read_u64(input)?;
+    Ok(Ring::new(version, read_texts(input)?))
+}
+
+fn write_route(out: &mut dyn Write, route: Route) -> io::Result<()> {
+    write_u64(out, route.version)?;
+    out.write_all(&[u8::from(route.asking)])
+}
+
+fn read_route(input: &mut dyn Read) -> io::Result<Route> {
+    Ok(Route {
+        version: read_u64(input)?,
+        asking: read_u8(input)? != 0,
+    })
+}
+
+fn write_query(out: &mut dyn Write, query: &Query) -> io::Result<()> {
+    match &query.prefix {
+        Some(prefix) => {
+            out.write_all(&[1])?;
+            write_text(out, prefix)?;
+        }
+        None => out.write_all(&[0])?,
+    }
+    write_u64(out, query.predicates.len() as u64)?;
+    for predicate in &query.predicates {
+        match &predicate.field {
+            Field::Key => out.write_all(&[0])?,
+            Field::Value => out.write_all(&[1])?,
+            Field::Index(name) => {
+                out.write_all(&[2])?;
+                write_text(out, name)?;
+            }
+        }
+        out.write_all(&[match predicate.op {
+            Comparison::Eq => 0,
+            Comparison::Ne => 1,
+            Comparison::Lt => 2,
+            Comparison::Le => 3,
+            Comparison::Gt => 4,
+            Comparison::Ge => 5,
+        }])?;
+        write_value(out, &predicate.value)?;
+    }
+    Ok(())
+}
+
+fn read_query(input: &mut dyn Read) -> io::Result<Query> {
+    let prefix = match read_u8(input)? {
+        0 => None,
+        _ => Some(read_text(input)?),
+    };
+    let mut query = Query::new(prefix);
+    for _ in 0..read_u64(input)? {
+        let field = match read_u8(input)? {
+            0 => Field::Key,
+            1 => Field::Value,
+            2 => Field::Index(read_text(input)?),
+            _ => return Err(invalid("Unknown query field")),
+        };
+        let op = match read_u8(input)? {
+            0 => Comparison::Eq,
+            1 => Comparison::Ne,
+            2 => Comparison::Lt,
+            3 => Comparison::Le,
+            4 => Comparison::Gt,
+            5 => Comparison::Ge,
+            _ => return Err(invalid("Unknown comparison")),
+        };
+        query = query.with(Predicate::new(field, op, read_value(input)?));
+    }
+    Ok(query)
+}
+
+fn write_outcome(out: &mut dyn Write, outcome: &Outcome) -> io::Result<()> {
+    match outcome {
+        Outcome::Value(value) => {
+            out.write_all(&[1])?;
+            write_option(out, value.as_ref())
+        }
+        Outcome::Values(values) => {
+            out.write_all(&[2])?;
+            write_values(out, values)
+        }
+        Outcome::Count(count) => {
+            out.write_all(&[3])?;
+            write_u64(out, *count as u64)
+        }
+        Outcome::Added(added) => out.write_all(&[4, u8::from(*added)]),
+        Outcome::Score(score) => {
+            out.write_all(&[5])?;
+            out.write_all(&score.to_le_bytes())
+        }
+    }
+}
+
+fn read_outcome(input: &mut dyn Read) -> io::Result<Outcome> {
+    Ok(match read_u8(input)? {
+        1 => Outcome::Value(read_option(input)?),
+        2 => Outcome::Values(read_values(input)?),
+        3 => Outcome::Count(read_u64(input)? as usize),
+        4 => Outcome::Added(read_u8(input)? != 0),
+        5 => Outcome::Score(read_f64(input)?),
+        _ => return Err(invalid("Unknown outcome")),
+    })
+}
+
+pub(crate) fn write_request(out: &mut dyn Write, request: &Request) -> io::Result<()> {
+    match request {
+        Request::Ring => out.write_all(&[RING]),
+        Request::Get { keys, route } => {
+            out.write_all(&[GET])?;
+            write_route(out, *route)?;
+            write_texts(out, keys)
+        }
+        Request::Write { mutation, route } => {
+            out.write_all(&[WRITE])?;
+            write_route(out, *route)?;
+            write_mutation(out, mutation)
+        }
+        Request::Keys { prefix } => {
+            out.write_all(&[KEYS])?;
+            write_text(out, prefix)
+        }
+        Request::Scan { query } => {
+            out.write_all(&[SCAN])?;
+            write_query(out, query)
+        }
+        Request::Prepare { ring, addr } => {
+            out.write_all(&[PREPARE])?;
+            write_ring(out, ring)?;
+            write_text(out, addr)
+        }
+        Request::Migrate => out.write_all(&[MIGRATE]),
+        Request::Import { entries } => {
+            out.write_all(&[IMPORT])?;
+            write_entries(out, entries)
+        }
+        Request::SetRing { ring, addr } => {
+            out.write_all(&[SET_RING])?;
+            write_ring(out, ring)?;
+            write_text(out, addr)
+        }
+    }
+}
+
+pub(crate) fn read_request(input: &mut dyn Read) -> io::Result<Request> {
+    Ok(match read_u8(input)? {
+        RING => Request::Ring,
+        GET => Request::Get {
+            route: read_route(input)?,
+            keys: read_texts(input)?,
+        },
+        WRITE => Request::Write {
+            route: read_route(input)?,
+            mutation: read_mutation(input)?,
+        },
+        KEYS => Request::Keys {
+            prefix: read_text(input)?,
+        },
+        SCAN => Request::Scan {
+            query: read_query(input)?,
+        },
+        PREPARE => Request::Prepare {
+            ring: read_ring(input)?,
+            addr: read_text(input)?,
+        },
+        MIGRATE => Request::Migrate,
+        IMPORT => Request::Import {
+            entries: read_entries(input)?,
+        },
+        SET_RING => Request::SetRing {
+            ring: read_ring(input)?,
+            addr: read_text(input)?,
+        },
+        _ => return Err(invalid("Unknown shard request")),
+    })
+}
+
+pub(crate) fn write_response(out: &mut dyn Write, response: &Response) -> io::Result<()> {
+    match response {
+        Response::Ring { ring, migrating } => {
+            out.write_all(&[RING])?;
+            write_ring(out, ring)?;
+            match migrating {
+                Some(ring) => {
+                    out.write_all(&[1])?;
+                    write_ring(out, ring)
+                }
+                None => out.write_all(&[0]),
+            }
+        }
+        Response::Values(values) => {
+            out.write_all(&[VALUES])?;
+            write_values(out, values)
+        }
+        Response::Outcome(outcome) => {
+            out.write_all(&[OUTCOME])?;
+            write_outcome(out, outcome)
+        }
+        Response::Keys(keys) => {
+            out.write_all(&[KEYS])?;
+            write_texts(out, keys)
+        }
+        Response::Entries(entries) => {
+            out.write_all(&[ENTRIES])?;
+            write_entries(out, entries)
+        }
+        Response::Done => out.write_all(&[DONE]),
+        Response::Migrated(count) => {
+            out.write_all(&[MIGRATED])?;
+            write_u64(out, *count)
+        }
+        Response::Moved(ring) => {
+            out.write_all(&[MOVED])?;
+            write_ring(out, ring)
+        }
+        Response::Ask(addr) => {
+            out.write_all(&[ASK])?;
+            write_text(out, addr)
+        }
+        Response::Failed { database, message } => {
+            out.write_all(&[FAILED, u8::from(*database)])?;
+            write_text(out, message)
+        }
+    }
+}
+
+pub(crate) fn read_response(input: &mut dyn Read) -> io::Result<Response> {
+    Ok(match read_u8(input)? {
+        RING => Response::Ring {
+            ring: read_ring(input)?,
+            migrating: match read_u8(input)? {
+                0 => None,
+                _ => Some(read_ring(input)?),
+            },
+        },
+        VALUES => Response::Values(read_values(input)?),
+        OUTCOME => Response::Outcome(read_outcome(input)?),
+        KEYS => Response::Keys(read_texts(input)?),
+        ENTRIES => Response::Entries(read_entries(input)?),
+        DONE => Response::Done,
+        MIGRATED => Response::Migrated(read_u64(input)?),
+        MOVED => Response::Moved(read_ring(input)?),
+        ASK => Response::Ask(read_text(input)?),
+        FAILED => Response::Failed {
+            database: read_u8(input)? != 0,
+            message: read_text(input)?,
+        },
+        _ => return Err(invalid("Unknown shard response")),
+    })
+}
+
+pub(crate) fn read_hello(input: &mut dyn Read) -> io::Result<()> {
+    let mut hello = [0; HELLO.len()];
+    input.read_exact(&mut hello)?;
+    if &hello != HELLO {
+        return Err(invalid("Not a HoyaDB shard router"));
+    }
+    Ok(())
+}
+
+/// A connection to a shard server
+#[derive(Debug)]
+pub(crate) struct Connection {
+    input: BufReader<TcpStream>,
+    out: BufWriter<TcpStream>,
+}
+
+impl Connection {
+    pub(crate) fn open(addr: &str) -> io::Result<Self> {
+        let target = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("`{addr}` isn't an address")))?;
+        let stream = TcpStream::connect_timeout(&target, TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut out = BufWriter::new(stream.try_clone()?);
+        out.write_all(HELLO)?;
+        Ok(Self {
+            input: BufReader::new(stream),
+            out,
+        })
+    }
+
+    /// Whether the server is still there, as far as can be told without a request. One
+    /// that restarted since has closed the connection
+    pub(crate) fn is_open(&self) -> bool {
+        let stream = self.input.get_ref();
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0];
+        let open = match stream.peek(&mut byte) {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+        };
+        stream.set_nonblocking(false).is_ok() && open
+    }
+
+    /// Makes the request and waits for the response. Without `timeout` it waits as long
+    /// as it takes, as moving a shard's records does
+    pub(crate) fn call(&mut self, request: &Request, timeout: bool) -> io::Result<Response> {
+        self.input
+            .get_ref()
+            .set_read_timeout(timeout.then_some(TIMEOUT))?;
+        write_request(&mut self.out, request)?;
+        self.out.flush()?;
+        read_response(&mut self.input)
+    }
+}
@@ -0,0 +1,567 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::ChaCha20Poly1305;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use super::{legacy, schema};
+use crate::db::Collection;
+use crate::{DBTypes, SnapshotError};
+
+/// Snapshots written before the header existed are a bare serialized `Collection`
+const MAGIC: &[u8; 4] = b"HOYA";
+/// Version 1 had the same header, with records in the derived bincode encoding.
+/// Version 2 writes them as laid out in `schema`
+pub(crate) const VERSION: u8 = 2;
+
+/// Plaintext bytes per encrypted chunk
+const CHUNK: usize = 64 * 1024;
+/// Bytes an AEAD adds to every chunk
+const TAG: usize = 16;
+const SALT: usize = 16;
+/// The STREAM construction takes 5 bytes of the 12-byte nonce for its counter
+const STREAM_NONCE: usize = 7;
+const NONCE: usize = 12;
+
+const MORE: u8 = 1;
+const END: u8 = 0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(compression: &str) -> Result<Self, Self::Err> {
+        match compression {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(format!(
+                "Expected the compression none, zstd or lz4, but found `{compression}`"
+            )),
+        }
+    }
+}
+
+/// An authenticated cipher with 256-bit keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl FromStr for Cipher {
+    type Err = String;
+
+    fn from_str(cipher: &str) -> Result<Self, Self::Err> {
+        match cipher {
+            "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+            "chacha20-poly1305" => Ok(Cipher::ChaCha20Poly1305),
+            _ => Err(format!(
+                "Expected the cipher aes-256-gcm or chacha20-poly1305, but found `{cipher}`"
+            )),
+        }
+    }
+}
+
+/// What snapshots are encrypted with. A passphrase is stretched into a key with Argon2id
+/// and a salt that's kept in the snapshot
+#[derive(Clone)]
+pub enum Secret {
+    Key([u8; 32]),
+    Passphrase(String),
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Secret::Key(_) => "Key(..)",
+            Secret::Passphrase(_) => "Passphrase(..)",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    pub cipher: Cipher,
+    pub secret: Secret,
+}
+
+/// How snapshots are written. Reading them only needs the secret, if they're encrypted,
+/// since the header says how they were written
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotOptions {
+    pub compression: Compression,
+    pub encryption: Option<Encryption>,
+}
+
+impl SnapshotOptions {
+    pub(crate) fn secret(&self) -> Option<&Secret> {
+        self.encryption
+            .as_ref()
+            .map(|encryption| &encryption.secret)
+    }
+}
+
+fn compression_code(compression: Compression) -> u8 {
+    match compression {
+        Compression::None => 0,
+        Compression::Zstd => 1,
+        Compression::Lz4 => 2,
+    }
+}
+
+fn cipher_code(cipher: Option<Cipher>) -> u8 {
+    match cipher {
+        None => 0,
+        Some(Cipher::Aes256Gcm) => 1,
+        Some(Cipher::ChaCha20Poly1305) => 2,
+    }
+}
+
+const KEY: u8 = 0;
+const ARGON2ID: u8 = 1;
+
+/// The most memory (in KiB), passes and lanes a snapshot may ask Argon2 for. The costs
+/// are read before the key check can tell whether the header was tampered with, so they
+/// must not be trusted to stay within what's reasonable to spend opening a snapshot
+const MAX_M_COST: u32 = 1 << 20;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<[u8; 32], argon2::Error> {
+    let mut key = [0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(
+        passphrase.as_bytes(),
+        salt,
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+fn corrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, SnapshotError::Corrupted)
+}
+
+/// One of the ciphers, so the rest of the file doesn't have to be generic over them.
+/// AES carries its expanded key schedule, so it's boxed
+enum Sealing {
+    Aes(Box<Aes256Gcm>),
+    ChaCha(ChaCha20Poly1305),
+}
+
+enum Encryptor {
+    Aes(Box<EncryptorBE32<Aes256Gcm>>),
+    ChaCha(EncryptorBE32<ChaCha20Poly1305>),
+}
+
+enum Decryptor {
+    Aes(Box<DecryptorBE32<Aes256Gcm>>),
+    ChaCha(DecryptorBE32<ChaCha20Poly1305>),
+}
+
+impl Sealing {
+    fn new(cipher: Cipher, key: &[u8; 32]) -> Self {
+        let key = GenericArray::from_slice(key);
+        match cipher {
+            Cipher::Aes256Gcm => Sealing::Aes(Box::new(Aes256Gcm::new(key))),
+            Cipher::ChaCha20Poly1305 => Sealing::ChaCha(ChaCha20Poly1305::new(key)),
+        }
+    }
+
+    /// A tag that only the right key reproduces, also covering `header`
+    fn key_check(&self, nonce: &[u8; NONCE], header: &[u8]) -> Vec<u8> {
+        let payload = Payload {
+            msg: &[],
+            aad: header,
+        };
+        let nonce = GenericArray::from_slice(nonce);
+        match self {
+            Sealing::Aes(aead) => aead.encrypt(nonce, payload),
+            Sealing::ChaCha(aead) => aead.encrypt(nonce, payload),
+        }
+        .expect("an empty message fits any cipher")
+    }
+
+    fn verify(&self, nonce: &[u8; NONCE], header: &[u8], tag: &[u8]) -> bool {
+        let payload = Payload {
+            msg: tag,
+            aad: header,
+        };
+        let nonce = GenericArray::from_slice(nonce);
+        match self {
+            Sealing::Aes(aead) => aead.decrypt(nonce, payload),
+            Sealing::ChaCha(aead) => aead.decrypt(nonce, payload),
+        }
+        .is_ok()
+    }
+
+    fn encryptor(self, nonce: &[u8; STREAM_NONCE]) -> Encryptor {
+        let nonce = GenericArray::from_slice(nonce);
+        match self {
+            Sealing::Aes(aead) => Encryptor::Aes(Box::new(EncryptorBE32::from_aead(*aead, nonce))),
+            Sealing::ChaCha(aead) => Encryptor::ChaCha(EncryptorBE32::from_aead(aead, nonce)),
+        }
+    }
+
+    fn decryptor(self, nonce: &[u8; STREAM_NONCE]) -> Decryptor {
+        let nonce = GenericArray::from_slice(nonce);
+        match self {
+            Sealing::Aes(aead) => Decryptor::Aes(Box::new(DecryptorBE32::from_aead(*aead, nonce))),
+            Sealing::ChaCha(aead) => Decryptor::ChaCha(DecryptorBE32::from_aead(aead, nonce)),
+        }
+    }
+}
+
+/// A writer that has to be told where the stream ends, to write out what it holds back
+pub(crate) trait Finish: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl Finish for BufWriter<File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()
+    }
+}
+
+impl Finish for zstd::stream::write::Encoder<'static, Box<dyn Finish>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        zstd::stream::write::Encoder::finish(*self)?.finish()
+    }
+}
+
+impl Finish for lz4_flex::frame::FrameEncoder<Box<dyn Finish>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        lz4_flex::frame::FrameEncoder::finish(*self)?.finish()
+    }
+}
+
+/// Encrypts what's written to it a chunk at a time. The last chunk is sealed as the last,
+/// so cutting the file short at a chunk boundary is noticed too
+struct Sealer {
+    inner: Box<dyn Finish>,
+    encryptor: Option<Encryptor>,
+    buffer: Vec<u8>,
+}
+
+impl Write for Sealer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        // Whatever is left when the stream ends is sealed by `finish`
+        while self.buffer.len() > CHUNK {
+            let rest = self.buffer.split_off(CHUNK);
+            let sealed = match self.encryptor.as_mut().expect("sealed before finishing") {
+                Encryptor::Aes(e) => e.encrypt_next(self.buffer.as_slice()),
+                Encryptor::ChaCha(e) => e.encrypt_next(self.buffer.as_slice()),
+            }
+            .map_err(|_| io::Error::other("The snapshot is too large to encrypt"))?;
+            self.inner.write_all(&sealed)?;
+            self.buffer = rest;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Finish for Sealer {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let sealed = match self.encryptor.take().expect("only finished once") {
+            Encryptor::Aes(e) => (*e).encrypt_last(self.buffer.as_slice()),
+            Encryptor::ChaCha(e) => e.encrypt_last(self.buffer.as_slice()),
+        }
+        .map_err(|_| io::Error::other("The snapshot is too large to encrypt"))?;
+        self.inner.write_all(&sealed)?;
+        self.inner.finish()
+    }
+}
+
+/// Decrypts what `Sealer` wrote
+struct Opener<R> {
+    inner: R,
+    decryptor: Option<Decryptor>,
+    plain: Vec<u8>,
+    read: usize,
+}
+
+impl<R: BufRead> Opener<R> {
+    /// Decrypts the next chunk. Returns false after the last one
+    fn open_next(&mut self) -> io::Result<bool> {
+        let Some(mut decryptor) = self.decryptor.take() else {
+            return Ok(false);
+        };
+        let mut sealed = Vec::with_capacity(CHUNK + TAG);
+        (&mut self.inner)
+            .take((CHUNK + TAG) as u64)
+            .read_to_end(&mut sealed)?;
+        let last = self.inner.fill_buf()?.is_empty();
+
+        let plain = if last {
+            match decryptor {
+                Decryptor::Aes(d) => (*d).decrypt_last(sealed.as_slice()),
+                Decryptor::ChaCha(d) => d.decrypt_last(sealed.as_slice()),
+            }
+        } else {
+            let plain = match &mut decryptor {
+                Decryptor::Aes(d) => d.decrypt_next(sealed.as_slice()),
+                Decryptor::ChaCha(d) => d.decrypt_next(sealed.as_slice()),
+            };
+            self.decryptor = Some(decryptor);
+            plain
+        };
+        self.plain = plain.map_err(|_| corrupted())?;
+        self.read = 0;
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for Opener<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.plain.len() {
+            if !self.open_next()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.plain.len() - self.read);
+        buf[..n].copy_from_slice(&self.plain[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+/// Writes the header to `out` and returns where the records go, through `write_record`
+/// and then `write_end`
+pub(crate) fn create(
+    mut out: Box<dyn Finish>,
+    options: &SnapshotOptions,
+) -> io::Result<Box<dyn Finish>> {
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    header.push(compression_code(options.compression));
+    header.push(cipher_code(options.encryption.as_ref().map(|e| e.cipher)));
+
+    match &options.encryption {
+        None => out.write_all(&header)?,
+        Some(encryption) => {
+            let key = match &encryption.secret {
+                Secret::Key(key) => {
+                    header.push(KEY);
+                    *key
+                }
+                Secret::Passphrase(passphrase) => {
+                    let salt = random::<SALT>();
+                    let params = Params::default();
+                    header.push(ARGON2ID);
+                    header.extend_from_slice(&salt);
+                    for cost in [params.m_cost(), params.t_cost(), params.p_cost()] {
+                        header.extend_from_slice(&cost.to_le_bytes());
+                    }
+                    derive_key(passphrase, &salt, params)
+                        .map_err(|e| io::Error::other(e.to_string()))?
+                }
+            };
+            let stream_nonce = random::<STREAM_NONCE>();
+            header.extend_from_slice(&stream_nonce);
+
+            let sealing = Sealing::new(encryption.cipher, &key);
+            let check_nonce = random::<NONCE>();
+            let check = sealing.key_check(&check_nonce, &header);
+            out.write_all(&header)?;
+            out.write_all(&check_nonce)?;
+            out.write_all(&check)?;
+
+            out = Box::new(Sealer {
+                inner: out,
+                encryptor: Some(sealing.encryptor(&stream_nonce)),
+                buffer: Vec::with_capacity(CHUNK),
+            });
+        }
+    }
+
+    Ok(match options.compression {
+        Compression::None => out,
+        Compression::Zstd => Box::new(zstd::stream::write::Encoder::new(out, 0)?),
+        Compression::Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(out)),
+    })
+}
+
+pub(crate) fn write_record(out: &mut dyn Write, key: &str, value: &DBTypes) -> io::Result<()> {
+    out.write_all(&[MORE])?;
+    schema::write_text(out, key)?;
+    schema::write_value(out, value)
+}
+
+/// Marks the end of the records, so a snapshot cut short can't pass for a smaller one
+pub(crate) fn write_end(out: &mut dyn Write, count: u64) -> io::Result<()> {
+    out.write_all(&[END])?;
+    out.write_all(&count.to_le_bytes())
+}
+
+/// Reads the header bytes into `header` as well, since the key check covers them
+fn read_bytes<const N: usize>(input: &mut impl Read, header: &mut Vec<u8>) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes).map_err(|_| corrupted())?;
+    header.extend_from_slice(&bytes);
+    Ok(bytes)
+}
+
+/// Reads a snapshot written by `create` or by an older HoyaDB
+pub(crate) fn read<'a>(
+    input: impl Read + 'a,
+    secret: Option<&Secret>,
+) -> Result<Collection, Box<dyn Error>> {
+    let mut input = BufReader::new(input);
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    (&mut input)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    if magic != MAGIC {
+        input.read_to_end(&mut magic)?;
+        return Ok(legacy::read_bare(&magic)?);
+    }
+
+    let mut header = magic;
+    let [version] = read_bytes(&mut input, &mut header)?;
+    if !(1..=VERSION).contains(&version) {
+        return Err(SnapshotError::UnknownFormat(format!("version {version}")).into());
+    }
+    let compression = match read_bytes(&mut input, &mut header)? {
+        [0] => Compression::None,
+        [1] => Compression::Zstd,
+        [2] => Compression::Lz4,
+        [code] => return Err(SnapshotError::UnknownFormat(format!("compression {code}")).into()),
+    };
+    let cipher = match read_bytes(&mut input, &mut header)? {
+        [0] => None,
+        [1] => Some(Cipher::Aes256Gcm),
+        [2] => Some(Cipher::ChaCha20Poly1305),
+        [code] => return Err(SnapshotError::UnknownFormat(format!("cipher {code}")).into()),
+    };
+
+    let body: Box<dyn Read + 'a> = match cipher {
+        None => Box::new(input),
+        Some(cipher) => {
+            let [derivation] = read_bytes(&mut input, &mut header)?;
+            let key = match (derivation, secret) {
+                (KEY | ARGON2ID, None) => return Err(SnapshotError::KeyRequired.into()),
+                (KEY, Some(Secret::Key(key))) => *key,
+                (ARGON2ID, Some(Secret::Passphrase(passphrase))) => {
+                    let salt = read_bytes::<SALT>(&mut input, &mut header)?;
+                    let mut cost = || -> io::Result<u32> {
+                        Ok(u32::from_le_bytes(read_bytes(&mut input, &mut header)?))
+                    };
+                    let (m, t, p) = (cost()?, cost()?, cost()?);
+                    if m > MAX_M_COST || t > MAX_T_COST || p > MAX_P_COST {
+                        return Err(SnapshotError::UnknownFormat(format!(
+                            "Argon2 costs m={m}, t={t}, p={p} beyond the limits"
+                        ))
+                        .into());
+                    }
+                    let params = Params::new(m, t, p, Some(32))
+                        .map_err(|e| SnapshotError::UnknownFormat(e.to_string()))?;
+                    derive_key(passphrase, &salt, params)
+                        .map_err(|e| SnapshotError::UnknownFormat(e.to_string()))?
+                }
+                (KEY, Some(Secret::Passphrase(_))) => {
+                    return Err(SnapshotError::WrongSecret { passphrase: false }.into())
+                }
+                (ARGON2ID, Some(Secret::Key(_))) => {
+                    return Err(SnapshotError::WrongSecret { passphrase: true }.into())
+                }
+                (code, _) => {
+                    return Err(
+                        SnapshotError::UnknownFormat(format!("key derivation {code}")).into(),
+                    )
+                }
+            };
+            let stream_nonce = read_bytes::<STREAM_NONCE>(&mut input, &mut header)?;
+
+            let sealing = Sealing::new(cipher, &key);
+            let check_nonce = read_bytes::<NONCE>(&mut input, &mut vec![])?;
+            let check = read_bytes::<TAG>(&mut input, &mut vec![])?;
+            if !sealing.verify(&check_nonce, &header, &check) {
+                return Err(SnapshotError::WrongKey.into());
+            }
+            Box::new(Opener {
+                inner: input,
+                decryptor: Some(sealing.decryptor(&stream_nonce)),
+                plain: vec![],
+                read: 0,
+            })
+        }
+    };
+    let body: Box<dyn Read + 'a> = match compression {
+        Compression::None => body,
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(body)?),
+        Compression::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(body)),
+    };
+
+    read_records(BufReader::new(body), version).map_err(|e| match e.get_ref() {
+        Some(inner) if inner.is::<SnapshotError>() => {
+            Box::new(inner.downcast_ref::<SnapshotError>().unwrap().clone())
+        }
+        _ if e.kind() == io::ErrorKind::UnexpectedEof => Box::new(SnapshotError::Corrupted),
+        _ => Box::new(e) as Box<dyn Error>,
+    })
+}
+
+fn read_records(mut body: impl BufRead, version: u8) -> io::Result<Collection> {
+    let mut records = Collection::new();
+    loop {
+        let mut marker = [0];
+        body.read_exact(&mut marker)?;
+        match marker {
+            [MORE] => {
+                let (key, value) = match version {
+                    1 => bincode::deserialize_from::<_, (String, legacy::Value)>(&mut body)
+                        .map(|(key, value)| (key, value.into()))
+                        .map_err(|e| match *e {
+                            bincode::ErrorKind::Io(e) => e,
+                            _ => corrupted(),
+                        })?,
+                    _ => (
+                        schema::read_text(&mut body)?,
+                        schema::read_value(&mut body)?,
+                    ),
+                };
+                records.insert(key, value);
+            }
+            [END] => {
+                let mut count = [0; 8];
+                body.read_exact(&mut count)?;
+                if u64::from_le_bytes(count) != records.len() as u64 || !body.fill_buf()?.is_empty()
+                {
+                    return Err(corrupted());
+                }
+                return Ok(records);
+            }
+            _ => return Err(corrupted()),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Readers for the snapshot versions written before records had their own schema. Those
+//! used the derived bincode encoding of `DBTypes`, which numbers the variants in their
+//! declaration order, so `Value` keeps a copy of the enum as it was when they were
+//! replaced. Versions before that only ever appended variants, so it reads all of them
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Duration, Utc};
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::db::Collection;
+use crate::DBTypes;
+
+/// `DBTypes` as of snapshot version 1. Never reorder or change these
+#[derive(Deserialize)]
+pub(crate) enum Value {
+    Number(isize),
+    Float(f64),
+    Boolean(bool),
+    Text(String),
+    List(Vec<Value>),
+    Unit(()),
+    Set(BTreeSet<String>),
+    SortedSet(BTreeMap<String, f64>),
+    Bytes(Vec<u8>),
+    Timestamp(DateTime<Utc>),
+    Duration(Duration),
+    BigInt(BigInt),
+    Decimal(Decimal),
+    Nil,
+}
+
+impl From<Value> for DBTypes {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Number(n) => DBTypes::Number(n),
+            Value::Float(f) => DBTypes::Float(f),
+            Value::Boolean(b) => DBTypes::Boolean(b),
+            Value::Text(text) => DBTypes::Text(text),
+            Value::List(list) => DBTypes::List(list.into_iter().map(DBTypes::from).collect()),
+            Value::Unit(()) => DBTypes::Unit(()),
+            Value::Set(set) => DBTypes::Set(set),
+            Value::SortedSet(set) => DBTypes::SortedSet(set),
+            Value::Bytes(bytes) => DBTypes::Bytes(bytes),
+            Value::Timestamp(at) => DBTypes::Timestamp(at),
+            Value::Duration(duration) => DBTypes::Duration(duration),
+            Value::BigInt(n) => DBTypes::BigInt(n),
+            Value::Decimal(d) => DBTypes::Decimal(d),
+            Value::Nil => DBTypes::Nil,
+        }
+    }
+}
+
+/// Snapshots from before the header: a bare serialized map of keys to values
+pub(crate) fn read_bare(bytes: &[u8]) -> bincode::Result<Collection> {
+    let records = bincode::deserialize::<BTreeMap<String, Value>>(bytes)?;
+    Ok(records
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect())
+}
@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Condvar, Mutex, MutexGuard, RwLock};
+use std::thread;
+
+use chrono::{DateTime, Utc};
+
+use crate::db::Store;
+use crate::shard::merge;
+use crate::{DBTypes, Database, DatabaseError};
+
+mod format;
+mod legacy;
+pub(crate) mod schema;
+
+pub(crate) use format::{create, read, write_end, write_record, Finish};
+pub use format::{Cipher, Compression, Encryption, Secret, SnapshotOptions};
+
+/// How many records a save copies out of a shard per lock
+const CHUNK: usize = 1024;
+
+/// The values a running save still has to write, as they were when the save started.
+/// Writers record a key's old value the first time they change it, so the save can
+/// read the live records a chunk at a time and still write one point in time
+#[derive(Debug, Default)]
+pub(crate) struct Freeze {
+    /// The last key already written. Changes up to it no longer matter
+    written: Option<String>,
+    /// `None` for keys that didn't exist yet
+    before: BTreeMap<String, Option<DBTypes>>,
+    /// The changes the save covers, counted as unsaved again unless it succeeds
+    covers: u64,
+}
+
+impl Freeze {
+    /// Keeps `old` as the value of `key` for the save, unless it's already kept or written
+    pub(crate) fn preserve(&mut self, key: &str, old: Option<&DBTypes>) {
+        if self
+            .written
+            .as_deref()
+            .is_some_and(|written| key <= written)
+            || self.before.contains_key(key)
+        {
+            return;
+        }
+        self.before.insert(key.to_owned(), old.cloned());
+    }
+}
+
+/// The status of saves, shared by every clone of a database
+#[derive(Debug, Default)]
+pub(crate) struct Saves {
+    status: Mutex<SaveStatus>,
+    /// Signalled whenever a save finishes
+    finished: Condvar,
+}
+
+/// Where the running save is up to, and how the last one went
+#[derive(Debug, Clone, Default)]
+pub struct SaveStatus {
+    /// What's being written, while a save or an export runs
+    pub running: Option<String>,
+    pub records_written: usize,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// The save asked for, from while it waits for an export to finish until it's done
+    saving: Option<String>,
+}
+
+impl fmt::Display for SaveStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.running {
+            Some(target) => write!(
+                f,
+                "Writing {target}: {} records written",
+                self.records_written
+            )?,
+            None => write!(f, "No save running")?,
+        }
+        if let Some(target) = self
+            .saving
+            .as_ref()
+            .filter(|&t| self.running.as_ref() != Some(t))
+        {
+            write!(f, "; {target} waits for it")?;
+        }
+        match self.last_success {
+            Some(at) => write!(f, "; last saved at {}", at.to_rfc3339())?,
+            None => write!(f, "; never saved")?,
+        }
+        if let Some(e) = &self.last_error {
+            write!(f, "; last save failed: {e}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Starts writing a point-in-time image of every record to `name.hoya` in the data
+    /// directory on another thread. Writes carry on meanwhile, and `save_status` shows how
+    /// far it got
+    pub fn bgsave(&self, name: &str) -> Result<(), DatabaseError> {
+        let path = self.data_dir.snapshot(name)?;
+        self.start_save(format!("`{name}.hoya`"))?;
+        // Frozen now unless an export holds the records, so later writes aren't in it
+        let frozen = self.try_freeze_for_save();
+        let db = self.clone();
+        thread::spawn(move || {
+            if !frozen {
+                db.freeze_for_save();
+            }
+            db.save(&path)
+        });
+        Ok(())
+    }
+
+    pub fn save_status(&self) -> SaveStatus {
+        self.saves.status.lock().unwrap().clone()
+    }
+
+    /// Blocks until no save is running or waiting to
+    pub fn wait_for_save(&self) {
+        let status = self.saves.status.lock().unwrap();
+        drop(
+            self.saves
+                .finished
+                .wait_while(status, |status| status.saving.is_some())
+                .unwrap(),
+        );
+    }
+
+    /// Claims the save for `target`. Only one save runs or waits at a time
+    pub(crate) fn start_save(&self, target: String) -> Result<(), DatabaseError> {
+        let mut status = self.saves.status.lock().unwrap();
+        if let Some(saving) = &status.saving {
+            return Err(DatabaseError::SaveInProgress {
+                target: saving.clone(),
+            });
+        }
+        status.saving = Some(target);
+        Ok(())
+    }
+
+    /// Freezes the records for the save claimed by `start_save`, once an export that
+    /// froze them first is done
+    pub(crate) fn freeze_for_save(&self) {
+        let status = self.saves.status.lock().unwrap();
+        let status = self
+            .saves
+            .finished
+            .wait_while(status, |status| status.running.is_some())
+            .unwrap();
+        let target = status.saving.clone().unwrap_or_default();
+        self.freeze_locked(status, target);
+    }
+
+    /// Freezes the records for the save claimed by `start_save` unless an export froze
+    /// them first. Returns whether it did
+    fn try_freeze_for_save(&self) -> bool {
+        let status = self.saves.status.lock().unwrap();
+        if status.running.is_some() {
+            return false;
+        }
+        let target = status.saving.clone().unwrap_or_default();
+        self.freeze_locked(status, target);
+        true
+    }
+
+    /// Freezes the records for an export or a full sync. Unlike a save, these are refused
+    /// while anything else is frozen or a save waits, rather than waiting themselves.
+    /// Returns the history and offset of the change log at that point
+    pub(crate) fn freeze(&self, target: String) -> Result<(String, u64), DatabaseError> {
+        let status = self.saves.status.lock().unwrap();
+        if let Some(running) = status.running.as_ref().or(status.saving.as_ref()) {
+            return Err(DatabaseError::SaveInProgress {
+                target: running.clone(),
+            });
+        }
+        Ok(self.freeze_locked(status, target))
+    }
+
+    /// Freezes every shard at once, so that what `write_frozen` reads is one point in time
+    /// across them. `target` describes what's being written, for the status. Returns the
+    /// history and offset of the change log at that point
+    fn freeze_locked(
+        &self,
+        mut status: MutexGuard<'_, SaveStatus>,
+        target: String,
+    ) -> (String, u64) {
+        let mut stores = self.write_all();
+        let position = {
+            let backlog = self.changes.lock();
+            (backlog.id.clone(), backlog.offset)
+        };
+        for store in &mut stores {
+            store.freeze = Some(Freeze {
+                covers: std::mem::take(&mut store.dirty),
+                ..Freeze::default()
+            });
+        }
+        drop(stores);
+        *status = SaveStatus {
+            running: Some(target),
+            records_written: 0,
+            ..status.clone()
+        };
+        position
+    }
+
+    /// Passes every frozen record to `write` in key order. Returns how many there were
+    pub(crate) fn write_frozen(
+        &self,
+        mut write: impl FnMut(&str, &DBTypes) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        let shards = self.shards.iter().map(frozen).collect::<Vec<_>>();
+        let mut count = 0u64;
+        for (key, value) in merge(shards, |(a, _), (b, _)| a.cmp(b)) {
+            write(&key, &value)?;
+            count += 1;
+            if count.is_multiple_of(CHUNK as u64) {
+                self.saves.status.lock().unwrap().records_written = count as usize;
+            }
+        }
+        self.saves.status.lock().unwrap().records_written = count as usize;
+        Ok(count)
+    }
+
+    /// Ends what `freeze` started. `saved` is how the save went, or `None` for an export,
+    /// which doesn't save the changes it wrote
+    pub(crate) fn thaw(&self, saved: Option<&io::Result<()>>) {
+        for mut store in self.write_all() {
+            let freeze = store.freeze.take();
+            if !matches!(saved, Some(Ok(()))) {
+                store.dirty += freeze.map_or(0, |freeze| freeze.covers);
+            }
+        }
+        let mut status = self.saves.status.lock().unwrap();
+        status.running = None;
+        if saved.is_some() {
+            status.saving = None;
+        }
+        match saved {
+            Some(Ok(())) => {
+                status.last_success = Some(Utc::now());
+                status.last_error = None;
+            }
+            Some(Err(e)) => status.last_error = Some(e.to_string()),
+            None => {}
+        }
+        self.saves.finished.notify_all();
+    }
+
+    /// Writes out the image frozen by `freeze_for_save` and records how it went
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        let result = self.write_image(path);
+        self.thaw(Some(&result));
+        result
+    }
+
+    /// Streams the records, compressed and encrypted as configured, into a temporary file
+    /// that replaces `path` once it's complete and synced
+    pub(crate) fn write_image(&self, path: &Path) -> io::Result<()> {
+        replace_file(path, |file| self.write_records(file))
+    }
+
+    fn write_records(&self, file: File) -> io::Result<()> {
+        let mut out = format::create(Box::new(BufWriter::new(file)), &self.snapshot_options)?;
+        let count = self.write_frozen(|key, value| format::write_record(&mut out, key, value))?;
+        format::write_end(&mut out, count)?;
+        out.finish()
+    }
+}
+
+/// Writes `path` through a temporary file that replaces it once it's complete. `write`
+/// syncs the file, and the rename is synced here
+pub(crate) fn replace_file(
+    path: &Path,
+    write: impl FnOnce(File) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    // Left over from a failed save, or a link put there: either way it's replaced by a
+    // new file rather than written through
+    match fs::remove_file(&temporary) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temporary)?;
+    let written = write(file);
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written?;
+    fs::rename(temporary, path)?;
+    sync_parent(path)
+}
+
+/// Rewrites the snapshot at `old` into `new` in the current format, compressed and
+/// encrypted by `options`. `old` can be any version, and `new` can be the same file.
+/// Returns how many records were copied
+pub fn migrate(
+    old: impl AsRef<Path>,
+    new: impl AsRef<Path>,
+    options: &SnapshotOptions,
+) -> Result<u64, Box<dyn Error>> {
+    let records = format::read(File::open(old)?, options.secret())?;
+    replace_file(new.as_ref(), |file| {
+        let mut out = format::create(Box::new(BufWriter::new(file)), options)?;
+        for (key, value) in &records {
+            format::write_record(&mut out, key, value)?;
+        }
+        format::write_end(&mut out, records.len() as u64)?;
+        out.finish()
+    })?;
+    Ok(records.len() as u64)
+}
+
+/// Makes a rename in the directory holding `path` durable
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+/// Directories can't be opened to be synced here
+#[cfg(not(unix))]
+fn sync_parent(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The frozen records of a shard in key order, read a chunk at a time
+fn frozen(shard: &RwLock<Store>) -> impl Iterator<Item = (String, DBTypes)> + '_ {
+    let mut chunk = BTreeMap::new().into_iter();
+    let mut done = false;
+    std::iter::from_fn(move || loop {
+        if let Some(record) = chunk.next() {
+            return Some(record);
+        }
+        if done {
+            return None;
+        }
+        let next;
+        (next, done) = next_chunk(&mut shard.write().unwrap());
+        chunk = next.into_iter();
+    })
+}
+
+/// The frozen records following the last chunk written out of `store`, and whether
+/// they're the last of them
+fn next_chunk(store: &mut Store) -> (BTreeMap<String, DBTypes>, bool) {
+    let Store {
+        records, freeze, ..
+    } = store;
+    let freeze = freeze.as_mut().expect("a save froze every shard");
+
+    let start = match &freeze.written {
+        Some(written) => Bound::Excluded(written.as_str()),
+        None => Bound::Unbounded,
+    };
+    let mut chunk = records
+        .scan((start, Bound::Unbounded))
+        .take(CHUNK)
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<BTreeMap<_, _>>();
+    let last = (chunk.len() == CHUNK).then(|| chunk.keys().next_back().unwrap().clone());
+    let end = match &last {
+        Some(last) => Bound::Included(last.as_str()),
+        None => Bound::Unbounded,
+    };
+
+    for (key, before) in freeze.before.range::<str, _>((start, end)) {
+        match before {
+            Some(value) => chunk.insert(key.clone(), value.clone()),
+            None => chunk.remove(key),
+        };
+    }
+
+    match last {
+        Some(last) => {
+            let mut rest = freeze.before.split_off(last.as_str());
+            rest.remove(&last);
+            freeze.before = rest;
+            freeze.written = Some(last);
+            (chunk, false)
+        }
+        None => {
+            freeze.before.clear();
+            (chunk, true)
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! How records are laid out in version 2 snapshots. Unlike the derived bincode encoding
+//! of earlier versions, every type has a fixed tag and every field a fixed width, so
+//! changing `DBTypes` can't change what's on disk. A change to this file needs a new
+//! format version, with a reader for this one kept in `legacy`
+//!
+//! | tag | type        | then                                                  |
+//! |-----|-------------|-------------------------------------------------------|
+//! | 0   | nil         |                                                       |
+//! | 1   | unit        |                                                       |
+//! | 2   | boolean     | u8, 0 or 1                                            |
+//! | 3   | number      | i64                                                   |
+//! | 4   | float       | f64 bits                                              |
+//! | 5   | text        | u64 length, UTF-8                                     |
+//! | 6   | list        | u64 length, values                                    |
+//! | 7   | set         | u64 length, texts in order                            |
+//! | 8   | sorted set  | u64 length, text and f64 bits per member, in order    |
+//! | 9   | bytes       | u64 length, bytes                                     |
+//! | 10  | timestamp   | i64 seconds and u32 nanoseconds since the Unix epoch  |
+//! | 11  | duration    | i64 seconds and i32 nanoseconds, both with its sign   |
+//! | 12  | big integer | u64 length, two's complement bytes                    |
+//! | 13  | decimal     | 16 bytes in `rust_decimal`'s serialized form          |
+//!
+//! Integers are little-endian. Keys are written as texts without a tag
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Write};
+
+use chrono::{DateTime, Duration};
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
+
+use crate::{DBTypes, SnapshotError};
+
+const NIL: u8 = 0;
+const UNIT: u8 = 1;
+const BOOLEAN: u8 = 2;
+const NUMBER: u8 = 3;
+const FLOAT: u8 = 4;
+const TEXT: u8 = 5;
+const LIST: u8 = 6;
+const SET: u8 = 7;
+const SORTED_SET: u8 = 8;
+const BYTES: u8 = 9;
+const TIMESTAMP: u8 = 10;
+const DURATION: u8 = 11;
+const BIGINT: u8 = 12;
+const DECIMAL: u8 = 13;
+
+/// How many lists can nest inside one another. Values are read by recursing once per
+/// list, so deeper input, which only a corrupt or crafted one could hold, is refused
+/// instead of overflowing the stack. Values that deep aren't written either, so that
+/// whatever is written can be read back
+pub(crate) const MAX_DEPTH: usize = 256;
+
+fn corrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, SnapshotError::Corrupted)
+}
+
+fn too_deep() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Lists can nest at most {MAX_DEPTH} deep"),
+    )
+}
+
+fn write_len(out: &mut dyn Write, len: usize) -> io::Result<()> {
+    out.write_all(&(len as u64).to_le_bytes())
+}
+
+pub(crate) fn write_bytes(out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    write_len(out, bytes.len())?;
+    out.write_all(bytes)
+}
+
+pub(crate) fn write_text(out: &mut dyn Write, text: &str) -> io::Result<()> {
+    write_bytes(out, text.as_bytes())
+}
+
+pub(crate) fn write_value(out: &mut dyn Write, value: &DBTypes) -> io::Result<()> {
+    write_nested(out, value, 0)
+}
+
+/// Writes `value`, which is inside `depth` lists
+fn write_nested(out: &mut dyn Write, value: &DBTypes, depth: usize) -> io::Result<()> {
+    match value {
+        DBTypes::Nil => out.write_all(&[NIL]),
+        DBTypes::Unit(()) => out.write_all(&[UNIT]),
+        DBTypes::Boolean(b) => out.write_all(&[BOOLEAN, u8::from(*b)]),
+        DBTypes::Number(n) => {
+            out.write_all(&[NUMBER])?;
+            out.write_all(&(*n as i64).to_le_bytes())
+        }
+        DBTypes::Float(f) => {
+            out.write_all(&[FLOAT])?;
+            out.write_all(&f.to_bits().to_le_bytes())
+        }
+        DBTypes::Text(text) => {
+            out.write_all(&[TEXT])?;
+            write_text(out, text)
+        }
+        DBTypes::List(_) if depth == MAX_DEPTH => Err(too_deep()),
+        DBTypes::List(list) => {
+            out.write_all(&[LIST])?;
+            write_len(out, list.len())?;
+            list.iter()
+                .try_for_each(|value| write_nested(out, value, depth + 1))
+        }
+        DBTypes::Set(set) => {
+            out.write_all(&[SET])?;
+            write_len(out, set.len())?;
+            set.iter().try_for_each(|member| write_text(out, member))
+        }
+        DBTypes::SortedSet(set) => {
+            out.write_all(&[SORTED_SET])?;
+            write_len(out, set.len())?;
+            set.iter().try_for_each(|(member, score)| {
+                write_text(out, member)?;
+                out.write_all(&score.to_bits().to_le_bytes())
+            })
+        }
+        DBTypes::Bytes(bytes) => {
+            out.write_all(&[BYTES])?;
+            write_bytes(out, bytes)
+        }
+        DBTypes::Timestamp(at) => {
+            out.write_all(&[TIMESTAMP])?;
+            out.write_all(&at.timestamp().to_le_bytes())?;
+            out.write_all(&at.timestamp_subsec_nanos().to_le_bytes())
+        }
+        DBTypes::Duration(duration) => {
+            out.write_all(&[DURATION])?;
+            out.write_all(&duration.num_seconds().to_le_bytes())?;
+            out.write_all(&duration.subsec_nanos().to_le_bytes())
+        }
+        DBTypes::BigInt(n) => {
+            out.write_all(&[BIGINT])?;
+            write_bytes(out, &n.to_signed_bytes_le())
+        }
+        DBTypes::Decimal(d) => {
+            out.write_all(&[DECIMAL])?;
+            out.write_all(&d.serialize())
+        }
+    }
+}
+
+fn read_array<const N: usize>(input: &mut dyn Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_len(input: &mut dyn Read) -> io::Result<usize> {
+    usize::try_from(u64::from_le_bytes(read_array(input)?)).map_err(|_| corrupted())
+}
+
+pub(crate) fn read_bytes(input: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let len = read_len(input)?;
+    // A corrupted length mustn't allocate more than the file holds
+    let mut bytes = Vec::with_capacity(len.min(64 * 1024));
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn read_text(input: &mut dyn Read) -> io::Result<String> {
+    String::from_utf8(read_bytes(input)?).map_err(|_| corrupted())
+}
+
+pub(crate) fn read_u8(input: &mut dyn Read) -> io::Result<u8> {
+    let [byte] = read_array(input)?;
+    Ok(byte)
+}
+
+pub(crate) fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(input)?))
+}
+
+pub(crate) fn read_f64(input: &mut dyn Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(input)?))
+}
+
+pub(crate) fn read_value(input: &mut dyn Read) -> io::Result<DBTypes> {
+    read_nested(input, 0)
+}
+
+/// Reads a value inside `depth` lists
+fn read_nested(input: &mut dyn Read, depth: usize) -> io::Result<DBTypes> {
+    let [tag] = read_array(input)?;
+    Ok(match tag {
+        NIL => DBTypes::Nil,
+        UNIT => DBTypes::Unit(()),
+        BOOLEAN => match read_array(input)? {
+            [0] => DBTypes::Boolean(false),
+            [1] => DBTypes::Boolean(true),
+            _ => return Err(corrupted()),
+        },
+        NUMBER => {
+            let n = i64::from_le_bytes(read_array(input)?);
+            DBTypes::Number(isize::try_from(n).map_err(|_| corrupted())?)
+        }
+        FLOAT => DBTypes::Float(read_f64(input)?),
+        TEXT => DBTypes::Text(read_text(input)?),
+        LIST if depth == MAX_DEPTH => return Err(too_deep()),
+        LIST => {
+            let len = read_len(input)?;
+            DBTypes::List(
+                (0..len)
+                    .map(|_| read_nested(input, depth + 1))
+                    .collect::<io::Result<_>>()?,
+            )
+        }
+        SET => {
+            let len = read_len(input)?;
+            DBTypes::Set(
+                (0..len)
+                    .map(|_| read_text(input))
+                    .collect::<io::Result<BTreeSet<_>>>()?,
+            )
+        }
+        SORTED_SET => {
+            let len = read_len(input)?;
+            DBTypes::SortedSet(
+                (0..len)
+                    .map(|_| Ok((read_text(input)?, read_f64(input)?)))
+                    .collect::<io::Result<BTreeMap<_, _>>>()?,
+            )
+        }
+        BYTES => DBTypes::Bytes(read_bytes(input)?),
+        TIMESTAMP => {
+            let seconds = i64::from_le_bytes(read_array(input)?);
+            let nanoseconds = u32::from_le_bytes(read_array(input)?);
+            DBTypes::Timestamp(
+                DateTime::from_timestamp(seconds, nanoseconds).ok_or_else(corrupted)?,
+            )
+        }
+        DURATION => {
+            let seconds = i64::from_le_bytes(read_array(input)?);
+            let nanoseconds = i32::from_le_bytes(read_array(input)?);
+            DBTypes::Duration(
+                Duration::try_seconds(seconds)
+                    .and_then(|d| d.checked_add(&Duration::nanoseconds(nanoseconds.into())))
+                    .ok_or_else(corrupted)?,
+            )
+        }
+        BIGINT => DBTypes::BigInt(BigInt::from_signed_bytes_le(&read_bytes(input)?)),
+        DECIMAL => DBTypes::Decimal(Decimal::deserialize(read_array(input)?)),
+        _ => return Err(corrupted()),
+    })
+}
@@ -0,0 +1,77 @@
+use std::thread;
+
+use db::{extract, DBTypes, Database};
+
+fn entries(pairs: &[(&str, isize)]) -> Vec<(String, DBTypes)> {
+    pairs
+        .iter()
+        .map(|(key, n)| (key.to_string(), DBTypes::Number(*n)))
+        .collect()
+}
+
+fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|k| k.to_string()).collect()
+}
+
+#[test]
+fn batches_return_what_they_replaced_in_order() {
+    let db = Database::default();
+    db.put(String::from("b"), DBTypes::Number(0)).unwrap();
+
+    assert_eq!(
+        db.put_many(entries(&[("a", 1), ("b", 2), ("a", 3)])),
+        Ok(vec![
+            None,
+            Some(DBTypes::Number(0)),
+            Some(DBTypes::Number(1))
+        ])
+    );
+    assert_eq!(
+        db.get_many(&keys(&["b", "missing", "a"])),
+        vec![Some(DBTypes::Number(2)), None, Some(DBTypes::Number(3))]
+    );
+    assert_eq!(
+        db.remove_many(&keys(&["a", "a", "missing"])),
+        Ok(vec![Some(DBTypes::Number(3)), None, None])
+    );
+    assert_eq!(db.get("a"), None);
+    assert_eq!(db.get("b"), Some(DBTypes::Number(2)));
+}
+
+#[test]
+fn batches_keep_indexes_current() {
+    let db = Database::default();
+    db.create_index("n", extract::value());
+
+    db.put_many(entries(&[("a", 1), ("b", 1), ("c", 2)]))
+        .unwrap();
+    assert_eq!(
+        db.find_by("n", &DBTypes::Number(1)),
+        Some(keys(&["a", "b"]))
+    );
+
+    db.remove_many(&keys(&["a", "c"])).unwrap();
+    assert_eq!(db.find_by("n", &DBTypes::Number(1)), Some(keys(&["b"])));
+    assert_eq!(db.find_by("n", &DBTypes::Number(2)), Some(vec![]));
+}
+
+#[test]
+fn readers_see_a_whole_batch_or_none_of_it() {
+    let db = Database::default();
+    db.put_many(entries(&[("x", 0), ("y", 0)])).unwrap();
+
+    let writer = {
+        let db = db.clone();
+        thread::spawn(move || {
+            for n in 1..=1000 {
+                db.put_many(entries(&[("x", n), ("y", n)])).unwrap();
+            }
+        })
+    };
+    for _ in 0..1000 {
+        let values = db.get_many(&keys(&["x", "y"]));
+        assert_eq!(values[0], values[1]);
+    }
+    writer.join().unwrap();
+    assert_eq!(db.get("y"), Some(DBTypes::Number(1000)));
+}
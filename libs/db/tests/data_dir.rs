@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use db::{DBTypes, DataDir, Database, DatabaseError};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoya-data-dir-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn database(dir: &PathBuf) -> Database {
+    let db = Database::default().with_data_dir(DataDir::new(dir).unwrap());
+    db.put(String::from("k"), DBTypes::Number(1)).unwrap();
+    db
+}
+
+fn error<T>(result: Result<T, Box<dyn Error>>) -> DatabaseError {
+    match result
+        .map(|_| ())
+        .map_err(|e| e.downcast::<DatabaseError>())
+    {
+        Ok(()) => panic!("expected an error"),
+        Err(Ok(e)) => *e,
+        Err(Err(e)) => panic!("expected a database error, got {e}"),
+    }
+}
+
+fn invalid(name: &str) -> DatabaseError {
+    DatabaseError::InvalidName {
+        name: name.to_owned(),
+    }
+}
+
+#[test]
+fn names_that_reach_outside_are_refused() {
+    let dir = scratch("names");
+    let outside = scratch("names-outside");
+    let db = database(&dir);
+
+    for name in ["", ".", "..", "../escape", "a/b", "a\\b", "/etc/passwd"] {
+        assert_eq!(db.data_dir().resolve(name), Err(invalid(name)), "{name:?}");
+    }
+    let absolute = outside.join("absolute").to_string_lossy().into_owned();
+    for name in ["", "../escape", "a/b", absolute.as_str()] {
+        assert_eq!(error(db.store(name)), invalid(name), "{name:?}");
+        assert_eq!(error(db.load(name)), invalid(name), "{name:?}");
+        assert_eq!(error(db.delete_snapshot(name)), invalid(name), "{name:?}");
+    }
+    assert_eq!(db.bgsave("../escape"), Err(invalid("../escape")));
+    assert!(fs::read_dir(&outside).unwrap().next().is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn symbolic_links_are_refused_even_when_dangling() {
+    use std::os::unix::fs::symlink;
+
+    let dir = scratch("links");
+    let outside = scratch("links-outside");
+    let db = database(&dir);
+
+    // Nothing exists at the target yet, so it can't be told from a new file by where it
+    // leads
+    symlink(outside.join("planted.hoya"), dir.join("dangling.hoya")).unwrap();
+    assert_eq!(error(db.store("dangling")), invalid("dangling"));
+    assert_eq!(
+        error(db.data_dir().create("dangling.hoya")),
+        invalid("dangling.hoya")
+    );
+    assert!(!outside.join("planted.hoya").exists());
+
+    fs::write(outside.join("secret"), "secret").unwrap();
+    symlink(outside.join("secret"), dir.join("secret")).unwrap();
+    assert_eq!(error(db.data_dir().open("secret")), invalid("secret"));
+
+    // Even a link that stays inside isn't followed
+    db.store("real").unwrap();
+    symlink(dir.join("real.hoya"), dir.join("alias.hoya")).unwrap();
+    assert_eq!(error(db.load("alias")), invalid("alias"));
+    assert_eq!(db.snapshots().unwrap(), ["real"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn a_link_in_place_of_the_temporary_file_is_replaced_not_written_through() {
+    use std::os::unix::fs::symlink;
+
+    let dir = scratch("temporary");
+    let outside = scratch("temporary-outside");
+    let db = database(&dir);
+
+    symlink(outside.join("victim"), dir.join("dump.hoya.tmp")).unwrap();
+    db.store("dump").unwrap();
+    assert!(!outside.join("victim").exists());
+    assert!(fs::symlink_metadata(dir.join("dump.hoya.tmp")).is_err());
+
+    let loaded = Database::default().with_data_dir(DataDir::new(&dir).unwrap());
+    loaded.load("dump").unwrap();
+    assert_eq!(loaded.get("k"), Some(DBTypes::Number(1)));
+}
+
+#[test]
+fn snapshots_lists_only_what_load_reads_and_delete_snapshot_removes_one() {
+    let dir = scratch("listing");
+    let db = database(&dir);
+    db.store("b").unwrap();
+    db.store("a").unwrap();
+    fs::write(dir.join("notes.txt"), "").unwrap();
+    fs::create_dir(dir.join("folder.hoya")).unwrap();
+
+    assert_eq!(db.snapshots().unwrap(), ["a", "b"]);
+    db.delete_snapshot("a").unwrap();
+    assert_eq!(db.snapshots().unwrap(), ["b"]);
+
+    let missing = DatabaseError::NotFound {
+        name: String::from("a.hoya"),
+    };
+    assert_eq!(error(db.delete_snapshot("a")), missing);
+    assert_eq!(error(db.load("a")), missing);
+    assert_eq!(
+        error(db.data_dir().open("notes.md")),
+        DatabaseError::NotFound {
+            name: String::from("notes.md")
+        }
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_files_are_reported_as_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = scratch("denied");
+    let db = database(&dir);
+    db.store("locked").unwrap();
+    let file = dir.join("locked.hoya");
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o000)).unwrap();
+
+    // Permissions don't stop the superuser
+    if fs::read(&file).is_err() {
+        assert_eq!(
+            error(db.load("locked")),
+            DatabaseError::PermissionDenied {
+                name: String::from("locked.hoya")
+            }
+        );
+        assert_eq!(
+            error(db.data_dir().open("locked.hoya")),
+            DatabaseError::PermissionDenied {
+                name: String::from("locked.hoya")
+            }
+        );
+    }
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+    db.load("locked").unwrap();
+}
@@ -0,0 +1,318 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use db::{extract, DBTypes, Database, DatabaseError, LogEngine, MmapEngine, StorageEngine};
+
+/// A fresh path for a log, in a directory of its own
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoya-engines-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("log")
+}
+
+fn append(path: &PathBuf, bytes: &[u8]) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+fn number(n: isize) -> Option<DBTypes> {
+    Some(DBTypes::Number(n))
+}
+
+/// Writes a few changes, leaving `a` removed, `b` overwritten and `c` as written
+fn write_some(engine: &mut impl StorageEngine) {
+    assert_eq!(
+        engine.put(String::from("a"), DBTypes::Number(1)).unwrap(),
+        None
+    );
+    assert_eq!(
+        engine.put(String::from("b"), DBTypes::Number(2)).unwrap(),
+        None
+    );
+    assert_eq!(
+        engine.put(String::from("b"), DBTypes::Number(3)).unwrap(),
+        number(2)
+    );
+    assert_eq!(
+        engine
+            .put(String::from("c"), DBTypes::Text(String::from("c")))
+            .unwrap(),
+        None
+    );
+    assert_eq!(engine.remove("a").unwrap(), number(1));
+    assert_eq!(engine.remove("missing").unwrap(), None);
+    engine.flush().unwrap();
+}
+
+fn check_some(engine: &impl StorageEngine) {
+    assert_eq!(engine.get("a"), None);
+    assert_eq!(engine.get("b").map(|v| v.into_owned()), number(3));
+    assert!(engine.contains("c"));
+    let keys = engine.snapshot().into_keys().collect::<Vec<_>>();
+    assert_eq!(keys, ["b", "c"]);
+}
+
+#[test]
+fn the_log_engine_replays_its_log() {
+    let path = scratch("log-replay");
+    write_some(&mut LogEngine::open(&path).unwrap());
+    check_some(&LogEngine::open(&path).unwrap());
+}
+
+#[test]
+fn the_mmap_engine_replays_its_log() {
+    let path = scratch("mmap-replay");
+    write_some(&mut MmapEngine::open(&path).unwrap());
+    check_some(&MmapEngine::open(&path).unwrap());
+}
+
+#[test]
+fn replacing_rewrites_the_log_with_only_live_records() {
+    for (name, mmap) in [("log-replace", false), ("mmap-replace", true)] {
+        let path = scratch(name);
+        let open = |path: &PathBuf| -> Box<dyn StorageEngine> {
+            if mmap {
+                Box::new(MmapEngine::open(path).unwrap())
+            } else {
+                Box::new(LogEngine::open(path).unwrap())
+            }
+        };
+
+        let mut engine = open(&path);
+        for n in 0..100 {
+            engine.put(String::from("k"), DBTypes::Number(n)).unwrap();
+        }
+        engine.flush().unwrap();
+        let before = fs::metadata(&path).unwrap().len();
+
+        let records = engine.snapshot();
+        engine.replace(records).unwrap();
+        engine
+            .put(String::from("after"), DBTypes::Number(0))
+            .unwrap();
+        engine.flush().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < before);
+
+        let reopened = open(&path);
+        assert_eq!(reopened.get("k").map(|v| v.into_owned()), number(99));
+        assert_eq!(reopened.get("after").map(|v| v.into_owned()), number(0));
+    }
+}
+
+#[test]
+fn a_torn_final_record_is_dropped() {
+    for (name, mmap) in [("log-torn", false), ("mmap-torn", true)] {
+        let path = scratch(name);
+        let mut engine = LogEngine::open(&path).unwrap();
+        engine
+            .put(String::from("kept"), DBTypes::Number(1))
+            .unwrap();
+        engine.flush().unwrap();
+        drop(engine);
+        let complete = fs::metadata(&path).unwrap().len();
+
+        // The start of a record whose write never finished
+        let source = scratch(&format!("{name}-source"));
+        let mut torn = LogEngine::open(&source).unwrap();
+        torn.put(String::from("lost"), DBTypes::Number(2)).unwrap();
+        torn.flush().unwrap();
+        let source = fs::read(source).unwrap();
+        append(&path, &source[..source.len().min(7)]);
+
+        let mut engine: Box<dyn StorageEngine> = if mmap {
+            Box::new(MmapEngine::open(&path).unwrap())
+        } else {
+            Box::new(LogEngine::open(&path).unwrap())
+        };
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+        assert_eq!(engine.get("kept").map(|v| v.into_owned()), number(1));
+        assert_eq!(engine.get("lost"), None);
+
+        // Later writes follow the last complete record
+        engine
+            .put(String::from("next"), DBTypes::Number(3))
+            .unwrap();
+        engine.flush().unwrap();
+        drop(engine);
+        let reopened = LogEngine::open(&path).unwrap();
+        assert_eq!(reopened.get("next").map(|v| v.into_owned()), number(3));
+    }
+}
+
+#[test]
+fn a_torn_record_holding_a_record_is_still_torn() {
+    // A value that is itself the image of a record
+    let path = scratch("image-inner");
+    let mut inner = LogEngine::open(&path).unwrap();
+    inner
+        .put(String::from("inner"), DBTypes::Number(1))
+        .unwrap();
+    inner.flush().unwrap();
+    let image = fs::read(path).unwrap();
+    let path = scratch("image-outer");
+    let mut outer = LogEngine::open(&path).unwrap();
+    let mut value = image.clone();
+    value.extend_from_slice(&[0xff; 8]);
+    outer
+        .put(String::from("lost"), DBTypes::Bytes(value))
+        .unwrap();
+    outer.flush().unwrap();
+    let outer = fs::read(path).unwrap();
+
+    for (name, mmap) in [("log-image", false), ("mmap-image", true)] {
+        let path = scratch(name);
+        let mut engine = LogEngine::open(&path).unwrap();
+        engine
+            .put(String::from("kept"), DBTypes::Number(1))
+            .unwrap();
+        engine.flush().unwrap();
+        drop(engine);
+        let complete = fs::metadata(&path).unwrap().len();
+
+        // The write stopped just past the image inside the value
+        let inside = outer
+            .windows(image.len())
+            .position(|window| window == image)
+            .unwrap();
+        append(&path, &outer[..inside + image.len() + 2]);
+
+        let engine: Box<dyn StorageEngine> = if mmap {
+            Box::new(MmapEngine::open(&path).unwrap())
+        } else {
+            Box::new(LogEngine::open(&path).unwrap())
+        };
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+        assert_eq!(engine.get("kept").map(|v| v.into_owned()), number(1));
+        assert_eq!(engine.get("inner"), None);
+        assert_eq!(engine.get("lost"), None);
+    }
+}
+
+#[test]
+fn a_zero_filled_tail_is_not_a_record() {
+    let path = scratch("zeroes");
+    let mut engine = LogEngine::open(&path).unwrap();
+    engine
+        .put(String::from("kept"), DBTypes::Number(1))
+        .unwrap();
+    engine.flush().unwrap();
+    drop(engine);
+    let complete = fs::metadata(&path).unwrap().len();
+    append(&path, &[0; 64]);
+
+    let engine = MmapEngine::open(&path).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+    assert!(!engine.contains(""));
+    assert_eq!(engine.snapshot().len(), 1);
+}
+
+#[test]
+fn corruption_before_the_end_is_an_error() {
+    let path = scratch("corrupt");
+    let mut engine = LogEngine::open(&path).unwrap();
+    engine
+        .put(String::from("first"), DBTypes::Number(1))
+        .unwrap();
+    engine
+        .put(String::from("second"), DBTypes::Number(2))
+        .unwrap();
+    engine.flush().unwrap();
+    drop(engine);
+
+    let mut log = fs::read(&path).unwrap();
+    // A bit flipped in the value of the first record
+    log[20] ^= 1;
+    fs::write(&path, &log).unwrap();
+
+    for error in [
+        LogEngine::open(&path).unwrap_err(),
+        MmapEngine::open(&path).unwrap_err(),
+    ] {
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "The log is corrupt at byte 0");
+    }
+    // Nothing was truncated away
+    assert_eq!(fs::read(&path).unwrap(), log);
+}
+
+#[test]
+fn a_damaged_length_before_the_end_is_not_a_torn_record() {
+    let path = scratch("corrupt-length");
+    let mut engine = LogEngine::open(&path).unwrap();
+    engine
+        .put(String::from("first"), DBTypes::Number(1))
+        .unwrap();
+    engine
+        .put(String::from("second"), DBTypes::Number(2))
+        .unwrap();
+    engine.flush().unwrap();
+    drop(engine);
+
+    let mut log = fs::read(&path).unwrap();
+    // The key length of the first record, now running far past the end of the log
+    log[8] = 0x7f;
+    fs::write(&path, &log).unwrap();
+
+    for error in [
+        LogEngine::open(&path).unwrap_err(),
+        MmapEngine::open(&path).unwrap_err(),
+    ] {
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "The log is corrupt at byte 0");
+    }
+    assert_eq!(fs::read(&path).unwrap(), log);
+}
+
+#[test]
+fn a_failed_write_is_not_applied_and_stops_later_writes() {
+    let mut engine = MmapEngine::open("/dev/full").unwrap();
+    let error = engine
+        .put(String::from("k"), DBTypes::Number(1))
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::StorageFull);
+    assert_eq!(engine.get("k"), None);
+
+    let later = engine
+        .put(String::from("other"), DBTypes::Number(2))
+        .unwrap_err();
+    assert!(later.to_string().starts_with("An earlier write failed"));
+    assert!(engine.flush().is_err());
+}
+
+#[test]
+fn the_database_reports_failed_writes_and_leaves_indexes_alone() {
+    let db = Database::with_engine(MmapEngine::open("/dev/full").unwrap());
+    db.create_index("n", extract::value());
+
+    assert!(matches!(
+        db.put(String::from("k"), DBTypes::Number(1)),
+        Err(DatabaseError::Storage(_))
+    ));
+    assert_eq!(db.get("k"), None);
+    assert_eq!(db.find_by("n", &DBTypes::Number(1)), Some(vec![]));
+    assert!(matches!(
+        db.rpush("l", DBTypes::Number(1)),
+        Err(DatabaseError::Storage(_))
+    ));
+    assert_eq!(db.get("l"), None);
+}
+
+#[test]
+fn a_database_over_a_log_engine_keeps_its_indexes_after_reopening() {
+    let path = scratch("database");
+    {
+        let db = Database::with_engine(LogEngine::open(&path).unwrap());
+        db.put(String::from("a"), DBTypes::Number(1)).unwrap();
+        db.rpush("l", DBTypes::Number(2)).unwrap();
+        db.flush().unwrap();
+    }
+    let db = Database::with_engine(LogEngine::open(&path).unwrap());
+    db.create_index("n", extract::value());
+    assert_eq!(
+        db.find_by("n", &DBTypes::Number(1)),
+        Some(vec![String::from("a")])
+    );
+    assert_eq!(db.get("l"), Some(DBTypes::List(vec![DBTypes::Number(2)])));
+}
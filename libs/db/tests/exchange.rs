@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::ControlFlow;
+
+use chrono::{DateTime, Duration, Utc};
+use db::{DBTypes, Database, Format, ImportMode, Query};
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
+
+const FORMATS: [Format; 3] = [Format::Json, Format::Ndjson, Format::Csv];
+
+/// A record of every type, with the values that are easy to lose in text
+fn every_type() -> Vec<(String, DBTypes)> {
+    let timestamp = DateTime::parse_from_rfc3339("2024-02-29T12:34:56.123456789Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    [
+        (
+            "bigint",
+            DBTypes::BigInt("-123456789012345678901234567890".parse::<BigInt>().unwrap()),
+        ),
+        ("boolean", DBTypes::Boolean(false)),
+        ("bytes", DBTypes::Bytes(vec![0, 1, 0xab, 0xff])),
+        (
+            "decimal",
+            DBTypes::Decimal("-0.000000000000000000000000001".parse::<Decimal>().unwrap()),
+        ),
+        ("duration", DBTypes::Duration(Duration::milliseconds(-1500))),
+        (
+            "duration:long",
+            DBTypes::Duration(Duration::days(10_000) + Duration::nanoseconds(1)),
+        ),
+        ("float", DBTypes::Float(1.0)),
+        ("float:-0", DBTypes::Float(-0.0)),
+        ("float:-inf", DBTypes::Float(f64::NEG_INFINITY)),
+        ("float:inf", DBTypes::Float(f64::INFINITY)),
+        ("float:nan", DBTypes::Float(f64::NAN)),
+        ("float:precise", DBTypes::Float(0.1 + 0.2)),
+        (
+            "list",
+            DBTypes::List(vec![
+                DBTypes::Nil,
+                DBTypes::Unit(()),
+                DBTypes::Number(1),
+                DBTypes::Float(1.0),
+                DBTypes::List(vec![]),
+            ]),
+        ),
+        ("number", DBTypes::Number(1)),
+        ("number:min", DBTypes::Number(isize::MIN)),
+        (
+            "set",
+            DBTypes::Set(BTreeSet::from([String::from("a,b"), String::from("\"c\"")])),
+        ),
+        (
+            "sorted-set",
+            DBTypes::SortedSet(BTreeMap::from([
+                (String::from("low"), f64::NEG_INFINITY),
+                (String::from("mid"), 0.5),
+                (String::from("nan"), f64::NAN),
+            ])),
+        ),
+        ("text", DBTypes::Text(String::from("a, \"quoted\"\nline"))),
+        ("text:empty", DBTypes::Text(String::new())),
+        ("text:null", DBTypes::Text(String::from("null"))),
+        ("timestamp", DBTypes::Timestamp(timestamp)),
+        ("unit", DBTypes::Unit(())),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_owned(), value))
+    .collect()
+}
+
+/// Every record, with values in their debug form so that NaNs compare equal
+fn records(db: &Database) -> Vec<(String, String)> {
+    let mut records = vec![];
+    db.scan(&Query::new(None), |entry| {
+        records.push((entry.key.to_owned(), format!("{:?}", entry.value)));
+        ControlFlow::Continue(())
+    });
+    records
+}
+
+fn exported(db: &Database, format: Format) -> Vec<u8> {
+    let mut out = vec![];
+    db.export(&mut out, format).unwrap();
+    out
+}
+
+fn with(records: &[(&str, isize)]) -> Database {
+    let db = Database::default();
+    for (key, n) in records {
+        db.put(key.to_string(), DBTypes::Number(*n)).unwrap();
+    }
+    db
+}
+
+#[test]
+fn every_type_round_trips_through_every_format() {
+    let db = Database::default();
+    db.put_many(every_type()).unwrap();
+
+    for format in FORMATS {
+        let out = exported(&db, format);
+        let imported = Database::default();
+        let count = imported
+            .import(out.as_slice(), format, ImportMode::Merge)
+            .unwrap_or_else(|e| panic!("{format}: {e}"));
+        assert_eq!(count as usize, every_type().len(), "{format}");
+        assert_eq!(records(&imported), records(&db), "{format}");
+    }
+}
+
+#[test]
+fn merging_keeps_records_the_import_lacks() {
+    for format in FORMATS {
+        let input = exported(&with(&[("a", 1), ("b", 2)]), format);
+        let db = with(&[("b", 0), ("c", 3)]);
+        db.import(input.as_slice(), format, ImportMode::Merge)
+            .unwrap();
+        assert_eq!(db.get("a"), Some(DBTypes::Number(1)), "{format}");
+        assert_eq!(db.get("b"), Some(DBTypes::Number(2)), "{format}");
+        assert_eq!(db.get("c"), Some(DBTypes::Number(3)), "{format}");
+    }
+}
+
+#[test]
+fn replacing_removes_records_the_import_lacks() {
+    for format in FORMATS {
+        let input = exported(&with(&[("a", 1), ("b", 2)]), format);
+        let db = with(&[("b", 0), ("c", 3)]);
+        db.import(input.as_slice(), format, ImportMode::Replace)
+            .unwrap();
+        assert_eq!(db.get("a"), Some(DBTypes::Number(1)), "{format}");
+        assert_eq!(db.get("b"), Some(DBTypes::Number(2)), "{format}");
+        assert_eq!(db.get("c"), None, "{format}");
+    }
+}
+
+#[test]
+fn a_malformed_record_after_a_full_batch_keeps_what_came_before() {
+    let source = Database::default();
+    source
+        .put_many((0..1500).map(|n| (format!("key:{n:04}"), DBTypes::Number(n))))
+        .unwrap();
+
+    let ndjson = String::from_utf8(exported(&source, Format::Ndjson)).unwrap() + "{\"key\": 1}\n";
+    let mut csv = String::from_utf8(exported(&source, Format::Csv)).unwrap();
+    csv.push_str("bad,number,one\n");
+
+    for (format, input, message) in [
+        (
+            Format::Ndjson,
+            ndjson,
+            "Line 1501: Expected a `key` holding text",
+        ),
+        (
+            Format::Csv,
+            csv,
+            "Line 1502: `\"one\"` isn't a valid number",
+        ),
+    ] {
+        let db = with(&[("stale", 0)]);
+        let error = db
+            .import(input.as_bytes(), format, ImportMode::Replace)
+            .unwrap_err();
+        assert_eq!(error.to_string(), message, "{format}");
+
+        // The first batch landed, the rest didn't, and nothing was removed
+        assert_eq!(db.get("key:0000"), Some(DBTypes::Number(0)), "{format}");
+        assert_eq!(db.get("key:1023"), Some(DBTypes::Number(1023)), "{format}");
+        assert_eq!(db.get("key:1024"), None, "{format}");
+        assert_eq!(db.get("stale"), Some(DBTypes::Number(0)), "{format}");
+    }
+}
+
+#[test]
+fn out_of_range_durations_are_refused() {
+    let max_seconds = Duration::MAX.num_seconds();
+    for payload in [
+        format!("{{\"seconds\": {}, \"nanoseconds\": 0}}", i64::MAX),
+        format!("{{\"seconds\": {max_seconds}, \"nanoseconds\": 999999999}}"),
+        format!(
+            "{{\"seconds\": {}, \"nanoseconds\": -999999999}}",
+            -max_seconds
+        ),
+        String::from("{\"seconds\": 0, \"nanoseconds\": 1000000000}"),
+        String::from("{\"seconds\": 0, \"nanoseconds\": -1000000000}"),
+        String::from("{\"seconds\": 1}"),
+    ] {
+        let input = format!("{{\"k\": {{\"duration\": {payload}}}}}");
+        let error = Database::default()
+            .import(input.as_bytes(), Format::Json, ImportMode::Merge)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("isn't a valid duration"),
+            "{payload}: {error}"
+        );
+    }
+
+    let input =
+        format!("{{\"k\": {{\"duration\": {{\"seconds\": {max_seconds}, \"nanoseconds\": 0}}}}}}");
+    let db = Database::default();
+    db.import(input.as_bytes(), Format::Json, ImportMode::Merge)
+        .unwrap();
+    assert_eq!(
+        db.get("k"),
+        Some(DBTypes::Duration(Duration::seconds(max_seconds)))
+    );
+}
+
+#[test]
+fn values_must_name_one_known_type() {
+    for (input, message) in [
+        (
+            "{\"k\": 1}",
+            "Expected a value like {\"text\": \"…\"}, but found 1",
+        ),
+        (
+            "{\"k\": {\"number\": 1, \"float\": 1.0}}",
+            "Expected a value with exactly one type",
+        ),
+        ("{\"k\": {\"number\": 1.5}}", "`1.5` isn't a valid number"),
+        ("{\"k\": {\"nil\": 0}}", "Unknown type `nil` with 0"),
+        ("{\"k\": {\"colour\": \"red\"}}", "Unknown type `colour`"),
+    ] {
+        let error = Database::default()
+            .import(input.as_bytes(), Format::Json, ImportMode::Merge)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains(message), "{input}: {error}");
+    }
+}
@@ -0,0 +1,98 @@
+use db::{extract, DBTypes, DataDir, Database};
+
+fn user(name: &str, age: isize) -> DBTypes {
+    DBTypes::List(vec![DBTypes::Text(name.to_owned()), DBTypes::Number(age)])
+}
+
+#[test]
+fn an_index_is_built_from_existing_records_and_kept_current() {
+    let db = Database::default();
+    db.put(String::from("u1"), user("ann", 31)).unwrap();
+    db.put(String::from("u2"), user("bob", 25)).unwrap();
+    db.create_index("age", extract::nth(1));
+
+    db.put(String::from("u3"), user("cy", 31)).unwrap();
+    db.put(String::from("u2"), user("bob", 26)).unwrap();
+    db.remove("u1").unwrap();
+
+    let age = |n| db.find_by("age", &DBTypes::Number(n));
+    assert_eq!(age(31), Some(vec![String::from("u3")]));
+    assert_eq!(age(25), Some(vec![]));
+    assert_eq!(age(26), Some(vec![String::from("u2")]));
+}
+
+#[test]
+fn find_range_is_inclusive_and_ordered_by_value() {
+    let db = Database::default();
+    for (key, n) in [("a", 5), ("b", 1), ("c", 3), ("d", 9)] {
+        db.put(key.to_owned(), DBTypes::Number(n)).unwrap();
+    }
+    db.create_index("n", extract::value());
+
+    let range = |lo, hi| db.find_range("n", &DBTypes::Number(lo), &DBTypes::Number(hi));
+    assert_eq!(
+        range(1, 5),
+        Some(vec![
+            String::from("b"),
+            String::from("c"),
+            String::from("a")
+        ])
+    );
+    assert_eq!(range(6, 2), Some(vec![]));
+}
+
+#[test]
+fn an_elements_index_lists_a_key_once_per_range() {
+    let db = Database::default();
+    db.create_index("tags", extract::elements());
+    db.put(
+        String::from("post"),
+        DBTypes::List(vec![DBTypes::Number(1), DBTypes::Number(2)]),
+    )
+    .unwrap();
+    db.put(String::from("scalar"), DBTypes::Number(1)).unwrap();
+
+    assert_eq!(
+        db.find_range("tags", &DBTypes::Number(0), &DBTypes::Number(9)),
+        Some(vec![String::from("post")])
+    );
+}
+
+#[test]
+fn dropped_and_unknown_indexes_find_nothing() {
+    let db = Database::default();
+    db.create_index("a", extract::value());
+    db.create_index("b", extract::value());
+    assert_eq!(db.indexes(), vec![String::from("a"), String::from("b")]);
+
+    assert!(db.drop_index("a"));
+    assert!(!db.drop_index("a"));
+    assert_eq!(db.indexes(), vec![String::from("b")]);
+    assert_eq!(db.find_by("a", &DBTypes::Number(1)), None);
+    assert_eq!(
+        db.find_range("a", &DBTypes::Number(1), &DBTypes::Number(2)),
+        None
+    );
+}
+
+#[test]
+fn load_rebuilds_indexes() {
+    let dir = std::env::temp_dir().join(format!("indexes-load-{}", std::process::id()));
+    let dir = DataDir::new(dir).unwrap();
+    let db = Database::default().with_data_dir(dir.clone());
+    db.put(String::from("k"), DBTypes::Number(7)).unwrap();
+    db.store("load").unwrap();
+
+    let other = Database::default().with_data_dir(dir);
+    other.create_index("n", extract::value());
+    other
+        .put(String::from("stale"), DBTypes::Number(7))
+        .unwrap();
+    other.load("load").unwrap();
+    other.delete_snapshot("load").unwrap();
+
+    assert_eq!(
+        other.find_by("n", &DBTypes::Number(7)),
+        Some(vec![String::from("k")])
+    );
+}
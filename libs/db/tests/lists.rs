@@ -0,0 +1,69 @@
+use db::{DBTypes, Database, DatabaseError};
+
+fn numbers(items: &[isize]) -> Vec<DBTypes> {
+    items.iter().map(|n| DBTypes::Number(*n)).collect()
+}
+
+#[test]
+fn pushes_and_pops_work_at_both_ends() {
+    let db = Database::default();
+    assert_eq!(db.rpush("l", DBTypes::Number(2)), Ok(1));
+    assert_eq!(db.rpush("l", DBTypes::Number(3)), Ok(2));
+    assert_eq!(db.lpush("l", DBTypes::Number(1)), Ok(3));
+    assert_eq!(db.get("l"), Some(DBTypes::List(numbers(&[1, 2, 3]))));
+
+    assert_eq!(db.lpop("l"), Ok(Some(DBTypes::Number(1))));
+    assert_eq!(db.rpop("l"), Ok(Some(DBTypes::Number(3))));
+    assert_eq!(db.rpop("l"), Ok(Some(DBTypes::Number(2))));
+    assert_eq!(db.rpop("l"), Ok(None));
+    assert_eq!(db.lpop("missing"), Ok(None));
+    assert_eq!(db.get("l"), Some(DBTypes::List(vec![])));
+}
+
+#[test]
+fn lrange_takes_inclusive_and_negative_positions() {
+    let db = Database::default();
+    for n in 0..5 {
+        db.rpush("l", DBTypes::Number(n)).unwrap();
+    }
+
+    assert_eq!(db.lrange("l", 1, 3), Ok(numbers(&[1, 2, 3])));
+    assert_eq!(db.lrange("l", -2, -1), Ok(numbers(&[3, 4])));
+    assert_eq!(db.lrange("l", 0, 99), Ok(numbers(&[0, 1, 2, 3, 4])));
+    assert_eq!(db.lrange("l", -99, 0), Ok(numbers(&[0])));
+    assert_eq!(db.lrange("l", 3, 1), Ok(vec![]));
+    assert_eq!(db.lrange("missing", 0, -1), Ok(vec![]));
+}
+
+#[test]
+fn list_operations_refuse_other_values() {
+    let db = Database::default();
+    db.put(String::from("n"), DBTypes::Number(1)).unwrap();
+    let wrong = Err(DatabaseError::WrongType {
+        key: String::from("n"),
+        expected: "list",
+    });
+
+    assert_eq!(db.rpush("n", DBTypes::Number(2)), wrong.clone().map(|()| 0));
+    assert_eq!(db.lpop("n"), wrong.clone().map(|()| None));
+    assert_eq!(db.lrange("n", 0, -1), wrong.map(|()| vec![]));
+    assert_eq!(db.get("n"), Some(DBTypes::Number(1)));
+}
+
+#[test]
+fn pushes_keep_indexes_current() {
+    let db = Database::default();
+    db.create_index("first", db::extract::nth(0));
+    db.rpush("l", DBTypes::Number(7)).unwrap();
+    assert_eq!(
+        db.find_by("first", &DBTypes::Number(7)),
+        Some(vec![String::from("l")])
+    );
+
+    db.lpush("l", DBTypes::Number(8)).unwrap();
+    assert_eq!(db.find_by("first", &DBTypes::Number(7)), Some(vec![]));
+    assert_eq!(
+        db.find_by("first", &DBTypes::Number(8)),
+        Some(vec![String::from("l")])
+    );
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use db::{DBTypes, LsmEngine, LsmOptions, StorageEngine};
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoya-lsm-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Options that write every change out as a table of its own
+fn tiny(compaction_threshold: usize) -> LsmOptions {
+    LsmOptions {
+        memtable_size: 1,
+        compaction_threshold,
+        ..LsmOptions::default()
+    }
+}
+
+fn tables(dir: &Path) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("sst".as_ref()))
+        .count()
+}
+
+/// Waits for the background compaction to leave at most `count` tables
+fn wait_for_tables(dir: &Path, count: usize) {
+    let start = Instant::now();
+    while tables(dir) > count {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "compaction never finished"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn number(n: isize) -> Option<DBTypes> {
+    Some(DBTypes::Number(n))
+}
+
+fn scanned(engine: &LsmEngine) -> Vec<(String, DBTypes)> {
+    engine
+        .scan((Bound::Unbounded, Bound::Unbounded))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect()
+}
+
+#[test]
+fn unflushed_writes_are_replayed_from_the_log() {
+    let dir = dir("replay");
+    {
+        let mut engine = LsmEngine::open(&dir).unwrap();
+        engine.put(String::from("a"), DBTypes::Number(1)).unwrap();
+        engine.put(String::from("b"), DBTypes::Number(2)).unwrap();
+        assert_eq!(engine.remove("a").unwrap(), number(1));
+        engine.flush().unwrap();
+    }
+    assert_eq!(tables(&dir), 0);
+
+    let engine = LsmEngine::open(&dir).unwrap();
+    assert_eq!(engine.get("a"), None);
+    assert_eq!(engine.get("b").map(|v| v.into_owned()), number(2));
+    drop(engine);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn a_full_memtable_is_written_out_as_a_table() {
+    let dir = dir("memtable");
+    let options = LsmOptions {
+        memtable_size: 256,
+        compaction_threshold: 100,
+        ..LsmOptions::default()
+    };
+    {
+        let mut engine = LsmEngine::open_with(&dir, options.clone()).unwrap();
+        for n in 0..100 {
+            engine
+                .put(format!("key:{n:03}"), DBTypes::Number(n))
+                .unwrap();
+        }
+        engine.flush().unwrap();
+        assert!(tables(&dir) > 1);
+        assert_eq!(engine.get("key:000").map(|v| v.into_owned()), number(0));
+    }
+
+    let engine = LsmEngine::open_with(&dir, options).unwrap();
+    for n in 0..100 {
+        assert_eq!(
+            engine.get(&format!("key:{n:03}")).map(|v| v.into_owned()),
+            number(n)
+        );
+    }
+    drop(engine);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn compaction_merges_tables_keeping_the_newest_values() {
+    let dir = dir("compaction");
+    {
+        let mut engine = LsmEngine::open_with(&dir, tiny(4)).unwrap();
+        for n in 0..4 {
+            engine.put(String::from("k"), DBTypes::Number(n)).unwrap();
+        }
+        wait_for_tables(&dir, 1);
+        assert_eq!(engine.get("k").map(|v| v.into_owned()), number(3));
+    }
+
+    let engine = LsmEngine::open_with(&dir, tiny(4)).unwrap();
+    assert_eq!(engine.get("k").map(|v| v.into_owned()), number(3));
+    drop(engine);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn removals_survive_compaction() {
+    let dir = dir("removals");
+    {
+        let mut engine = LsmEngine::open_with(&dir, tiny(3)).unwrap();
+        engine
+            .put(String::from("gone"), DBTypes::Number(1))
+            .unwrap();
+        engine
+            .put(String::from("kept"), DBTypes::Number(2))
+            .unwrap();
+        assert_eq!(engine.remove("gone").unwrap(), number(1));
+        wait_for_tables(&dir, 1);
+        assert_eq!(engine.get("gone"), None);
+    }
+
+    let engine = LsmEngine::open_with(&dir, tiny(3)).unwrap();
+    assert_eq!(engine.get("gone"), None);
+    assert_eq!(
+        scanned(&engine),
+        [(String::from("kept"), DBTypes::Number(2))]
+    );
+    drop(engine);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn scans_merge_the_memtable_and_tables_in_key_order() {
+    let dir = dir("scan");
+    let mut engine = LsmEngine::open_with(&dir, tiny(100)).unwrap();
+    for key in ["d", "b", "a", "c"] {
+        engine.put(key.to_owned(), DBTypes::Number(0)).unwrap();
+    }
+    engine.put(String::from("b"), DBTypes::Number(1)).unwrap();
+    engine.remove("c").unwrap();
+    let options = LsmOptions {
+        memtable_size: 1 << 20,
+        ..tiny(100)
+    };
+    drop(engine);
+
+    // Writes that stay in the memtable, over the tables written above
+    let mut engine = LsmEngine::open_with(&dir, options).unwrap();
+    engine.put(String::from("e"), DBTypes::Number(2)).unwrap();
+    engine.put(String::from("a"), DBTypes::Number(3)).unwrap();
+    engine.remove("d").unwrap();
+
+    assert_eq!(
+        scanned(&engine),
+        [
+            (String::from("a"), DBTypes::Number(3)),
+            (String::from("b"), DBTypes::Number(1)),
+            (String::from("e"), DBTypes::Number(2)),
+        ]
+    );
+    let from_b = engine
+        .scan((Bound::Included("b"), Bound::Excluded("e")))
+        .map(|(key, _)| key.into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(from_b, ["b"]);
+    drop(engine);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn a_failed_read_stops_writes_until_replaced() {
+    let dir = dir("failed-read");
+    let options = LsmOptions {
+        memtable_size: 1,
+        ..LsmOptions::default()
+    };
+    {
+        let mut engine = LsmEngine::open_with(&dir, options.clone()).unwrap();
+        engine
+            .put(String::from("key"), DBTypes::Text("x".repeat(4096)))
+            .unwrap();
+        engine.flush().unwrap();
+    }
+    // Garble the table the value was written out to
+    let table = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.file_name().unwrap() != "wal.log")
+        .unwrap();
+    let mut file = OpenOptions::new().write(true).open(&table).unwrap();
+    file.seek(SeekFrom::Start(16)).unwrap();
+    file.write_all(&[0xff; 64]).unwrap();
+    drop(file);
+
+    let mut engine = LsmEngine::open_with(&dir, options).unwrap();
+    assert!(engine.get("key").is_none());
+    assert!(
+        engine
+            .put(String::from("other"), DBTypes::Number(1))
+            .is_err(),
+        "writes are refused once a read failed"
+    );
+    assert!(engine.get("other").is_none());
+    assert!(engine.flush().is_err());
+    assert!(
+        engine.flush().is_err(),
+        "the error is reported until replaced"
+    );
+
+    let records = BTreeMap::from([(String::from("key"), DBTypes::Number(2))]);
+    engine.replace(records).unwrap();
+    engine.flush().unwrap();
+    engine
+        .put(String::from("other"), DBTypes::Number(1))
+        .unwrap();
+    assert_eq!(engine.get("other").as_deref(), Some(&DBTypes::Number(1)));
+    drop(engine);
+    let _ = fs::remove_dir_all(&dir);
+}
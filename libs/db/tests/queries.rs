@@ -0,0 +1,172 @@
+use std::ops::{Bound, ControlFlow};
+
+use db::{extract, Comparison, DBTypes, Database, Field, Plan, Predicate, Query};
+
+fn people() -> Database {
+    let db = Database::default();
+    for (key, name, age) in [
+        ("user:1", "ann", 31),
+        ("user:2", "bob", 25),
+        ("user:3", "cy", 40),
+        ("post:1", "hello", 0),
+    ] {
+        db.put(
+            key.to_owned(),
+            DBTypes::List(vec![DBTypes::Text(name.to_owned()), DBTypes::Number(age)]),
+        )
+        .unwrap();
+    }
+    db
+}
+
+fn keys(db: &Database, query: &Query) -> Vec<String> {
+    let mut keys = vec![];
+    db.scan(query, |entry| {
+        keys.push(entry.key.to_owned());
+        ControlFlow::Continue(())
+    });
+    keys
+}
+
+fn age(op: Comparison, n: isize) -> Predicate {
+    Predicate::new(Field::from("age"), op, DBTypes::Number(n))
+}
+
+#[test]
+fn a_prefix_becomes_a_key_range() {
+    let db = people();
+    let query = Query::new(Some(String::from("user:")));
+
+    assert_eq!(
+        db.plan(&query),
+        Plan::KeyRange {
+            start: Bound::Included(String::from("user:")),
+            end: Bound::Excluded(String::from("user;")),
+        }
+    );
+    assert_eq!(keys(&db, &query), ["user:1", "user:2", "user:3"]);
+}
+
+#[test]
+fn key_comparisons_narrow_the_range() {
+    let db = people();
+    let query = Query::new(Some(String::from("user:"))).with(Predicate::new(
+        Field::Key,
+        Comparison::Gt,
+        DBTypes::Text(String::from("user:1")),
+    ));
+
+    assert_eq!(
+        db.plan(&query),
+        Plan::KeyRange {
+            start: Bound::Excluded(String::from("user:1")),
+            end: Bound::Excluded(String::from("user;")),
+        }
+    );
+    assert_eq!(keys(&db, &query), ["user:2", "user:3"]);
+}
+
+#[test]
+fn value_predicates_fall_back_to_a_full_scan() {
+    let db = people();
+    let query = Query::new(None).with(Predicate::new(
+        Field::Value,
+        Comparison::Ne,
+        DBTypes::Number(1),
+    ));
+
+    assert_eq!(db.plan(&query), Plan::FullScan);
+    assert_eq!(db.count(&query), 4);
+}
+
+#[test]
+fn an_indexed_equality_is_a_lookup() {
+    let db = people();
+    db.create_index("age", extract::nth(1));
+    let query = Query::new(Some(String::from("user:"))).with(age(Comparison::Eq, 25));
+
+    assert_eq!(
+        db.plan(&query),
+        Plan::IndexLookup {
+            index: String::from("age"),
+            value: DBTypes::Number(25),
+        }
+    );
+    assert_eq!(keys(&db, &query), ["user:2"]);
+}
+
+#[test]
+fn indexed_comparisons_become_an_index_range() {
+    let db = people();
+    let query = Query::new(Some(String::from("user:")))
+        .with(age(Comparison::Ge, 26))
+        .with(age(Comparison::Lt, 40));
+    assert_eq!(
+        db.plan(&query),
+        Plan::KeyRange {
+            start: Bound::Included(String::from("user:")),
+            end: Bound::Excluded(String::from("user;")),
+        }
+    );
+    assert!(keys(&db, &query).is_empty());
+
+    db.create_index("age", extract::nth(1));
+    assert_eq!(
+        db.plan(&query),
+        Plan::IndexRange {
+            index: String::from("age"),
+            start: Bound::Included(DBTypes::Number(26)),
+            end: Bound::Excluded(DBTypes::Number(40)),
+        }
+    );
+    assert_eq!(keys(&db, &query), ["user:1"]);
+}
+
+#[test]
+fn an_empty_range_visits_nothing() {
+    let db = people();
+    let query = Query::new(None)
+        .with(Predicate::new(
+            Field::Key,
+            Comparison::Gt,
+            DBTypes::Text(String::from("user:3")),
+        ))
+        .with(Predicate::new(
+            Field::Key,
+            Comparison::Lt,
+            DBTypes::Text(String::from("user:1")),
+        ));
+
+    assert_eq!(db.count(&query), 0);
+}
+
+#[test]
+fn scan_stops_when_asked() {
+    let db = people();
+    let mut visited = 0;
+    db.scan(&Query::new(None), |_| {
+        visited += 1;
+        ControlFlow::Break(())
+    });
+    assert_eq!(visited, 1);
+}
+
+#[test]
+fn integers_and_floats_compare_by_value() {
+    let (one, float) = (DBTypes::Number(1), DBTypes::Float(1.0));
+    assert!(Comparison::Eq.compare(&one, &float));
+    assert!(!Comparison::Lt.compare(&one, &float));
+    assert!(Comparison::Lt.compare(&DBTypes::Float(0.5), &one));
+    assert!(Comparison::Ne.compare(&one, &DBTypes::Text(String::from("1"))));
+    assert!(!Comparison::Lt.compare(&one, &DBTypes::Text(String::from("1"))));
+
+    let db = Database::default();
+    db.put(String::from("float"), DBTypes::Float(25.0)).unwrap();
+    db.put(String::from("int"), DBTypes::Number(25)).unwrap();
+    let query = Query::new(None).with(Predicate::new(
+        Field::Value,
+        Comparison::Eq,
+        DBTypes::Number(25),
+    ));
+    assert_eq!(keys(&db, &query), ["float", "int"]);
+}
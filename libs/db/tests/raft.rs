@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use db::{
+    Cluster, ClusterConfig, ClusterError, DBTypes, DataDir, Database, LocalNetwork, Mutation,
+    NodeId, Outcome, Role,
+};
+
+type Nodes = BTreeMap<NodeId, (Database, Cluster)>;
+
+fn dir(test: &str, id: NodeId) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoya-raft-{test}-{}", std::process::id()));
+    dir.join(format!("n{id}"))
+}
+
+fn config(id: NodeId, members: &[NodeId]) -> ClusterConfig {
+    let mut config = ClusterConfig::new(id, format!("n{id}"));
+    config.members = members.iter().map(|id| (*id, format!("n{id}"))).collect();
+    config.timeout = Duration::from_secs(2);
+    config
+}
+
+/// Starts a node kept in the test's directory, with the records it had if it ran before
+fn start(test: &str, config: ClusterConfig, net: &LocalNetwork) -> (Database, Cluster) {
+    let db = Database::default().with_data_dir(DataDir::new(dir(test, config.id)).unwrap());
+    let cluster = db.join_local_cluster(config, net).unwrap();
+    (db, cluster)
+}
+
+/// Starts a new cluster of `ids`
+fn cluster(test: &str, ids: &[NodeId], net: &LocalNetwork) -> Nodes {
+    let _ = std::fs::remove_dir_all(dir(test, 0).parent().unwrap());
+    ids.iter()
+        .map(|&id| (id, start(test, config(id, ids), net)))
+        .collect()
+}
+
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(
+            start.elapsed() < Duration::from_secs(20),
+            "timed out: {what}"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// The leader among the nodes not in `except`, once there's one
+fn leader(nodes: &Nodes, except: &[NodeId]) -> NodeId {
+    let mut leader = None;
+    wait_until("a leader is elected", || {
+        leader = nodes
+            .iter()
+            .filter(|(id, _)| !except.contains(id))
+            .find(|(_, (_, cluster))| cluster.status().role == Role::Leader)
+            .map(|(id, _)| *id);
+        leader.is_some()
+    });
+    leader.unwrap()
+}
+
+fn put(cluster: &Cluster, key: &str, value: isize) -> Result<Outcome, ClusterError> {
+    cluster.write(Mutation::Put {
+        key: key.to_owned(),
+        value: DBTypes::Number(value),
+    })
+}
+
+fn put_range(cluster: &Cluster, range: std::ops::Range<isize>) {
+    for i in range {
+        put(cluster, &format!("key{i:04}"), i).unwrap();
+    }
+}
+
+/// Waits until every node in `ids` has the value `put_range` gave key `i`
+fn replicated(nodes: &Nodes, ids: &[NodeId], i: isize) {
+    wait_until(&format!("key {i} reaches {ids:?}"), || {
+        ids.iter()
+            .all(|id| nodes[id].0.get(&format!("key{i:04}")) == Some(DBTypes::Number(i)))
+    });
+}
+
+#[test]
+fn a_leader_is_elected_and_replicates_its_log() {
+    let net = LocalNetwork::new();
+    let ids = [1, 2, 3];
+    let nodes = cluster("replicate", &ids, &net);
+    let leader = leader(&nodes, &[]);
+    for (id, (_, cluster)) in &nodes {
+        wait_until("everyone follows the leader", || {
+            cluster.status().leader == Some(leader)
+        });
+        if *id != leader {
+            assert!(matches!(
+                put(cluster, "elsewhere", 0),
+                Err(ClusterError::NotLeader {
+                    leader: Some((id, _))
+                }) if id == leader
+            ));
+        }
+    }
+
+    put_range(&nodes[&leader].1, 0..100);
+    replicated(&nodes, &ids, 99);
+    let status = nodes[&leader].1.status();
+    for (_, cluster) in nodes.values() {
+        wait_until("everyone applies every entry", || {
+            let follower = cluster.status();
+            follower.commit == status.commit && follower.applied == status.commit
+        });
+    }
+    assert!(nodes.values().all(|(db, _)| db.get("elsewhere").is_none()));
+}
+
+#[test]
+fn a_restarted_follower_catches_up_and_commits() {
+    let net = LocalNetwork::new();
+    let ids = [1, 2, 3];
+    let mut nodes = cluster("restart", &ids, &net);
+    let leader = leader(&nodes, &[]);
+    put_range(&nodes[&leader].1, 0..50);
+    replicated(&nodes, &ids, 49);
+
+    // The other two are still most of the cluster
+    let follower = *ids.iter().find(|&&id| id != leader).unwrap();
+    drop(nodes.remove(&follower));
+    put_range(&nodes[&leader].1, 50..100);
+
+    nodes.insert(follower, start("restart", config(follower, &[]), &net));
+    replicated(&nodes, &ids, 99);
+    let leader = self::leader(&nodes, &[]);
+    put_range(&nodes[&leader].1, 100..110);
+    replicated(&nodes, &ids, 109);
+    let status = nodes[&follower].1.status();
+    assert_eq!(status.members.keys().copied().collect::<Vec<_>>(), ids);
+    assert_eq!(nodes[&follower].0.get("key0000"), Some(DBTypes::Number(0)));
+}
+
+#[test]
+fn a_follower_that_fell_behind_is_sent_a_snapshot() {
+    let net = LocalNetwork::new();
+    let ids = [1, 2, 3];
+    let _ = std::fs::remove_dir_all(dir("snapshot", 0).parent().unwrap());
+    let nodes = ids
+        .iter()
+        .map(|&id| {
+            let mut config = config(id, &ids);
+            config.snapshot_every = 50;
+            (id, start("snapshot", config, &net))
+        })
+        .collect::<Nodes>();
+    let leader = leader(&nodes, &[]);
+    let follower = *ids.iter().find(|&&id| id != leader).unwrap();
+    put_range(&nodes[&leader].1, 0..10);
+    replicated(&nodes, &ids, 9);
+
+    net.isolate(follower);
+    put_range(&nodes[&leader].1, 10..300);
+    wait_until("the leader drops the entries the follower lacks", || {
+        nodes[&leader].1.status().snapshot_index > 100
+    });
+    assert_eq!(nodes[&follower].0.get("key0100"), None);
+    net.heal();
+
+    replicated(&nodes, &ids, 299);
+    assert!(nodes[&follower].1.status().snapshot_index > 100);
+    assert_eq!(nodes[&follower].0.get("key0010"), Some(DBTypes::Number(10)));
+}
+
+#[test]
+fn members_are_added_and_removed_one_at_a_time() {
+    let net = LocalNetwork::new();
+    let ids = [1, 2, 3];
+    let mut nodes = cluster("members", &ids, &net);
+    let leader = leader(&nodes, &[]);
+    put_range(&nodes[&leader].1, 0..50);
+
+    nodes.insert(4, start("members", config(4, &[]), &net));
+    nodes[&leader].1.add_member(4, "n4").unwrap();
+    replicated(&nodes, &[1, 2, 3, 4], 49);
+    wait_until("the new member learns of the others", || {
+        nodes[&4].1.status().members.len() == 4
+    });
+    // Adding a member again changes nothing
+    nodes[&leader].1.add_member(4, "n4").unwrap();
+    put_range(&nodes[&leader].1, 50..60);
+    replicated(&nodes, &[1, 2, 3, 4], 59);
+
+    // Removing the leader makes the rest elect another
+    nodes[&leader].1.remove_member(leader).unwrap();
+    let remaining = [1, 2, 3, 4]
+        .into_iter()
+        .filter(|&id| id != leader)
+        .collect::<Vec<_>>();
+    let next = self::leader(&nodes, &[leader]);
+    wait_until("the removal reaches every member", || {
+        remaining
+            .iter()
+            .all(|id| !nodes[id].1.status().members.contains_key(&leader))
+    });
+    put_range(&nodes[&next].1, 60..70);
+    replicated(&nodes, &remaining, 69);
+    assert!(matches!(
+        nodes[&next].1.remove_member(99),
+        Err(ClusterError::NotMember(99))
+    ));
+}
+
+#[test]
+fn a_minority_leader_loses_nothing_committed_when_the_partition_heals() {
+    let net = LocalNetwork::new();
+    let ids = [1, 2, 3, 4, 5];
+    let nodes = cluster("partition", &ids, &net);
+    let old = leader(&nodes, &[]);
+    put_range(&nodes[&old].1, 0..20);
+    replicated(&nodes, &ids, 19);
+
+    let follower = *ids.iter().find(|&&id| id != old).unwrap();
+    let minority = [old, follower];
+    let majority = ids
+        .into_iter()
+        .filter(|id| !minority.contains(id))
+        .collect::<Vec<_>>();
+    net.partition(&minority, &majority);
+
+    // The old leader can't commit without most of the cluster
+    assert!(put(&nodes[&old].1, "lost", 1).is_err());
+    let new = leader(&nodes, &minority);
+    put_range(&nodes[&new].1, 20..40);
+    replicated(&nodes, &majority, 39);
+    assert_eq!(nodes[&old].0.get("key0039"), None);
+
+    net.heal();
+    replicated(&nodes, &ids, 39);
+    wait_until("the old leader steps down", || {
+        nodes[&old].1.status().role != Role::Leader
+    });
+    for (db, _) in nodes.values() {
+        assert_eq!(db.get("lost"), None);
+        assert_eq!(db.get("key0000"), Some(DBTypes::Number(0)));
+    }
+    let leader = leader(&nodes, &[]);
+    put_range(&nodes[&leader].1, 40..41);
+    replicated(&nodes, &ids, 40);
+}
@@ -0,0 +1,181 @@
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use db::{DBTypes, Database, MmapEngine, Primary, Query, Replica, ReplicationStatus};
+
+/// How many changes the primary keeps for replicas that reconnect
+const BACKLOG: usize = 1 << 16;
+
+/// Polls until `done` holds, failing after a while
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(
+            start.elapsed() < Duration::from_secs(20),
+            "timed out: {what}"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn put_range(db: &Database, prefix: &str, range: std::ops::Range<usize>) {
+    for i in range {
+        db.put(format!("{prefix}{i:06}"), DBTypes::Number(i as isize))
+            .unwrap();
+    }
+}
+
+fn primary_offset(db: &Database) -> u64 {
+    match db.replication_status() {
+        ReplicationStatus::Primary { offset, .. } => offset,
+        status => panic!("expected a primary, found {status}"),
+    }
+}
+
+/// Waits until `replica` has every change made on `primary`
+fn caught_up(primary: &Database, replica: &Replica) {
+    wait_until("the replica catches up", || {
+        let status = replica.status();
+        status.connected && status.offset == primary_offset(primary)
+    });
+}
+
+/// Stops serving replicas and serves them again on the same address, so they reconnect
+fn restart(db: &Database, primary: Primary, replica: &Replica) -> Primary {
+    let addr: SocketAddr = primary.local_addr();
+    drop(primary);
+    wait_until("the replica notices", || !replica.status().connected);
+    db.serve_replicas(addr).unwrap()
+}
+
+#[test]
+fn a_new_replica_syncs_everything() {
+    let primary_db = Database::sharded(4);
+    put_range(&primary_db, "before", 0..1000);
+    let primary = primary_db.serve_replicas("127.0.0.1:0").unwrap();
+
+    let replica_db = Database::sharded(4);
+    replica_db
+        .put(String::from("stale"), DBTypes::Number(0))
+        .unwrap();
+    let replica = replica_db.replicate_from(primary.local_addr().to_string());
+    caught_up(&primary_db, &replica);
+    assert_eq!(
+        replica_db.get("stale"),
+        None,
+        "the sync replaces what it held"
+    );
+    assert_eq!(replica_db.count(&Query::default()), 1000);
+
+    put_range(&primary_db, "after", 0..100);
+    primary_db.remove("before000000").unwrap();
+    caught_up(&primary_db, &replica);
+    assert_eq!(replica_db.get("after000099"), Some(DBTypes::Number(99)));
+    assert_eq!(replica_db.get("before000000"), None);
+    assert_eq!(replica_db.count(&Query::default()), 1099);
+
+    let status = replica.status();
+    assert_eq!((status.full_syncs, status.partial_syncs), (1, 0));
+}
+
+#[test]
+fn a_reconnecting_replica_gets_only_what_it_missed() {
+    let primary_db = Database::default();
+    put_range(&primary_db, "key", 0..100);
+    let primary = primary_db.serve_replicas("127.0.0.1:0").unwrap();
+    let replica_db = Database::default();
+    let replica = replica_db.replicate_from(primary.local_addr().to_string());
+    caught_up(&primary_db, &replica);
+
+    let primary = restart(&primary_db, primary, &replica);
+    put_range(&primary_db, "missed", 0..100);
+    caught_up(&primary_db, &replica);
+
+    let status = replica.status();
+    assert_eq!((status.full_syncs, status.partial_syncs), (1, 1));
+    assert_eq!(replica_db.get("missed000099"), Some(DBTypes::Number(99)));
+    assert_eq!(replica_db.count(&Query::default()), 200);
+    drop(primary);
+}
+
+#[test]
+fn a_replica_that_missed_more_than_the_backlog_syncs_again() {
+    let primary_db = Database::default();
+    let primary = primary_db.serve_replicas("127.0.0.1:0").unwrap();
+    let replica_db = Database::default();
+    let replica = replica_db.replicate_from(primary.local_addr().to_string());
+    caught_up(&primary_db, &replica);
+
+    let primary = restart(&primary_db, primary, &replica);
+    put_range(&primary_db, "missed", 0..BACKLOG + 10);
+    caught_up(&primary_db, &replica);
+
+    let status = replica.status();
+    assert_eq!((status.full_syncs, status.partial_syncs), (2, 0));
+    assert_eq!(replica_db.count(&Query::default()), BACKLOG + 10);
+    drop(primary);
+}
+
+#[test]
+fn the_status_reports_offsets_and_lag() {
+    let primary_db = Database::default();
+    assert!(matches!(
+        primary_db.replication_status(),
+        ReplicationStatus::Standalone
+    ));
+    let primary = primary_db.serve_replicas("127.0.0.1:0").unwrap();
+    put_range(&primary_db, "key", 0..50);
+    assert_eq!(primary_offset(&primary_db), 50);
+
+    let replica_db = Database::default();
+    let replica = replica_db.replicate_from(primary.local_addr().to_string());
+    caught_up(&primary_db, &replica);
+    put_range(&primary_db, "more", 0..25);
+    caught_up(&primary_db, &replica);
+
+    let status = replica.status();
+    assert_eq!(status.primary, primary.local_addr().to_string());
+    assert_eq!(
+        (status.offset, status.primary_offset, status.lag()),
+        (75, 75, 0)
+    );
+    assert!(status.last_contact.is_some() && status.last_error.is_none());
+    assert!(matches!(
+        replica_db.replication_status(),
+        ReplicationStatus::Replica(ref status) if status.offset == 75
+    ));
+    wait_until(
+        "the primary sees the replica's offset",
+        || match primary_db.replication_status() {
+            ReplicationStatus::Primary { offset, replicas } => {
+                offset == 75 && replicas.len() == 1 && replicas[0].offset == 75
+            }
+            _ => false,
+        },
+    );
+
+    // A replica that can't reach its primary knows how far behind it was last told it is
+    let primary = restart(&primary_db, primary, &replica);
+    drop(primary);
+    let status = replica.status();
+    assert!(!status.connected && status.lag() == 0);
+
+    drop(replica);
+    assert!(matches!(
+        replica_db.replication_status(),
+        ReplicationStatus::Standalone
+    ));
+}
+
+#[test]
+fn writes_the_engine_refuses_are_not_replicated() {
+    let primary_db = Database::with_engine(MmapEngine::open("/dev/full").unwrap());
+    let _primary = primary_db.serve_replicas("127.0.0.1:0").unwrap();
+
+    assert!(primary_db
+        .put(String::from("k"), DBTypes::Number(1))
+        .is_err());
+    assert!(primary_db.rpush("list", DBTypes::Number(2)).is_err());
+    assert_eq!(primary_offset(&primary_db), 0);
+}
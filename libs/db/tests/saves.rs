@@ -0,0 +1,132 @@
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use db::{DBTypes, DataDir, Database, DatabaseError, Format};
+
+fn data_dir() -> DataDir {
+    DataDir::new(std::env::temp_dir().join(format!("hoya-saves-{}", std::process::id()))).unwrap()
+}
+
+/// Says when it's first written to, then holds the write until told to go on
+struct Held {
+    started: Option<Sender<()>>,
+    proceed: Receiver<()>,
+}
+
+impl Write for Held {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(started) = self.started.take() {
+            started.send(()).unwrap();
+            self.proceed.recv().unwrap();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn a_background_save_writes_the_records_as_they_were_when_it_started() {
+    let db = Database::sharded(8).with_data_dir(data_dir());
+    for i in 0..5000 {
+        db.put(format!("key{i:04}"), DBTypes::Number(i)).unwrap();
+    }
+
+    db.bgsave("point-in-time").unwrap();
+    db.put(String::from("key0042"), DBTypes::Number(-1))
+        .unwrap();
+    db.remove("key0043").unwrap();
+    db.put(String::from("late"), DBTypes::Number(0)).unwrap();
+    db.wait_for_save();
+
+    let status = db.save_status();
+    assert!(status.last_error.is_none() && status.last_success.is_some());
+    assert_eq!(status.records_written, 5000);
+    assert!(status
+        .to_string()
+        .starts_with("No save running; last saved at"));
+
+    let loaded = Database::default().with_data_dir(data_dir());
+    loaded.load("point-in-time").unwrap();
+    loaded.delete_snapshot("point-in-time").unwrap();
+    assert_eq!(loaded.get("key0042"), Some(DBTypes::Number(42)));
+    assert_eq!(loaded.get("key0043"), Some(DBTypes::Number(43)));
+    assert_eq!(loaded.get("late"), None);
+    assert_eq!(db.get("key0042"), Some(DBTypes::Number(-1)));
+}
+
+#[test]
+fn a_failed_background_save_is_reported_and_unfreezes_the_records() {
+    let root = std::env::temp_dir().join(format!("hoya-saves-gone-{}", std::process::id()));
+    let db = Database::default().with_data_dir(DataDir::new(&root).unwrap());
+    db.put(String::from("k"), DBTypes::Number(1)).unwrap();
+
+    std::fs::remove_dir_all(&root).unwrap();
+    db.bgsave("lost").unwrap();
+    db.wait_for_save();
+    let status = db.save_status();
+    assert!(status.last_success.is_none());
+    assert!(status.last_error.is_some());
+    assert!(status
+        .to_string()
+        .contains("; never saved; last save failed: "));
+
+    std::fs::create_dir_all(&root).unwrap();
+    db.put(String::from("k"), DBTypes::Number(2)).unwrap();
+    db.store("after-failure").unwrap();
+    let loaded = Database::default().with_data_dir(DataDir::new(&root).unwrap());
+    loaded.load("after-failure").unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(loaded.get("k"), Some(DBTypes::Number(2)));
+    assert!(db.save_status().last_error.is_none());
+}
+
+#[test]
+fn saves_wait_for_an_export_instead_of_failing() {
+    let db = Database::default().with_data_dir(data_dir());
+    for i in 0..100 {
+        db.put(format!("key{i:03}"), DBTypes::Number(i)).unwrap();
+    }
+
+    let (started, export_started) = mpsc::channel();
+    let (proceed, held) = mpsc::channel();
+    let export = {
+        let db = db.clone();
+        thread::spawn(move || {
+            let out = Held {
+                started: Some(started),
+                proceed: held,
+            };
+            db.export(out, Format::Ndjson).unwrap()
+        })
+    };
+    export_started.recv().unwrap();
+
+    db.bgsave("first").unwrap();
+    assert_eq!(
+        db.bgsave("second"),
+        Err(DatabaseError::SaveInProgress {
+            target: String::from("`first.hoya`")
+        })
+    );
+    assert!(db.export(io::sink(), Format::Json).is_err());
+    db.put(String::from("late"), DBTypes::Number(0)).unwrap();
+
+    proceed.send(()).unwrap();
+    assert_eq!(export.join().unwrap(), 100);
+    db.wait_for_save();
+    db.store("second").unwrap();
+
+    let status = db.save_status();
+    assert!(status.last_error.is_none() && status.last_success.is_some());
+    for name in ["first", "second"] {
+        let loaded = Database::default().with_data_dir(data_dir());
+        loaded.load(name).unwrap();
+        loaded.delete_snapshot(name).unwrap();
+        assert_eq!(loaded.get("late"), Some(DBTypes::Number(0)));
+        assert_eq!(loaded.get("key042"), Some(DBTypes::Number(42)));
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeSet;
+
+use db::{DBTypes, Database, DatabaseError};
+
+fn members(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
+fn set(items: &[&str]) -> BTreeSet<String> {
+    members(items).into_iter().collect()
+}
+
+#[test]
+fn sadd_and_srem_count_what_changed() {
+    let db = Database::default();
+    assert_eq!(db.sadd("s", members(&["a", "b"])), Ok(2));
+    assert_eq!(db.sadd("s", members(&["b", "c"])), Ok(1));
+    assert_eq!(db.srem("s", &members(&["a", "z"])), Ok(1));
+    assert_eq!(db.srem("missing", &members(&["a"])), Ok(0));
+    assert_eq!(db.smembers("s"), Ok(set(&["b", "c"])));
+    assert_eq!(db.smembers("missing"), Ok(set(&[])));
+}
+
+#[test]
+fn sinter_and_sunion_treat_missing_keys_as_empty() {
+    let db = Database::default();
+    db.sadd("x", members(&["a", "b", "c"])).unwrap();
+    db.sadd("y", members(&["b", "c", "d"])).unwrap();
+    let keys = |k: &[&str]| members(k);
+
+    assert_eq!(db.sinter(&keys(&["x", "y"])), Ok(set(&["b", "c"])));
+    assert_eq!(
+        db.sunion(&keys(&["x", "y"])),
+        Ok(set(&["a", "b", "c", "d"]))
+    );
+    assert_eq!(db.sinter(&keys(&["x", "missing"])), Ok(set(&[])));
+    assert_eq!(db.sunion(&keys(&[])), Ok(set(&[])));
+}
+
+#[test]
+fn sorted_sets_rank_by_score_then_member() {
+    let db = Database::default();
+    assert_eq!(db.zadd("z", String::from("b"), 2.0), Ok(true));
+    assert_eq!(db.zadd("z", String::from("a"), 2.0), Ok(true));
+    assert_eq!(db.zadd("z", String::from("c"), 1.0), Ok(true));
+    assert_eq!(db.zadd("z", String::from("c"), 5.0), Ok(false));
+    assert_eq!(db.zincr("z", String::from("d"), 1.5), Ok(1.5));
+    assert_eq!(db.zincr("z", String::from("d"), 1.0), Ok(2.5));
+
+    assert_eq!(db.zrank("z", "a"), Ok(Some(0)));
+    assert_eq!(db.zrank("z", "b"), Ok(Some(1)));
+    assert_eq!(db.zrank("z", "c"), Ok(Some(3)));
+    assert_eq!(db.zrank("z", "nope"), Ok(None));
+    assert_eq!(
+        db.zrange_by_score("z", 2.0, 2.5),
+        Ok(vec![
+            (String::from("a"), 2.0),
+            (String::from("b"), 2.0),
+            (String::from("d"), 2.5)
+        ])
+    );
+    assert_eq!(db.zrange_by_score("missing", 0.0, 9.0), Ok(vec![]));
+}
+
+#[test]
+fn set_operations_refuse_other_values() {
+    let db = Database::default();
+    db.put(String::from("n"), DBTypes::Number(1)).unwrap();
+    db.sadd("s", members(&["a"])).unwrap();
+    let wrong = |key: &str, expected| DatabaseError::WrongType {
+        key: key.to_owned(),
+        expected,
+    };
+
+    assert_eq!(db.sadd("n", members(&["a"])), Err(wrong("n", "set")));
+    assert_eq!(db.sinter(&members(&["s", "n"])), Err(wrong("n", "set")));
+    assert_eq!(
+        db.zadd("s", String::from("a"), 1.0),
+        Err(wrong("s", "sorted set"))
+    );
+    assert_eq!(db.zrank("n", "a"), Err(wrong("n", "sorted set")));
+    assert_eq!(db.get("n"), Some(DBTypes::Number(1)));
+}
+
+#[test]
+fn scores_that_arent_finite_are_refused() {
+    let db = Database::default();
+    let refused = |score| DatabaseError::NonFiniteScore {
+        key: String::from("z"),
+        score,
+    };
+    for score in [f64::INFINITY, f64::NEG_INFINITY] {
+        assert_eq!(db.zadd("z", String::from("a"), score), Err(refused(score)));
+    }
+    assert!(db.zadd("z", String::from("a"), f64::NAN).is_err());
+    assert_eq!(db.get("z"), None);
+
+    // Finite increments can still add up past the largest float
+    assert_eq!(db.zincr("z", String::from("a"), 1e308), Ok(1e308));
+    assert_eq!(
+        db.zincr("z", String::from("a"), 1e308),
+        Err(refused(f64::INFINITY))
+    );
+    assert!(db.zincr("z", String::from("b"), f64::NAN).is_err());
+    assert_eq!(
+        db.zrange_by_score("z", f64::MIN, f64::MAX),
+        Ok(vec![(String::from("a"), 1e308)])
+    );
+}
@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use db::{DBTypes, DataDir, Database, Mutation, Query, ShardRouter, ShardServer};
+
+struct Shard {
+    db: Database,
+    server: ShardServer,
+}
+
+impl Shard {
+    fn addr(&self) -> String {
+        self.server.local_addr().to_string()
+    }
+}
+
+fn shards(test: &str, count: usize) -> Vec<Shard> {
+    let dir = std::env::temp_dir().join(format!("hoya-sharding-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    (0..count)
+        .map(|i| {
+            let db =
+                Database::default().with_data_dir(DataDir::new(dir.join(i.to_string())).unwrap());
+            let server = db.serve_shard("127.0.0.1:0").unwrap();
+            Shard { db, server }
+        })
+        .collect()
+}
+
+fn put(router: &ShardRouter, key: &str, value: isize) {
+    router
+        .write(Mutation::Put {
+            key: key.to_owned(),
+            value: DBTypes::Number(value),
+        })
+        .unwrap();
+}
+
+fn total(shards: &[Shard]) -> usize {
+    shards
+        .iter()
+        .map(|shard| shard.db.count(&Query::default()))
+        .sum()
+}
+
+#[test]
+fn keys_are_kept_by_their_owner() {
+    let shards = shards("owner", 3);
+    let addrs = shards.iter().map(Shard::addr).collect::<Vec<_>>();
+    let router = ShardRouter::connect(&addrs).unwrap();
+    for i in 0..500 {
+        put(&router, &format!("key{i:03}"), i);
+    }
+
+    let ring = router.ring();
+    assert_eq!(ring.shards().len(), 3);
+    for shard in &shards {
+        assert_eq!(shard.server.ring(), ring);
+        let held = shard.db.count(&Query::default());
+        assert!(held > 50, "{} holds only {held} keys", shard.addr());
+    }
+    for i in 0..500 {
+        let key = format!("key{i:03}");
+        let owner = ring.owner(&key).unwrap();
+        for shard in &shards {
+            let expected = (shard.addr() == owner).then_some(DBTypes::Number(i));
+            assert_eq!(shard.db.get(&key), expected, "{key} on {}", shard.addr());
+        }
+        assert_eq!(router.get(&key).unwrap(), Some(DBTypes::Number(i)));
+    }
+    assert_eq!(total(&shards), 500);
+    assert_eq!(router.keys("key").unwrap().len(), 500);
+}
+
+#[test]
+fn a_router_on_an_old_ring_is_sent_the_new_one() {
+    let shards = shards("stale", 3);
+    let addrs = shards.iter().map(Shard::addr).collect::<Vec<_>>();
+    let router = ShardRouter::connect(&addrs[..2]).unwrap();
+    let stale = ShardRouter::connect(&addrs[..1]).unwrap();
+    for i in 0..500 {
+        put(&router, &format!("key{i:03}"), i);
+    }
+
+    assert!(router.add_shard(&addrs[2]).unwrap() > 0);
+    let ring = router.ring();
+    assert_eq!(stale.ring().version() + 1, ring.version());
+
+    // The first key that moved is refused by its old owner, which sends the new ring
+    let moved = (0..500)
+        .find(|i| ring.owner(&format!("key{i:03}")) == Some(addrs[2].as_str()))
+        .unwrap();
+    assert_eq!(
+        stale.get(&format!("key{moved:03}")).unwrap(),
+        Some(DBTypes::Number(moved))
+    );
+    assert_eq!(stale.ring(), ring);
+    put(&stale, "after", 1);
+    assert_eq!(
+        shards[2].db.get("after").is_some(),
+        ring.owner("after") == Some(&addrs[2])
+    );
+    assert_eq!(router.get("after").unwrap(), Some(DBTypes::Number(1)));
+}
+
+#[test]
+fn writes_made_while_resharding_survive_it() {
+    let shards = shards("writes", 4);
+    let addrs = shards.iter().map(Shard::addr).collect::<Vec<_>>();
+    let router = ShardRouter::connect(&addrs[..3]).unwrap();
+    for i in 0..2000 {
+        put(&router, &format!("key{i:04}"), i);
+    }
+
+    // Another router keeps writing and reading back through both reshards
+    let writer = Arc::new(ShardRouter::connect(&addrs[..1]).unwrap());
+    let stop = Arc::new(AtomicBool::new(false));
+    let writing = {
+        let (writer, stop) = (writer.clone(), stop.clone());
+        thread::spawn(move || {
+            let mut written = 0;
+            while !stop.load(Ordering::SeqCst) || written < 100 {
+                let key = format!("written{written:06}");
+                put(&writer, &key, written);
+                assert_eq!(writer.get(&key).unwrap(), Some(DBTypes::Number(written)));
+                let old = format!("key{:04}", written % 2000);
+                assert_eq!(
+                    writer.get(&old).unwrap(),
+                    Some(DBTypes::Number(written % 2000))
+                );
+                written += 1;
+            }
+            written
+        })
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(router.add_shard(&addrs[3]).unwrap() > 0);
+    assert!(router.remove_shard(&addrs[0]).unwrap() > 0);
+    stop.store(true, Ordering::SeqCst);
+    let written = writing.join().unwrap();
+
+    assert_eq!(shards[0].db.count(&Query::default()), 0);
+    assert_eq!(total(&shards), 2000 + written as usize);
+    for i in 0..written {
+        let key = format!("written{i:06}");
+        assert_eq!(router.get(&key).unwrap(), Some(DBTypes::Number(i)), "{key}");
+    }
+    for i in 0..2000 {
+        let key = format!("key{i:04}");
+        assert_eq!(router.get(&key).unwrap(), Some(DBTypes::Number(i)), "{key}");
+    }
+}
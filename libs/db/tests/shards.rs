@@ -0,0 +1,114 @@
+use std::ops::ControlFlow;
+use std::thread;
+
+use db::{extract, DBTypes, DataDir, Database, Query};
+
+fn numbered(db: &Database, count: isize) {
+    for n in 0..count {
+        db.put(format!("key:{n:03}"), DBTypes::Number(n)).unwrap();
+    }
+}
+
+fn scanned(db: &Database, query: &Query) -> Vec<String> {
+    let mut keys = vec![];
+    db.scan(query, |entry| {
+        keys.push(entry.key.to_owned());
+        ControlFlow::Continue(())
+    });
+    keys
+}
+
+#[test]
+fn keys_are_found_whichever_shard_holds_them() {
+    let db = Database::sharded(8);
+    numbered(&db, 100);
+    for n in 0..100 {
+        assert_eq!(db.get(&format!("key:{n:03}")), Some(DBTypes::Number(n)));
+    }
+    assert_eq!(db.remove("key:042"), Ok(Some(DBTypes::Number(42))));
+    assert!(!db.exists("key:042"));
+    assert!(db.exists("key:043"));
+}
+
+#[test]
+fn scans_merge_shards_in_key_order() {
+    let db = Database::sharded(8);
+    numbered(&db, 100);
+    db.put(String::from("other"), DBTypes::Number(0)).unwrap();
+
+    let all = scanned(&db, &Query::new(Some(String::from("key:"))));
+    let expected = (0..100).map(|n| format!("key:{n:03}")).collect::<Vec<_>>();
+    assert_eq!(all, expected);
+
+    let mut first = vec![];
+    db.scan(&Query::new(None), |entry| {
+        first.push(entry.key.to_owned());
+        if first.len() == 3 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert_eq!(first, ["key:000", "key:001", "key:002"]);
+}
+
+#[test]
+fn index_lookups_merge_shards_in_order() {
+    let db = Database::sharded(4);
+    for (key, n) in [("e", 1), ("a", 2), ("d", 1), ("b", 3), ("c", 2)] {
+        db.put(key.to_owned(), DBTypes::Number(n)).unwrap();
+    }
+    db.create_index("n", extract::value());
+
+    assert_eq!(
+        db.find_by("n", &DBTypes::Number(1)),
+        Some(vec![String::from("d"), String::from("e")])
+    );
+    assert_eq!(
+        db.find_range("n", &DBTypes::Number(1), &DBTypes::Number(2)),
+        Some(["d", "e", "a", "c"].map(String::from).to_vec())
+    );
+    assert_eq!(db.find_by("missing", &DBTypes::Number(1)), None);
+}
+
+#[test]
+fn parallel_writers_lose_nothing() {
+    let db = Database::sharded(16);
+    let writers = (0..8)
+        .map(|t| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for n in 0..500 {
+                    db.put(format!("{t}:{n}"), DBTypes::Number(n)).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    writers.into_iter().for_each(|w| w.join().unwrap());
+
+    assert_eq!(scanned(&db, &Query::new(None)).len(), 8 * 500);
+}
+
+#[test]
+fn a_sharded_database_reloads_into_any_shard_count() {
+    let dir = std::env::temp_dir().join(format!("hoya-shards-{}", std::process::id()));
+    let dir = DataDir::new(dir).unwrap();
+
+    let db = Database::sharded(8).with_data_dir(dir.clone());
+    numbered(&db, 50);
+    db.store("sharded").unwrap();
+
+    let reloaded = Database::sharded(3).with_data_dir(dir);
+    reloaded.create_index("n", extract::value());
+    reloaded.load("sharded").unwrap();
+    reloaded.delete_snapshot("sharded").unwrap();
+
+    assert_eq!(
+        scanned(&reloaded, &Query::new(None)),
+        scanned(&db, &Query::new(None))
+    );
+    assert_eq!(
+        reloaded.find_by("n", &DBTypes::Number(7)),
+        Some(vec![String::from("key:007")])
+    );
+}
@@ -0,0 +1,141 @@
+//! The files in `fixtures/snapshots` pin how snapshots are laid out on disk, with one
+//! record of every type. `v0.hoya` is a bare map from before the header, `v1.hoya` has
+//! records in the derived bincode encoding and `v2.hoya` is the current format. They're
+//! never regenerated: a failure here means old snapshots would no longer read, or new
+//! ones would be written differently
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration};
+use db::{migrate, DBTypes, DataDir, Database, Query, SnapshotOptions};
+
+const VERSIONS: [&str; 3] = ["v0", "v1", "v2"];
+
+fn fixture(version: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/snapshots")
+        .join(format!("{version}.hoya"))
+}
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoya-fixtures-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A database holding the fixture of `version` as `name`
+fn database(version: &str) -> Database {
+    let dir = scratch(&format!("db-{version}"));
+    fs::copy(fixture(version), dir.join(format!("{version}.hoya"))).unwrap();
+    Database::default().with_data_dir(DataDir::new(dir).unwrap())
+}
+
+/// What every fixture holds
+fn records() -> BTreeMap<String, DBTypes> {
+    let records = [
+        (
+            "bigint",
+            DBTypes::BigInt("-123456789012345678901234567890".parse().unwrap()),
+        ),
+        ("boolean", DBTypes::Boolean(true)),
+        ("bytes", DBTypes::Bytes(vec![0, 1, 254, 255])),
+        ("decimal", DBTypes::Decimal("-1234.5678".parse().unwrap())),
+        (
+            "duration",
+            DBTypes::Duration(-(Duration::seconds(90) + Duration::nanoseconds(5))),
+        ),
+        ("float", DBTypes::Float(-0.1)),
+        (
+            "list",
+            DBTypes::List(vec![
+                DBTypes::Nil,
+                DBTypes::Number(1),
+                DBTypes::List(vec![DBTypes::Text(String::from("nested"))]),
+            ]),
+        ),
+        ("number", DBTypes::Number(-42)),
+        (
+            "set",
+            DBTypes::Set(BTreeSet::from([String::from("a"), String::from("b")])),
+        ),
+        (
+            "sorted_set",
+            DBTypes::SortedSet(BTreeMap::from([
+                (String::from("x"), 1.5),
+                (String::from("y"), -2.0),
+            ])),
+        ),
+        ("text", DBTypes::Text(String::from("héllo"))),
+        (
+            "timestamp",
+            DBTypes::Timestamp(DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap()),
+        ),
+        ("unit", DBTypes::Unit(())),
+    ];
+    records
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value))
+        .collect()
+}
+
+fn assert_holds_records(db: &Database) {
+    let records = records();
+    assert_eq!(db.count(&Query::default()), records.len());
+    for (key, value) in records {
+        assert_eq!(db.get(&key), Some(value), "{key}");
+    }
+}
+
+#[test]
+fn every_version_reads_every_type() {
+    for version in VERSIONS {
+        let db = database(version);
+        db.load(version).unwrap();
+        assert_holds_records(&db);
+    }
+}
+
+#[test]
+fn the_current_version_is_written_byte_for_byte() {
+    let db = Database::default().with_data_dir(DataDir::new(scratch("written")).unwrap());
+    db.put_many(records()).unwrap();
+    db.store("written").unwrap();
+    let written = fs::read(db.data_dir().root().join("written.hoya")).unwrap();
+    assert_eq!(written, fs::read(fixture("v2")).unwrap());
+}
+
+#[test]
+fn every_version_re_encodes_to_the_current_fixture() {
+    let current = fs::read(fixture("v2")).unwrap();
+    for version in VERSIONS {
+        let db = database(version);
+        db.load(version).unwrap();
+        db.store("again").unwrap();
+        let again = fs::read(db.data_dir().root().join("again.hoya")).unwrap();
+        assert_eq!(again, current, "{version}");
+    }
+}
+
+#[test]
+fn migrate_round_trips_every_version() {
+    let dir = scratch("migrate");
+    let current = fs::read(fixture("v2")).unwrap();
+    let options = SnapshotOptions::default();
+    for version in VERSIONS {
+        let migrated = dir.join(format!("{version}.hoya"));
+        let count = migrate(fixture(version), &migrated, &options).unwrap();
+        assert_eq!(count, records().len() as u64);
+        assert_eq!(fs::read(&migrated).unwrap(), current, "{version}");
+
+        // Migrating a current snapshot in place leaves it as it was
+        migrate(&migrated, &migrated, &options).unwrap();
+        assert_eq!(fs::read(&migrated).unwrap(), current, "{version} in place");
+
+        let db = Database::default().with_data_dir(DataDir::new(&dir).unwrap());
+        db.load(version).unwrap();
+        assert_holds_records(&db);
+    }
+}
@@ -0,0 +1,289 @@
+use std::fs;
+use std::path::PathBuf;
+
+use db::{
+    Cipher, Compression, DBTypes, DataDir, Database, Encryption, Secret, SnapshotError,
+    SnapshotOptions,
+};
+
+const KEY: [u8; 32] = [7; 32];
+
+fn data_dir() -> DataDir {
+    DataDir::new(std::env::temp_dir().join(format!("hoya-formats-{}", std::process::id()))).unwrap()
+}
+
+/// The file the snapshot `name` is saved in
+fn file(name: &str) -> PathBuf {
+    data_dir().root().join(format!("{name}.hoya"))
+}
+
+fn options(compression: Compression, encryption: Option<(Cipher, Secret)>) -> SnapshotOptions {
+    SnapshotOptions {
+        compression,
+        encryption: encryption.map(|(cipher, secret)| Encryption { cipher, secret }),
+    }
+}
+
+fn passphrase() -> Secret {
+    Secret::Passphrase(String::from("correct horse battery staple"))
+}
+
+/// Enough records to span several encrypted chunks
+fn records() -> Vec<(String, DBTypes)> {
+    (0..2000)
+        .map(|n| (format!("key:{n:04}"), DBTypes::Text(format!("{n:0>100}"))))
+        .collect()
+}
+
+/// Saves the records as the snapshot `name` and returns its name
+fn saved(name: &str, options: SnapshotOptions) -> String {
+    let db = Database::default()
+        .with_data_dir(data_dir())
+        .with_snapshot_options(options);
+    db.put_many(records()).unwrap();
+    db.store(name).unwrap();
+    name.to_owned()
+}
+
+fn reading(secret: Option<Secret>) -> Database {
+    Database::default()
+        .with_data_dir(data_dir())
+        .with_snapshot_options(options(
+            Compression::None,
+            secret.map(|secret| (Cipher::default(), secret)),
+        ))
+}
+
+fn load_error(db: &Database, path: &str) -> SnapshotError {
+    match db.load(path).unwrap_err().downcast::<SnapshotError>() {
+        Ok(e) => *e,
+        Err(e) => panic!("{path}: expected a snapshot error, got {e}"),
+    }
+}
+
+fn check_loaded(db: &Database) {
+    assert_eq!(db.get("key:0000"), Some(records()[0].1.clone()));
+    assert_eq!(db.get("key:1999"), Some(records()[1999].1.clone()));
+}
+
+fn edit(path: &str, change: impl FnOnce(&mut Vec<u8>)) {
+    let file = file(path);
+    let mut bytes = fs::read(&file).unwrap();
+    change(&mut bytes);
+    fs::write(&file, bytes).unwrap();
+}
+
+#[test]
+fn compressed_snapshots_round_trip() {
+    let plain = fs::metadata(file(&saved("none", options(Compression::None, None))))
+        .unwrap()
+        .len();
+    for (name, compression) in [("zstd", Compression::Zstd), ("lz4", Compression::Lz4)] {
+        let path = saved(name, options(compression, None));
+        assert!(fs::metadata(file(&path)).unwrap().len() < plain);
+
+        let db = reading(None);
+        db.load(&path).unwrap();
+        check_loaded(&db);
+    }
+}
+
+#[test]
+fn encrypted_snapshots_round_trip_with_a_key_or_a_passphrase() {
+    for (cipher, name) in [
+        (Cipher::Aes256Gcm, "aes"),
+        (Cipher::ChaCha20Poly1305, "chacha"),
+    ] {
+        for (secret, kind) in [(Secret::Key(KEY), "key"), (passphrase(), "passphrase")] {
+            let path = saved(
+                &format!("{name}-{kind}"),
+                options(Compression::Zstd, Some((cipher, secret.clone()))),
+            );
+            let contents = fs::read(file(&path)).unwrap();
+            assert!(!contents.windows(8).any(|w| w == b"key:0000"));
+
+            let db = reading(Some(secret));
+            db.load(&path).unwrap();
+            check_loaded(&db);
+        }
+    }
+}
+
+#[test]
+fn the_wrong_secret_is_refused() {
+    let with_key = saved(
+        "refused-key",
+        options(
+            Compression::None,
+            Some((Cipher::Aes256Gcm, Secret::Key(KEY))),
+        ),
+    );
+    let with_passphrase = saved(
+        "refused-passphrase",
+        options(
+            Compression::None,
+            Some((Cipher::ChaCha20Poly1305, passphrase())),
+        ),
+    );
+
+    for path in [&with_key, &with_passphrase] {
+        assert_eq!(load_error(&reading(None), path), SnapshotError::KeyRequired);
+    }
+    assert_eq!(
+        load_error(&reading(Some(Secret::Key([8; 32]))), &with_key),
+        SnapshotError::WrongKey
+    );
+    assert_eq!(
+        load_error(
+            &reading(Some(Secret::Passphrase(String::from("wrong")))),
+            &with_passphrase
+        ),
+        SnapshotError::WrongKey
+    );
+    assert_eq!(
+        load_error(&reading(Some(passphrase())), &with_key),
+        SnapshotError::WrongSecret { passphrase: false }
+    );
+    assert_eq!(
+        load_error(&reading(Some(Secret::Key(KEY))), &with_passphrase),
+        SnapshotError::WrongSecret { passphrase: true }
+    );
+}
+
+#[test]
+fn truncated_snapshots_are_refused() {
+    for (name, compression, encryption) in [
+        ("cut-plain", Compression::None, None),
+        ("cut-zstd", Compression::Zstd, None),
+        ("cut-lz4", Compression::Lz4, None),
+        (
+            "cut-encrypted",
+            Compression::None,
+            Some((Cipher::Aes256Gcm, Secret::Key(KEY))),
+        ),
+    ] {
+        let secret = encryption.as_ref().map(|(_, secret)| secret.clone());
+        let path = saved(name, options(compression, encryption));
+        let full = fs::read(file(&path)).unwrap();
+        // An lz4 frame missing only its 4-byte end mark still holds every record
+        for cut in [5, 9, full.len() / 2] {
+            fs::write(file(&path), &full[..full.len() - cut]).unwrap();
+            let db = reading(secret.clone());
+            assert!(
+                db.load(&path).is_err(),
+                "{name} without its last {cut} bytes"
+            );
+        }
+        // Whole again, it loads
+        fs::write(file(&path), full).unwrap();
+        let db = reading(secret);
+        db.load(&path).unwrap();
+        check_loaded(&db);
+    }
+}
+
+#[test]
+fn tampered_snapshots_are_refused() {
+    let secret = Secret::Key(KEY);
+    let path = saved(
+        "tampered",
+        options(Compression::None, Some((Cipher::Aes256Gcm, secret.clone()))),
+    );
+    let original = fs::read(file(&path)).unwrap();
+
+    // The body, inside a chunk
+    edit(&path, |bytes| bytes[2000] ^= 1);
+    assert_eq!(
+        load_error(&reading(Some(secret.clone())), &path),
+        SnapshotError::Corrupted
+    );
+
+    // The header, claiming the snapshot is compressed
+    fs::write(file(&path), &original).unwrap();
+    edit(&path, |bytes| bytes[5] = 1);
+    assert_eq!(
+        load_error(&reading(Some(secret.clone())), &path),
+        SnapshotError::WrongKey
+    );
+
+    // A chunk dropped from the middle
+    fs::write(file(&path), &original).unwrap();
+    edit(&path, |bytes| {
+        bytes.drain(1000..1000 + 64 * 1024 + 16);
+    });
+    assert_eq!(
+        load_error(&reading(Some(secret)), &path),
+        SnapshotError::Corrupted
+    );
+}
+
+#[test]
+fn unreasonable_argon2_costs_are_refused_before_deriving_a_key() {
+    let path = saved(
+        "costs",
+        options(Compression::None, Some((Cipher::Aes256Gcm, passphrase()))),
+    );
+    // Magic, version, compression, cipher, derivation and salt come before the costs
+    let costs = 4 + 1 + 1 + 1 + 1 + 16;
+    for (at, cost) in [
+        (costs, u32::MAX),
+        (costs + 4, 1 << 30),
+        (costs + 8, 1 << 24),
+    ] {
+        let original = fs::read(file(&path)).unwrap();
+        edit(&path, |bytes| {
+            bytes[at..at + 4].copy_from_slice(&cost.to_le_bytes())
+        });
+        let error = load_error(&reading(Some(passphrase())), &path);
+        assert!(
+            matches!(&error, SnapshotError::UnknownFormat(what) if what.contains("beyond the limits")),
+            "{error}"
+        );
+        fs::write(file(&path), original).unwrap();
+    }
+}
+
+#[test]
+fn unknown_formats_are_refused() {
+    let path = saved("unknown", options(Compression::None, None));
+    for (at, byte, what) in [
+        (4, 9, "version 9"),
+        (5, 9, "compression 9"),
+        (6, 9, "cipher 9"),
+    ] {
+        let original = fs::read(file(&path)).unwrap();
+        edit(&path, |bytes| bytes[at] = byte);
+        assert_eq!(
+            load_error(&reading(None), &path),
+            SnapshotError::UnknownFormat(String::from(what))
+        );
+        fs::write(file(&path), original).unwrap();
+    }
+}
+
+#[test]
+fn lists_nested_too_deep_are_refused_rather_than_overflowing() {
+    let nested = |depth| (0..depth).fold(DBTypes::Nil, |value, _| DBTypes::List(vec![value]));
+    let db = Database::default().with_data_dir(data_dir());
+    db.put(String::from("deep"), nested(200)).unwrap();
+    db.store("nested").unwrap();
+    let db = reading(None);
+    db.load("nested").unwrap();
+    assert_eq!(db.get("deep"), Some(nested(200)));
+
+    // A list inside itself far more often than anything written would be
+    edit("nested", |bytes| {
+        let list = [6, 1, 0, 0, 0, 0, 0, 0, 0];
+        let at = bytes.windows(list.len()).position(|w| w == list).unwrap();
+        let deeper = list.repeat(100_000);
+        bytes.splice(at..at, deeper);
+    });
+    let error = reading(None).load("nested").unwrap_err().to_string();
+    assert!(error.contains("Lists can nest at most"), "{error}");
+
+    // Nor is a value that deep written
+    let db = Database::default().with_data_dir(data_dir());
+    db.put(String::from("deep"), nested(1000)).unwrap();
+    let error = db.store("deeper").unwrap_err().to_string();
+    assert!(error.contains("Lists can nest at most"), "{error}");
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+use db::{ClusterError, DatabaseError, ShardError};
+use thiserror::Error;
+
+use crate::parser::ast::Span;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    #[error("Function `{0}` does not exist")]
+    FunctionNotFound(String),
+    #[error("Variable `{0}` does not exist")]
+    VariableNotFound(String),
+    #[error("`{0}` is not a function")]
+    NotAFunction(String),
+    #[error("Expected {expected} arguments, but found {found}")]
+    WrongArgumentCount { expected: usize, found: usize },
+    #[error("{0}")]
+    InvalidArguments(String),
+    #[error("Cannot apply `{op}` to {left} and {right}")]
+    InvalidOperation {
+        op: String,
+        left: String,
+        right: String,
+    },
+    #[error("{0}")]
+    Database(String),
+    #[error("{0}")]
+    Io(String),
+    #[error("`{0}` changes records, but this database is a read-only replica")]
+    ReadOnly(String),
+    #[error("{0}")]
+    Cluster(String),
+    #[error("{0}")]
+    Shard(String),
+    #[error("Evaluation can nest at most {0} deep")]
+    TooDeep(usize),
+    /// Raised by `(error "msg")`
+    #[error("{0}")]
+    Raised(String),
+    /// The error raised by the call at `span`
+    #[error("{error}")]
+    At {
+        span: Span,
+        error: Box<RuntimeError>,
+    },
+}
+
+impl RuntimeError {
+    /// Attributes the error to the call at `span`, unless a call nested in it was already blamed
+    pub fn at(self, span: &Span) -> Self {
+        match self {
+            located @ RuntimeError::At { .. } => located,
+            error => RuntimeError::At {
+                span: span.clone(),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// The source of the call that raised the error, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RuntimeError::At { span, .. } => Some(span.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl From<DatabaseError> for RuntimeError {
+    fn from(e: DatabaseError) -> Self {
+        RuntimeError::Database(e.to_string())
+    }
+}
+
+impl From<ClusterError> for RuntimeError {
+    fn from(e: ClusterError) -> Self {
+        match e {
+            ClusterError::Database(e) => e.into(),
+            e => RuntimeError::Cluster(e.to_string()),
+        }
+    }
+}
+
+impl From<ShardError> for RuntimeError {
+    fn from(e: ShardError) -> Self {
+        match e {
+            ShardError::Database(message) => RuntimeError::Database(message),
+            e => RuntimeError::Shard(e.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(e: io::Error) -> Self {
+        RuntimeError::Io(e.to_string())
+    }
+}
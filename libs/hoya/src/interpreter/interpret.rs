@@ -1,20 +1,73 @@
 use ariadne::{Label, Report, ReportKind, Source};
-use combine::ParseError;
+use chrono::Utc;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::{self, Write};
-use std::{ops::Deref, rc::Rc};
+use std::ops::ControlFlow;
+use std::{ops::Deref, sync::Arc};
 
-use db::{DBTypes, Database};
+use db::{
+    extract, ClusterClient, Comparison, DBTypes, Database, Entry, Field, Format, ImportMode,
+    IndexKey, Mutation, Outcome, Predicate, Query, ShardRouter,
+};
 
-use super::types::InterpreterValue;
-use crate::parser::parse;
+use super::errors::RuntimeError;
+use super::ops::{accepts, combine, REDUCE_OPERATORS};
+use super::text;
+use super::time;
+use super::types::{Closure, InterpreterValue, Scope};
+use crate::parser::{parse, MAX_DEPTH};
 use crate::typechecker::bidirectional_typechecker::Typechecker;
+use crate::typechecker::env::{local, writes};
 use crate::typechecker::types::InternalType;
 use crate::{parser::ast::Expr, typechecker::env::Environment};
 
+/// How deeply evaluation can nest. Parsed code is already limited to `MAX_DEPTH`, which
+/// takes one more level to evaluate, but expressions built without the parser aren't
+pub const MAX_EVAL_DEPTH: usize = MAX_DEPTH + 1;
+
+thread_local! {
+    /// How many `eval`s on this thread are running inside one another
+    static EVAL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// One level of `eval`, counted until it's dropped
+struct Nested;
+
+impl Nested {
+    fn enter() -> Result<Self, RuntimeError> {
+        EVAL_DEPTH.with(|depth| {
+            if depth.get() >= MAX_EVAL_DEPTH {
+                return Err(RuntimeError::TooDeep(MAX_EVAL_DEPTH));
+            }
+            depth.set(depth.get() + 1);
+            Ok(Nested)
+        })
+    }
+}
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        EVAL_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// The most numbers one `range` makes, so a large one is refused rather than exhausting memory
+pub const MAX_RANGE: i128 = 1_000_000;
+
 pub struct Interpreter<'a> {
     typechecker: Typechecker<'a>,
     env: Environment<'a>,
     db: Database,
+    /// Refuses builtins that change records
+    read_only: bool,
+    /// Makes writes through this node's cluster rather than on the records directly
+    cluster: Option<ClusterClient>,
+    /// Sends reads and writes to the shards that own their keys rather than using `db`
+    shards: Option<Arc<ShardRouter>>,
 }
 
 impl Interpreter<'_> {
@@ -27,13 +80,285 @@ impl Interpreter<'_> {
             typechecker,
             env,
             db,
+            read_only: false,
+            cluster: None,
+            shards: None,
         }
     }
 
-    fn text_expr_to_string(&self, expr: &Expr) -> String {
-        match expr {
-            Expr::Text(t) => t.to_string(),
-            _ => unreachable!(),
+    /// Refuses builtins that change records, as on a replica
+    pub fn read_only(self) -> Self {
+        Self {
+            read_only: true,
+            ..self
+        }
+    }
+
+    /// Makes writes through `cluster`, which the database is a node of
+    pub fn with_cluster(self, cluster: ClusterClient) -> Self {
+        Self {
+            cluster: Some(cluster),
+            ..self
+        }
+    }
+
+    /// Routes reads and writes through `router` to the shards that own their keys
+    pub fn with_shards(self, router: Arc<ShardRouter>) -> Self {
+        Self {
+            shards: Some(router),
+            ..self
+        }
+    }
+
+    /// Makes a write on the shard that owns its key or through the cluster when there is
+    /// one, and on the records otherwise
+    fn mutate(&self, mutation: Mutation) -> Result<Outcome, RuntimeError> {
+        match (&self.shards, &self.cluster) {
+            (Some(shards), _) => Ok(shards.write(mutation)?),
+            (None, Some(cluster)) => Ok(cluster.write(mutation)?),
+            (None, None) => Ok(mutation.apply(&self.db)?),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<DBTypes>, RuntimeError> {
+        match &self.shards {
+            Some(shards) => Ok(shards.get(key)?),
+            None => Ok(self.db.get(key)),
+        }
+    }
+
+    fn get_many(&self, keys: &[String]) -> Result<Vec<Option<DBTypes>>, RuntimeError> {
+        match &self.shards {
+            Some(shards) => Ok(shards.get_many(keys)?),
+            None => Ok(self.db.get_many(keys)),
+        }
+    }
+
+    /// Visits the entries matching `query`, on every shard when there are shards
+    fn scan<F>(&self, query: &Query, f: F) -> Result<(), RuntimeError>
+    where
+        F: FnMut(Entry<'_>) -> ControlFlow<()>,
+    {
+        match &self.shards {
+            Some(shards) => Ok(shards.scan(query, f)?),
+            None => {
+                self.db.scan(query, f);
+                Ok(())
+            }
+        }
+    }
+
+    fn count(&self, query: &Query) -> Result<usize, RuntimeError> {
+        match &self.shards {
+            Some(_) => {
+                let mut count = 0;
+                self.scan(query, |_| {
+                    count += 1;
+                    ControlFlow::Continue(())
+                })?;
+                Ok(count)
+            }
+            None => Ok(self.db.count(query)),
+        }
+    }
+
+    /// Refuses `builtin`, which replaces records without going through the cluster
+    fn outside_cluster(&self, builtin: &str) -> Result<(), RuntimeError> {
+        match self.cluster {
+            Some(_) => Err(RuntimeError::Cluster(format!(
+                "`{builtin}` would replace records behind the cluster's back"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits for what the cluster committed, when reads have to see every write
+    fn read_index(&self) -> Result<(), RuntimeError> {
+        match &self.cluster {
+            Some(cluster) if cluster.linearizable_reads() => Ok(cluster.read_index()?),
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates an argument that has to be text, like a key or a file name
+    fn eval_text(&self, expr: &Expr, scope: &Scope) -> Result<String, RuntimeError> {
+        match self.eval(expr, scope)? {
+            InterpreterValue::Text(t) => Ok(t.to_string()),
+            value => Err(RuntimeError::InvalidArguments(format!(
+                "Expected text, but found {}",
+                self.stringify(&value)
+            ))),
+        }
+    }
+
+    fn keys_to_list(
+        &self,
+        keys: Option<Vec<String>>,
+        index: &str,
+    ) -> Result<InterpreterValue, RuntimeError> {
+        match keys {
+            Some(keys) => Ok(InterpreterValue::List(Arc::new(
+                keys.into_iter()
+                    .map(|k| InterpreterValue::Text(Arc::new(k)))
+                    .collect(),
+            ))),
+            None => Err(RuntimeError::InvalidArguments(format!(
+                "Index `{index}` does not exist"
+            ))),
+        }
+    }
+
+    /// Converts a value to be stored or matched against stored values, running any queries
+    /// it contains. Functions can't be stored, so they are refused rather than kept as their
+    /// signature
+    fn to_db(&self, val: InterpreterValue) -> Result<DBTypes, RuntimeError> {
+        Ok(match val {
+            InterpreterValue::Query(q) => DBTypes::List(self.rows(&q)?),
+            InterpreterValue::Function(_) => {
+                return Err(RuntimeError::InvalidArguments(String::from(
+                    "Functions can't be stored",
+                )))
+            }
+            InterpreterValue::List(l) => DBTypes::List(
+                l.iter()
+                    .map(|v| self.to_db(v.clone()))
+                    .collect::<Result<_, _>>()?,
+            ),
+            v => v.into(),
+        })
+    }
+
+    /// `[key value]` pairs of every entry matching `query`
+    fn rows(&self, query: &Query) -> Result<Vec<DBTypes>, RuntimeError> {
+        let mut rows = vec![];
+        self.scan(query, |entry| {
+            rows.push(DBTypes::List(vec![
+                DBTypes::Text(entry.key.to_owned()),
+                entry.value.clone(),
+            ]));
+            ControlFlow::Continue(())
+        })?;
+        Ok(rows)
+    }
+
+    fn not_a_collection() -> RuntimeError {
+        RuntimeError::InvalidArguments(String::from("Expected a query or a list"))
+    }
+
+    /// Feeds every value of a query or a list to `f`
+    fn for_each_value(
+        &self,
+        source: &InterpreterValue,
+        mut f: impl FnMut(&DBTypes),
+    ) -> Result<(), RuntimeError> {
+        match source {
+            InterpreterValue::Query(q) => self.scan(q, |entry| {
+                f(entry.value);
+                ControlFlow::Continue(())
+            }),
+            InterpreterValue::List(l) => {
+                for v in l.iter() {
+                    f(&self.to_db(v.clone())?);
+                }
+                Ok(())
+            }
+            _ => Err(Self::not_a_collection()),
+        }
+    }
+
+    /// Feeds every element of a query or a list to `f`, stopping at the first error.
+    /// User functions may write to the database, so query results are collected before they run
+    fn for_each_item(
+        &self,
+        source: &InterpreterValue,
+        mut f: impl FnMut(InterpreterValue) -> Result<ControlFlow<()>, RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        let items = match source {
+            InterpreterValue::Query(q) => {
+                let mut values = vec![];
+                self.scan(q, |entry| {
+                    values.push(entry.value.clone().into());
+                    ControlFlow::Continue(())
+                })?;
+                values
+            }
+            InterpreterValue::List(l) => l.to_vec(),
+            _ => return Err(Self::not_a_collection()),
+        };
+        for item in items {
+            if f(item)?.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls a lambda with already evaluated arguments
+    fn apply(
+        &self,
+        f: &InterpreterValue,
+        args: Vec<InterpreterValue>,
+    ) -> Result<InterpreterValue, RuntimeError> {
+        match f {
+            InterpreterValue::Function(closure) if closure.params.len() == args.len() => {
+                let mut scope = closure.scope.clone();
+                for ((name, _), value) in closure.params.iter().zip(args) {
+                    scope.insert(name.clone(), value);
+                }
+                self.eval(&closure.body, &scope)
+            }
+            InterpreterValue::Function(closure) => Err(RuntimeError::WrongArgumentCount {
+                expected: closure.params.len(),
+                found: args.len(),
+            }),
+            other => Err(RuntimeError::NotAFunction(self.stringify(other))),
+        }
+    }
+
+    /// Whether `f` returns `true` for `item`
+    fn test(&self, f: &InterpreterValue, item: InterpreterValue) -> Result<bool, RuntimeError> {
+        Ok(matches!(self.apply(f, vec![item])?, InterpreterValue::Boolean(b) if *b))
+    }
+
+    /// Folds the values of a query or a list with a `reduce` operator,
+    /// skipping values the operator doesn't take. `Nil` if none remain.
+    /// A value it takes but can't combine, such as an overflowing sum, is an error
+    fn reduce(
+        &self,
+        source: &InterpreterValue,
+        op: &str,
+    ) -> Result<InterpreterValue, RuntimeError> {
+        if !REDUCE_OPERATORS.contains(&op) {
+            return Err(RuntimeError::InvalidArguments(format!(
+                "Unknown operator `{op}`, expected one of {:?}",
+                REDUCE_OPERATORS
+            )));
+        }
+
+        let mut acc: Option<DBTypes> = None;
+        let mut failed = None;
+        self.for_each_value(source, |value| {
+            if failed.is_some() || !accepts(op, value) {
+                return;
+            }
+            acc = match acc.take() {
+                None => Some(value.clone()),
+                Some(acc) => match combine(op, &acc, value) {
+                    Some(combined) => Some(combined),
+                    None => {
+                        failed = Some(RuntimeError::InvalidOperation {
+                            op: op.to_owned(),
+                            left: self.stringify(&acc.into()),
+                            right: self.stringify(&value.clone().into()),
+                        });
+                        None
+                    }
+                },
+            }
+        })?;
+        match failed {
+            Some(error) => Err(error),
+            None => Ok(acc.into()),
         }
     }
 
@@ -43,8 +368,12 @@ impl Interpreter<'_> {
             InterpreterValue::Boolean(b) => (*b).to_string(),
             InterpreterValue::List(l) => format!("{:?}", *l),
             InterpreterValue::Number(n) => (*n).to_string(),
+            InterpreterValue::BigInt(n) => (*n).to_string(),
+            InterpreterValue::Decimal(d) => (*d).to_string(),
             InterpreterValue::Float(f) => (*f).to_string(),
             InterpreterValue::Unit(_) => "()".to_string(),
+            InterpreterValue::Nil => "nil".to_string(),
+            InterpreterValue::Error(e) => format!("Error: {}", *e),
             InterpreterValue::Identifier(i) => format!("{}", *i),
             InterpreterValue::Call(i, a) => format!(
                 "({} {:?})",
@@ -54,111 +383,1246 @@ impl Interpreter<'_> {
                     .map(|a| self.stringify(a))
                     .collect::<Vec<String>>()
             ),
+            InterpreterValue::Query(q) => match self.rows(q) {
+                Ok(rows) => self.stringify(&DBTypes::List(rows).into()),
+                Err(e) => format!("Error: {e}"),
+            },
+            InterpreterValue::Function(closure) => format!(
+                "(fn ({}) -> {})",
+                closure
+                    .params
+                    .iter()
+                    .map(|(name, t)| format!("{name}: {t}"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                closure.ret
+            ),
+            InterpreterValue::Set(s) => format!("{:?}", *s),
+            InterpreterValue::SortedSet(s) => format!("{:?}", *s),
+            InterpreterValue::Bytes(b) => time::bytes(b),
+            InterpreterValue::Timestamp(t) => time::timestamp(t),
+            InterpreterValue::Duration(d) => time::duration(d),
         }
     }
 
-    fn eval_builtin(&self, expr: &Expr) -> InterpreterValue {
-        match expr {
-            Expr::Call(name, args) => {
-                if let Expr::Identifier(identifier) = &**name {
-                    match &identifier[..] {
-                        "write" => {
-                            let mut stdout = io::stdout();
-                            stdout
-                                .write_all(self.stringify(&self.eval_expr(&args[0])).as_bytes())
-                                .unwrap();
-                            InterpreterValue::Unit(Rc::new(()))
+    /// Evaluates a member or a list of members of a set
+    fn eval_members(
+        &self,
+        expr: &Expr,
+        scope: &Scope,
+    ) -> Result<Option<Vec<String>>, RuntimeError> {
+        Ok(match self.eval(expr, scope)? {
+            InterpreterValue::Text(t) => Some(vec![t.to_string()]),
+            InterpreterValue::List(l) => l
+                .iter()
+                .map(|member| match member {
+                    InterpreterValue::Text(t) => Some(t.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        })
+    }
+
+    /// The `[key value]` pairs of a list, checked before any of them is stored
+    fn eval_entries(
+        &self,
+        expr: &Expr,
+        scope: &Scope,
+    ) -> Result<Vec<(String, DBTypes)>, RuntimeError> {
+        let malformed = || {
+            RuntimeError::InvalidArguments(String::from("Expected a list of `[key value]` pairs"))
+        };
+        match self.eval(expr, scope)? {
+            InterpreterValue::List(entries) => entries
+                .iter()
+                .map(|entry| match entry {
+                    InterpreterValue::List(pair) => match &pair[..] {
+                        [_, InterpreterValue::Nil] => Err(RuntimeError::InvalidArguments(
+                            String::from("Can't store nil, use `mremove` to delete keys"),
+                        )),
+                        [InterpreterValue::Text(key), value] => {
+                            Ok((key.to_string(), self.to_db(value.clone())?))
                         }
-                        "writeln" => {
-                            let mut stdout = io::stdout();
-                            stdout
-                                .write_all(
-                                    (self.stringify(&self.eval_expr(&args[0])) + "\n").as_bytes(),
-                                )
-                                .unwrap();
-                            InterpreterValue::Unit(Rc::new(()))
+                        _ => Err(malformed()),
+                    },
+                    _ => Err(malformed()),
+                })
+                .collect(),
+            _ => Err(malformed()),
+        }
+    }
+
+    /// A score given as a number of any kind, as a float. Ones too large for a float
+    /// become infinite
+    fn eval_score(&self, expr: &Expr, scope: &Scope) -> Result<Option<f64>, RuntimeError> {
+        Ok(match self.eval(expr, scope)? {
+            InterpreterValue::Number(n) => Some(*n as f64),
+            InterpreterValue::BigInt(n) => n.to_f64(),
+            InterpreterValue::Decimal(d) => d.to_f64(),
+            InterpreterValue::Float(f) => Some(*f),
+            _ => None,
+        })
+    }
+
+    /// Fails unless `args` fit the builtin's parameters, since builtins index into them
+    fn check_arity(&self, name: &str, args: &[Expr]) -> Result<(), RuntimeError> {
+        let params = self.env.param_types_of(name);
+        let (expected, variadic) = match params.last() {
+            Some(InternalType::Rest(_)) => (params.len() - 1, true),
+            _ => (params.len(), false),
+        };
+        if args.len() == expected || variadic && args.len() > expected {
+            Ok(())
+        } else {
+            Err(RuntimeError::WrongArgumentCount {
+                expected,
+                found: args.len(),
+            })
+        }
+    }
+
+    fn eval_builtin(
+        &self,
+        identifier: &str,
+        args: &[Expr],
+        scope: &Scope,
+    ) -> Result<InterpreterValue, RuntimeError> {
+        if !self.env.is_defined(identifier) {
+            return Err(RuntimeError::FunctionNotFound(identifier.to_owned()));
+        }
+        self.check_arity(identifier, args)?;
+        if self.read_only && writes(identifier) {
+            return Err(RuntimeError::ReadOnly(identifier.to_owned()));
+        }
+        if self.shards.is_some() && local(identifier) {
+            return Err(RuntimeError::Shard(format!(
+                "`{identifier}` only works on this server's own records, which are on the shards"
+            )));
+        }
+
+        Ok(match identifier {
+            "write" => {
+                let mut stdout = io::stdout();
+                stdout.write_all(self.stringify(&self.eval(&args[0], scope)?).as_bytes())?;
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "writeln" => {
+                let mut stdout = io::stdout();
+                stdout
+                    .write_all((self.stringify(&self.eval(&args[0], scope)?) + "\n").as_bytes())?;
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "put" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Nil => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Can't store nil, use `remove` to delete a key",
+                    )))
+                }
+                value => self
+                    .mutate(Mutation::Put {
+                        key: self.eval_text(&args[1], scope)?,
+                        value: self.to_db(value)?,
+                    })?
+                    .into(),
+            },
+            "get" => self.get(&self.eval_text(&args[0], scope)?)?.into(),
+            "get-or" => match self.get(&self.eval_text(&args[0], scope)?)? {
+                Some(value) => value.into(),
+                None => self.eval(&args[1], scope)?,
+            },
+            "unwrap-or" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Nil => self.eval(&args[1], scope)?,
+                value => value,
+            },
+            "is-nil" => InterpreterValue::Boolean(Arc::new(matches!(
+                self.eval(&args[0], scope)?,
+                InterpreterValue::Nil
+            ))),
+            "is-error" => InterpreterValue::Boolean(Arc::new(matches!(
+                self.eval(&args[0], scope)?,
+                InterpreterValue::Error(_)
+            ))),
+            "error" => {
+                return Err(RuntimeError::Raised(match self.eval(&args[0], scope)? {
+                    InterpreterValue::Text(msg) | InterpreterValue::Error(msg) => msg.to_string(),
+                    value => self.stringify(&value),
+                }))
+            }
+            "message" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Error(msg) => InterpreterValue::Text(msg),
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected an error",
+                    )))
+                }
+            },
+            "exists" => {
+                let key = self.eval_text(&args[0], scope)?;
+                InterpreterValue::Boolean(Arc::new(match &self.shards {
+                    Some(shards) => shards.exists(&key)?,
+                    None => self.db.exists(&key),
+                }))
+            }
+            "remove" => self
+                .mutate(Mutation::Remove {
+                    key: self.eval_text(&args[0], scope)?,
+                })?
+                .into(),
+            op @ ("mget" | "mremove") => match self.eval_members(&args[0], scope)? {
+                Some(keys) if op == "mget" => InterpreterValue::List(Arc::new(
+                    self.get_many(&keys)?.into_iter().map(Into::into).collect(),
+                )),
+                Some(keys) => self.mutate(Mutation::RemoveMany(keys))?.into(),
+                None => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a list of keys",
+                    )))
+                }
+            },
+            "mput" => {
+                let entries = self.eval_entries(&args[0], scope)?;
+                self.mutate(Mutation::PutMany(entries))?.into()
+            }
+            "store" => {
+                self.db
+                    .store(&self.eval_text(&args[0], scope)?)
+                    .map_err(|e| RuntimeError::Io(e.to_string()))?;
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "load" => {
+                self.outside_cluster("load")?;
+                self.db
+                    .load(&self.eval_text(&args[0], scope)?)
+                    .map_err(|e| RuntimeError::Io(e.to_string()))?;
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "bgsave" => {
+                self.db.bgsave(&self.eval_text(&args[0], scope)?)?;
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "save-status" => InterpreterValue::Text(Arc::new(self.db.save_status().to_string())),
+            "replication-status" => {
+                InterpreterValue::Text(Arc::new(self.db.replication_status().to_string()))
+            }
+            "cluster-status" => InterpreterValue::Text(Arc::new(match &self.cluster {
+                Some(cluster) => cluster.status().to_string(),
+                None => String::from("Not part of a cluster"),
+            })),
+            "shard-status" => InterpreterValue::Text(Arc::new(match &self.shards {
+                Some(shards) => {
+                    let mut status = shards.ring().to_string();
+                    for (shard, target) in shards.resharding()? {
+                        status += &format!(
+                            "\n{shard} is resharding to ring version {}",
+                            target.version()
+                        );
+                    }
+                    status
+                }
+                None => String::from("Not routing to shards"),
+            })),
+            op @ ("add-shard" | "remove-shard") => {
+                let Some(shards) = &self.shards else {
+                    return Err(RuntimeError::Shard(String::from(
+                        "This server isn't routing to shards",
+                    )));
+                };
+                let addr = self.eval_text(&args[0], scope)?;
+                let moved = if op == "add-shard" {
+                    shards.add_shard(&addr)?
+                } else {
+                    shards.remove_shard(&addr)?
+                };
+                InterpreterValue::Number(Arc::new(moved as isize))
+            }
+            op @ ("add-member" | "remove-member") => {
+                let Some(cluster) = &self.cluster else {
+                    return Err(RuntimeError::Cluster(String::from(
+                        "This database isn't part of a cluster",
+                    )));
+                };
+                let id = match self.eval(&args[0], scope)? {
+                    InterpreterValue::Number(id) if *id >= 0 => *id as u64,
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a node id",
+                        )))
+                    }
+                };
+                if op == "add-member" {
+                    cluster.add_member(id, self.eval_text(&args[1], scope)?)?;
+                } else {
+                    cluster.remove_member(id)?;
+                }
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "snapshots" => InterpreterValue::List(Arc::new(
+                self.db
+                    .snapshots()?
+                    .into_iter()
+                    .map(|name| InterpreterValue::Text(Arc::new(name)))
+                    .collect(),
+            )),
+            "delete-snapshot" => {
+                self.db
+                    .delete_snapshot(&self.eval_text(&args[0], scope)?)
+                    .map_err(|e| RuntimeError::Io(e.to_string()))?;
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "export" => {
+                let path = self.eval_text(&args[0], scope)?;
+                let format = self
+                    .eval_text(&args[1], scope)?
+                    .parse::<Format>()
+                    .map_err(RuntimeError::InvalidArguments)?;
+                let count = self
+                    .db
+                    .data_dir()
+                    .create(&path)
+                    .and_then(|file| self.db.export(file, format))
+                    .map_err(|e| RuntimeError::Io(e.to_string()))?;
+                InterpreterValue::Number(Arc::new(count as isize))
+            }
+            "import" => {
+                self.outside_cluster("import")?;
+                let path = self.eval_text(&args[0], scope)?;
+                let format = self
+                    .eval_text(&args[1], scope)?
+                    .parse::<Format>()
+                    .map_err(RuntimeError::InvalidArguments)?;
+                let mode = self
+                    .eval_text(&args[2], scope)?
+                    .parse::<ImportMode>()
+                    .map_err(RuntimeError::InvalidArguments)?;
+                let count = self
+                    .db
+                    .data_dir()
+                    .open(&path)
+                    .and_then(|file| self.db.import(file, format, mode))
+                    .map_err(|e| RuntimeError::Io(e.to_string()))?;
+                InterpreterValue::Number(Arc::new(count as isize))
+            }
+            "create-index" => {
+                let extractor = match self.eval(&args[1], scope)? {
+                    InterpreterValue::Text(kind) if *kind == "value" => extract::value(),
+                    InterpreterValue::Text(kind) if *kind == "elements" => extract::elements(),
+                    InterpreterValue::Number(n) if *n >= 0 => extract::nth(*n as usize),
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "An index extracts \"value\", \"elements\" or a list position",
+                        )))
+                    }
+                };
+                self.db
+                    .create_index(&self.eval_text(&args[0], scope)?, extractor);
+                InterpreterValue::Unit(Arc::new(()))
+            }
+            "find-by" => {
+                let index = self.eval_text(&args[0], scope)?;
+                self.keys_to_list(
+                    self.db
+                        .find_by(&index, &self.to_db(self.eval(&args[1], scope)?)?),
+                    &index,
+                )?
+            }
+            "find-range" => {
+                let index = self.eval_text(&args[0], scope)?;
+                self.keys_to_list(
+                    self.db.find_range(
+                        &index,
+                        &self.to_db(self.eval(&args[1], scope)?)?,
+                        &self.to_db(self.eval(&args[2], scope)?)?,
+                    ),
+                    &index,
+                )?
+            }
+            "keys" => {
+                let prefix = self.eval_text(&args[0], scope)?;
+                let keys = match &self.shards {
+                    Some(shards) => shards.keys(&prefix)?,
+                    None => {
+                        let mut keys = vec![];
+                        self.db.scan(
+                            &Query::new(Some(prefix).filter(|p| !p.is_empty())),
+                            |entry| {
+                                keys.push(entry.key.to_owned());
+                                ControlFlow::Continue(())
+                            },
+                        );
+                        keys
+                    }
+                };
+                InterpreterValue::List(Arc::new(
+                    keys.into_iter()
+                        .map(|k| InterpreterValue::Text(Arc::new(k)))
+                        .collect(),
+                ))
+            }
+            "select" => {
+                let prefix = self.eval_text(&args[0], scope)?;
+                InterpreterValue::Query(Arc::new(Query::new(
+                    Some(prefix).filter(|p| !p.is_empty()),
+                )))
+            }
+            "where" => {
+                let field = self.eval_text(&args[1], scope)?;
+                let op = self.eval_text(&args[2], scope)?;
+                match (self.eval(&args[0], scope)?, Comparison::parse(&op)) {
+                    (InterpreterValue::Query(q), Some(op)) => {
+                        InterpreterValue::Query(Arc::new((*q).clone().with(Predicate::new(
+                            Field::from(&field[..]),
+                            op,
+                            self.to_db(self.eval(&args[3], scope)?)?,
+                        ))))
+                    }
+                    (_, None) => {
+                        return Err(RuntimeError::InvalidArguments(format!(
+                            "Unknown comparison `{op}`"
+                        )))
+                    }
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a query",
+                        )))
+                    }
+                }
+            }
+            "explain" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Query(q) => {
+                    InterpreterValue::Text(Arc::new(self.db.plan(&q).to_string()))
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a query",
+                    )))
+                }
+            },
+            "map" => {
+                let source = self.eval(&args[1], scope)?;
+                match (self.eval(&args[0], scope)?, source) {
+                    (InterpreterValue::Text(field), InterpreterValue::Query(q)) => {
+                        let field = Field::from(&field[..]);
+                        let mut projected = vec![];
+                        self.scan(&q, |entry| {
+                            if let Some(value) = entry.field(&field).into_iter().next() {
+                                projected.push(value.into());
+                            }
+                            ControlFlow::Continue(())
+                        })?;
+                        InterpreterValue::List(Arc::new(projected))
+                    }
+                    (f @ InterpreterValue::Function(_), source) => {
+                        let mut mapped = vec![];
+                        self.for_each_item(&source, |item| {
+                            mapped.push(self.apply(&f, vec![item])?);
+                            Ok(ControlFlow::Continue(()))
+                        })?;
+                        InterpreterValue::List(Arc::new(mapped))
+                    }
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a field of a query or a function",
+                        )))
+                    }
+                }
+            }
+            "group-by" => {
+                let source = self.eval(&args[1], scope)?;
+                let mut groups = BTreeMap::<IndexKey, Vec<InterpreterValue>>::new();
+                match (self.eval(&args[0], scope)?, source) {
+                    (InterpreterValue::Text(field), InterpreterValue::Query(q)) => {
+                        let field = Field::from(&field[..]);
+                        self.scan(&q, |entry| {
+                            for group in entry.field(&field) {
+                                groups
+                                    .entry(IndexKey(group))
+                                    .or_default()
+                                    .push(entry.value.clone().into());
+                            }
+                            ControlFlow::Continue(())
+                        })?;
+                    }
+                    (f @ InterpreterValue::Function(_), source) => {
+                        self.for_each_item(&source, |item| {
+                            let group = self.to_db(self.apply(&f, vec![item.clone()])?)?;
+                            groups.entry(IndexKey(group)).or_default().push(item);
+                            Ok(ControlFlow::Continue(()))
+                        })?;
+                    }
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a field of a query or a function",
+                        )))
+                    }
+                }
+                InterpreterValue::List(Arc::new(
+                    groups
+                        .into_iter()
+                        .map(|(IndexKey(group), items)| {
+                            InterpreterValue::List(Arc::new(vec![
+                                group.into(),
+                                InterpreterValue::List(Arc::new(items)),
+                            ]))
+                        })
+                        .collect(),
+                ))
+            }
+            "reduce" => {
+                let source = self.eval(&args[1], scope)?;
+                match self.eval(&args[0], scope)? {
+                    InterpreterValue::Text(op) => self.reduce(&source, &op)?,
+                    f => {
+                        let mut acc = None;
+                        self.for_each_item(&source, |item| {
+                            acc = Some(match acc.take() {
+                                None => item,
+                                Some(acc) => self.apply(&f, vec![acc, item])?,
+                            });
+                            Ok(ControlFlow::Continue(()))
+                        })?;
+                        acc.unwrap_or(InterpreterValue::Nil)
+                    }
+                }
+            }
+            "fold" => {
+                let f = self.eval(&args[0], scope)?;
+                let mut acc = self.eval(&args[1], scope)?;
+                self.for_each_item(&self.eval(&args[2], scope)?, |item| {
+                    acc = self.apply(&f, vec![acc.clone(), item])?;
+                    Ok(ControlFlow::Continue(()))
+                })?;
+                acc
+            }
+            "filter" => {
+                let f = self.eval(&args[0], scope)?;
+                let mut kept = vec![];
+                self.for_each_item(&self.eval(&args[1], scope)?, |item| {
+                    if self.test(&f, item.clone())? {
+                        kept.push(item);
+                    }
+                    Ok(ControlFlow::Continue(()))
+                })?;
+                InterpreterValue::List(Arc::new(kept))
+            }
+            "sort-by" => {
+                let f = self.eval(&args[0], scope)?;
+                let mut keyed = vec![];
+                self.for_each_item(&self.eval(&args[1], scope)?, |item| {
+                    keyed.push((self.to_db(self.apply(&f, vec![item.clone()])?)?, item));
+                    Ok(ControlFlow::Continue(()))
+                })?;
+                keyed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                InterpreterValue::List(Arc::new(keyed.into_iter().map(|(_, item)| item).collect()))
+            }
+            "any" | "all" => {
+                let f = self.eval(&args[0], scope)?;
+                // `any` stops at the first match, `all` at the first mismatch
+                let stop_on = identifier == "any";
+                let mut stopped = false;
+                self.for_each_item(&self.eval(&args[1], scope)?, |item| {
+                    if self.test(&f, item)? == stop_on {
+                        stopped = true;
+                        Ok(ControlFlow::Break(()))
+                    } else {
+                        Ok(ControlFlow::Continue(()))
+                    }
+                })?;
+                InterpreterValue::Boolean(Arc::new(stopped == stop_on))
+            }
+            op @ ("+" | "-" | "*" | "/") => {
+                let a = self.to_db(self.eval(&args[0], scope)?)?;
+                let b = self.to_db(self.eval(&args[1], scope)?)?;
+                match combine(op, &a, &b) {
+                    Some(result) => result.into(),
+                    None => {
+                        return Err(RuntimeError::InvalidOperation {
+                            op: op.to_string(),
+                            left: self.stringify(&a.into()),
+                            right: self.stringify(&b.into()),
+                        })
+                    }
+                }
+            }
+            op @ ("=" | "!=" | "<" | "<=" | ">" | ">=") => {
+                let a = self.to_db(self.eval(&args[0], scope)?)?;
+                let b = self.to_db(self.eval(&args[1], scope)?)?;
+                InterpreterValue::Boolean(Arc::new(
+                    Comparison::parse(op)
+                        .map(|op| op.compare(&a, &b))
+                        .unwrap_or_default(),
+                ))
+            }
+            "not" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Boolean(b) => InterpreterValue::Boolean(Arc::new(!*b)),
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a boolean",
+                    )))
+                }
+            },
+            op @ ("and" | "or") => {
+                match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                    (InterpreterValue::Boolean(a), InterpreterValue::Boolean(b)) => {
+                        InterpreterValue::Boolean(Arc::new(if op == "and" {
+                            *a && *b
+                        } else {
+                            *a || *b
+                        }))
+                    }
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected booleans",
+                        )))
+                    }
+                }
+            }
+            "count" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Query(q) => {
+                    InterpreterValue::Number(Arc::new(self.count(&q)? as isize))
+                }
+                InterpreterValue::List(l) => InterpreterValue::Number(Arc::new(l.len() as isize)),
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a query or a list",
+                    )))
+                }
+            },
+            "sum" => match self.reduce(&self.eval(&args[0], scope)?, "+")? {
+                InterpreterValue::Nil => InterpreterValue::Number(Arc::new(0)),
+                sum => sum,
+            },
+            "min" => self.reduce(&self.eval(&args[0], scope)?, "min")?,
+            "max" => self.reduce(&self.eval(&args[0], scope)?, "max")?,
+            "concat" => InterpreterValue::Text(Arc::new(
+                args.iter()
+                    .map(|arg| Ok(self.stringify(&self.eval(arg, scope)?)))
+                    .collect::<Result<_, RuntimeError>>()?,
+            )),
+            "len" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Text(t) => {
+                    InterpreterValue::Number(Arc::new(text::len(&t) as isize))
+                }
+                InterpreterValue::List(l) => InterpreterValue::Number(Arc::new(l.len() as isize)),
+                InterpreterValue::Set(s) => InterpreterValue::Number(Arc::new(s.len() as isize)),
+                InterpreterValue::SortedSet(s) => {
+                    InterpreterValue::Number(Arc::new(s.len() as isize))
+                }
+                InterpreterValue::Bytes(b) => InterpreterValue::Number(Arc::new(b.len() as isize)),
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected text, bytes, a list or a set",
+                    )))
+                }
+            },
+            "substr" => {
+                match (
+                    self.eval(&args[0], scope)?,
+                    self.eval(&args[1], scope)?,
+                    self.eval(&args[2], scope)?,
+                ) {
+                    (
+                        InterpreterValue::Text(t),
+                        InterpreterValue::Number(start),
+                        InterpreterValue::Number(len),
+                    ) if *start >= 0 && *len >= 0 => InterpreterValue::Text(Arc::new(
+                        text::substr(&t, *start as usize, *len as usize),
+                    )),
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected text, a start and a length that aren't negative",
+                        )))
+                    }
+                }
+            }
+            "split" => match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                (InterpreterValue::Text(t), InterpreterValue::Text(separator)) => {
+                    InterpreterValue::List(Arc::new(
+                        text::split(&t, &separator)
+                            .into_iter()
+                            .map(|part| InterpreterValue::Text(Arc::new(part)))
+                            .collect(),
+                    ))
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected text",
+                    )))
+                }
+            },
+            "join" => match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                (InterpreterValue::List(l), InterpreterValue::Text(separator)) => {
+                    InterpreterValue::Text(Arc::new(
+                        l.iter()
+                            .map(|item| self.stringify(item))
+                            .collect::<Vec<_>>()
+                            .join(&separator),
+                    ))
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a list and text",
+                    )))
+                }
+            },
+            op @ ("upper" | "lower" | "trim") => match self.eval(&args[0], scope)? {
+                InterpreterValue::Text(t) => InterpreterValue::Text(Arc::new(match op {
+                    "upper" => t.to_uppercase(),
+                    "lower" => t.to_lowercase(),
+                    _ => t.trim().to_owned(),
+                })),
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected text",
+                    )))
+                }
+            },
+            op @ ("starts-with" | "contains" | "matches") => {
+                match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                    (InterpreterValue::Text(t), InterpreterValue::Text(pattern)) => match op {
+                        "starts-with" => {
+                            InterpreterValue::Boolean(Arc::new(t.starts_with(pattern.as_str())))
                         }
-                        "put" => self
-                            .db
-                            .put(
-                                self.text_expr_to_string(&args[1]),
-                                DBTypes::from(self.eval_expr(&args[0])),
-                            )
-                            .into(),
-                        "get" => self.db.get(&self.text_expr_to_string(&args[0])).into(),
-                        "exists" => InterpreterValue::Boolean(Rc::new(
-                            self.db.exists(&self.text_expr_to_string(&args[0])),
-                        )),
-                        "remove" => self.db.remove(&self.text_expr_to_string(&args[0])).into(),
-                        "store" => match self.db.store(&self.text_expr_to_string(&args[0])) {
-                            Ok(_) => InterpreterValue::Unit(Rc::new(())),
-                            Err(e) => InterpreterValue::Text(Rc::new(format!("{}", e))),
-                        },
-                        "load" => match self.db.load(&self.text_expr_to_string(&args[0])) {
-                            Ok(_) => InterpreterValue::Unit(Rc::new(())),
-                            Err(e) => InterpreterValue::Text(Rc::new(format!("{}", e))),
+                        "contains" => {
+                            InterpreterValue::Boolean(Arc::new(t.contains(pattern.as_str())))
+                        }
+                        _ => match text::matches(&t, &pattern) {
+                            Ok(matched) => InterpreterValue::Boolean(Arc::new(matched)),
+                            Err(e) => return Err(RuntimeError::InvalidArguments(e)),
                         },
-                        _ => todo!(),
+                    },
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected text",
+                        )))
                     }
-                } else {
-                    unreachable!()
                 }
             }
-            _ => unreachable!(),
-        }
+            "replace" => match (
+                self.eval(&args[0], scope)?,
+                self.eval(&args[1], scope)?,
+                self.eval(&args[2], scope)?,
+            ) {
+                (
+                    InterpreterValue::Text(t),
+                    InterpreterValue::Text(from),
+                    InterpreterValue::Text(to),
+                ) => InterpreterValue::Text(Arc::new(t.replace(from.as_str(), &to))),
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected text",
+                    )))
+                }
+            },
+            "format" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Text(template) => {
+                    let values = args[1..]
+                        .iter()
+                        .map(|arg| Ok(self.stringify(&self.eval(arg, scope)?)))
+                        .collect::<Result<Vec<_>, RuntimeError>>()?;
+                    match text::format(&template, &values) {
+                        Ok(t) => InterpreterValue::Text(Arc::new(t)),
+                        Err(e) => return Err(RuntimeError::InvalidArguments(e)),
+                    }
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected text",
+                    )))
+                }
+            },
+            "nth" => match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                (InterpreterValue::List(l), InterpreterValue::Number(n)) => usize::try_from(*n)
+                    .ok()
+                    .and_then(|n| l.get(n).cloned())
+                    .unwrap_or(InterpreterValue::Nil),
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a list and a position",
+                    )))
+                }
+            },
+            op @ ("head" | "tail" | "reverse" | "sort" | "uniq") => {
+                let l = match self.eval(&args[0], scope)? {
+                    InterpreterValue::List(l) => l,
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a list",
+                        )))
+                    }
+                };
+                let mut items = l.iter().cloned();
+                InterpreterValue::List(Arc::new(match op {
+                    "head" => return Ok(items.next().unwrap_or(InterpreterValue::Nil)),
+                    "tail" => items.skip(1).collect(),
+                    "reverse" => items.rev().collect(),
+                    "sort" => {
+                        let mut keyed = items
+                            .map(|item| Ok((self.to_db(item.clone())?, item)))
+                            .collect::<Result<Vec<_>, RuntimeError>>()?;
+                        keyed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                        keyed.into_iter().map(|(_, item)| item).collect()
+                    }
+                    _ => {
+                        let mut seen = BTreeSet::new();
+                        let mut unique = vec![];
+                        for item in items {
+                            if seen.insert(IndexKey(self.to_db(item.clone())?)) {
+                                unique.push(item);
+                            }
+                        }
+                        unique
+                    }
+                }))
+            }
+            op @ ("append" | "prepend") => {
+                match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                    (InterpreterValue::List(l), item) => {
+                        let mut items = (*l).clone();
+                        if op == "append" {
+                            items.push(item);
+                        } else {
+                            items.insert(0, item);
+                        }
+                        InterpreterValue::List(Arc::new(items))
+                    }
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a list",
+                        )))
+                    }
+                }
+            }
+            "slice" => match (
+                self.eval(&args[0], scope)?,
+                self.eval(&args[1], scope)?,
+                self.eval(&args[2], scope)?,
+            ) {
+                (
+                    InterpreterValue::List(l),
+                    InterpreterValue::Number(start),
+                    InterpreterValue::Number(end),
+                ) => {
+                    let clamp = |i: isize| (i.max(0) as usize).min(l.len());
+                    let (start, end) = (clamp(*start), clamp(*end));
+                    InterpreterValue::List(Arc::new(if start < end {
+                        l[start..end].to_vec()
+                    } else {
+                        vec![]
+                    }))
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a list, a start and an end",
+                    )))
+                }
+            },
+            "range" => match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                (InterpreterValue::Number(start), InterpreterValue::Number(end)) => {
+                    if *end as i128 - *start as i128 > MAX_RANGE {
+                        return Err(RuntimeError::InvalidArguments(format!(
+                            "A range holds at most {MAX_RANGE} numbers"
+                        )));
+                    }
+                    InterpreterValue::List(Arc::new(
+                        (*start..*end)
+                            .map(|n| InterpreterValue::Number(Arc::new(n)))
+                            .collect(),
+                    ))
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a start and an end",
+                    )))
+                }
+            },
+            op @ ("lpush" | "rpush") => {
+                let key = self.eval_text(&args[1], scope)?;
+                let value = self.to_db(self.eval(&args[0], scope)?)?;
+                self.mutate(Mutation::Push {
+                    key,
+                    value,
+                    front: op == "lpush",
+                })?
+                .into()
+            }
+            op @ ("lpop" | "rpop") => {
+                let key = self.eval_text(&args[0], scope)?;
+                self.mutate(Mutation::Pop {
+                    key,
+                    front: op == "lpop",
+                })?
+                .into()
+            }
+            "lrange" => match (self.eval(&args[1], scope)?, self.eval(&args[2], scope)?) {
+                (InterpreterValue::Number(start), InterpreterValue::Number(stop)) => match self
+                    .db
+                    .lrange(&self.eval_text(&args[0], scope)?, *start, *stop)
+                {
+                    Ok(values) => DBTypes::List(values).into(),
+                    Err(e) => return Err(e.into()),
+                },
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a start and a stop",
+                    )))
+                }
+            },
+            op @ ("sadd" | "srem") => {
+                let key = self.eval_text(&args[1], scope)?;
+                match self.eval_members(&args[0], scope)? {
+                    Some(members) if op == "sadd" => {
+                        self.mutate(Mutation::Sadd { key, members })?.into()
+                    }
+                    Some(members) => self.mutate(Mutation::Srem { key, members })?.into(),
+                    None => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a member or a list of members",
+                        )))
+                    }
+                }
+            }
+            "smembers" => match self.db.smembers(&self.eval_text(&args[0], scope)?) {
+                Ok(set) => InterpreterValue::Set(Arc::new(set)),
+                Err(e) => return Err(e.into()),
+            },
+            op @ ("sinter" | "sunion") => match self.eval_members(&args[0], scope)? {
+                Some(keys) => {
+                    let combined = if op == "sinter" {
+                        self.db.sinter(&keys)
+                    } else {
+                        self.db.sunion(&keys)
+                    };
+                    match combined {
+                        Ok(set) => InterpreterValue::Set(Arc::new(set)),
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                None => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a list of keys",
+                    )))
+                }
+            },
+            op @ ("zadd" | "zincr") => {
+                let key = self.eval_text(&args[2], scope)?;
+                match (
+                    self.eval(&args[0], scope)?,
+                    self.eval_score(&args[1], scope)?,
+                ) {
+                    // Scores that aren't finite would leave the members without an order
+                    (InterpreterValue::Text(_), Some(score)) if !score.is_finite() => {
+                        return Err(RuntimeError::InvalidArguments(format!(
+                            "Expected a finite score, but found {score}"
+                        )))
+                    }
+                    (InterpreterValue::Text(member), Some(score)) if op == "zadd" => self
+                        .mutate(Mutation::Zadd {
+                            key,
+                            member: member.to_string(),
+                            score,
+                        })?
+                        .into(),
+                    (InterpreterValue::Text(member), Some(by)) => self
+                        .mutate(Mutation::Zincr {
+                            key,
+                            member: member.to_string(),
+                            by,
+                        })?
+                        .into(),
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a member and a score",
+                        )))
+                    }
+                }
+            }
+            "zrange-by-score" => {
+                match (
+                    self.eval_score(&args[1], scope)?,
+                    self.eval_score(&args[2], scope)?,
+                ) {
+                    (Some(min), Some(max)) => {
+                        match self
+                            .db
+                            .zrange_by_score(&self.eval_text(&args[0], scope)?, min, max)
+                        {
+                            Ok(members) => InterpreterValue::List(Arc::new(
+                                members
+                                    .into_iter()
+                                    .map(|(member, score)| {
+                                        InterpreterValue::List(Arc::new(vec![
+                                            InterpreterValue::Text(Arc::new(member)),
+                                            InterpreterValue::Float(Arc::new(score)),
+                                        ]))
+                                    })
+                                    .collect(),
+                            )),
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a minimum and a maximum score",
+                        )))
+                    }
+                }
+            }
+            "zrank" => match self.eval(&args[1], scope)? {
+                InterpreterValue::Text(member) => {
+                    match self.db.zrank(&self.eval_text(&args[0], scope)?, &member) {
+                        Ok(Some(rank)) => InterpreterValue::Number(Arc::new(rank as isize)),
+                        Ok(None) => InterpreterValue::Nil,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a member",
+                    )))
+                }
+            },
+            "now" => InterpreterValue::Timestamp(Arc::new(Utc::now())),
+            "duration" => match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                (InterpreterValue::Number(amount), InterpreterValue::Text(unit)) => {
+                    match time::duration_of(*amount as i64, &unit) {
+                        Ok(d) => InterpreterValue::Duration(Arc::new(d)),
+                        Err(e) => return Err(RuntimeError::InvalidArguments(e)),
+                    }
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected an amount and a unit of time",
+                    )))
+                }
+            },
+            "format-time" => match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                (InterpreterValue::Timestamp(t), InterpreterValue::Text(format)) => {
+                    match time::format(&t, &format) {
+                        Ok(t) => InterpreterValue::Text(Arc::new(t)),
+                        Err(e) => return Err(RuntimeError::InvalidArguments(e)),
+                    }
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a timestamp and a format",
+                    )))
+                }
+            },
+            "bytes" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Text(t) => {
+                    InterpreterValue::Bytes(Arc::new(t.as_bytes().to_vec()))
+                }
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected text",
+                    )))
+                }
+            },
+            "bigint" => match self.eval(&args[0], scope)? {
+                InterpreterValue::Number(n) => InterpreterValue::BigInt(Arc::new(BigInt::from(*n))),
+                n @ InterpreterValue::BigInt(_) => n,
+                // Fractions are truncated
+                InterpreterValue::Decimal(d) => {
+                    InterpreterValue::BigInt(Arc::new(BigInt::from(d.trunc().mantissa())))
+                }
+                InterpreterValue::Float(f) => match BigInt::from_f64(f.trunc()) {
+                    Some(n) => InterpreterValue::BigInt(Arc::new(n)),
+                    None => {
+                        return Err(RuntimeError::InvalidArguments(format!(
+                            "{f} is not a finite number"
+                        )))
+                    }
+                },
+                InterpreterValue::Text(t) => match t.trim().parse::<BigInt>() {
+                    Ok(n) => InterpreterValue::BigInt(Arc::new(n)),
+                    Err(_) => {
+                        return Err(RuntimeError::InvalidArguments(format!(
+                            "`{t}` is not an integer"
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a number or text",
+                    )))
+                }
+            },
+            "decimal" => {
+                let converted = match self.eval(&args[0], scope)? {
+                    InterpreterValue::Number(n) => Decimal::from_isize(*n),
+                    InterpreterValue::BigInt(n) => n.to_i128().and_then(Decimal::from_i128),
+                    InterpreterValue::Decimal(d) => Some(*d),
+                    InterpreterValue::Float(f) => Decimal::from_f64(*f),
+                    InterpreterValue::Text(t) => Decimal::from_str_exact(t.trim()).ok(),
+                    _ => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Expected a number or text",
+                        )))
+                    }
+                };
+                match converted {
+                    Some(d) => InterpreterValue::Decimal(Arc::new(d)),
+                    None => {
+                        return Err(RuntimeError::InvalidArguments(String::from(
+                            "Not representable as a decimal",
+                        )))
+                    }
+                }
+            }
+            // Halves round away from zero
+            "round" => match (self.eval(&args[0], scope)?, self.eval(&args[1], scope)?) {
+                (_, InterpreterValue::Number(places)) if *places < 0 => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a non-negative number of places",
+                    )))
+                }
+                (InterpreterValue::Decimal(d), InterpreterValue::Number(places)) => {
+                    InterpreterValue::Decimal(Arc::new(d.round_dp_with_strategy(
+                        u32::try_from(*places).unwrap_or(u32::MAX),
+                        RoundingStrategy::MidpointAwayFromZero,
+                    )))
+                }
+                (InterpreterValue::Float(f), InterpreterValue::Number(places)) => {
+                    // Past 15 places a float has nothing left to round
+                    let scale = 10f64.powi((*places).min(15) as i32);
+                    InterpreterValue::Float(Arc::new((*f * scale).round() / scale))
+                }
+                (
+                    n @ (InterpreterValue::Number(_) | InterpreterValue::BigInt(_)),
+                    InterpreterValue::Number(_),
+                ) => n,
+                _ => {
+                    return Err(RuntimeError::InvalidArguments(String::from(
+                        "Expected a number and a number of places",
+                    )))
+                }
+            },
+            _ => return Err(RuntimeError::FunctionNotFound(identifier.to_owned())),
+        })
     }
 
-    pub fn eval_expr(&self, expr: &Expr) -> InterpreterValue {
+    pub fn eval_expr(&self, expr: &Expr) -> Result<InterpreterValue, RuntimeError> {
+        self.eval(expr, &Scope::new())
+    }
+
+    fn eval(&self, expr: &Expr, scope: &Scope) -> Result<InterpreterValue, RuntimeError> {
+        let _nested = Nested::enter()?;
         match expr {
-            Expr::Identifier(..) => todo!("No definable functions yet"),
-            Expr::Call(..) => {
+            Expr::Identifier(name) => scope
+                .get(name)
+                .cloned()
+                .ok_or_else(|| RuntimeError::VariableNotFound(name.to_owned())),
+            Expr::Call(name, args, span) => match &**name {
+                // Variables shadow builtins
+                Expr::Identifier(name) if scope.contains_key(name) => self.apply(
+                    &scope[name],
+                    args.iter()
+                        .map(|arg| self.eval(arg, scope))
+                        .collect::<Result<_, _>>()?,
+                ),
+                Expr::Identifier(name) if name == "try" => self.eval_try(args, scope),
                 // Temporarily calling eval_builtin until I implement the def statement
                 // Example: (def greet (name: Text) -> Text
                 //              (writeln (concat "Hello " name "!")))
-                self.eval_builtin(expr)
+                Expr::Identifier(name) => self.eval_builtin(name, args, scope),
+                _ => unreachable!(),
             }
-            e => e.to_owned().into(),
+            .map_err(|e| e.at(span)),
+            Expr::Lambda(params, ret, body) => Ok(InterpreterValue::Function(Arc::new(Closure {
+                params: params.clone(),
+                ret: ret.clone(),
+                body: *body.clone(),
+                scope: scope.clone(),
+            }))),
+            Expr::List(l) => Ok(InterpreterValue::List(Arc::new(
+                l.iter()
+                    .map(|e| self.eval(e, scope))
+                    .collect::<Result<_, _>>()?,
+            ))),
+            e => Ok(e.to_owned().into()),
         }
     }
 
-    pub fn interpret(&self, code: &str) {
-        let parser_result = &parse(code).map(|x| x.0);
+    /// `(try expr (catch e handler))` evaluates `handler` with the error bound to `e`
+    /// if evaluating `expr` fails
+    fn eval_try(&self, args: &[Expr], scope: &Scope) -> Result<InterpreterValue, RuntimeError> {
+        match args {
+            [body, Expr::Call(catch, clause, _)] if matches!(&**catch, Expr::Identifier(c) if c == "catch") => {
+                match clause.as_slice() {
+                    [Expr::Identifier(e), handler] => self.eval(body, scope).or_else(|error| {
+                        let mut scope = scope.clone();
+                        scope.insert(
+                            e.clone(),
+                            InterpreterValue::Error(Arc::new(error.to_string())),
+                        );
+                        self.eval(handler, &scope)
+                    }),
+                    _ => Err(Self::malformed_try()),
+                }
+            }
+            _ => Err(Self::malformed_try()),
+        }
+    }
+
+    fn malformed_try() -> RuntimeError {
+        RuntimeError::InvalidArguments(String::from("Expected `(try expr (catch e handler))`"))
+    }
 
-        if let Ok(parsed) = parser_result {
-            let checked = self.typechecker.check(&InternalType::Any, parsed);
+    /// Runs `code` and prints its value, or reports what's wrong with it. Failing to
+    /// print isn't reported, since there'd be nowhere to report it
+    pub fn interpret(&self, code: &str) {
+        let parsed = match parse(code) {
+            Ok((parsed, _)) => parsed,
+            Err(err) => {
+                // The first line only says parsing failed
+                let formatted_error = err.to_string();
+                let lines = formatted_error.lines().skip(1).collect::<Vec<_>>();
+                let message = match &lines[..] {
+                    [] => String::from("Couldn't parse the input"),
+                    [line] => line.to_string(),
+                    [first, second, ..] => format!("{first}\n{second}"),
+                };
+                let _ = Report::build(ReportKind::Error, (), 1)
+                    .with_message(message)
+                    .with_label(
+                        Label::new(err.position..err.position + 1).with_message(
+                            err.errors
+                                .first()
+                                .map(ToString::to_string)
+                                .unwrap_or_else(|| String::from("unexpected input")),
+                        ),
+                    )
+                    .finish()
+                    .print(Source::from(code));
+                return;
+            }
+        };
 
-            match checked {
-                Ok(_) => {
-                    println!("{}", self.stringify(&self.eval_expr(parsed)));
+        match self.typechecker.check(&InternalType::Any, &parsed) {
+            Ok(_) => match self.read_index().and_then(|()| self.eval_expr(&parsed)) {
+                Ok(value) => {
+                    let _ = writeln!(io::stdout(), "{}", self.stringify(&value));
                 }
-                Err(errors) => errors.into_iter().for_each(|e| {
-                    Report::build(ReportKind::Error, (), 1)
-                        .with_message(e.to_short_error().to_string())
-                        .with_label(Label::new(0..1))
-                        .with_label(Label::new(0..1).with_message(format!("{e}")))
+                Err(e) => {
+                    let _ = Report::build(ReportKind::Error, (), 1)
+                        .with_message(e.to_string())
+                        .with_label(
+                            Label::new(e.span().unwrap_or(0..code.chars().count()))
+                                .with_message("raised here"),
+                        )
                         .finish()
-                        .print(Source::from(code))
-                        .unwrap()
-                }),
-            }
-        } else {
-            let err = match parser_result {
-                Err(e) => e,
-                _ => unreachable!(),
-            };
-            Report::build(ReportKind::Error, (), 1)
-                .with_message({
-                    let formatted_error = format!("{}", err);
-                    let err_vec = formatted_error.split('\n').skip(1).collect::<Vec<_>>();
-                    format!("{}\n{}", err_vec[0], err_vec[1])
-                })
-                .with_label(
-                    Label::new(err.position().line as usize..err.position().column as usize)
-                        .with_message(err.errors.first().unwrap()),
-                )
-                .finish()
-                .print(Source::from(code))
-                .unwrap();
+                        .print(Source::from(code));
+                }
+            },
+            Err(errors) => errors.into_iter().for_each(|e| {
+                let _ = Report::build(ReportKind::Error, (), 1)
+                    .with_message(e.to_short_error().to_string())
+                    .with_label(Label::new(0..1))
+                    .with_label(Label::new(0..1).with_message(format!("{e}")))
+                    .finish()
+                    .print(Source::from(code));
+            }),
         }
     }
 }
@@ -169,6 +1633,9 @@ impl Default for Interpreter<'_> {
             typechecker: Typechecker::new(Environment::builtin()),
             env: Environment::builtin(),
             db: Database::default(),
+            read_only: false,
+            cluster: None,
+            shards: None,
         }
     }
 }
@@ -1,2 +1,7 @@
+pub mod errors;
 pub mod interpret;
+mod ops;
+pub mod shared;
+mod text;
+mod time;
 pub mod types;
@@ -0,0 +1,137 @@
+use db::DBTypes;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+
+pub(crate) const REDUCE_OPERATORS: [&str; 7] = ["+", "*", "min", "max", "and", "or", "concat"];
+
+fn is_number(value: &DBTypes) -> bool {
+    matches!(
+        value,
+        DBTypes::Number(_) | DBTypes::BigInt(_) | DBTypes::Decimal(_) | DBTypes::Float(_)
+    )
+}
+
+/// Whether a `reduce` operator can start from `value`
+pub(crate) fn accepts(op: &str, value: &DBTypes) -> bool {
+    match op {
+        "+" => is_number(value) || matches!(value, DBTypes::Duration(_)),
+        "*" => is_number(value),
+        "and" | "or" => matches!(value, DBTypes::Boolean(_)),
+        "concat" => matches!(
+            value,
+            DBTypes::Text(_) | DBTypes::List(_) | DBTypes::Bytes(_)
+        ),
+        _ => true,
+    }
+}
+
+fn integer(v: &DBTypes) -> Option<BigInt> {
+    match v {
+        DBTypes::Number(n) => Some(BigInt::from(*n)),
+        DBTypes::BigInt(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+/// Integers too large for a decimal don't convert
+fn decimal(v: &DBTypes) -> Option<Decimal> {
+    match v {
+        DBTypes::Decimal(d) => Some(*d),
+        v => integer(v)?.to_i128().and_then(Decimal::from_i128),
+    }
+}
+
+fn float(v: &DBTypes) -> Option<f64> {
+    match v {
+        DBTypes::Number(n) => Some(*n as f64),
+        DBTypes::BigInt(n) => n.to_f64(),
+        DBTypes::Decimal(d) => d.to_f64(),
+        DBTypes::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+/// Applies an arithmetic operator to two numbers, promoting both to the least exact kind
+/// among them, in the order `Number`, `BigInt`, `Decimal`, `Float`:
+///
+/// - `Number`s that overflow don't apply, as their result is typed as a `Number`.
+///   `BigInt`s never overflow
+/// - integers combined with a `Decimal` stay exact, but don't apply past its 28 significant digits
+/// - anything combined with a `Float` becomes a `Float`
+///
+/// Integer division truncates. Dividing an integer or a decimal by zero doesn't apply
+fn arithmetic(op: &str, a: &DBTypes, b: &DBTypes) -> Option<DBTypes> {
+    use DBTypes::*;
+
+    match (a, b) {
+        (Number(x), Number(y)) => {
+            let exact = match op {
+                "+" => x.checked_add(*y),
+                "-" => x.checked_sub(*y),
+                "*" => x.checked_mul(*y),
+                _ if *y == 0 => return None,
+                _ => x.checked_div(*y),
+            };
+            exact.map(Number)
+        }
+        (Number(_) | BigInt(_), Number(_) | BigInt(_)) => {
+            let (x, y) = (integer(a)?, integer(b)?);
+            Some(BigInt(match op {
+                "+" => x + y,
+                "-" => x - y,
+                "*" => x * y,
+                _ => x.checked_div(&y)?,
+            }))
+        }
+        (Float(_), _) | (_, Float(_)) => {
+            let (x, y) = (float(a)?, float(b)?);
+            Some(Float(match op {
+                "+" => x + y,
+                "-" => x - y,
+                "*" => x * y,
+                _ => x / y,
+            }))
+        }
+        _ => {
+            let (x, y) = (decimal(a)?, decimal(b)?);
+            match op {
+                "+" => x.checked_add(y),
+                "-" => x.checked_sub(y),
+                "*" => x.checked_mul(y),
+                _ => x.checked_div(y),
+            }
+            .map(Decimal)
+        }
+    }
+}
+
+/// Combines two values with a `reduce` operator or an arithmetic builtin,
+/// or `None` if it doesn't apply to them.
+/// Numbers, times and durations that overflow don't apply
+pub(crate) fn combine(op: &str, acc: &DBTypes, value: &DBTypes) -> Option<DBTypes> {
+    use DBTypes::*;
+
+    match (op, acc, value) {
+        ("+" | "-" | "*" | "/", a, b) if is_number(a) && is_number(b) => arithmetic(op, a, b),
+        ("+", Timestamp(t), Duration(d)) | ("+", Duration(d), Timestamp(t)) => {
+            t.checked_add_signed(*d).map(Timestamp)
+        }
+        ("-", Timestamp(t), Duration(d)) => t.checked_sub_signed(*d).map(Timestamp),
+        ("-", Timestamp(a), Timestamp(b)) => Some(Duration(a.signed_duration_since(*b))),
+        ("+", Duration(a), Duration(b)) => a.checked_add(b).map(Duration),
+        ("-", Duration(a), Duration(b)) => a.checked_sub(b).map(Duration),
+        ("*", Duration(d), Number(n)) | ("*", Number(n), Duration(d)) => {
+            d.checked_mul(i32::try_from(*n).ok()?).map(Duration)
+        }
+        ("/", Duration(d), Number(n)) => d.checked_div(i32::try_from(*n).ok()?).map(Duration),
+        ("min", a, b) => Some(if b.total_cmp(a).is_lt() { b } else { a }.clone()),
+        ("max", a, b) => Some(if b.total_cmp(a).is_gt() { b } else { a }.clone()),
+        ("and", Boolean(a), Boolean(b)) => Some(Boolean(*a && *b)),
+        ("or", Boolean(a), Boolean(b)) => Some(Boolean(*a || *b)),
+        ("concat", Text(a), Text(b)) => Some(Text(a.to_owned() + b)),
+        ("concat", Bytes(a), Bytes(b)) => Some(Bytes([&a[..], b].concat())),
+        ("concat", List(a), List(b)) => Some(List(a.iter().chain(b).cloned().collect())),
+        _ => None,
+    }
+}
@@ -0,0 +1,84 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use db::{ClusterClient, Database, ShardRouter};
+
+use super::interpret::Interpreter;
+use crate::typechecker::bidirectional_typechecker::Typechecker;
+use crate::typechecker::env::Environment;
+
+/// An interpreter that many threads can run code on at once, all against the same database.
+/// Clones share the interpreter, and writes are isolated by the database's own locks
+#[derive(Clone)]
+pub struct SharedInterpreter {
+    interpreter: Arc<Interpreter<'static>>,
+}
+
+impl SharedInterpreter {
+    pub fn new(db: Database) -> Self {
+        Self {
+            interpreter: Arc::new(Interpreter::new(
+                db,
+                Environment::builtin(),
+                Typechecker::new(Environment::builtin()),
+            )),
+        }
+    }
+
+    /// Like `new`, but refuses to change records, for a database following a primary
+    pub fn read_only(db: Database) -> Self {
+        Self {
+            interpreter: Arc::new(
+                Interpreter::new(
+                    db,
+                    Environment::builtin(),
+                    Typechecker::new(Environment::builtin()),
+                )
+                .read_only(),
+            ),
+        }
+    }
+
+    /// Like `new`, but makes writes through `cluster`, which `db` is a node of
+    pub fn clustered(db: Database, cluster: ClusterClient) -> Self {
+        Self {
+            interpreter: Arc::new(
+                Interpreter::new(
+                    db,
+                    Environment::builtin(),
+                    Typechecker::new(Environment::builtin()),
+                )
+                .with_cluster(cluster),
+            ),
+        }
+    }
+
+    /// Like `new`, but reads and writes the records on the shards behind `router`
+    /// instead of `db`
+    pub fn routed(db: Database, router: ShardRouter) -> Self {
+        Self {
+            interpreter: Arc::new(
+                Interpreter::new(
+                    db,
+                    Environment::builtin(),
+                    Typechecker::new(Environment::builtin()),
+                )
+                .with_shards(Arc::new(router)),
+            ),
+        }
+    }
+}
+
+impl Deref for SharedInterpreter {
+    type Target = Interpreter<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.interpreter
+    }
+}
+
+impl Default for SharedInterpreter {
+    fn default() -> Self {
+        Self::new(Database::default())
+    }
+}
@@ -0,0 +1,77 @@
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Positions and lengths count grapheme clusters, so "é" is one character
+// whether or not it is written with a combining accent
+
+pub(crate) fn len(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+/// At most `len` characters starting at `start`
+pub(crate) fn substr(text: &str, start: usize, len: usize) -> String {
+    text.graphemes(true).skip(start).take(len).collect()
+}
+
+/// An empty separator splits the text into its characters
+pub(crate) fn split(text: &str, separator: &str) -> Vec<String> {
+    if separator.is_empty() {
+        text.graphemes(true).map(String::from).collect()
+    } else {
+        text.split(separator).map(String::from).collect()
+    }
+}
+
+/// Fills `{}` placeholders in order and `{n}` placeholders by position.
+/// `{{` and `}}` stand for literal braces
+pub(crate) fn format(template: &str, args: &[String]) -> Result<String, String> {
+    let mut formatted = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                formatted.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                formatted.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(String::from("Unclosed `{` in format string")),
+                    }
+                }
+
+                let position = if placeholder.is_empty() {
+                    next += 1;
+                    next - 1
+                } else {
+                    placeholder
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid placeholder `{{{placeholder}}}`"))?
+                };
+                formatted += args
+                    .get(position)
+                    .ok_or_else(|| format!("No argument for placeholder {position}"))?;
+            }
+            '}' => return Err(String::from("Unmatched `}` in format string")),
+            c => formatted.push(c),
+        }
+    }
+
+    Ok(formatted)
+}
+
+pub(crate) fn matches(text: &str, pattern: &str) -> Result<bool, String> {
+    Regex::new(pattern)
+        .map(|regex| regex.is_match(text))
+        .map_err(|e| e.to_string())
+}
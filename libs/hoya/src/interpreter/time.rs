@@ -0,0 +1,68 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+
+/// The literal a timestamp is written as
+pub(crate) fn timestamp(time: &DateTime<Utc>) -> String {
+    format!(
+        "#t\"{}\"",
+        time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    )
+}
+
+/// The literal a byte string is written as, in hex
+pub(crate) fn bytes(bytes: &[u8]) -> String {
+    let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    format!("#b\"{hex}\"")
+}
+
+/// Like `1d 2h 30m 15s`, with milliseconds kept only when there are any
+pub(crate) fn duration(duration: &Duration) -> String {
+    if duration.is_zero() {
+        return String::from("0s");
+    }
+
+    let sign = if *duration < Duration::zero() {
+        "-"
+    } else {
+        ""
+    };
+    let duration = duration.abs();
+    let parts = [
+        (duration.num_days(), "d"),
+        (duration.num_hours() % 24, "h"),
+        (duration.num_minutes() % 60, "m"),
+        (duration.num_seconds() % 60, "s"),
+        (duration.subsec_millis() as i64, "ms"),
+    ];
+    let formatted = parts
+        .iter()
+        .filter(|(amount, _)| *amount != 0)
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!("{sign}{formatted}")
+}
+
+/// `amount` of `unit`, which may be singular, plural or abbreviated
+pub(crate) fn duration_of(amount: i64, unit: &str) -> Result<Duration, String> {
+    let of = match unit {
+        "ms" | "millisecond" | "milliseconds" => Duration::try_milliseconds,
+        "s" | "second" | "seconds" => Duration::try_seconds,
+        "m" | "minute" | "minutes" => Duration::try_minutes,
+        "h" | "hour" | "hours" => Duration::try_hours,
+        "d" | "day" | "days" => Duration::try_days,
+        "w" | "week" | "weeks" => Duration::try_weeks,
+        unknown => return Err(format!("Unknown unit of time `{unknown}`")),
+    };
+    of(amount).ok_or_else(|| format!("{amount} {unit} is out of range"))
+}
+
+/// Formats `time` with `strftime` specifiers, rejecting unknown ones instead of panicking
+pub(crate) fn format(time: &DateTime<Utc>, format: &str) -> Result<String, String> {
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Invalid time format `{format}`"));
+    }
+    Ok(time.format_with_items(items.into_iter()).to_string())
+}
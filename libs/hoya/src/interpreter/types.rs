@@ -1,35 +1,76 @@
-use std::rc::Rc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 
-use db::DBTypes;
+use chrono::{DateTime, Duration, Utc};
+use db::{DBTypes, Outcome, Query};
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
 
-use crate::parser::ast::Expr;
+use crate::parser::ast::{Expr, Type};
+
+/// The variables bound by enclosing lambdas
+pub type Scope = BTreeMap<String, InterpreterValue>;
+
+/// A lambda together with the variables it captured
+#[derive(Debug)]
+pub struct Closure {
+    pub params: Vec<(String, Type)>,
+    pub ret: Type,
+    pub body: Expr,
+    pub scope: Scope,
+}
 
 #[derive(Debug, Clone)]
 pub enum InterpreterValue {
-    Number(Rc<isize>),
-    Float(Rc<f64>),
-    Boolean(Rc<bool>),
-    Text(Rc<String>),
-    List(Rc<Vec<InterpreterValue>>),
-    Identifier(Rc<String>),
-    Call(Rc<InterpreterValue>, Rc<Vec<InterpreterValue>>),
-    Unit(Rc<()>),
+    Number(Arc<isize>),
+    BigInt(Arc<BigInt>),
+    Decimal(Arc<Decimal>),
+    Float(Arc<f64>),
+    Boolean(Arc<bool>),
+    Text(Arc<String>),
+    List(Arc<Vec<InterpreterValue>>),
+    Identifier(Arc<String>),
+    Call(Arc<InterpreterValue>, Arc<Vec<InterpreterValue>>),
+    Unit(Arc<()>),
+    /// The absence of a value, like a missing key
+    Nil,
+    /// A failed operation, kept apart from the data it could be mistaken for
+    Error(Arc<String>),
+    Query(Arc<Query>),
+    Function(Arc<Closure>),
+    Set(Arc<BTreeSet<String>>),
+    SortedSet(Arc<BTreeMap<String, f64>>),
+    Bytes(Arc<Vec<u8>>),
+    Timestamp(Arc<DateTime<Utc>>),
+    Duration(Arc<Duration>),
 }
 
 impl From<Expr> for InterpreterValue {
     fn from(e: Expr) -> Self {
         match e {
-            Expr::Text(t) => Self::Text(Rc::new(t)),
-            Expr::List(l) => Self::List(Rc::new(l.into_iter().map(|e| e.into()).collect())),
-            Expr::Call(n, a) => Self::Call(
-                Rc::new(InterpreterValue::from(*n)),
-                Rc::new(a.into_iter().map(|e| e.into()).collect()),
+            Expr::Text(t) => Self::Text(Arc::new(t)),
+            Expr::List(l) => Self::List(Arc::new(l.into_iter().map(|e| e.into()).collect())),
+            Expr::Call(n, a, _) => Self::Call(
+                Arc::new(InterpreterValue::from(*n)),
+                Arc::new(a.into_iter().map(|e| e.into()).collect()),
             ),
-            Expr::Float(f) => Self::Float(Rc::new(f)),
-            Expr::Number(n) => Self::Number(Rc::new(n)),
-            Expr::Identifier(i) => Self::Identifier(Rc::new(i)),
-            Expr::Boolean(b) => Self::Boolean(Rc::new(b)),
-            Expr::Unit(()) => Self::Unit(Rc::new(())),
+            Expr::Float(f) => Self::Float(Arc::new(f)),
+            Expr::Number(n) => Self::Number(Arc::new(n)),
+            Expr::BigInt(n) => Self::BigInt(Arc::new(n)),
+            Expr::Decimal(d) => Self::Decimal(Arc::new(d)),
+            Expr::Identifier(i) => Self::Identifier(Arc::new(i)),
+            Expr::Boolean(b) => Self::Boolean(Arc::new(b)),
+            Expr::Unit(()) => Self::Unit(Arc::new(())),
+            Expr::Nil => Self::Nil,
+            Expr::Bytes(b) => Self::Bytes(Arc::new(b)),
+            Expr::Timestamp(t) => Self::Timestamp(Arc::new(t)),
+            Expr::Duration(d) => Self::Duration(Arc::new(d)),
+            Expr::Lambda(params, ret, body) => Self::Function(Arc::new(Closure {
+                params,
+                ret,
+                body: *body,
+                scope: Scope::new(),
+            })),
         }
     }
 }
@@ -37,12 +78,20 @@ impl From<Expr> for InterpreterValue {
 impl From<DBTypes> for InterpreterValue {
     fn from(d: DBTypes) -> Self {
         match d {
-            DBTypes::Text(t) => Self::Text(Rc::new(t)),
-            DBTypes::Number(n) => Self::Number(Rc::new(n)),
-            DBTypes::Float(f) => Self::Float(Rc::new(f)),
-            DBTypes::Boolean(b) => Self::Boolean(Rc::new(b)),
-            DBTypes::Unit(u) => Self::Unit(Rc::new(u)),
-            DBTypes::List(l) => Self::List(Rc::new(l.into_iter().map(|d| d.into()).collect())),
+            DBTypes::Text(t) => Self::Text(Arc::new(t)),
+            DBTypes::Number(n) => Self::Number(Arc::new(n)),
+            DBTypes::BigInt(n) => Self::BigInt(Arc::new(n)),
+            DBTypes::Decimal(d) => Self::Decimal(Arc::new(d)),
+            DBTypes::Float(f) => Self::Float(Arc::new(f)),
+            DBTypes::Boolean(b) => Self::Boolean(Arc::new(b)),
+            DBTypes::Unit(u) => Self::Unit(Arc::new(u)),
+            DBTypes::Nil => Self::Nil,
+            DBTypes::List(l) => Self::List(Arc::new(l.into_iter().map(|d| d.into()).collect())),
+            DBTypes::Set(s) => Self::Set(Arc::new(s)),
+            DBTypes::SortedSet(s) => Self::SortedSet(Arc::new(s)),
+            DBTypes::Bytes(b) => Self::Bytes(Arc::new(b)),
+            DBTypes::Timestamp(t) => Self::Timestamp(Arc::new(t)),
+            DBTypes::Duration(d) => Self::Duration(Arc::new(d)),
         }
     }
 }
@@ -51,7 +100,21 @@ impl From<Option<DBTypes>> for InterpreterValue {
     fn from(d: Option<DBTypes>) -> Self {
         match d {
             Some(s) => s.into(),
-            None => Self::Unit(Rc::new(())),
+            None => Self::Nil,
+        }
+    }
+}
+
+impl From<Outcome> for InterpreterValue {
+    fn from(o: Outcome) -> Self {
+        match o {
+            Outcome::Value(value) => value.into(),
+            Outcome::Values(values) => {
+                Self::List(Arc::new(values.into_iter().map(Into::into).collect()))
+            }
+            Outcome::Count(n) => Self::Number(Arc::new(n as isize)),
+            Outcome::Added(added) => Self::Boolean(Arc::new(added)),
+            Outcome::Score(score) => Self::Float(Arc::new(score)),
         }
     }
 }
@@ -61,9 +124,13 @@ impl From<InterpreterValue> for DBTypes {
         match i {
             InterpreterValue::Text(t) => Self::Text(t.to_string()),
             InterpreterValue::Number(n) => Self::Number(*n),
+            InterpreterValue::BigInt(n) => Self::BigInt((*n).clone()),
+            InterpreterValue::Decimal(d) => Self::Decimal(*d),
             InterpreterValue::Float(f) => Self::Float(*f),
             InterpreterValue::Boolean(b) => Self::Boolean(*b),
             InterpreterValue::Unit(_) => Self::Unit(()),
+            InterpreterValue::Nil => Self::Nil,
+            InterpreterValue::Error(e) => Self::Text(e.to_string()),
             InterpreterValue::List(l) => {
                 let mut list: Vec<DBTypes> = vec![];
                 for i in (*l).clone().into_iter() {
@@ -71,6 +138,11 @@ impl From<InterpreterValue> for DBTypes {
                 }
                 Self::List(list)
             }
+            InterpreterValue::Set(s) => Self::Set((*s).clone()),
+            InterpreterValue::SortedSet(s) => Self::SortedSet((*s).clone()),
+            InterpreterValue::Bytes(b) => Self::Bytes((*b).clone()),
+            InterpreterValue::Timestamp(t) => Self::Timestamp(*t),
+            InterpreterValue::Duration(d) => Self::Duration(*d),
             _ => unreachable!(),
         }
     }
@@ -1,13 +1,63 @@
+use std::fmt;
+use std::ops::Range;
+
+use chrono::{DateTime, Duration, Utc};
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
+
+/// Character offsets into the source
+pub type Span = Range<usize>;
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Number(isize),
+    /// `123n`, an integer of any size
+    BigInt(BigInt),
+    /// `19.99d`, an exact decimal
+    Decimal(Decimal),
     Float(f64),
     Boolean(bool),
     Text(String),
+    /// `#b"00ff"`, written in hex
+    Bytes(Vec<u8>),
+    /// `#t"2026-10-18T00:00:00Z"`, written in RFC 3339
+    Timestamp(DateTime<Utc>),
+    /// `#d"1h 30m"`, in weeks, days, hours, minutes, seconds and milliseconds
+    Duration(Duration),
     List(Vec<Expr>),
     Identifier(String),
-    Call(Box<Expr>, Vec<Expr>),
+    Call(Box<Expr>, Vec<Expr>, Span),
+    Lambda(Vec<(String, Type)>, Type, Box<Expr>),
     Unit(()),
+    /// `nil`, the absence of a value
+    Nil,
+}
+
+/// A type annotation, as written in the source
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Named(String),
+    List(Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+    /// `T?`, a `T` or `nil`
+    Optional(Box<Type>),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Named(name) => write!(f, "{name}"),
+            Type::List(element) => write!(f, "[{element}]"),
+            Type::Function(params, ret) => {
+                write!(f, "(")?;
+                for param in params {
+                    write!(f, "{param} ")?;
+                }
+                write!(f, "-> {ret})")
+            }
+            Type::Optional(t) => write!(f, "{t}?"),
+        }
+    }
 }
 
 // TODO: Function definitions, if statements, variable arguments, quoting, comments
@@ -1,3 +1,4 @@
 pub mod ast;
+#[allow(clippy::module_inception)]
 mod parser;
 pub use parser::*;
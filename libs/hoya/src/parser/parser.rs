@@ -1,19 +1,24 @@
-use crate::parser::ast::Expr;
-use combine::error::ParseError;
+use crate::parser::ast::{Expr, Type};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use combine::easy;
+use combine::error::{ParseError, StreamError};
 use combine::parser::char::{char, digit, letter, spaces, string, tab};
 use combine::parser::EasyParser;
-use combine::stream::{position, Stream};
+use combine::stream::{position, Stream, StreamErrorFor};
 use combine::{
-    attempt, between, many1, optional, satisfy, sep_by, sep_by1, skip_many, token, Parser,
+    attempt, between, many, many1, one_of, optional, position, satisfy, sep_by, sep_by1, skip_many,
+    token, Parser,
 };
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
 
-pub type EasyStreamError<'a> = combine::easy::Errors<char, &'a str, position::SourcePosition>;
-pub type EasyStreamOk<'a> = (Expr, position::Stream<&'a str, position::SourcePosition>);
+pub type EasyStreamError<'a> = combine::easy::Errors<char, &'a str, usize>;
+pub type EasyStreamOk<'a> = (Expr, position::Stream<&'a str, position::IndexPositioner>);
 pub type ParserResult<'a> = Result<EasyStreamOk<'a>, EasyStreamError<'a>>;
 
 fn whitespace<Input>() -> impl Parser<Input>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     spaces().or(skip_many(tab()))
@@ -21,7 +26,7 @@ where
 
 fn lex_char<Input>(c: char) -> impl Parser<Input, Output = char>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     let skip_spaces = || whitespace().silent();
@@ -31,21 +36,70 @@ where
 
 fn int<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (optional(char('-')), many1(digit())).map(|(sign, digits): (Option<char>, String)| {
-        Expr::Number(if sign.is_some() {
-            -digits.parse::<isize>().unwrap()
-        } else {
-            digits.parse::<isize>().unwrap()
-        })
-    })
+    attempt((optional(char('-')), many1(digit()))).and_then(
+        |(sign, digits): (Option<char>, String)| {
+            format!("{}{digits}", sign.map(String::from).unwrap_or_default())
+                .parse::<isize>()
+                .map(Expr::Number)
+                .map_err(|_| {
+                    StreamErrorFor::<Input>::message_static_message(
+                        "Number out of range, write big integers with an `n` suffix",
+                    )
+                })
+        },
+    )
+}
+
+/// `123456789012345678901234567890n`
+fn big_int<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (optional(char('-')), many1(digit()), char('n')).map(
+        |(sign, digits, _): (Option<char>, String, char)| {
+            let n = digits.parse::<BigInt>().unwrap();
+            Expr::BigInt(if sign.is_some() { -n } else { n })
+        },
+    )
+}
+
+/// `19.99d` or `100d`, exact up to 28 significant digits
+fn decimal<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    attempt((
+        optional(char('-')),
+        many1(digit()),
+        optional((char('.'), many1(digit()))),
+        char('d'),
+    ))
+    .and_then(
+        |(sign, whole, fraction, _): (Option<char>, String, Option<(char, String)>, char)| {
+            let fraction = fraction.map(|(_, f)| format!(".{f}")).unwrap_or_default();
+            format!(
+                "{}{whole}{fraction}",
+                sign.map(String::from).unwrap_or_default()
+            )
+            .parse::<Decimal>()
+            .map(Expr::Decimal)
+            .map_err(|_| {
+                StreamErrorFor::<Input>::message_static_message(
+                    "Decimal out of range, decimals hold at most 28 significant digits",
+                )
+            })
+        },
+    )
 }
 
 fn float<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (
@@ -67,7 +121,7 @@ where
 
 fn bool<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     string("true")
@@ -75,22 +129,148 @@ where
         .map(|chosen| Expr::Boolean(chosen == "true"))
 }
 
+fn nil<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    string("nil").map(|_| Expr::Nil)
+}
+
 fn text<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     between(
         token('\"'),
         token('\"'),
-        many1(satisfy(|c| c != '"' && c != '\'')),
+        many(satisfy(|c| c != '"' && c != '\'')),
     )
     .map(Expr::Text)
 }
 
+/// The contents of a `#<tag>"..."` literal
+fn tagged<Input>(tag: char) -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    attempt((
+        char('#'),
+        char(tag),
+        between(token('"'), token('"'), many(satisfy(|c| c != '"'))),
+    ))
+    .map(|(_, _, contents)| contents)
+}
+
+fn bytes<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    tagged('b').and_then(|hex: String| {
+        let digits = hex
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| StreamErrorFor::<Input>::message_static_message("Invalid hex digit"))?;
+        if digits.len() % 2 != 0 {
+            return Err(StreamErrorFor::<Input>::message_static_message(
+                "Odd number of hex digits",
+            ));
+        }
+        Ok(Expr::Bytes(
+            digits
+                .chunks(2)
+                .map(|pair| pair[0] << 4 | pair[1])
+                .collect(),
+        ))
+    })
+}
+
+/// An RFC 3339 date and time, or a date standing for its midnight in UTC
+fn timestamp<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    tagged('t').and_then(|time: String| {
+        DateTime::parse_from_rfc3339(&time)
+            .map(|t| t.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDate::parse_from_str(&time, "%Y-%m-%d")
+                    .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+            })
+            .map(Expr::Timestamp)
+            .map_err(|_| {
+                StreamErrorFor::<Input>::message_static_message("Invalid RFC 3339 timestamp")
+            })
+    })
+}
+
+/// `1d 2h 30m 15s 500ms`, with the amounts in any order and optionally negated as a whole
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let (negative, mut rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, text),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut total = Duration::zero();
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount = rest[..digits].parse::<i64>().ok()?;
+        rest = &rest[digits..];
+        let letters = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let of = match &rest[..letters] {
+            "ms" => Duration::try_milliseconds,
+            "s" => Duration::try_seconds,
+            "m" => Duration::try_minutes,
+            "h" => Duration::try_hours,
+            "d" => Duration::try_days,
+            "w" => Duration::try_weeks,
+            _ => return None,
+        };
+        total = total.checked_add(&of(amount)?)?;
+        rest = rest[letters..].trim_start();
+    }
+
+    Some(if negative { -total } else { total })
+}
+
+fn duration<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    tagged('d').and_then(|duration: String| {
+        parse_duration(&duration)
+            .map(Expr::Duration)
+            .ok_or_else(|| StreamErrorFor::<Input>::message_static_message("Invalid duration"))
+    })
+}
+
+parser! {
+    /// A `#b`, `#t` or `#d` literal
+    fn tagged_literal[Input]()(Input) -> Expr
+    where [Input: Stream<Token = char, Position = usize>]
+    {
+        choice!(bytes(), timestamp(), duration())
+    }
+}
+
 fn list_<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     let comma_list = sep_by(expr(), whitespace());
@@ -98,18 +278,96 @@ where
     between(lex_char('['), lex_char(']'), comma_list).map(Expr::List)
 }
 
+fn symbol<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    many1(letter().or(digit()).or(one_of("-+*/<>=!?".chars())))
+}
+
 fn name<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    symbol().map(Expr::Identifier)
+}
+
+/// A bare name, referring to a variable or a builtin
+fn variable<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    many1(letter().or(digit())).map(Expr::Identifier)
+    name()
+}
+
+fn type_name<Input>() -> impl Parser<Input, Output = Type>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    many1(letter().or(digit())).map(Type::Named)
+}
+
+fn list_type<Input>() -> impl Parser<Input, Output = Type>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    between(lex_char('['), char(']'), type_().skip(whitespace()))
+        .map(|element| Type::List(Box::new(element)))
+}
+
+/// `(Number Number -> Boolean)`
+fn function_type<Input>() -> impl Parser<Input, Output = Type>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        lex_char('('),
+        many(attempt(type_().skip(whitespace()))),
+        string("->"),
+        whitespace(),
+        type_(),
+        whitespace(),
+        char(')'),
+    )
+        .map(|(_, params, _, _, ret, _, _)| Type::Function(params, Box::new(ret)))
+}
+
+/// `(fn (x: Number y: Number) -> Number (+ x y))`
+fn lambda<Input>() -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char, Position = usize>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let param = (symbol(), lex_char(':'), type_(), whitespace()).map(|(name, _, t, _)| (name, t));
+
+    (
+        lex_char('('),
+        string("fn"),
+        whitespace(),
+        between(lex_char('('), lex_char(')'), many(param)),
+        string("->"),
+        whitespace(),
+        type_(),
+        whitespace(),
+        expr(),
+        whitespace(),
+        char(')'),
+    )
+        .map(|(_, _, _, params, _, _, ret, _, body, _, _)| {
+            Expr::Lambda(params, ret, Box::new(body))
+        })
 }
 
 // Syntactic sugar
 fn atom<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (lex_char('\''), name()).map(|(_, at)| {
@@ -123,53 +381,116 @@ where
 
 fn identifier<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (lex_char('('), name(), lex_char(')')).map(|(_, n, _)| n)
+    (
+        position(),
+        lex_char('('),
+        name(),
+        whitespace(),
+        char(')'),
+        position(),
+        whitespace(),
+    )
+        .map(|(start, _, n, _, _, end, _)| Expr::Call(Box::new(n), vec![], start..end))
 }
 
 fn call<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     let expr_list = sep_by1(expr(), whitespace());
 
     (
+        position(),
         lex_char('('),
         name(),
         whitespace(),
         expr_list,
-        lex_char(')'),
+        char(')'),
+        position(),
+        whitespace(),
     )
-        .map(|(_, name, _, args, _)| Expr::Call(Box::new(name), args))
+        .map(|(start, _, name, _, args, _, end, _)| Expr::Call(Box::new(name), args, start..end))
 }
 
 fn unit<Input>() -> impl Parser<Input, Output = Expr>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = usize>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (lex_char('('), lex_char(')')).map(|_| Expr::Unit(()))
 }
 
-pub fn parse(code: &str) -> ParserResult {
-    expr().easy_parse(position::Stream::new(code))
+/// How deeply brackets can nest. Each level takes stack to parse, so deeper input is
+/// refused instead of overflowing it
+pub const MAX_DEPTH: usize = 64;
+
+/// Where brackets outside text first nest deeper than `MAX_DEPTH`, if they do
+fn too_deep(code: &str) -> Option<usize> {
+    let (mut depth, mut in_text) = (0usize, false);
+    for (position, c) in code.chars().enumerate() {
+        match c {
+            '"' => in_text = !in_text,
+            '(' | '[' if !in_text => {
+                depth += 1;
+                if depth > MAX_DEPTH {
+                    return Some(position);
+                }
+            }
+            ')' | ']' if !in_text => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn parse(code: &str) -> ParserResult<'_> {
+    if let Some(position) = too_deep(code) {
+        return Err(EasyStreamError::new(
+            position,
+            easy::Error::Message(easy::Info::Owned(format!(
+                "Expressions can nest at most {MAX_DEPTH} deep"
+            ))),
+        ));
+    }
+    expr().easy_parse(position::Stream::with_positioner(
+        code,
+        position::IndexPositioner::new(),
+    ))
 }
 
 parser! {
     pub fn list[Input]()(Input) -> Expr
-    where [Input: Stream<Token = char>]
+    where [Input: Stream<Token = char, Position = usize>]
     {
         list_()
     }
 }
 
+parser! {
+    pub fn type_[Input]()(Input) -> Type
+    where [Input: Stream<Token = char, Position = usize>]
+    {
+        (
+            choice!(attempt(type_name()), attempt(list_type()), attempt(function_type())),
+            optional(char('?')),
+        )
+            .map(|(t, optional)| match optional {
+                Some(_) => Type::Optional(Box::new(t)),
+                None => t,
+            })
+    }
+}
+
+// Literals that are well formed but invalid, like an overflowing number,
+// fail without backtracking so their error is the one reported
 parser! {
     pub fn expr[Input]()(Input) -> Expr
-    where [Input: Stream<Token = char>]
+    where [Input: Stream<Token = char, Position = usize>]
     {
-        choice!(attempt(bool()), attempt(float()), attempt(int()), attempt(text()), attempt(list()), attempt(call()), attempt(atom()), attempt(identifier()), attempt(unit()))
+        choice!(attempt(bool()), attempt(nil()), decimal(), attempt(big_int()), attempt(float()), int(), attempt(text()), tagged_literal(), attempt(list()), attempt(lambda()), attempt(call()), attempt(atom()), attempt(identifier()), attempt(unit()), attempt(variable()))
     }
 }
@@ -1,9 +1,14 @@
-use crate::parser::ast::Expr;
+use std::collections::BTreeMap;
 
-use super::env::Environment;
+use crate::parser::ast::{Expr, Type};
+
+use super::env::{key_position, Environment};
 use super::errors::TypeCheckerError;
 use super::types::InternalType;
 
+/// The types of the variables bound by enclosing lambdas
+pub(crate) type Scope<'a> = BTreeMap<&'a str, InternalType<'a>>;
+
 #[derive(Debug, Default)]
 pub struct Typechecker<'a> {
     pub env: Environment<'a>,
@@ -17,86 +22,321 @@ impl Typechecker<'_> {
     fn single_result_synthesize<'a>(
         &'a self,
         ast: &'a Expr,
-    ) -> Result<InternalType, TypeCheckerError> {
+        scope: &Scope<'a>,
+    ) -> Result<InternalType<'a>, TypeCheckerError<'a>> {
         match ast {
             Expr::Text(_) => Ok(InternalType::Text),
-            Expr::List(_) => Ok(InternalType::List),
+            Expr::Bytes(_) => Ok(InternalType::Bytes),
+            Expr::Timestamp(_) => Ok(InternalType::Timestamp),
+            Expr::Duration(_) => Ok(InternalType::Duration),
             Expr::Float(_) => Ok(InternalType::Float),
             Expr::Number(_) => Ok(InternalType::Number),
+            Expr::BigInt(_) => Ok(InternalType::BigInt),
+            Expr::Decimal(_) => Ok(InternalType::Decimal),
             Expr::Boolean(_) => Ok(InternalType::Boolean),
             Expr::Unit(_) => Ok(InternalType::Unit),
-            Expr::Identifier(name) => {
-                if let Some(ret) = self.env.return_type_of(name) {
-                    Ok(InternalType::Application(name, vec![], Box::new(ret)))
-                } else {
-                    Err(TypeCheckerError::FunctionNotFound(name.into()))
-                }
-            }
+            Expr::Nil => Ok(InternalType::Optional(Box::new(InternalType::Any))),
+            Expr::Identifier(name) => scope
+                .get(&name[..])
+                .cloned()
+                .ok_or_else(|| TypeCheckerError::VariableNotFound(name.into())),
             _ => unreachable!(),
         }
     }
 
-    pub fn synthesize<'a>(&'a self, ast: &'a Expr) -> Result<InternalType, Vec<TypeCheckerError>> {
+    fn annotation<'a>(&self, t: &Type) -> Result<InternalType<'a>, Vec<TypeCheckerError<'a>>> {
+        InternalType::try_from(t).map_err(|name| vec![TypeCheckerError::UnknownType(name)])
+    }
+
+    fn synthesize_all<'a>(
+        &'a self,
+        exprs: &'a [Expr],
+        scope: &Scope<'a>,
+    ) -> Result<Vec<InternalType<'a>>, Vec<TypeCheckerError<'a>>> {
+        let results = exprs
+            .iter()
+            .map(|e| self.synthesize_in(e, scope))
+            .collect::<Vec<_>>();
+
+        if results.iter().any(|result| result.is_err()) {
+            Err(results
+                .into_iter()
+                .filter_map(|result| result.err())
+                .flatten()
+                .collect())
+        } else {
+            Ok(results.into_iter().map(|result| result.unwrap()).collect())
+        }
+    }
+
+    pub fn synthesize<'a>(
+        &'a self,
+        ast: &'a Expr,
+    ) -> Result<InternalType<'a>, Vec<TypeCheckerError<'a>>> {
+        self.synthesize_in(ast, &Scope::new())
+    }
+
+    fn synthesize_in<'a>(
+        &'a self,
+        ast: &'a Expr,
+        scope: &Scope<'a>,
+    ) -> Result<InternalType<'a>, Vec<TypeCheckerError<'a>>> {
         match ast {
-            Expr::Call(name, args) => {
+            Expr::Call(name, args, _) => {
                 let str_name = match &**name {
                     Expr::Identifier(f) => f,
                     _ => unreachable!(),
                 };
-                if let Some(ret) = self.env.return_type_of(str_name) {
-                    let application_args =
-                        args.iter().map(|e| self.synthesize(e)).collect::<Vec<_>>();
-
-                    let contains_errors = application_args
-                        .iter()
-                        .map(|arg| arg.is_err())
-                        .all(|arg| arg);
-
-                    if !contains_errors {
-                        Ok(InternalType::Application(
-                            str_name,
-                            application_args
-                                .into_iter()
-                                .map(|arg| arg.unwrap())
-                                .collect::<Vec<_>>(),
-                            Box::new(ret),
-                        ))
-                    } else {
-                        Err(application_args
-                            .into_iter()
-                            .map(|arg| match arg {
-                                Err(e) => e,
-                                _ => unreachable!(),
-                            })
-                            .collect::<Vec<_>>()
-                            .into_iter()
-                            .flatten()
-                            .collect())
+                if str_name == "try" && !scope.contains_key("try") {
+                    return self.synthesize_try(args, scope);
+                }
+                let mut application_args = self.synthesize_all(args, scope)?;
+
+                let (params, ret) = match scope.get(&str_name[..]) {
+                    Some(InternalType::Function(params, ret)) => (params.clone(), (**ret).clone()),
+                    Some(InternalType::Any) => (application_args.clone(), InternalType::Any),
+                    Some(_) => {
+                        return Err(vec![TypeCheckerError::NotAFunction(str_name.to_string())])
                     }
-                } else {
-                    Err(vec![TypeCheckerError::FunctionNotFound(
-                        str_name.to_string(),
-                    )])
+                    None => match self.env.return_type_of(str_name) {
+                        Some(ret) => (self.env.param_types_of(str_name), ret.clone()),
+                        None => {
+                            return Err(vec![TypeCheckerError::FunctionNotFound(
+                                str_name.to_string(),
+                            )])
+                        }
+                    },
+                };
+
+                if !scope.contains_key(&str_name[..]) {
+                    Self::coerce_key(str_name, &mut application_args);
                 }
+                if !InternalType::params_match(&params, &application_args) {
+                    return Err(vec![TypeCheckerError::InvalidTypesFound {
+                        expected: params,
+                        found: application_args,
+                    }]);
+                }
+
+                let ret = self.refine(str_name, &application_args, ret)?;
+                Ok(InternalType::Application(
+                    str_name,
+                    application_args,
+                    Box::new(ret),
+                ))
+            }
+            Expr::List(elements) => {
+                let types = self
+                    .synthesize_all(elements, scope)?
+                    .iter()
+                    .map(InternalType::value_type)
+                    .collect::<Vec<_>>();
+
+                match types.first() {
+                    Some(first)
+                        if !first.is(&InternalType::Any) && types.iter().all(|t| t.is(first)) =>
+                    {
+                        Ok(InternalType::ListOf(Box::new(first.clone())))
+                    }
+                    _ => Ok(InternalType::List),
+                }
+            }
+            Expr::Lambda(params, ret, body) => {
+                let mut inner = scope.clone();
+                let mut param_types = vec![];
+                for (name, t) in params {
+                    let t = self.annotation(t)?;
+                    inner.insert(name, t.clone());
+                    param_types.push(t);
+                }
+                let ret = self.annotation(ret)?;
+
+                let body_type = self.synthesize_in(body, &inner)?;
+                if !ret.accepts(&body_type) {
+                    return Err(vec![TypeCheckerError::InvalidTypeFound {
+                        expected: ret,
+                        found: body_type,
+                    }]);
+                }
+                Ok(InternalType::Function(param_types, Box::new(ret)))
             }
             single_res => self
-                .single_result_synthesize(single_res)
+                .single_result_synthesize(single_res, scope)
                 .map_err(|err| vec![err]),
         }
     }
 
+    /// Lets an optional key through as `Text`, so keys can be looked up from other keys
+    fn coerce_key(name: &str, args: &mut [InternalType<'_>]) {
+        if let Some(key) = key_position(name).and_then(|i| args.get_mut(i)) {
+            if let InternalType::Optional(t) = key.value_type() {
+                if InternalType::Text.accepts(&t) {
+                    *key = InternalType::Text;
+                }
+            }
+        }
+    }
+
+    /// `(try expr (catch e handler))` is the type of `expr` when the handler agrees with it.
+    /// `e` is bound to the error in the handler
+    fn synthesize_try<'a>(
+        &'a self,
+        args: &'a [Expr],
+        scope: &Scope<'a>,
+    ) -> Result<InternalType<'a>, Vec<TypeCheckerError<'a>>> {
+        let (body, error, handler) = match args {
+            [body, Expr::Call(catch, clause, _)] => match (&**catch, &clause[..]) {
+                (Expr::Identifier(c), [Expr::Identifier(e), handler]) if c == "catch" => {
+                    (body, e, handler)
+                }
+                _ => return Err(vec![Self::malformed_try()]),
+            },
+            _ => return Err(vec![Self::malformed_try()]),
+        };
+
+        let body = self.synthesize_in(body, scope)?.value_type();
+        let mut inner = scope.clone();
+        inner.insert(error, InternalType::Any);
+        let handler = self.synthesize_in(handler, &inner)?.value_type();
+
+        Ok(if body.is(&handler) {
+            body
+        } else {
+            InternalType::Any
+        })
+    }
+
+    fn malformed_try<'a>() -> TypeCheckerError<'a> {
+        TypeCheckerError::InvalidForm(String::from("`(try expr (catch e handler))`"))
+    }
+
+    /// The return type of builtins whose result depends on their arguments.
+    /// Also checks functions passed to higher-order builtins against the elements they receive
+    fn refine<'a>(
+        &'a self,
+        name: &'a str,
+        args: &[InternalType<'a>],
+        ret: InternalType<'a>,
+    ) -> Result<InternalType<'a>, Vec<TypeCheckerError<'a>>> {
+        use InternalType::*;
+
+        let args = args
+            .iter()
+            .map(InternalType::value_type)
+            .collect::<Vec<_>>();
+        let expect_params = |params: &Vec<InternalType<'a>>, expected: Vec<InternalType<'a>>| {
+            if *params == expected {
+                Ok(())
+            } else {
+                Err(vec![TypeCheckerError::InvalidTypesFound {
+                    expected,
+                    found: params.clone(),
+                }])
+            }
+        };
+        let expect_return = |found: &InternalType<'a>, expected: InternalType<'a>| {
+            if *found == expected {
+                Ok(())
+            } else {
+                Err(vec![TypeCheckerError::InvalidTypeFound {
+                    expected,
+                    found: found.clone(),
+                }])
+            }
+        };
+        let list_of = |source: &InternalType<'a>| match source {
+            ListOf(element) => ListOf(element.clone()),
+            _ => List,
+        };
+
+        match (name, &args[..]) {
+            ("put" | "lpush" | "rpush" | "mput", [value, _]) | ("where", [_, _, _, value])
+                if value.holds_function() =>
+            {
+                Err(vec![TypeCheckerError::Unstorable(value.clone())])
+            }
+            // Absent values have to be handled before they're computed with
+            ("+" | "-" | "*" | "/", [Optional(t), _] | [_, Optional(t)]) => {
+                Err(vec![TypeCheckerError::InvalidTypeFound {
+                    expected: (**t).clone(),
+                    found: Optional(t.clone()),
+                }])
+            }
+            ("+" | "-" | "*" | "/", [a, b]) => match (name, a, b) {
+                (_, Number, Number) => Ok(Number),
+                ("+", Timestamp, Duration) | ("+", Duration, Timestamp) => Ok(Timestamp),
+                ("-", Timestamp, Duration) => Ok(Timestamp),
+                ("-", Timestamp, Timestamp) => Ok(Duration),
+                ("+" | "-", Duration, Duration) => Ok(Duration),
+                ("*", Duration, Number) | ("*", Number, Duration) => Ok(Duration),
+                ("/", Duration, Number) => Ok(Duration),
+                (_, Number | BigInt, Number | BigInt) => Ok(BigInt),
+                (_, Number | BigInt | Decimal, Number | BigInt | Decimal) => Ok(Decimal),
+                (_, Number | BigInt | Decimal | Float, Number | BigInt | Decimal | Float) => {
+                    Ok(Float)
+                }
+                _ => Ok(ret),
+            },
+            ("map", [Function(params, r), source]) => {
+                expect_params(params, vec![source.element_type()])?;
+                Ok(ListOf(r.clone()))
+            }
+            ("filter", [Function(params, r), source]) => {
+                expect_params(params, vec![source.element_type()])?;
+                expect_return(r, Boolean)?;
+                Ok(list_of(source))
+            }
+            ("sort-by", [Function(params, _), source]) => {
+                expect_params(params, vec![source.element_type()])?;
+                Ok(list_of(source))
+            }
+            ("any" | "all", [Function(params, r), source]) => {
+                expect_params(params, vec![source.element_type()])?;
+                expect_return(r, Boolean)?;
+                Ok(ret)
+            }
+            ("group-by", [Function(params, _), source]) => {
+                expect_params(params, vec![source.element_type()])?;
+                Ok(ret)
+            }
+            ("fold", [Function(params, r), init, source]) => {
+                expect_params(params, vec![init.clone(), source.element_type()])?;
+                expect_return(r, init.clone())?;
+                Ok(*r.clone())
+            }
+            ("round", [n @ (Number | BigInt | Decimal | Float), _]) => Ok(n.clone()),
+            ("nth" | "head", [list, ..]) => Ok(Optional(Box::new(list.element_type()))),
+            ("unwrap-or", [value, _]) => match value {
+                Optional(t) => Ok(*t.clone()),
+                t => Ok(t.clone()),
+            },
+            ("tail" | "slice" | "reverse" | "sort" | "uniq", [list, ..]) => Ok(list_of(list)),
+            ("append" | "prepend", [list, item]) => match list {
+                ListOf(element) if item.is(element) => Ok(list_of(list)),
+                _ => Ok(List),
+            },
+            ("reduce", [Function(params, r), source]) => {
+                let element = source.element_type();
+                expect_params(params, vec![element.clone(), element])?;
+                Ok(Optional(r.clone()))
+            }
+            ("reduce", _) => Ok(Optional(Box::new(ret))),
+            _ => Ok(ret),
+        }
+    }
+
     pub fn check<'a>(
         &'a self,
         expected: &'a InternalType,
         ast: &'a Expr,
-    ) -> Result<(), Vec<TypeCheckerError>> {
+    ) -> Result<(), Vec<TypeCheckerError<'a>>> {
         let synthesized = self.synthesize(ast);
         match synthesized {
             Ok(internal_type) => match internal_type {
                 InternalType::Application(name, ref arg_types, _) => {
                     let expected = self.env.param_types_of(name);
 
-                    if expected == *arg_types {
+                    if InternalType::params_match(&expected, arg_types) {
                         Ok(())
                     } else {
                         Err(vec![TypeCheckerError::InvalidTypesFound {
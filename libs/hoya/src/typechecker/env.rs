@@ -1,15 +1,46 @@
 use super::types::{FunctionEnvironment, InternalType};
 use std::collections::BTreeMap;
 
-pub(crate) fn builtins<'a>() -> [(String, Vec<InternalType<'a>>); 8] {
+pub(crate) fn builtins<'a>() -> [(String, Vec<InternalType<'a>>); 106] {
     [
         (
             String::from("put"),
-            vec![InternalType::Any, InternalType::Text, InternalType::Any],
+            vec![
+                InternalType::Any,
+                InternalType::Text,
+                InternalType::Optional(Box::new(InternalType::Any)),
+            ],
         ),
         (
             String::from("get"),
-            vec![InternalType::Text, InternalType::Any],
+            vec![
+                InternalType::Text,
+                InternalType::Optional(Box::new(InternalType::Any)),
+            ],
+        ),
+        (
+            String::from("get-or"),
+            vec![InternalType::Text, InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("unwrap-or"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("is-nil"),
+            vec![InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("is-error"),
+            vec![InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("error"),
+            vec![InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("message"),
+            vec![InternalType::Any, InternalType::Text],
         ),
         (
             String::from("exists"),
@@ -17,7 +48,28 @@ pub(crate) fn builtins<'a>() -> [(String, Vec<InternalType<'a>>); 8] {
         ),
         (
             String::from("remove"),
-            vec![InternalType::Text, InternalType::Any],
+            vec![
+                InternalType::Text,
+                InternalType::Optional(Box::new(InternalType::Any)),
+            ],
+        ),
+        (
+            String::from("mget"),
+            vec![
+                InternalType::ListOf(Box::new(InternalType::Text)),
+                InternalType::List,
+            ],
+        ),
+        (
+            String::from("mput"),
+            vec![InternalType::List, InternalType::List],
+        ),
+        (
+            String::from("mremove"),
+            vec![
+                InternalType::ListOf(Box::new(InternalType::Text)),
+                InternalType::List,
+            ],
         ),
         (
             String::from("store"),
@@ -27,6 +79,51 @@ pub(crate) fn builtins<'a>() -> [(String, Vec<InternalType<'a>>); 8] {
             String::from("load"),
             vec![InternalType::Text, InternalType::Unit],
         ),
+        (
+            String::from("bgsave"),
+            vec![InternalType::Text, InternalType::Unit],
+        ),
+        (String::from("save-status"), vec![InternalType::Text]),
+        (
+            String::from("snapshots"),
+            vec![InternalType::ListOf(Box::new(InternalType::Text))],
+        ),
+        (
+            String::from("delete-snapshot"),
+            vec![InternalType::Text, InternalType::Unit],
+        ),
+        (String::from("replication-status"), vec![InternalType::Text]),
+        (String::from("cluster-status"), vec![InternalType::Text]),
+        (
+            String::from("add-member"),
+            vec![InternalType::Number, InternalType::Text, InternalType::Unit],
+        ),
+        (
+            String::from("remove-member"),
+            vec![InternalType::Number, InternalType::Unit],
+        ),
+        (String::from("shard-status"), vec![InternalType::Text]),
+        (
+            String::from("add-shard"),
+            vec![InternalType::Text, InternalType::Number],
+        ),
+        (
+            String::from("remove-shard"),
+            vec![InternalType::Text, InternalType::Number],
+        ),
+        (
+            String::from("export"),
+            vec![InternalType::Text, InternalType::Text, InternalType::Number],
+        ),
+        (
+            String::from("import"),
+            vec![
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Number,
+            ],
+        ),
         (
             String::from("write"),
             vec![InternalType::Text, InternalType::Unit],
@@ -35,9 +132,491 @@ pub(crate) fn builtins<'a>() -> [(String, Vec<InternalType<'a>>); 8] {
             String::from("writeln"),
             vec![InternalType::Text, InternalType::Unit],
         ),
+        (
+            String::from("create-index"),
+            vec![InternalType::Text, InternalType::Any, InternalType::Unit],
+        ),
+        (
+            String::from("find-by"),
+            vec![InternalType::Text, InternalType::Any, InternalType::List],
+        ),
+        (
+            String::from("find-range"),
+            vec![
+                InternalType::Text,
+                InternalType::Any,
+                InternalType::Any,
+                InternalType::List,
+            ],
+        ),
+        (
+            String::from("keys"),
+            vec![
+                InternalType::Text,
+                InternalType::ListOf(Box::new(InternalType::Text)),
+            ],
+        ),
+        (
+            String::from("select"),
+            vec![InternalType::Text, InternalType::Query],
+        ),
+        (
+            String::from("where"),
+            vec![
+                InternalType::Query,
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Any,
+                InternalType::Query,
+            ],
+        ),
+        (
+            String::from("explain"),
+            vec![InternalType::Query, InternalType::Text],
+        ),
+        (
+            String::from("map"),
+            vec![InternalType::Any, InternalType::Any, InternalType::List],
+        ),
+        (
+            String::from("reduce"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("count"),
+            vec![InternalType::Any, InternalType::Number],
+        ),
+        (
+            String::from("sum"),
+            vec![InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("min"),
+            vec![
+                InternalType::Any,
+                InternalType::Optional(Box::new(InternalType::Any)),
+            ],
+        ),
+        (
+            String::from("max"),
+            vec![
+                InternalType::Any,
+                InternalType::Optional(Box::new(InternalType::Any)),
+            ],
+        ),
+        (
+            String::from("group-by"),
+            vec![InternalType::Any, InternalType::Any, InternalType::List],
+        ),
+        (
+            String::from("filter"),
+            vec![InternalType::Any, InternalType::Any, InternalType::List],
+        ),
+        (
+            String::from("sort-by"),
+            vec![InternalType::Any, InternalType::Any, InternalType::List],
+        ),
+        (
+            String::from("any"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("all"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("fold"),
+            vec![
+                InternalType::Any,
+                InternalType::Any,
+                InternalType::Any,
+                InternalType::Any,
+            ],
+        ),
+        (
+            String::from("+"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("-"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("*"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("/"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Any],
+        ),
+        (
+            String::from("="),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("!="),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("<"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("<="),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from(">"),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from(">="),
+            vec![InternalType::Any, InternalType::Any, InternalType::Boolean],
+        ),
+        (
+            String::from("not"),
+            vec![InternalType::Boolean, InternalType::Boolean],
+        ),
+        (
+            String::from("and"),
+            vec![
+                InternalType::Boolean,
+                InternalType::Boolean,
+                InternalType::Boolean,
+            ],
+        ),
+        (
+            String::from("or"),
+            vec![
+                InternalType::Boolean,
+                InternalType::Boolean,
+                InternalType::Boolean,
+            ],
+        ),
+        (
+            String::from("concat"),
+            vec![
+                InternalType::Rest(Box::new(InternalType::Any)),
+                InternalType::Text,
+            ],
+        ),
+        (
+            String::from("len"),
+            vec![InternalType::Any, InternalType::Number],
+        ),
+        (
+            String::from("substr"),
+            vec![
+                InternalType::Text,
+                InternalType::Number,
+                InternalType::Number,
+                InternalType::Text,
+            ],
+        ),
+        (
+            String::from("split"),
+            vec![
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::ListOf(Box::new(InternalType::Text)),
+            ],
+        ),
+        (
+            String::from("join"),
+            vec![
+                InternalType::ListOf(Box::new(InternalType::Text)),
+                InternalType::Text,
+                InternalType::Text,
+            ],
+        ),
+        (
+            String::from("upper"),
+            vec![InternalType::Text, InternalType::Text],
+        ),
+        (
+            String::from("lower"),
+            vec![InternalType::Text, InternalType::Text],
+        ),
+        (
+            String::from("trim"),
+            vec![InternalType::Text, InternalType::Text],
+        ),
+        (
+            String::from("starts-with"),
+            vec![
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Boolean,
+            ],
+        ),
+        (
+            String::from("contains"),
+            vec![
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Boolean,
+            ],
+        ),
+        (
+            String::from("replace"),
+            vec![
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Text,
+            ],
+        ),
+        (
+            String::from("format"),
+            vec![
+                InternalType::Text,
+                InternalType::Rest(Box::new(InternalType::Any)),
+                InternalType::Text,
+            ],
+        ),
+        (
+            String::from("matches"),
+            vec![
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Boolean,
+            ],
+        ),
+        (
+            String::from("nth"),
+            vec![InternalType::List, InternalType::Number, InternalType::Any],
+        ),
+        (
+            String::from("head"),
+            vec![InternalType::List, InternalType::Any],
+        ),
+        (
+            String::from("tail"),
+            vec![InternalType::List, InternalType::List],
+        ),
+        (
+            String::from("append"),
+            vec![InternalType::List, InternalType::Any, InternalType::List],
+        ),
+        (
+            String::from("prepend"),
+            vec![InternalType::List, InternalType::Any, InternalType::List],
+        ),
+        (
+            String::from("slice"),
+            vec![
+                InternalType::List,
+                InternalType::Number,
+                InternalType::Number,
+                InternalType::List,
+            ],
+        ),
+        (
+            String::from("reverse"),
+            vec![InternalType::List, InternalType::List],
+        ),
+        (
+            String::from("sort"),
+            vec![InternalType::List, InternalType::List],
+        ),
+        (
+            String::from("uniq"),
+            vec![InternalType::List, InternalType::List],
+        ),
+        (
+            String::from("range"),
+            vec![
+                InternalType::Number,
+                InternalType::Number,
+                InternalType::ListOf(Box::new(InternalType::Number)),
+            ],
+        ),
+        (
+            String::from("lpush"),
+            vec![InternalType::Any, InternalType::Text, InternalType::Number],
+        ),
+        (
+            String::from("rpush"),
+            vec![InternalType::Any, InternalType::Text, InternalType::Number],
+        ),
+        (
+            String::from("lpop"),
+            vec![
+                InternalType::Text,
+                InternalType::Optional(Box::new(InternalType::Any)),
+            ],
+        ),
+        (
+            String::from("rpop"),
+            vec![
+                InternalType::Text,
+                InternalType::Optional(Box::new(InternalType::Any)),
+            ],
+        ),
+        (
+            String::from("lrange"),
+            vec![
+                InternalType::Text,
+                InternalType::Number,
+                InternalType::Number,
+                InternalType::List,
+            ],
+        ),
+        (
+            String::from("sadd"),
+            vec![InternalType::Any, InternalType::Text, InternalType::Number],
+        ),
+        (
+            String::from("srem"),
+            vec![InternalType::Any, InternalType::Text, InternalType::Number],
+        ),
+        (
+            String::from("smembers"),
+            vec![InternalType::Text, InternalType::Set],
+        ),
+        (
+            String::from("sinter"),
+            vec![
+                InternalType::ListOf(Box::new(InternalType::Text)),
+                InternalType::Set,
+            ],
+        ),
+        (
+            String::from("sunion"),
+            vec![
+                InternalType::ListOf(Box::new(InternalType::Text)),
+                InternalType::Set,
+            ],
+        ),
+        (
+            String::from("zadd"),
+            vec![
+                InternalType::Text,
+                InternalType::Any,
+                InternalType::Text,
+                InternalType::Boolean,
+            ],
+        ),
+        (
+            String::from("zincr"),
+            vec![
+                InternalType::Text,
+                InternalType::Any,
+                InternalType::Text,
+                InternalType::Float,
+            ],
+        ),
+        (
+            String::from("zrange-by-score"),
+            vec![
+                InternalType::Text,
+                InternalType::Any,
+                InternalType::Any,
+                InternalType::List,
+            ],
+        ),
+        (
+            String::from("zrank"),
+            vec![
+                InternalType::Text,
+                InternalType::Text,
+                InternalType::Optional(Box::new(InternalType::Number)),
+            ],
+        ),
+        (String::from("now"), vec![InternalType::Timestamp]),
+        (
+            String::from("duration"),
+            vec![
+                InternalType::Number,
+                InternalType::Text,
+                InternalType::Duration,
+            ],
+        ),
+        (
+            String::from("format-time"),
+            vec![
+                InternalType::Timestamp,
+                InternalType::Text,
+                InternalType::Text,
+            ],
+        ),
+        (
+            String::from("bytes"),
+            vec![InternalType::Text, InternalType::Bytes],
+        ),
+        (
+            String::from("bigint"),
+            vec![InternalType::Any, InternalType::BigInt],
+        ),
+        (
+            String::from("decimal"),
+            vec![InternalType::Any, InternalType::Decimal],
+        ),
+        (
+            String::from("round"),
+            vec![InternalType::Any, InternalType::Number, InternalType::Any],
+        ),
     ]
 }
 
+/// The position of the key argument of builtins that address a stored value.
+/// Keys may be computed, and an optional key, like the result of `get`, fails when it runs
+pub(crate) fn key_position(name: &str) -> Option<usize> {
+    match name {
+        "get" | "get-or" | "exists" | "remove" | "store" | "load" | "delete-snapshot" | "lpop"
+        | "rpop" | "lrange" | "smembers" | "zrange-by-score" | "zrank" => Some(0),
+        "put" | "lpush" | "rpush" | "sadd" | "srem" => Some(1),
+        "zadd" | "zincr" => Some(2),
+        _ => None,
+    }
+}
+
+/// Whether the builtin changes records, which replicas only take from their primary
+pub(crate) fn writes(name: &str) -> bool {
+    matches!(
+        name,
+        "put"
+            | "remove"
+            | "mput"
+            | "mremove"
+            | "load"
+            | "import"
+            | "lpush"
+            | "rpush"
+            | "lpop"
+            | "rpop"
+            | "sadd"
+            | "srem"
+            | "zadd"
+            | "zincr"
+    )
+}
+
+/// Whether the builtin only works on this server's own records, which a shard router
+/// doesn't have
+pub(crate) fn local(name: &str) -> bool {
+    matches!(
+        name,
+        "store"
+            | "load"
+            | "bgsave"
+            | "snapshots"
+            | "delete-snapshot"
+            | "export"
+            | "import"
+            | "create-index"
+            | "find-by"
+            | "find-range"
+            | "explain"
+            | "lrange"
+            | "smembers"
+            | "sinter"
+            | "sunion"
+            | "zrange-by-score"
+            | "zrank"
+    )
+}
+
 // TODO: Storing and retrieving functions from an `Environment`
 #[derive(Debug)]
 pub struct Environment<'a> {
@@ -45,7 +624,7 @@ pub struct Environment<'a> {
 }
 
 impl Environment<'_> {
-    pub fn new<const N: usize>(functions: [(String, Vec<InternalType<'_>>); N]) -> Environment {
+    pub fn new<const N: usize>(functions: [(String, Vec<InternalType<'_>>); N]) -> Environment<'_> {
         Environment {
             env: BTreeMap::from(functions),
         }
@@ -59,14 +638,14 @@ impl Environment<'_> {
         self.env.contains_key(name)
     }
 
-    pub fn return_type_of(&self, name: &str) -> Option<&InternalType> {
+    pub fn return_type_of(&self, name: &str) -> Option<&InternalType<'_>> {
         match self.env.get(name) {
             Some(types) => Some(types.last().unwrap()),
             None => None,
         }
     }
 
-    pub fn param_types_of(&self, name: &str) -> Vec<InternalType> {
+    pub fn param_types_of(&self, name: &str) -> Vec<InternalType<'_>> {
         match self.env.get(name) {
             Some(types) => {
                 if types.len() == 1 {
@@ -79,9 +658,9 @@ impl Environment<'_> {
         }
     }
 
-    pub fn function_type_of<'a>(&'a self, name: &'a str) -> Option<InternalType> {
+    pub fn function_type_of<'a>(&'a self, name: &'a str) -> Option<InternalType<'a>> {
         match (self.param_types_of(name), self.return_type_of(name)) {
-            (t, Some(r)) => Some(InternalType::Application(name, t, Box::new(r))),
+            (t, Some(r)) => Some(InternalType::Application(name, t, Box::new(r.clone()))),
             _ => None,
         }
     }
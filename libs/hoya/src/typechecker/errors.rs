@@ -6,6 +6,16 @@ use super::types::InternalType;
 pub enum TypeCheckerError<'a> {
     #[error("Function `{0}` does not exist")]
     FunctionNotFound(String),
+    #[error("Variable `{0}` does not exist")]
+    VariableNotFound(String),
+    #[error("`{0}` is not a function")]
+    NotAFunction(String),
+    #[error("Type `{0}` does not exist")]
+    UnknownType(String),
+    #[error("Functions can't be stored, but found {0}")]
+    Unstorable(InternalType<'a>),
+    #[error("Expected the form {0}")]
+    InvalidForm(String),
     #[error("Expected type {expected}, but found {found}")]
     InvalidTypeFound {
         expected: InternalType<'a>,
@@ -22,6 +32,16 @@ pub enum TypeCheckerError<'a> {
 pub enum ShortTypeCheckerError<'a> {
     #[error("Function `{0}` not found")]
     FunctionNotFound(String),
+    #[error("Variable `{0}` not found")]
+    VariableNotFound(String),
+    #[error("`{0}` is not a function")]
+    NotAFunction(String),
+    #[error("Unknown type `{0}`")]
+    UnknownType(String),
+    #[error("Unstorable Type `{0}` Found")]
+    Unstorable(InternalType<'a>),
+    #[error("Malformed expression")]
+    InvalidForm(String),
     #[error("Invalid Type `{found}` Found")]
     InvalidTypeFound {
         expected: InternalType<'a>,
@@ -38,6 +58,11 @@ impl ShortTypeCheckerError<'_> {
     pub fn to_long_error(&'_ self) -> TypeCheckerError<'_> {
         match self {
             Self::FunctionNotFound(f) => TypeCheckerError::FunctionNotFound(f.to_string()),
+            Self::VariableNotFound(v) => TypeCheckerError::VariableNotFound(v.to_string()),
+            Self::NotAFunction(v) => TypeCheckerError::NotAFunction(v.to_string()),
+            Self::UnknownType(t) => TypeCheckerError::UnknownType(t.to_string()),
+            Self::Unstorable(t) => TypeCheckerError::Unstorable(t.to_owned()),
+            Self::InvalidForm(f) => TypeCheckerError::InvalidForm(f.to_string()),
             Self::InvalidTypeFound { expected, found } => TypeCheckerError::InvalidTypeFound {
                 expected: expected.to_owned(),
                 found: found.to_owned(),
@@ -54,6 +79,11 @@ impl TypeCheckerError<'_> {
     pub fn to_short_error(&'_ self) -> ShortTypeCheckerError<'_> {
         match self {
             Self::FunctionNotFound(f) => ShortTypeCheckerError::FunctionNotFound(f.to_string()),
+            Self::VariableNotFound(v) => ShortTypeCheckerError::VariableNotFound(v.to_string()),
+            Self::NotAFunction(v) => ShortTypeCheckerError::NotAFunction(v.to_string()),
+            Self::UnknownType(t) => ShortTypeCheckerError::UnknownType(t.to_string()),
+            Self::Unstorable(t) => ShortTypeCheckerError::Unstorable(t.to_owned()),
+            Self::InvalidForm(f) => ShortTypeCheckerError::InvalidForm(f.to_string()),
             Self::InvalidTypeFound { expected, found } => ShortTypeCheckerError::InvalidTypeFound {
                 expected: expected.to_owned(),
                 found: found.to_owned(),
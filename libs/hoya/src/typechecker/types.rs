@@ -2,23 +2,133 @@ use std::{collections::BTreeMap, fmt::Display};
 
 use std::fmt;
 
+use crate::parser::ast::Type;
+
 pub(crate) type FunctionEnvironment<'a> = BTreeMap<String, Vec<InternalType<'a>>>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum InternalType<'a> {
     Number,
+    BigInt,
+    Decimal,
     Float,
     Boolean,
     Text,
     List,
+    ListOf(Box<InternalType<'a>>),
+    Query,
+    Set,
+    SortedSet,
+    Bytes,
+    Timestamp,
+    Duration,
+    Function(Vec<InternalType<'a>>, Box<InternalType<'a>>),
+    /// A value of this type or `nil`
+    Optional(Box<InternalType<'a>>),
+    /// Any number of arguments of this type, only valid as the last parameter
+    Rest(Box<InternalType<'a>>),
+    #[default]
     Any,
     Unit,
-    Application(&'a str, Vec<InternalType<'a>>, Box<&'a InternalType<'a>>),
+    Application(&'a str, Vec<InternalType<'a>>, Box<InternalType<'a>>),
+}
+
+impl<'a> InternalType<'a> {
+    /// The type of the value this stands for, looking through calls
+    pub fn value_type(&self) -> InternalType<'a> {
+        match self {
+            InternalType::Application(_, _, ret) => ret.value_type(),
+            t => t.clone(),
+        }
+    }
+
+    /// The type of the elements of a list or query of this type
+    pub fn element_type(&self) -> InternalType<'a> {
+        match self.value_type() {
+            InternalType::ListOf(element) => *element,
+            _ => InternalType::Any,
+        }
+    }
+
+    /// Whether a value of this type is or holds a function, which can't be stored
+    pub fn holds_function(&self) -> bool {
+        match self.value_type() {
+            InternalType::Function(..) => true,
+            InternalType::ListOf(element) => element.holds_function(),
+            _ => false,
+        }
+    }
+
+    /// Whether a value of type `arg` can be used where this type is expected.
+    /// Unlike `==`, an optional type also takes values that are always present
+    pub fn accepts(&self, arg: &InternalType<'a>) -> bool {
+        match (self, arg.value_type()) {
+            (InternalType::Optional(t), InternalType::Optional(a)) => t.accepts(&a),
+            (InternalType::Optional(t), a) => t.accepts(&a),
+            _ => self == arg,
+        }
+    }
+
+    /// Whether arguments of types `args` can be passed to parameters of types `params`
+    pub fn params_match(params: &[InternalType<'a>], args: &[InternalType<'a>]) -> bool {
+        match params.split_last() {
+            Some((InternalType::Rest(rest), fixed)) => {
+                args.len() >= fixed.len()
+                    && fixed.iter().zip(args).all(|(p, a)| p.accepts(a))
+                    && args[fixed.len()..].iter().all(|a| rest.accepts(a))
+            }
+            _ => params.len() == args.len() && params.iter().zip(args).all(|(p, a)| p.accepts(a)),
+        }
+    }
+
+    /// Structural equality, unlike `==` which lets `Any` stand for every type
+    pub fn is(&self, other: &InternalType<'_>) -> bool {
+        use InternalType::*;
+        match (self, other) {
+            (ListOf(a), ListOf(b)) | (Rest(a), Rest(b)) | (Optional(a), Optional(b)) => a.is(b),
+            (Function(a, r), Function(b, s)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.is(b)) && r.is(s)
+            }
+            (Application(..), _) | (_, Application(..)) => false,
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
 }
 
-impl Default for InternalType<'_> {
-    fn default() -> Self {
-        Self::Any
+impl TryFrom<&Type> for InternalType<'_> {
+    type Error = String;
+
+    /// Fails with the name of the first unknown type
+    fn try_from(t: &Type) -> Result<Self, Self::Error> {
+        match t {
+            Type::Named(name) => match &name[..] {
+                "Number" => Ok(InternalType::Number),
+                "BigInt" => Ok(InternalType::BigInt),
+                "Decimal" => Ok(InternalType::Decimal),
+                "Float" => Ok(InternalType::Float),
+                "Boolean" => Ok(InternalType::Boolean),
+                "Text" => Ok(InternalType::Text),
+                "List" => Ok(InternalType::List),
+                "Query" => Ok(InternalType::Query),
+                "Set" => Ok(InternalType::Set),
+                "SortedSet" => Ok(InternalType::SortedSet),
+                "Bytes" => Ok(InternalType::Bytes),
+                "Timestamp" => Ok(InternalType::Timestamp),
+                "Duration" => Ok(InternalType::Duration),
+                "Any" => Ok(InternalType::Any),
+                "Unit" => Ok(InternalType::Unit),
+                unknown => Err(unknown.to_owned()),
+            },
+            Type::List(element) => Ok(InternalType::ListOf(Box::new(element.as_ref().try_into()?))),
+            Type::Function(params, ret) => Ok(InternalType::Function(
+                params
+                    .iter()
+                    .map(InternalType::try_from)
+                    .collect::<Result<_, _>>()?,
+                Box::new(ret.as_ref().try_into()?),
+            )),
+            Type::Optional(t) => Ok(InternalType::Optional(Box::new(t.as_ref().try_into()?))),
+        }
     }
 }
 
@@ -26,10 +136,28 @@ impl Display for InternalType<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             InternalType::Number => write!(f, "Number"),
+            InternalType::BigInt => write!(f, "BigInt"),
+            InternalType::Decimal => write!(f, "Decimal"),
             InternalType::Float => write!(f, "Float"),
             InternalType::Boolean => write!(f, "Boolean"),
             InternalType::Text => write!(f, "Text"),
             InternalType::List => write!(f, "List"),
+            InternalType::ListOf(element) => write!(f, "[{element}]"),
+            InternalType::Query => write!(f, "Query"),
+            InternalType::Set => write!(f, "Set"),
+            InternalType::SortedSet => write!(f, "SortedSet"),
+            InternalType::Bytes => write!(f, "Bytes"),
+            InternalType::Timestamp => write!(f, "Timestamp"),
+            InternalType::Duration => write!(f, "Duration"),
+            InternalType::Function(params, ret) => {
+                write!(f, "(")?;
+                for param in params {
+                    write!(f, "{param} ")?;
+                }
+                write!(f, "-> {ret})")
+            }
+            InternalType::Optional(t) => write!(f, "{t}?"),
+            InternalType::Rest(t) => write!(f, "{t}..."),
             InternalType::Any => write!(f, "Any"),
             InternalType::Unit => write!(f, "Unit"),
             InternalType::Application(_, _, _) => write!(f, "Application"),
@@ -45,12 +173,27 @@ impl<'a> PartialEq for InternalType<'a> {
             (Any, _) => true,
             (_, Any) => true,
             (Number, Number) => true,
+            (BigInt, BigInt) => true,
+            (Decimal, Decimal) => true,
             (Float, Float) => true,
             (Boolean, Boolean) => true,
             (Text, Text) => true,
             (List, List) => true,
+            (List, ListOf(_)) | (ListOf(_), List) => true,
+            (ListOf(a), ListOf(b)) => a == b,
+            (Query, Query) => true,
+            (Set, Set) => true,
+            (SortedSet, SortedSet) => true,
+            (Bytes, Bytes) => true,
+            (Timestamp, Timestamp) => true,
+            (Duration, Duration) => true,
+            (Function(a, b), Function(c, d)) => a == c && b == d,
+            (Optional(a), Optional(b)) => a == b,
+            (Rest(a), Rest(b)) => a == b,
             (Unit, Unit) => true,
             (Application(a, b, c), Application(d, e, f)) => a == d && b == e && c == f,
+            // A call stands for the value it returns
+            (Application(_, _, ret), other) | (other, Application(_, _, ret)) => **ret == *other,
             _ => false,
         }
     }
@@ -59,10 +202,24 @@ impl<'a> From<&'a InternalType<'a>> for InternalType<'a> {
     fn from<'b>(l: &'b InternalType) -> InternalType<'b> {
         match l {
             InternalType::Number => InternalType::Number,
+            InternalType::BigInt => InternalType::BigInt,
+            InternalType::Decimal => InternalType::Decimal,
             InternalType::Float => InternalType::Float,
             InternalType::Boolean => InternalType::Boolean,
             InternalType::Text => InternalType::Text,
             InternalType::List => InternalType::List,
+            InternalType::ListOf(element) => InternalType::ListOf(element.to_owned()),
+            InternalType::Query => InternalType::Query,
+            InternalType::Set => InternalType::Set,
+            InternalType::SortedSet => InternalType::SortedSet,
+            InternalType::Bytes => InternalType::Bytes,
+            InternalType::Timestamp => InternalType::Timestamp,
+            InternalType::Duration => InternalType::Duration,
+            InternalType::Function(params, ret) => {
+                InternalType::Function(params.to_owned(), ret.to_owned())
+            }
+            InternalType::Optional(t) => InternalType::Optional(t.to_owned()),
+            InternalType::Rest(t) => InternalType::Rest(t.to_owned()),
             InternalType::Any => InternalType::Any,
             InternalType::Unit => InternalType::Unit,
             InternalType::Application(name, args, ret) => {
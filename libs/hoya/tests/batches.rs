@@ -0,0 +1,44 @@
+mod common;
+
+use common::Shell;
+use db::DBTypes;
+
+#[test]
+fn batch_builtins_take_lists_of_keys_and_pairs() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("(mput [[\"a\" 1] [\"b\" 2]])"),
+        DBTypes::List(vec![DBTypes::Nil, DBTypes::Nil])
+    );
+    assert_eq!(
+        shell.eval("(mget [\"b\" \"missing\" \"a\"])"),
+        DBTypes::List(vec![DBTypes::Number(2), DBTypes::Nil, DBTypes::Number(1)])
+    );
+    assert_eq!(
+        shell.eval("(mput [[\"a\" 3]])"),
+        DBTypes::List(vec![DBTypes::Number(1)])
+    );
+    assert_eq!(
+        shell.eval("(mremove [\"a\" \"b\"])"),
+        DBTypes::List(vec![DBTypes::Number(3), DBTypes::Number(2)])
+    );
+    assert_eq!(shell.eval("(exists \"a\")"), DBTypes::Boolean(false));
+}
+
+#[test]
+fn a_bad_pair_fails_the_whole_batch() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.error("(mput [[\"a\" 1] [\"b\"]])"),
+        "Expected a list of `[key value]` pairs"
+    );
+    assert_eq!(
+        shell.error("(mput [[\"a\" 1] [\"b\" nil]])"),
+        "Can't store nil, use `mremove` to delete keys"
+    );
+    assert_eq!(
+        shell.error("(mput [[\"a\" 1] [\"b\" (fn (x: Number) -> Number x)]])"),
+        "Functions can't be stored"
+    );
+    assert_eq!(shell.eval("(exists \"a\")"), DBTypes::Boolean(false));
+}
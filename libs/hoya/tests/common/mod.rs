@@ -0,0 +1,77 @@
+#![allow(dead_code)]
+
+use db::{DBTypes, Database};
+use hoya::interpreter::errors::RuntimeError;
+use hoya::interpreter::interpret::Interpreter;
+use hoya::interpreter::types::InterpreterValue;
+use hoya::parser::parse;
+use hoya::typechecker::bidirectional_typechecker::Typechecker;
+use hoya::typechecker::env::Environment;
+use hoya::typechecker::types::InternalType;
+
+/// An interpreter over a fresh database, the way the REPL runs one line
+pub struct Shell {
+    pub db: Database,
+    interpreter: Interpreter<'static>,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self::over(Database::default())
+    }
+
+    /// An interpreter over `db`
+    pub fn over(db: Database) -> Self {
+        let interpreter = Interpreter::new(
+            db.clone(),
+            Environment::builtin(),
+            Typechecker::new(Environment::builtin()),
+        );
+        Self { db, interpreter }
+    }
+
+    /// Parses, typechecks and evaluates `code`, panicking on any error
+    pub fn eval(&self, code: &str) -> DBTypes {
+        match self.run(code) {
+            Ok(value) => value.into(),
+            Err(e) => panic!("{code}: {e}"),
+        }
+    }
+
+    /// The runtime error `code` raises, panicking if it evaluates
+    pub fn error(&self, code: &str) -> String {
+        match self.run(code) {
+            Ok(value) => panic!("{code}: expected an error, got {:?}", DBTypes::from(value)),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn run(&self, code: &str) -> Result<InterpreterValue, RuntimeError> {
+        let expr = parse(code).unwrap_or_else(|e| panic!("{code}: {e}")).0;
+        if let Err(errors) =
+            Typechecker::new(Environment::builtin()).check(&InternalType::Any, &expr)
+        {
+            panic!("{code}: {}", errors[0]);
+        }
+        self.interpreter.eval_expr(&expr)
+    }
+
+    /// The type errors `code` is rejected with, empty if it typechecks
+    pub fn type_errors(&self, code: &str) -> Vec<String> {
+        let expr = parse(code).unwrap_or_else(|e| panic!("{code}: {e}")).0;
+        match Typechecker::new(Environment::builtin()).check(&InternalType::Any, &expr) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+/// Shorthand for a text value
+pub fn text(s: &str) -> DBTypes {
+    DBTypes::Text(s.to_owned())
+}
+
+/// Shorthand for a list of texts, as returned by the index lookups
+pub fn texts(items: &[&str]) -> DBTypes {
+    DBTypes::List(items.iter().map(|s| text(s)).collect())
+}
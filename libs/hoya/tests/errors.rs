@@ -0,0 +1,132 @@
+mod common;
+
+use common::{text, Shell};
+use db::{DBTypes, Database, MmapEngine};
+use hoya::interpreter::interpret::{Interpreter, MAX_EVAL_DEPTH};
+use hoya::parser::ast::Expr;
+use hoya::parser::{parse, MAX_DEPTH};
+
+#[test]
+fn runtime_errors_are_returned_not_panicked() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.error("(load \"nonexistent\")"),
+        "There's no file `nonexistent.hoya`"
+    );
+    assert_eq!(
+        shell.error("(load \"/nonexistent/hoya\")"),
+        "`/nonexistent/hoya` isn't a file name in the data directory"
+    );
+    assert_eq!(shell.error("(error \"boom\")"), "boom");
+    assert_eq!(shell.error("(error 42)"), "42");
+}
+
+#[test]
+fn catch_binds_the_error() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("(try (error \"boom\") (catch e (message e)))"),
+        text("boom")
+    );
+    assert_eq!(
+        shell.eval("(try (/ 1 0) (catch e (is-error e)))"),
+        DBTypes::Boolean(true)
+    );
+    assert_eq!(shell.eval("(try (+ 1 2) (catch e 0))"), DBTypes::Number(3));
+    assert_eq!(
+        shell.eval("(try (error \"boom\") (catch e 0))"),
+        DBTypes::Number(0)
+    );
+}
+
+#[test]
+fn errors_in_a_handler_propagate() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.error("(try (error \"first\") (catch e (error \"second\")))"),
+        "second"
+    );
+    assert_eq!(
+        shell.eval(
+            "(try (try (error \"inner\") (catch e (error (concat \"outer: \" (message e))))) \
+             (catch e (message e)))"
+        ),
+        text("outer: inner")
+    );
+}
+
+#[test]
+fn errors_stop_writes_that_follow_them() {
+    let shell = Shell::new();
+    shell.error("(put (error \"boom\") \"k\")");
+    assert_eq!(shell.eval("(exists \"k\")"), DBTypes::Boolean(false));
+}
+
+#[test]
+fn a_malformed_try_is_rejected() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.type_errors("(try 1 2)"),
+        vec!["Expected the form `(try expr (catch e handler))`"]
+    );
+}
+
+#[test]
+fn failed_writes_raise_an_error() {
+    let shell = Shell::over(Database::with_engine(
+        MmapEngine::open("/dev/full").unwrap(),
+    ));
+    assert_eq!(
+        shell.error("(put 1 \"k\")"),
+        "Couldn't write to storage: No space left on device (os error 28)"
+    );
+    assert_eq!(shell.eval("(get \"k\")"), DBTypes::Nil);
+}
+
+#[test]
+fn nesting_too_deep_is_refused_not_overflowed() {
+    // Debug builds take more stack per level than a test thread has, so run where the
+    // REPL does, on a main-thread sized stack
+    std::thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(|| {
+            let nested = |open: &str, leaf: &str, close: &str, depth: usize| {
+                format!("{}{leaf}{}", open.repeat(depth), close.repeat(depth))
+            };
+            for code in [
+                nested("[", "1", "]", 20_000),
+                nested("(not ", "true", ")", 5_000),
+            ] {
+                let error = parse(&code).map(|_| ()).unwrap_err().to_string();
+                assert!(
+                    error.contains(&format!("Expressions can nest at most {MAX_DEPTH} deep")),
+                    "{error}"
+                );
+            }
+
+            // Brackets inside text don't nest anything
+            let shell = Shell::new();
+            let brackets = "(".repeat(100);
+            assert_eq!(shell.eval(&format!("\"{brackets}\"")), text(&brackets));
+            assert_eq!(
+                shell.eval(&nested("(not ", "true", ")", 10)),
+                DBTypes::Boolean(true)
+            );
+
+            // Expressions built without the parser are stopped while they're evaluated
+            let mut expr = Expr::Boolean(true);
+            for _ in 0..5_000 {
+                expr = Expr::Call(Box::new(Expr::Identifier("not".into())), vec![expr], 0..0);
+            }
+            let error = Interpreter::default().eval_expr(&expr).unwrap_err();
+            assert!(
+                error.to_string().contains(&format!(
+                    "Evaluation can nest at most {MAX_EVAL_DEPTH} deep"
+                )),
+                "{error}"
+            );
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
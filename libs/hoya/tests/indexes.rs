@@ -0,0 +1,81 @@
+mod common;
+
+use common::{texts, Shell};
+use db::DBTypes;
+
+#[test]
+fn find_by_looks_up_records_through_an_index() {
+    let shell = Shell::new();
+    shell.eval("(put \"red\" \"apple\")");
+    shell.eval("(put \"yellow\" \"banana\")");
+    shell.eval("(put \"red\" \"cherry\")");
+    assert_eq!(
+        shell.eval("(create-index \"colour\" \"value\")"),
+        DBTypes::Unit(())
+    );
+
+    assert_eq!(
+        shell.eval("(find-by \"colour\" \"red\")"),
+        texts(&["apple", "cherry"])
+    );
+    assert_eq!(shell.eval("(find-by \"colour\" \"green\")"), texts(&[]));
+}
+
+#[test]
+fn an_index_follows_puts_and_removes() {
+    let shell = Shell::new();
+    shell.eval("(create-index \"colour\" \"value\")");
+    shell.eval("(put \"red\" \"apple\")");
+    shell.eval("(put \"green\" \"apple\")");
+    shell.eval("(put \"red\" \"cherry\")");
+    shell.eval("(remove \"cherry\")");
+
+    assert_eq!(shell.eval("(find-by \"colour\" \"red\")"), texts(&[]));
+    assert_eq!(
+        shell.eval("(find-by \"colour\" \"green\")"),
+        texts(&["apple"])
+    );
+}
+
+#[test]
+fn find_range_walks_an_index_in_order() {
+    let shell = Shell::new();
+    shell.eval("(create-index \"age\" 1)");
+    shell.eval("(put [\"ann\" 31] \"u1\")");
+    shell.eval("(put [\"bob\" 25] \"u2\")");
+    shell.eval("(put [\"cy\" 40] \"u3\")");
+
+    assert_eq!(
+        shell.eval("(find-range \"age\" 25 35)"),
+        texts(&["u2", "u1"])
+    );
+    assert_eq!(shell.eval("(find-range \"age\" 35 25)"), texts(&[]));
+}
+
+#[test]
+fn an_elements_index_finds_lists_by_any_member() {
+    let shell = Shell::new();
+    shell.eval("(create-index \"tags\" \"elements\")");
+    shell.eval("(put [\"db\" \"rust\"] \"post1\")");
+    shell.eval("(put [\"rust\"] \"post2\")");
+
+    assert_eq!(
+        shell.eval("(find-by \"tags\" \"rust\")"),
+        texts(&["post1", "post2"])
+    );
+    assert_eq!(shell.eval("(find-by \"tags\" \"db\")"), texts(&["post1"]));
+}
+
+#[test]
+fn a_missing_or_malformed_index_is_reported() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.error("(find-by \"nope\" 1)"),
+        "Index `nope` does not exist"
+    );
+    assert_eq!(
+        shell.error("(create-index \"bad\" \"keys\")"),
+        "An index extracts \"value\", \"elements\" or a list position"
+    );
+    assert!(shell.db.indexes().is_empty());
+}
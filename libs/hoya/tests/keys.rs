@@ -0,0 +1,61 @@
+mod common;
+
+use common::Shell;
+use db::{DBTypes, DataDir, Database};
+
+#[test]
+fn keys_can_be_built() {
+    let shell = Shell::new();
+    shell.eval("(put 1 (concat \"user:\" 42))");
+    assert_eq!(shell.eval("(get \"user:42\")"), DBTypes::Number(1));
+    assert_eq!(
+        shell.eval("(exists (concat \"user:\" (+ 40 2)))"),
+        DBTypes::Boolean(true)
+    );
+    assert_eq!(
+        shell.eval("(remove (concat \"user:\" 42))"),
+        DBTypes::Number(1)
+    );
+    assert_eq!(shell.eval("(exists \"user:42\")"), DBTypes::Boolean(false));
+}
+
+#[test]
+fn keys_can_be_looked_up_from_other_keys() {
+    let shell = Shell::new();
+    shell.eval("(put \"target\" \"pointer\")");
+    shell.eval("(put 5 \"target\")");
+    assert_eq!(shell.eval("(get (get \"pointer\"))"), DBTypes::Number(5));
+    assert_eq!(
+        shell.error("(get (get \"missing\"))"),
+        "Expected text, but found nil"
+    );
+    shell.eval("(put 1 \"number\")");
+    assert_eq!(
+        shell.error("(get (get \"number\"))"),
+        "Expected text, but found 1"
+    );
+}
+
+#[test]
+fn keys_are_typechecked_as_text() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.type_errors("(get 1)"),
+        vec!["Expected types [Text], but found [Number]"]
+    );
+    assert!(shell.type_errors("(put 1 (get \"k\"))").is_empty());
+}
+
+#[test]
+fn store_and_load_take_computed_names() {
+    let dir = std::env::temp_dir().join(format!("hoya-keys-{}", std::process::id()));
+    let shell = Shell::over(Database::default().with_data_dir(DataDir::new(&dir).unwrap()));
+
+    shell.eval("(put 1 \"k\")");
+    shell.eval("(store (concat \"d\" \"b\"))");
+    shell.eval("(remove \"k\")");
+    shell.eval("(load (concat \"d\" \"b\"))");
+    assert_eq!(shell.eval("(get \"k\")"), DBTypes::Number(1));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{texts, Shell};
+use db::DBTypes;
+
+fn numbers(items: &[isize]) -> DBTypes {
+    DBTypes::List(items.iter().map(|n| DBTypes::Number(*n)).collect())
+}
+
+#[test]
+fn higher_order_builtins_call_lambdas() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("(map (fn (x: Number) -> Number (* x x)) [1 2 3])"),
+        numbers(&[1, 4, 9])
+    );
+    assert_eq!(
+        shell.eval("(filter (fn (x: Number) -> Boolean (> x 1)) [1 2 3])"),
+        numbers(&[2, 3])
+    );
+    assert_eq!(
+        shell.eval("(sort-by (fn (x: Number) -> Number (- 0 x)) [2 3 1])"),
+        numbers(&[3, 2, 1])
+    );
+    assert_eq!(
+        shell.eval("(fold (fn (acc: Number x: Number) -> Number (+ acc x)) 10 [1 2 3])"),
+        DBTypes::Number(16)
+    );
+    assert_eq!(
+        shell.eval("(reduce (fn (a: Number b: Number) -> Number (* a b)) [2 3 4])"),
+        DBTypes::Number(24)
+    );
+    assert_eq!(
+        shell.eval("(any (fn (x: Number) -> Boolean (= x 2)) [1 2 3])"),
+        DBTypes::Boolean(true)
+    );
+    assert_eq!(
+        shell.eval("(all (fn (x: Number) -> Boolean (= x 2)) [1 2 3])"),
+        DBTypes::Boolean(false)
+    );
+}
+
+#[test]
+fn lambdas_run_over_query_results() {
+    let shell = Shell::new();
+    shell.eval("(put 3 \"n:a\")");
+    shell.eval("(put 5 \"n:b\")");
+    shell.eval("(put \"x\" \"other\")");
+
+    assert_eq!(
+        shell.eval("(map (fn (x: Number) -> Number (+ x 1)) (select \"n:\"))"),
+        numbers(&[4, 6])
+    );
+}
+
+#[test]
+fn lambdas_close_over_enclosing_parameters() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval(
+            "(map (fn (x: Number) -> [Number] (map (fn (y: Number) -> Number (+ x y)) [10 20])) [1 2])"
+        ),
+        DBTypes::List(vec![numbers(&[11, 21]), numbers(&[12, 22])])
+    );
+}
+
+#[test]
+fn lambdas_are_checked_against_their_annotations() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.type_errors("(fn (x: Number) -> Boolean x)"),
+        ["Expected type Boolean, but found Number"]
+    );
+    assert_eq!(
+        shell.type_errors("(fn (x: Numbr) -> Number x)"),
+        ["Type `Numbr` does not exist"]
+    );
+    assert_eq!(
+        shell.type_errors("(fn (x: Number) -> Number y)"),
+        ["Variable `y` does not exist"]
+    );
+    assert_eq!(
+        shell.type_errors("(filter (fn (x: Number) -> Number x) [1 2])"),
+        ["Expected type Boolean, but found Number"]
+    );
+    assert_eq!(
+        shell.type_errors("(map (fn (x: Text) -> Text x) [1 2])"),
+        ["Expected types [Number], but found [Text]"]
+    );
+}
+
+#[test]
+fn functions_cant_be_stored() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.type_errors("(put (fn (x: Number) -> Number x) \"f\")"),
+        ["Functions can't be stored, but found (Number -> Number)"]
+    );
+    assert_eq!(
+        shell.type_errors("(where (select \"\") \"value\" \"=\" [(fn (x: Number) -> Number x)])"),
+        ["Functions can't be stored, but found [(Number -> Number)]"]
+    );
+
+    // A list mixing functions with other values types as a plain `List`, so it's caught at runtime
+    assert_eq!(
+        shell.error("(put [1 (fn (x: Number) -> Number x)] \"f\")"),
+        "Functions can't be stored"
+    );
+    assert_eq!(shell.db.get("f"), None);
+    // Nor are they compared or grouped as their signature
+    for code in [
+        "(uniq [1 (fn (x: Number) -> Number x)])",
+        "(= [1 (fn (x: Number) -> Number x)] [1])",
+        "(where (select \"\") \"value\" \"=\" [1 (fn (x: Number) -> Number x)])",
+    ] {
+        assert_eq!(shell.error(code), "Functions can't be stored", "{code}");
+    }
+    assert_eq!(
+        shell.eval("(map (fn (x: Number) -> Text \"n\") [1])"),
+        texts(&["n"])
+    );
+}
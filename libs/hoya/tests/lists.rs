@@ -0,0 +1,83 @@
+mod common;
+
+use common::Shell;
+use db::DBTypes;
+use hoya::interpreter::interpret::MAX_RANGE;
+
+fn numbers(items: &[isize]) -> DBTypes {
+    DBTypes::List(items.iter().map(|n| DBTypes::Number(*n)).collect())
+}
+
+#[test]
+fn list_builtins_build_new_lists() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(len [1 2 3])"), DBTypes::Number(3));
+    assert_eq!(shell.eval("(nth [1 2 3] 1)"), DBTypes::Number(2));
+    assert_eq!(shell.eval("(nth [1 2 3] 5)"), DBTypes::Nil);
+    assert_eq!(shell.eval("(head [1 2 3])"), DBTypes::Number(1));
+    assert_eq!(shell.eval("(head [])"), DBTypes::Nil);
+    assert_eq!(shell.eval("(tail [1 2 3])"), numbers(&[2, 3]));
+    assert_eq!(shell.eval("(append [1 2] 3)"), numbers(&[1, 2, 3]));
+    assert_eq!(shell.eval("(prepend [1 2] 0)"), numbers(&[0, 1, 2]));
+    assert_eq!(shell.eval("(slice [1 2 3 4] 1 3)"), numbers(&[2, 3]));
+    assert_eq!(shell.eval("(slice [1 2 3 4] 3 99)"), numbers(&[4]));
+    assert_eq!(shell.eval("(slice [1 2 3 4] 3 1)"), numbers(&[]));
+    assert_eq!(shell.eval("(reverse [1 2 3])"), numbers(&[3, 2, 1]));
+    assert_eq!(shell.eval("(sort [3 1 2])"), numbers(&[1, 2, 3]));
+    assert_eq!(shell.eval("(uniq [1 2 1 3 2])"), numbers(&[1, 2, 3]));
+    assert_eq!(shell.eval("(range 2 5)"), numbers(&[2, 3, 4]));
+    assert_eq!(shell.eval("(range 5 2)"), numbers(&[]));
+}
+
+#[test]
+fn a_range_too_large_to_hold_is_refused() {
+    let shell = Shell::new();
+    let refused = format!("A range holds at most {MAX_RANGE} numbers");
+    assert_eq!(shell.error("(range 0 1000000000000)"), refused);
+    assert_eq!(
+        shell.error("(range -9223372036854775807 9223372036854775807)"),
+        refused
+    );
+    assert_eq!(
+        shell.eval(&format!("(len (range 1 {}))", MAX_RANGE + 1)),
+        DBTypes::Number(MAX_RANGE as isize)
+    );
+}
+
+#[test]
+fn stored_lists_change_in_place() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(rpush 2 \"l\")"), DBTypes::Number(1));
+    assert_eq!(shell.eval("(rpush 3 \"l\")"), DBTypes::Number(2));
+    assert_eq!(shell.eval("(lpush 1 \"l\")"), DBTypes::Number(3));
+    assert_eq!(shell.eval("(lrange \"l\" 0 5)"), numbers(&[1, 2, 3]));
+    assert_eq!(shell.eval("(lrange \"l\" 1 1)"), numbers(&[2]));
+    assert_eq!(shell.eval("(lpop \"l\")"), DBTypes::Number(1));
+    assert_eq!(shell.eval("(rpop \"l\")"), DBTypes::Number(3));
+    assert_eq!(shell.eval("(get \"l\")"), numbers(&[2]));
+    assert_eq!(shell.eval("(lpop \"missing\")"), DBTypes::Nil);
+}
+
+#[test]
+fn stored_list_builtins_report_the_wrong_type() {
+    let shell = Shell::new();
+    shell.eval("(put 1 \"n\")");
+    let wrong = "Key `n` doesn't hold a list";
+    assert_eq!(shell.error("(rpush 2 \"n\")"), wrong);
+    assert_eq!(shell.error("(lpop \"n\")"), wrong);
+    assert_eq!(shell.error("(lrange \"n\" 0 1)"), wrong);
+}
+
+#[test]
+fn functions_cant_be_pushed() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.type_errors("(rpush (fn (x: Number) -> Number x) \"l\")"),
+        ["Functions can't be stored, but found (Number -> Number)"]
+    );
+    assert_eq!(
+        shell.error("(rpush [1 (fn (x: Number) -> Number x)] \"l\")"),
+        "Functions can't be stored"
+    );
+    assert_eq!(shell.db.get("l"), None);
+}
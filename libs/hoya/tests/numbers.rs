@@ -0,0 +1,187 @@
+mod common;
+
+use std::str::FromStr;
+
+use common::{texts, Shell};
+use db::DBTypes;
+use hoya::parser::parse;
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
+
+fn big(n: &str) -> DBTypes {
+    DBTypes::BigInt(BigInt::from_str(n).unwrap())
+}
+
+fn decimal(d: &str) -> DBTypes {
+    DBTypes::Decimal(Decimal::from_str(d).unwrap())
+}
+
+#[test]
+fn literals_pick_their_kind_by_suffix() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("-42"), DBTypes::Number(-42));
+    assert_eq!(
+        shell.eval("123456789012345678901234567890n"),
+        big("123456789012345678901234567890")
+    );
+    assert_eq!(shell.eval("-5n"), big("-5"));
+    assert_eq!(shell.eval("19.99d"), decimal("19.99"));
+    assert_eq!(shell.eval("100d"), decimal("100"));
+    assert_eq!(shell.eval("1.5"), DBTypes::Float(1.5));
+}
+
+#[test]
+fn literals_out_of_range_are_parse_errors() {
+    let error = |code: &str| parse(code).map(|_| ()).unwrap_err().to_string();
+    assert!(error("9223372036854775808")
+        .contains("Number out of range, write big integers with an `n` suffix"));
+    assert!(error("-9223372036854775809").contains("Number out of range"));
+    assert!(error("12345678901234567890123456789012345d")
+        .contains("Decimal out of range, decimals hold at most 28 significant digits"));
+    assert!(parse("9223372036854775807").is_ok());
+}
+
+#[test]
+fn arithmetic_promotes_to_the_least_exact_kind() {
+    let shell = Shell::new();
+    let cases = [
+        ("(+ 1 2)", DBTypes::Number(3)),
+        ("(+ 1 2n)", big("3")),
+        ("(* 2n 3n)", big("6")),
+        ("(+ 1 0.5d)", decimal("1.5")),
+        ("(+ 1n 0.5d)", decimal("1.5")),
+        ("(+ 0.1d 0.2d)", decimal("0.3")),
+        ("(+ 1 0.5)", DBTypes::Float(1.5)),
+        ("(+ 1n 0.5)", DBTypes::Float(1.5)),
+        ("(+ 0.5d 0.25)", DBTypes::Float(0.75)),
+        ("(/ 7 2)", DBTypes::Number(3)),
+        ("(/ -7n 2n)", big("-3")),
+        ("(/ 1d 4)", decimal("0.25")),
+        ("(/ 1 4.0)", DBTypes::Float(0.25)),
+    ];
+    for (code, expected) in cases {
+        assert_eq!(shell.eval(code), expected, "{code}");
+    }
+}
+
+#[test]
+fn typechecker_follows_the_promotion_table() {
+    let shell = Shell::new();
+    assert!(shell.type_errors("(substr \"abc\" (+ 1 1) 1)").is_empty());
+    assert_eq!(shell.type_errors("(substr \"abc\" (+ 1 1n) 1)").len(), 1);
+    assert_eq!(shell.type_errors("(substr \"abc\" (+ 1 1d) 1)").len(), 1);
+    assert_eq!(shell.type_errors("(substr \"abc\" (+ 1 1.0) 1)").len(), 1);
+}
+
+#[test]
+fn numbers_that_overflow_are_refused_by_every_builtin() {
+    let shell = Shell::new();
+    let refused = |op: &str| format!("Cannot apply `{op}` to 9223372036854775807 and 2");
+    assert_eq!(shell.error("(+ 9223372036854775807 2)"), refused("+"));
+    assert_eq!(shell.error("(* 9223372036854775807 2)"), refused("*"));
+    assert_eq!(shell.error("(sum [9223372036854775807 2])"), refused("+"));
+    assert_eq!(
+        shell.error("(reduce \"*\" [9223372036854775807 2])"),
+        refused("*")
+    );
+
+    // Big integers are written or converted explicitly, and never overflow
+    assert_eq!(
+        shell.eval("(+ 9223372036854775807n 2)"),
+        big("9223372036854775809")
+    );
+    assert_eq!(
+        shell.eval("(sum [(bigint 9223372036854775807) 2])"),
+        big("9223372036854775809")
+    );
+}
+
+#[test]
+fn dividing_by_zero_is_refused() {
+    let shell = Shell::new();
+    assert_eq!(shell.error("(/ 1 0)"), "Cannot apply `/` to 1 and 0");
+    assert_eq!(shell.error("(/ 1n 0n)"), "Cannot apply `/` to 1 and 0");
+    assert_eq!(shell.error("(/ 1d 0)"), "Cannot apply `/` to 1 and 0");
+    assert_eq!(shell.eval("(/ 1 0.0)"), DBTypes::Float(f64::INFINITY));
+}
+
+#[test]
+fn conversions_and_rounding() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(bigint 2.9d)"), big("2"));
+    assert_eq!(shell.eval("(bigint \" 12 \")"), big("12"));
+    assert_eq!(shell.error("(bigint \"x\")"), "`x` is not an integer");
+    assert_eq!(shell.eval("(decimal \"0.10\")"), decimal("0.10"));
+    assert_eq!(
+        shell.error("(decimal 100000000000000000000000000000000n)"),
+        "Not representable as a decimal"
+    );
+    assert_eq!(shell.eval("(round 2.345d 2)"), decimal("2.35"));
+    assert_eq!(shell.eval("(round -2.5d 0)"), decimal("-3"));
+    assert_eq!(shell.eval("(round 2.345 1)"), DBTypes::Float(2.3));
+    assert_eq!(
+        shell.error("(round 1.5 (- 0 1))"),
+        "Expected a non-negative number of places"
+    );
+}
+
+#[test]
+fn comparisons_promote_across_kinds() {
+    let shell = Shell::new();
+    let cases = [
+        ("(= 5n 5)", true),
+        ("(= 1.5d 1.5)", true),
+        ("(= 2d 2n)", true),
+        ("(= 1 1.0)", true),
+        ("(!= 1 1.0)", false),
+        ("(< 1 1.0)", false),
+        ("(< 5 5n)", false),
+        ("(> 2d 2)", false),
+        ("(<= 2d 2)", true),
+        ("(< 1 1.5d)", true),
+        ("(> 10000000000000000000000n 1.5)", true),
+        ("(< 0.1d 0.2)", true),
+        ("(= 5 \"5\")", false),
+    ];
+    for (code, expected) in cases {
+        assert_eq!(shell.eval(code), DBTypes::Boolean(expected), "{code}");
+    }
+}
+
+#[test]
+fn queries_match_numbers_of_any_kind_with_or_without_an_index() {
+    let shell = Shell::new();
+    for (value, key) in [
+        ("5n", "a"),
+        ("5", "b"),
+        ("5.0", "c"),
+        ("5d", "d"),
+        ("6", "e"),
+    ] {
+        shell.eval(&format!("(put {value} \"{key}\")"));
+    }
+    let matching = |field: &str, op: &str, value: &str| {
+        shell.eval(&format!(
+            "(sort (map \"key\" (where (select \"\") \"{field}\" \"{op}\" {value})))"
+        ))
+    };
+    for field in ["value", "n"] {
+        if field == "n" {
+            shell.eval("(create-index \"n\" \"value\")");
+        }
+        assert_eq!(matching(field, "=", "5"), texts(&["a", "b", "c", "d"]));
+        assert_eq!(matching(field, "=", "5.0d"), texts(&["a", "b", "c", "d"]));
+        assert_eq!(matching(field, "<", "5n"), texts(&[]));
+        assert_eq!(matching(field, "<=", "5.0"), texts(&["a", "b", "c", "d"]));
+        assert_eq!(matching(field, ">", "5"), texts(&["e"]));
+        assert_eq!(
+            matching(field, ">", "4.5"),
+            texts(&["a", "b", "c", "d", "e"])
+        );
+    }
+    assert_eq!(
+        shell.eval("(find-by \"n\" 5n)"),
+        texts(&["a", "b", "c", "d"])
+    );
+    assert_eq!(shell.eval("(find-range \"n\" 5.5d 6n)"), texts(&["e"]));
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::Shell;
+use db::DBTypes;
+
+#[test]
+fn missing_keys_are_nil_not_unit() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(get \"missing\")"), DBTypes::Nil);
+    assert_eq!(shell.eval("(remove \"missing\")"), DBTypes::Nil);
+    shell.eval("(put () \"unit\")");
+    assert_eq!(shell.eval("(get \"unit\")"), DBTypes::Unit(()));
+    assert_eq!(
+        shell.eval("(is-nil (get \"missing\"))"),
+        DBTypes::Boolean(true)
+    );
+    assert_eq!(
+        shell.eval("(is-nil (get \"unit\"))"),
+        DBTypes::Boolean(false)
+    );
+}
+
+#[test]
+fn defaults_stand_in_for_absent_values() {
+    let shell = Shell::new();
+    shell.eval("(put 1 \"one\")");
+    assert_eq!(shell.eval("(get-or \"one\" 0)"), DBTypes::Number(1));
+    assert_eq!(shell.eval("(get-or \"missing\" 0)"), DBTypes::Number(0));
+    assert_eq!(shell.eval("(unwrap-or (head [1 2]) 0)"), DBTypes::Number(1));
+    assert_eq!(shell.eval("(unwrap-or (head []) 0)"), DBTypes::Number(0));
+    assert_eq!(shell.eval("(unwrap-or () 0)"), DBTypes::Unit(()));
+}
+
+#[test]
+fn nil_is_not_stored() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.error("(put nil \"k\")"),
+        "Can't store nil, use `remove` to delete a key"
+    );
+    assert_eq!(shell.eval("(exists \"k\")"), DBTypes::Boolean(false));
+}
+
+#[test]
+fn optional_values_must_be_handled_before_arithmetic() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.type_errors("(+ (head [1 2]) 1)"),
+        vec!["Expected type Number, but found Number?"]
+    );
+    assert!(shell
+        .type_errors("(+ (unwrap-or (head [1 2]) 0) 1)")
+        .is_empty());
+    assert_eq!(
+        shell.eval("(+ (unwrap-or (head [1 2]) 0) 1)"),
+        DBTypes::Number(2)
+    );
+}
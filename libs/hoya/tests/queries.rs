@@ -0,0 +1,126 @@
+mod common;
+
+use common::{text, texts, Shell};
+use db::DBTypes;
+
+fn people() -> Shell {
+    let shell = Shell::new();
+    shell.eval("(put [\"ann\" 31] \"user:1\")");
+    shell.eval("(put [\"bob\" 25] \"user:2\")");
+    shell.eval("(put [\"cy\" 40] \"user:3\")");
+    shell.eval("(put 7 \"post:1\")");
+    shell
+}
+
+fn row(key: &str, value: DBTypes) -> DBTypes {
+    DBTypes::List(vec![text(key), value])
+}
+
+#[test]
+fn select_and_where_filter_entries() {
+    let shell = people();
+    shell.eval("(put (where (select \"\") \"value\" \"=\" 7) \"found\")");
+    assert_eq!(
+        shell.db.get("found"),
+        Some(DBTypes::List(vec![row("post:1", DBTypes::Number(7))]))
+    );
+    assert_eq!(shell.eval("(count (select \"user:\"))"), DBTypes::Number(3));
+    assert_eq!(
+        shell.eval("(map \"key\" (where (select \"user:\") \"key\" \">=\" \"user:2\"))"),
+        texts(&["user:2", "user:3"])
+    );
+}
+
+#[test]
+fn explain_shows_the_planner_using_an_index() {
+    let shell = people();
+    let query = "(where (select \"user:\") \"age\" \">\" 30)";
+    assert_eq!(
+        shell.eval(&format!("(explain {query})")),
+        text("key range [\"user:\", \"user;\")")
+    );
+
+    shell.eval("(create-index \"age\" 1)");
+    assert_eq!(
+        shell.eval(&format!("(explain {query})")),
+        text("index `age` range (Number(30), +inf)")
+    );
+    assert_eq!(
+        shell.eval(&format!("(map \"key\" {query})")),
+        texts(&["user:1", "user:3"])
+    );
+    assert_eq!(
+        shell.eval("(explain (where (select \"\") \"age\" \"=\" 25))"),
+        text("index `age` lookup of Number(25)")
+    );
+    assert_eq!(shell.eval("(explain (select \"\"))"), text("full scan"));
+}
+
+#[test]
+fn group_by_buckets_values_by_a_field() {
+    let shell = Shell::new();
+    shell.eval("(put [\"red\" 1] \"a\")");
+    shell.eval("(put [\"blue\" 2] \"b\")");
+    shell.eval("(put [\"red\" 3] \"c\")");
+    shell.eval("(create-index \"colour\" 0)");
+
+    let value = |c: &str, n| DBTypes::List(vec![text(c), DBTypes::Number(n)]);
+    assert_eq!(
+        shell.eval("(group-by \"colour\" (select \"\"))"),
+        DBTypes::List(vec![
+            DBTypes::List(vec![text("blue"), DBTypes::List(vec![value("blue", 2)])]),
+            DBTypes::List(vec![
+                text("red"),
+                DBTypes::List(vec![value("red", 1), value("red", 3)])
+            ]),
+        ])
+    );
+}
+
+#[test]
+fn aggregates_skip_values_they_dont_take() {
+    let shell = people();
+    assert_eq!(shell.eval("(sum [1 \"x\" 2 true])"), DBTypes::Number(3));
+    assert_eq!(shell.eval("(sum [])"), DBTypes::Number(0));
+    assert_eq!(shell.eval("(sum [1 2.5])"), DBTypes::Float(3.5));
+    assert_eq!(shell.eval("(reduce \"*\" [2 3 4])"), DBTypes::Number(24));
+    assert_eq!(
+        shell.eval("(reduce \"or\" [true false])"),
+        DBTypes::Boolean(true)
+    );
+    assert_eq!(
+        shell.eval("(reduce \"concat\" [\"a\" 1 \"b\"])"),
+        text("ab")
+    );
+    assert_eq!(shell.eval("(min [3 1 2])"), DBTypes::Number(1));
+    assert_eq!(
+        shell.eval("(max (map \"key\" (select \"user:\")))"),
+        text("user:3")
+    );
+    assert_eq!(shell.eval("(sum (select \"post:\"))"), DBTypes::Number(7));
+    assert_eq!(shell.eval("(max [])"), DBTypes::Nil);
+}
+
+#[test]
+fn an_overflowing_aggregate_is_an_error() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.error("(sum [9223372036854775807 1])"),
+        "Cannot apply `+` to 9223372036854775807 and 1"
+    );
+    assert_eq!(
+        shell.error("(reduce \"*\" [4611686018427387904 2])"),
+        "Cannot apply `*` to 4611686018427387904 and 2"
+    );
+}
+
+#[test]
+fn malformed_queries_are_reported() {
+    let shell = people();
+    assert_eq!(
+        shell.error("(where (select \"\") \"value\" \"~\" 1)"),
+        "Unknown comparison `~`"
+    );
+    assert_eq!(shell.error("(reduce \"-\" [1])"), "Unknown operator `-`, expected one of [\"+\", \"*\", \"min\", \"max\", \"and\", \"or\", \"concat\"]");
+    assert_eq!(shell.error("(sum 1)"), "Expected a query or a list");
+}
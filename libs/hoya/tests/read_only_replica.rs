@@ -0,0 +1,42 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use db::{DBTypes, Database};
+use hoya::interpreter::shared::SharedInterpreter;
+use hoya::interpreter::types::InterpreterValue;
+use hoya::parser::parse;
+
+#[test]
+fn a_replica_serves_reads_and_refuses_writes() {
+    let primary_db = Database::default();
+    primary_db
+        .put(String::from("key"), DBTypes::Number(1))
+        .unwrap();
+    let primary = primary_db.serve_replicas("127.0.0.1:0").unwrap();
+    let replica_db = Database::default();
+    let replica = replica_db.replicate_from(primary.local_addr().to_string());
+    let start = Instant::now();
+    while replica_db.get("key").is_none() {
+        assert!(start.elapsed() < Duration::from_secs(20), "never synced");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let interpreter = SharedInterpreter::read_only(replica_db.clone());
+    let eval = |code: &str| interpreter.eval_expr(&parse(code).unwrap().0);
+    assert!(matches!(
+        eval("(get \"key\")").unwrap(),
+        InterpreterValue::Number(n) if *n == 1
+    ));
+    for write in [
+        "(put 2 \"key\")",
+        "(remove \"key\")",
+        "(rpush 1 \"list\")",
+        "(sadd \"a\" \"set\")",
+    ] {
+        let error = eval(write).unwrap_err().to_string();
+        assert!(error.contains("read-only replica"), "{write}: {error}");
+    }
+    assert_eq!(replica_db.get("key"), Some(DBTypes::Number(1)));
+    assert_eq!(replica_db.get("list"), None);
+    assert_eq!(replica.status().offset, 0);
+}
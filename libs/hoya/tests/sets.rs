@@ -0,0 +1,113 @@
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::{text, Shell};
+use db::DBTypes;
+
+fn set(items: &[&str]) -> DBTypes {
+    DBTypes::Set(items.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>())
+}
+
+#[test]
+fn set_builtins_take_a_member_or_a_list() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(sadd \"a\" \"s\")"), DBTypes::Number(1));
+    assert_eq!(
+        shell.eval("(sadd [\"a\" \"b\" \"c\"] \"s\")"),
+        DBTypes::Number(2)
+    );
+    assert_eq!(shell.eval("(srem [\"c\" \"z\"] \"s\")"), DBTypes::Number(1));
+    assert_eq!(shell.eval("(smembers \"s\")"), set(&["a", "b"]));
+    assert_eq!(shell.eval("(len (smembers \"s\"))"), DBTypes::Number(2));
+
+    shell.eval("(sadd [\"b\" \"d\"] \"t\")");
+    assert_eq!(shell.eval("(sinter [\"s\" \"t\"])"), set(&["b"]));
+    assert_eq!(shell.eval("(sunion [\"s\" \"t\"])"), set(&["a", "b", "d"]));
+    assert_eq!(
+        shell.error("(sadd 1 \"s\")"),
+        "Expected a member or a list of members"
+    );
+}
+
+#[test]
+fn sorted_set_builtins_rank_members() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(zadd \"a\" 3 \"z\")"), DBTypes::Boolean(true));
+    assert_eq!(shell.eval("(zadd \"b\" 1.5 \"z\")"), DBTypes::Boolean(true));
+    assert_eq!(shell.eval("(zadd \"a\" 1 \"z\")"), DBTypes::Boolean(false));
+    assert_eq!(shell.eval("(zincr \"c\" 2 \"z\")"), DBTypes::Float(2.0));
+
+    assert_eq!(shell.eval("(zrank \"z\" \"a\")"), DBTypes::Number(0));
+    assert_eq!(shell.eval("(zrank \"z\" \"c\")"), DBTypes::Number(2));
+    assert_eq!(shell.eval("(zrank \"z\" \"nope\")"), DBTypes::Nil);
+    assert_eq!(
+        shell.eval("(zrange-by-score \"z\" 1.5 2)"),
+        DBTypes::List(vec![
+            DBTypes::List(vec![text("b"), DBTypes::Float(1.5)]),
+            DBTypes::List(vec![text("c"), DBTypes::Float(2.0)]),
+        ])
+    );
+    assert_eq!(
+        shell.error("(zadd \"a\" \"x\" \"z\")"),
+        "Expected a member and a score"
+    );
+    for (code, score) in [
+        ("(zadd \"d\" (/ 0.0 0.0) \"z\")", "NaN"),
+        ("(zadd \"d\" (/ 1 0.0) \"z\")", "inf"),
+        ("(zincr \"a\" (/ -1 0.0) \"z\")", "-inf"),
+    ] {
+        assert_eq!(
+            shell.error(code),
+            format!("Expected a finite score, but found {score}")
+        );
+    }
+    assert_eq!(shell.eval("(zrank \"z\" \"d\")"), DBTypes::Nil);
+    assert_eq!(shell.eval("(zrank \"z\" \"a\")"), DBTypes::Number(0));
+
+    // Adding finite scores can still overflow
+    let huge = format!("1{}.0", "0".repeat(308));
+    shell.eval(&format!("(zadd \"big\" {huge} \"z\")"));
+    assert_eq!(
+        shell.error(&format!("(zincr \"big\" {huge} \"z\")")),
+        "Scores in `z` must be finite, but one would be inf"
+    );
+    assert_eq!(
+        shell.eval(&format!("(zrange-by-score \"z\" {huge} {huge})")),
+        DBTypes::List(vec![DBTypes::List(vec![
+            text("big"),
+            DBTypes::Float(1e308)
+        ])])
+    );
+
+    // Scores of any kind of number are kept as floats
+    assert_eq!(shell.eval("(zincr \"e\" 2n \"z\")"), DBTypes::Float(2.0));
+    assert_eq!(shell.eval("(zincr \"e\" 0.5d \"z\")"), DBTypes::Float(2.5));
+    assert_eq!(
+        shell.error(&format!("(zadd \"e\" {}n \"z\")", "9".repeat(400))),
+        "Expected a finite score, but found inf"
+    );
+}
+
+#[test]
+fn set_builtins_report_the_wrong_type() {
+    let shell = Shell::new();
+    shell.eval("(put 1 \"n\")");
+    shell.eval("(sadd \"a\" \"s\")");
+    assert_eq!(
+        shell.error("(sadd \"a\" \"n\")"),
+        "Key `n` doesn't hold a set"
+    );
+    assert_eq!(
+        shell.error("(smembers \"n\")"),
+        "Key `n` doesn't hold a set"
+    );
+    assert_eq!(
+        shell.error("(zadd \"a\" 1 \"s\")"),
+        "Key `s` doesn't hold a sorted set"
+    );
+    assert_eq!(
+        shell.error("(zincr \"a\" 1 \"s\")"),
+        "Key `s` doesn't hold a sorted set"
+    );
+}
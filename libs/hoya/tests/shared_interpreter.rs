@@ -0,0 +1,51 @@
+use db::{DBTypes, Database};
+use hoya::interpreter::shared::SharedInterpreter;
+use rayon::prelude::*;
+
+const THREADS: usize = 8;
+const RUNS: usize = 100;
+
+#[test]
+fn concurrent_writes_and_reads_all_land() {
+    let db = Database::sharded(16);
+    let interpreter = SharedInterpreter::new(db.clone());
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(THREADS)
+        .build()
+        .unwrap();
+
+    pool.install(|| {
+        (0..THREADS * RUNS).into_par_iter().for_each(|i| {
+            interpreter.interpret(&format!("(put {i} \"key:{i}\")"));
+            interpreter.interpret(&format!("(get \"key:{i}\")"));
+            interpreter.interpret(&format!("(rpush {i} \"pushed\")"));
+            interpreter.interpret(&format!("(sadd \"m{}\" \"members\")", i % 10));
+            interpreter.interpret("(zincr \"hits\" 1 \"counter\")");
+            interpreter.interpret("(count (select \"key:\"))");
+        })
+    });
+
+    let total = THREADS * RUNS;
+    for i in 0..total {
+        assert_eq!(
+            db.get(&format!("key:{i}")),
+            Some(DBTypes::Number(i as isize))
+        );
+    }
+    let mut pushed = match db.get("pushed") {
+        Some(DBTypes::List(values)) => values,
+        other => panic!("expected a list, found {other:?}"),
+    };
+    pushed.sort_by(|a, b| a.total_cmp(b));
+    assert_eq!(
+        pushed,
+        (0..total)
+            .map(|i| DBTypes::Number(i as isize))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(db.smembers("members").unwrap().len(), 10);
+    assert_eq!(
+        db.zrange_by_score("counter", f64::MIN, f64::MAX).unwrap(),
+        vec![(String::from("hits"), total as f64)]
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{text, texts, Shell};
+use db::DBTypes;
+
+#[test]
+fn concat_joins_any_values_as_text() {
+    let shell = Shell::new();
+    shell.eval("(put 42 \"a\")");
+    assert_eq!(shell.eval("(concat \"a\" 1)"), text("a1"));
+    assert_eq!(
+        shell.eval("(concat \"user:\" (get \"a\"))"),
+        text("user:42")
+    );
+    assert_eq!(shell.eval("(concat \"a\" true 1.5)"), text("atrue1.5"));
+    assert_eq!(shell.eval("(concat)"), text(""));
+}
+
+#[test]
+fn lengths_and_positions_count_graphemes() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(len \"héllo\")"), DBTypes::Number(5));
+    // "e" followed by a combining acute accent
+    assert_eq!(shell.eval("(len \"he\u{301}llo\")"), DBTypes::Number(5));
+    assert_eq!(shell.eval("(len \"👩‍👩‍👧\")"), DBTypes::Number(1));
+    assert_eq!(shell.eval("(len \"\")"), DBTypes::Number(0));
+
+    assert_eq!(shell.eval("(substr \"héllo\" 1 3)"), text("éll"));
+    assert_eq!(shell.eval("(substr \"日本語\" 2 5)"), text("語"));
+    assert_eq!(shell.eval("(substr \"abc\" 5 1)"), text(""));
+    assert_eq!(
+        shell.error("(substr \"abc\" (- 0 1) 1)"),
+        "Expected text, a start and a length that aren't negative"
+    );
+    assert_eq!(shell.eval("(split \"né\" \"\")"), texts(&["n", "é"]));
+}
+
+#[test]
+fn case_mapping_handles_unicode() {
+    let shell = Shell::new();
+    assert_eq!(shell.eval("(upper \"straße\")"), text("STRASSE"));
+    assert_eq!(shell.eval("(lower \"ÀÉÎ\")"), text("àéî"));
+    assert_eq!(shell.eval("(trim \"  a b \")"), text("a b"));
+}
+
+#[test]
+fn split_and_join_round_trip() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("(split \"a,b,,c\" \",\")"),
+        texts(&["a", "b", "", "c"])
+    );
+    assert_eq!(
+        shell.eval("(join (split \"a,b\" \",\") \"-\")"),
+        text("a-b")
+    );
+}
+
+#[test]
+fn searching_and_replacing() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("(starts-with \"user:1\" \"user:\")"),
+        DBTypes::Boolean(true)
+    );
+    assert_eq!(
+        shell.eval("(contains \"héllo\" \"él\")"),
+        DBTypes::Boolean(true)
+    );
+    assert_eq!(shell.eval("(replace \"a-b-c\" \"-\" \"+\")"), text("a+b+c"));
+    assert_eq!(
+        shell.eval("(matches \"abc123\" \"^[a-z]+[0-9]+$\")"),
+        DBTypes::Boolean(true)
+    );
+    assert!(shell.error("(matches \"a\" \"(\")").contains("regex"));
+}
+
+#[test]
+fn format_fills_placeholders() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("(format \"{} + {} = {}\" 1 2 3)"),
+        text("1 + 2 = 3")
+    );
+    assert_eq!(
+        shell.eval("(format \"{1} {0} {{}}\" \"a\" \"b\")"),
+        text("b a {}")
+    );
+    assert_eq!(
+        shell.error("(format \"{} {}\" 1)"),
+        "No argument for placeholder 1"
+    );
+    assert_eq!(
+        shell.error("(format \"{x}\" 1)"),
+        "Invalid placeholder `{x}`"
+    );
+    assert_eq!(
+        shell.error("(format \"{\" 1)"),
+        "Unclosed `{` in format string"
+    );
+}
+
+#[test]
+fn text_builtins_are_typechecked() {
+    let shell = Shell::new();
+    assert_eq!(shell.type_errors("(upper 1)").len(), 1);
+    assert_eq!(shell.type_errors("(trim 1)").len(), 1);
+    assert!(shell.type_errors("(concat [1] (len \"a\"))").is_empty());
+}
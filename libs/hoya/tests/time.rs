@@ -0,0 +1,172 @@
+mod common;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use common::{text, Shell};
+use db::DBTypes;
+use hoya::parser::parse;
+
+fn at(rfc3339: &str) -> DBTypes {
+    DBTypes::Timestamp(
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc),
+    )
+}
+
+fn parse_error(code: &str) -> String {
+    match parse(code) {
+        Ok(_) => panic!("{code} parsed"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn literals_parse_to_their_values() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("#b\"00ff 10\""),
+        DBTypes::Bytes(vec![0x00, 0xff, 0x10])
+    );
+    assert_eq!(shell.eval("#b\"\""), DBTypes::Bytes(vec![]));
+    assert_eq!(
+        shell.eval("#t\"2026-10-18T12:30:00+02:00\""),
+        at("2026-10-18T10:30:00Z")
+    );
+    assert_eq!(
+        shell.eval("#t\"2026-10-18\""),
+        DBTypes::Timestamp(Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap())
+    );
+    assert_eq!(
+        shell.eval("#d\"1h30m\""),
+        DBTypes::Duration(Duration::minutes(90))
+    );
+    assert_eq!(
+        shell.eval("#d\"1d 2h 3m 4s 5ms\""),
+        DBTypes::Duration(
+            Duration::days(1)
+                + Duration::hours(2)
+                + Duration::minutes(3)
+                + Duration::seconds(4)
+                + Duration::milliseconds(5)
+        )
+    );
+    assert_eq!(
+        shell.eval("#d\"-2w\""),
+        DBTypes::Duration(Duration::weeks(-2))
+    );
+    assert_eq!(shell.eval("#d\"0s\""), DBTypes::Duration(Duration::zero()));
+}
+
+#[test]
+fn malformed_literals_are_parse_errors() {
+    assert!(parse_error("#b\"abc\"").contains("Odd number of hex digits"));
+    assert!(parse_error("#b\"zz\"").contains("Invalid hex digit"));
+    assert!(parse_error("#t\"2026-13-01\"").contains("Invalid RFC 3339 timestamp"));
+    assert!(parse_error("#t\"yesterday\"").contains("Invalid RFC 3339 timestamp"));
+    assert!(parse_error("#d\"1y\"").contains("Invalid duration"));
+    assert!(parse_error("#d\"h\"").contains("Invalid duration"));
+    assert!(parse_error("#d\"\"").contains("Invalid duration"));
+    assert!(parse_error("#d\"99999999999999999999w\"").contains("Invalid duration"));
+}
+
+#[test]
+fn time_arithmetic() {
+    let shell = Shell::new();
+    let day = "#t\"2026-10-18T00:00:00Z\"";
+    assert_eq!(
+        shell.eval(&format!("(+ {day} #d\"1h30m\")")),
+        at("2026-10-18T01:30:00Z")
+    );
+    assert_eq!(
+        shell.eval(&format!("(+ #d\"1d\" {day})")),
+        at("2026-10-19T00:00:00Z")
+    );
+    assert_eq!(
+        shell.eval(&format!("(- {day} (duration 2 \"days\"))")),
+        at("2026-10-16T00:00:00Z")
+    );
+    assert_eq!(
+        shell.eval(&format!("(- {day} #t\"2026-10-17T23:00:00Z\")")),
+        DBTypes::Duration(Duration::hours(1))
+    );
+    assert_eq!(
+        shell.eval(&format!("(- #t\"2026-10-17T23:00:00Z\" {day})")),
+        DBTypes::Duration(Duration::hours(-1))
+    );
+    assert_eq!(
+        shell.eval("(+ #d\"1h\" #d\"-90m\")"),
+        DBTypes::Duration(Duration::minutes(-30))
+    );
+    assert_eq!(
+        shell.eval("(* #d\"90s\" 2)"),
+        DBTypes::Duration(Duration::minutes(3))
+    );
+    assert_eq!(
+        shell.eval("(/ #d\"1h\" 4)"),
+        DBTypes::Duration(Duration::minutes(15))
+    );
+    assert_eq!(
+        shell.eval("(sum [#d\"1h\" #d\"30m\"])"),
+        DBTypes::Duration(Duration::minutes(90))
+    );
+}
+
+#[test]
+fn time_arithmetic_that_doesnt_apply_is_reported() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.error("(/ #d\"1h\" 0)"),
+        "Cannot apply `/` to 1h and 0"
+    );
+    assert_eq!(
+        shell.error("(* #d\"1h\" 9999999999)"),
+        "Cannot apply `*` to 1h and 9999999999"
+    );
+    assert_eq!(
+        shell.error("(+ #t\"9999-12-31T00:00:00Z\" #d\"100000000w\")"),
+        "Cannot apply `+` to #t\"9999-12-31T00:00:00Z\" and 700000000d"
+    );
+}
+
+#[test]
+fn time_builtins() {
+    let shell = Shell::new();
+    assert_eq!(
+        shell.eval("(duration 90 \"minutes\")"),
+        DBTypes::Duration(Duration::minutes(90))
+    );
+    assert_eq!(
+        shell.error("(duration 1 \"fortnight\")"),
+        "Unknown unit of time `fortnight`"
+    );
+    assert_eq!(
+        shell.eval("(format-time #t\"2026-10-18T09:05:00Z\" \"%Y/%m/%d %H:%M\")"),
+        text("2026/10/18 09:05")
+    );
+    assert_eq!(
+        shell.error("(format-time #t\"2026-10-18\" \"%Q\")"),
+        "Invalid time format `%Q`"
+    );
+    assert_eq!(shell.eval("(len (bytes \"hé\"))"), DBTypes::Number(3));
+    assert_eq!(
+        shell.eval("(concat #d\"90m\" \" \" #b\"0aff\")"),
+        text("1h 30m #b\"0aff\"")
+    );
+    assert!(matches!(shell.eval("(now)"), DBTypes::Timestamp(_)));
+}
+
+#[test]
+fn time_values_are_stored() {
+    let shell = Shell::new();
+    shell.eval("(put [#t\"2026-10-18\" #d\"-1s\" #b\"01\"] \"k\")");
+    assert_eq!(
+        shell.db.get("k"),
+        Some(DBTypes::List(vec![
+            at("2026-10-18T00:00:00Z"),
+            DBTypes::Duration(Duration::seconds(-1)),
+            DBTypes::Bytes(vec![1]),
+        ]))
+    );
+    assert!(shell.type_errors("(+ #t\"2026-10-18\" 1)").is_empty());
+    assert_eq!(shell.type_errors("(format-time #d\"1h\" \"%Y\")").len(), 1);
+}
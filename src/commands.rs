@@ -0,0 +1,65 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use db::ImportMode;
+
+use crate::config::{Command, Config};
+
+pub fn run(command: Command, config: &Config) -> Result<(), Box<dyn Error>> {
+    if let Command::Migrate { old, new } = command {
+        if !old.exists() {
+            return Err(format!("There's no snapshot at `{}`", old.display()).into());
+        }
+        let count = db::migrate(&old, &new, &config.snapshot_options()?)?;
+        eprintln!("Migrated {count} records into `{}`", new.display());
+        return Ok(());
+    }
+
+    let snapshot = &config.snapshot;
+    // The records engine isn't opened, so the snapshot is all these work on
+    let db = config.snapshots()?;
+    let exists = db.snapshots()?.contains(snapshot);
+
+    match command {
+        Command::Export { format, file } => {
+            if !exists {
+                return Err(format!(
+                    "There's no snapshot `{snapshot}.hoya` in `{}`",
+                    config.data_dir.display()
+                )
+                .into());
+            }
+            db.load(snapshot)?;
+            let out: Box<dyn Write> = match file {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout().lock()),
+            };
+            let count = db.export(out, format)?;
+            eprintln!("Exported {count} records");
+        }
+        Command::Import {
+            format,
+            replace,
+            file,
+        } => {
+            if exists {
+                db.load(snapshot)?;
+            }
+            let input: Box<dyn Read> = match file {
+                Some(path) => Box::new(File::open(path)?),
+                None => Box::new(io::stdin().lock()),
+            };
+            let mode = if replace {
+                ImportMode::Replace
+            } else {
+                ImportMode::Merge
+            };
+            let count = db.import(input, format, mode)?;
+            db.store(snapshot)?;
+            eprintln!("Imported {count} records into `{snapshot}.hoya`");
+        }
+        Command::Migrate { .. } => unreachable!("migrated above"),
+    }
+    Ok(())
+}
@@ -0,0 +1,462 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use db::{
+    Cipher, ClusterConfig, Compression, DataDir, Database, Encryption, Format, LogEngine,
+    LsmEngine, MmapEngine, NodeId, SaveRule, Secret, ShardRouter, ShardServer, SnapshotOptions,
+};
+use serde::{Deserialize, Deserializer};
+
+/// Read when `--config` isn't given, if it exists
+const DEFAULT_CONFIG: &str = "hoya.toml";
+
+/// Where the engines that persist every write keep the records, in the data directory
+const LOG: &str = "records.log";
+const MMAP: &str = "records.mmap";
+const LSM: &str = "records.lsm";
+
+/// Encrypts snapshots with a key stretched from its value, when no key file is given
+const PASSPHRASE_VAR: &str = "HOYA_PASSPHRASE";
+
+#[derive(Debug, Parser)]
+#[command(version, about = "The HoyaDB shell")]
+pub struct Options {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// A TOML file to read settings from. Flags override it
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// The directory snapshots are kept in
+    #[arg(long, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Where records are kept: memory, log, mmap or lsm. All but memory keep every write
+    /// in the data directory as it's made
+    #[arg(long, value_name = "ENGINE")]
+    pub engine: Option<Engine>,
+    /// A snapshot in the data directory to load at startup, if it exists
+    #[arg(long, value_name = "NAME")]
+    pub autoload: Option<String>,
+    /// The snapshot in the data directory that the save rules and shutdown write
+    #[arg(long, value_name = "NAME")]
+    pub snapshot: Option<String>,
+    /// Save once SECONDS have passed if there were at least CHANGES writes. Can be
+    /// given more than once, and replaces the rules in the config file
+    #[arg(long = "save", value_name = "SECONDS:CHANGES", value_parser = parse_rule)]
+    pub save: Vec<Rule>,
+    /// How snapshots are compressed: none, zstd or lz4
+    #[arg(long, value_name = "COMPRESSION")]
+    pub compression: Option<Compression>,
+    /// The cipher snapshots are encrypted with when there's a key or passphrase:
+    /// aes-256-gcm or chacha20-poly1305
+    #[arg(long, value_name = "CIPHER")]
+    pub cipher: Option<Cipher>,
+    /// A file holding the 32-byte snapshot key, raw or as 64 hex digits. Without one,
+    /// snapshots are encrypted with the passphrase in HOYA_PASSPHRASE if it's set
+    #[arg(long, value_name = "FILE")]
+    pub key_file: Option<PathBuf>,
+    /// Accept replicas on ADDR, like `0.0.0.0:7400`
+    #[arg(long, value_name = "ADDR")]
+    pub serve_replicas: Option<String>,
+    /// Follow the primary at HOST:PORT, refusing writes from the shell
+    #[arg(long, value_name = "HOST:PORT")]
+    pub replica_of: Option<String>,
+    /// Run as node ID of a cluster, which keeps the records in `raft-ID` in the data
+    /// directory instead of loading a snapshot
+    #[arg(long, value_name = "ID")]
+    pub node_id: Option<NodeId>,
+    /// Where this node listens for the others in its cluster
+    #[arg(long, value_name = "ADDR")]
+    pub cluster_addr: Option<String>,
+    /// A member to start a new cluster with, this node included. Can be given more than
+    /// once, and replaces the members in the config file. Leave them out to join a
+    /// cluster that adds this node
+    #[arg(long = "cluster-member", value_name = "ID=ADDR", value_parser = parse_member)]
+    pub cluster_members: Vec<Member>,
+    /// Confirm with the cluster before each read that nothing newer was committed.
+    /// Reads then only work on the leader
+    #[arg(long)]
+    pub linearizable_reads: bool,
+    /// Serve this server's share of the keys to shard routers on ADDR. It's named by
+    /// the address routers reach it at, which they're given when adding it
+    #[arg(long, value_name = "ADDR")]
+    pub serve_shard: Option<String>,
+    /// A shard server to route the shell's reads and writes to. Can be given more than
+    /// once, and replaces the shards in the config file. Shards that aren't in a ring
+    /// yet start one
+    #[arg(long = "shard", value_name = "HOST:PORT")]
+    pub shards: Vec<String>,
+}
+
+/// The storage engine records are kept in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    #[default]
+    Memory,
+    Log,
+    Mmap,
+    Lsm,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(engine: &str) -> Result<Self, Self::Err> {
+        match engine {
+            "memory" => Ok(Engine::Memory),
+            "log" => Ok(Engine::Log),
+            "mmap" => Ok(Engine::Mmap),
+            "lsm" => Ok(Engine::Lsm),
+            _ => Err(format!(
+                "Expected the engine memory, log, mmap or lsm, but found `{engine}`"
+            )),
+        }
+    }
+}
+
+/// Reads a config value written the way its flag is
+fn parse<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Works on the snapshot in the data directory instead of starting the shell
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Writes every record of the snapshot out as text
+    Export {
+        #[arg(long, default_value = "json")]
+        format: Format,
+        /// Where to write, instead of standard output
+        file: Option<PathBuf>,
+    },
+    /// Reads records into the snapshot, creating it if needed
+    Import {
+        #[arg(long, default_value = "json")]
+        format: Format,
+        /// Drop the records the import doesn't have, instead of keeping them
+        #[arg(long)]
+        replace: bool,
+        /// Where to read from, instead of standard input
+        file: Option<PathBuf>,
+    },
+    /// Rewrites a snapshot of any earlier version in the current format, compressed and
+    /// encrypted as configured. Both are paths to `.hoya` files and can be the same
+    Migrate { old: PathBuf, new: PathBuf },
+}
+
+/// A save rule as written in the config file, like `{ after = 60, changes = 1000 }`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Seconds
+    after: u64,
+    changes: u64,
+}
+
+impl From<Rule> for SaveRule {
+    fn from(rule: Rule) -> Self {
+        SaveRule {
+            after: Duration::from_secs(rule.after),
+            changes: rule.changes,
+        }
+    }
+}
+
+/// A cluster member as written in the config file, like `{ id = 1, addr = "10.0.0.1:7500" }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Member {
+    id: NodeId,
+    addr: String,
+}
+
+fn parse_member(member: &str) -> Result<Member, String> {
+    let invalid = || format!("Expected ID=ADDR, but found `{member}`");
+    let (id, addr) = member.split_once('=').ok_or_else(invalid)?;
+    Ok(Member {
+        id: id.trim().parse().map_err(|_| invalid())?,
+        addr: addr.trim().to_owned(),
+    })
+}
+
+fn parse_rule(rule: &str) -> Result<Rule, String> {
+    let invalid = || format!("Expected SECONDS:CHANGES, but found `{rule}`");
+    let (after, changes) = rule.split_once(':').ok_or_else(invalid)?;
+    Ok(Rule {
+        after: after.trim().parse().map_err(|_| invalid())?,
+        changes: changes.trim().parse().map_err(|_| invalid())?,
+    })
+}
+
+/// Where the database keeps its data and when it saves it. Without a config file or
+/// flags nothing is loaded or saved automatically
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    #[serde(deserialize_with = "parse")]
+    pub engine: Engine,
+    pub autoload: Option<String>,
+    pub snapshot: String,
+    pub save: Vec<Rule>,
+    #[serde(deserialize_with = "parse")]
+    pub compression: Compression,
+    #[serde(deserialize_with = "parse")]
+    pub cipher: Cipher,
+    pub key_file: Option<PathBuf>,
+    pub serve_replicas: Option<String>,
+    pub replica_of: Option<String>,
+    pub node_id: Option<NodeId>,
+    pub cluster_addr: Option<String>,
+    pub cluster_members: Vec<Member>,
+    pub linearizable_reads: bool,
+    pub serve_shard: Option<String>,
+    pub shards: Vec<String>,
+}
+
+/// A key file holds the key itself or its hex digits
+fn read_key(path: &Path) -> Result<[u8; 32], Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("Couldn't read `{}`: {e}", path.display()))?;
+    if let Ok(key) = <[u8; 32]>::try_from(bytes.as_slice()) {
+        return Ok(key);
+    }
+    let invalid = || format!("`{}` doesn't hold a 32-byte key", path.display());
+    let digits = std::str::from_utf8(&bytes).map_err(|_| invalid())?.trim();
+    if digits.len() != 64 || !digits.is_ascii() {
+        return Err(invalid().into());
+    }
+    let mut key = [0; 32];
+    for (byte, pair) in key.iter_mut().zip(digits.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("."),
+            engine: Engine::default(),
+            autoload: None,
+            snapshot: String::from("dump"),
+            save: vec![],
+            compression: Compression::default(),
+            cipher: Cipher::default(),
+            key_file: None,
+            serve_replicas: None,
+            replica_of: None,
+            node_id: None,
+            cluster_addr: None,
+            cluster_members: vec![],
+            linearizable_reads: false,
+            serve_shard: None,
+            shards: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// The config file named by `options`, or the default one, with the flags applied
+    pub fn from_options(options: Options) -> Result<Self, Box<dyn Error>> {
+        let mut config = match &options.config {
+            Some(path) => Self::read(path)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => Self::read(Path::new(DEFAULT_CONFIG))?,
+            None => Self::default(),
+        };
+
+        if let Some(data_dir) = options.data_dir {
+            config.data_dir = data_dir;
+        }
+        if let Some(engine) = options.engine {
+            config.engine = engine;
+        }
+        if let Some(autoload) = options.autoload {
+            config.autoload = Some(autoload);
+        }
+        if let Some(snapshot) = options.snapshot {
+            config.snapshot = snapshot;
+        }
+        if !options.save.is_empty() {
+            config.save = options.save;
+        }
+        if let Some(compression) = options.compression {
+            config.compression = compression;
+        }
+        if let Some(cipher) = options.cipher {
+            config.cipher = cipher;
+        }
+        if let Some(key_file) = options.key_file {
+            config.key_file = Some(key_file);
+        }
+        if let Some(addr) = options.serve_replicas {
+            config.serve_replicas = Some(addr);
+        }
+        if let Some(primary) = options.replica_of {
+            config.replica_of = Some(primary);
+        }
+        if let Some(id) = options.node_id {
+            config.node_id = Some(id);
+        }
+        if let Some(addr) = options.cluster_addr {
+            config.cluster_addr = Some(addr);
+        }
+        if !options.cluster_members.is_empty() {
+            config.cluster_members = options.cluster_members;
+        }
+        if options.linearizable_reads {
+            config.linearizable_reads = true;
+        }
+        if let Some(addr) = options.serve_shard {
+            config.serve_shard = Some(addr);
+        }
+        if !options.shards.is_empty() {
+            config.shards = options.shards;
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read `{}`: {e}", path.display()))?;
+        Ok(toml::from_str(&text).map_err(|e| format!("In `{}`: {e}", path.display()))?)
+    }
+
+    /// Whether anything is ever saved without being asked to
+    pub fn persists(&self) -> bool {
+        !self.save.is_empty()
+    }
+
+    pub fn save_rules(&self) -> Vec<SaveRule> {
+        self.save.iter().copied().map(SaveRule::from).collect()
+    }
+
+    /// The data directory, created if needed
+    fn data_dir(&self) -> Result<DataDir, Box<dyn Error>> {
+        DataDir::new(&self.data_dir).map_err(|e| {
+            let dir = self.data_dir.display();
+            format!("Couldn't create the data directory `{dir}`: {e}").into()
+        })
+    }
+
+    /// How snapshots are compressed and encrypted, with the key read if there's a key file
+    pub fn snapshot_options(&self) -> Result<SnapshotOptions, Box<dyn Error>> {
+        let secret = match (&self.key_file, env::var(PASSPHRASE_VAR)) {
+            (Some(path), _) => Some(Secret::Key(read_key(path)?)),
+            (None, Ok(passphrase)) if !passphrase.is_empty() => {
+                Some(Secret::Passphrase(passphrase))
+            }
+            (None, _) => None,
+        };
+        Ok(SnapshotOptions {
+            compression: self.compression,
+            encryption: secret.map(|secret| Encryption {
+                cipher: self.cipher,
+                secret,
+            }),
+        })
+    }
+
+    /// A database keeping its records in the configured engine and its snapshots in the
+    /// data directory, and writing them as configured
+    pub fn database(&self) -> Result<Database, Box<dyn Error>> {
+        let data_dir = self.data_dir()?;
+        let path = |name: &str| data_dir.root().join(name);
+        let opened = match self.engine {
+            Engine::Memory => Ok(Database::default()),
+            Engine::Log => LogEngine::open(path(LOG)).map(Database::with_engine),
+            Engine::Mmap => MmapEngine::open(path(MMAP)).map(Database::with_engine),
+            Engine::Lsm => LsmEngine::open(path(LSM)).map(Database::with_engine),
+        };
+        let db = opened.map_err(|e| format!("Couldn't open the records: {e}"))?;
+        Ok(db
+            .with_data_dir(data_dir)
+            .with_snapshot_options(self.snapshot_options()?))
+    }
+
+    /// A database over the snapshots in the data directory alone, without opening the
+    /// records engine
+    pub fn snapshots(&self) -> Result<Database, Box<dyn Error>> {
+        Ok(Database::default()
+            .with_data_dir(self.data_dir()?)
+            .with_snapshot_options(self.snapshot_options()?))
+    }
+
+    /// How to run as a node of a cluster, when a node id is configured
+    pub fn cluster(&self) -> Result<Option<ClusterConfig>, Box<dyn Error>> {
+        let Some(id) = self.node_id else {
+            return Ok(None);
+        };
+        if self.replica_of.is_some() {
+            return Err("A cluster node can't also be a replica".into());
+        }
+        if self.serve_shard.is_some() || !self.shards.is_empty() {
+            return Err("A cluster node can't also be a shard or route to shards".into());
+        }
+        let addr = match &self.cluster_addr {
+            Some(addr) => addr.clone(),
+            None => self
+                .cluster_members
+                .iter()
+                .find(|member| member.id == id)
+                .map(|member| member.addr.clone())
+                .ok_or("A cluster node needs an address to listen on")?,
+        };
+        let mut cluster = ClusterConfig::new(id, addr);
+        cluster.members = self
+            .cluster_members
+            .iter()
+            .map(|member| (member.id, member.addr.clone()))
+            .collect();
+        cluster.linearizable_reads = self.linearizable_reads;
+        Ok(Some(cluster))
+    }
+
+    /// Serves `db` as a shard when an address to serve it on is configured
+    pub fn shard_server(&self, db: &Database) -> Result<Option<ShardServer>, Box<dyn Error>> {
+        let Some(addr) = &self.serve_shard else {
+            return Ok(None);
+        };
+        if self.replica_of.is_some() {
+            return Err("A replica can't also be a shard".into());
+        }
+        let server = db
+            .serve_shard(addr)
+            .map_err(|e| format!("Couldn't serve the shard on `{addr}`: {e}"))?;
+        Ok(Some(server))
+    }
+
+    /// Routes to the configured shards, when there are any
+    pub fn router(&self) -> Result<Option<ShardRouter>, Box<dyn Error>> {
+        if self.shards.is_empty() {
+            return Ok(None);
+        }
+        if self.replica_of.is_some() {
+            return Err("A replica can't route to shards".into());
+        }
+        let router = ShardRouter::connect(&self.shards)
+            .map_err(|e| format!("Couldn't route to the shards: {e}"))?;
+        Ok(Some(router))
+    }
+
+    /// Loads the autoload snapshot into `db`, if there is one
+    pub fn prepare(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        if let Some(autoload) = &self.autoload {
+            // Nothing has been saved yet on the first run
+            if db.snapshots()?.contains(autoload) {
+                db.load(autoload)?;
+            }
+        }
+        Ok(())
+    }
+}
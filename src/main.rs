@@ -1,12 +1,96 @@
-use hoya::interpreter::interpret::Interpreter;
+mod commands;
+mod config;
+
+use std::process;
+use std::sync::Once;
+
+use clap::Parser;
+use config::{Config, Options};
+use db::Database;
+use hoya::interpreter::shared::SharedInterpreter;
 use rustyline::error::ReadlineError;
 use rustyline::{Editor, Result};
 
+/// Makes sure only one of the REPL and the Ctrl-C handler shuts down
+static SHUTDOWN: Once = Once::new();
+
 fn main() -> Result<()> {
+    let mut options = Options::parse();
+    let command = options.command.take();
+    let config = Config::from_options(options).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(2)
+    });
+
+    if let Some(command) = command {
+        if let Err(e) = commands::run(command, &config) {
+            eprintln!("{e}");
+            process::exit(1)
+        }
+        return Ok(());
+    }
+
+    let db = config.database().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1)
+    });
+    let cluster = config.cluster().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(2)
+    });
+    // A cluster node starts from what it kept itself
+    let cluster = match cluster {
+        Some(cluster) => Some(db.join_cluster(cluster).unwrap_or_else(|e| {
+            eprintln!("Couldn't start the cluster node: {e}");
+            process::exit(1)
+        })),
+        None => {
+            if let Err(e) = config.prepare(&db) {
+                eprintln!("Couldn't load the data directory: {e}");
+                process::exit(1)
+            }
+            None
+        }
+    };
+    let autosave = db.autosave(&config.snapshot, config.save_rules());
+    let primary = config.serve_replicas.as_ref().map(|addr| {
+        db.serve_replicas(addr).unwrap_or_else(|e| {
+            eprintln!("Couldn't serve replicas on `{addr}`: {e}");
+            process::exit(1)
+        })
+    });
+    let replica = config
+        .replica_of
+        .as_ref()
+        .map(|primary| db.replicate_from(primary));
+    let shard = config.shard_server(&db).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1)
+    });
+    let router = config.router().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1)
+    });
+
+    // Ctrl-C while a command runs, since the prompt handles it itself
+    {
+        let (db, config) = (db.clone(), config.clone());
+        ctrlc::set_handler(move || {
+            shutdown(&db, &config);
+            process::exit(130)
+        })
+        .expect("the Ctrl-C handler is only set once");
+    }
+
     let mut rl = Editor::<()>::new()?;
     rl.load_history("history.txt")?;
 
-    let interpreter = Interpreter::default();
+    let interpreter = match (&replica, &cluster, router) {
+        (Some(_), _, _) => SharedInterpreter::read_only(db.clone()),
+        (None, Some(cluster), _) => SharedInterpreter::clustered(db.clone(), cluster.client()),
+        (None, None, Some(router)) => SharedInterpreter::routed(db.clone(), router),
+        (None, None, None) => SharedInterpreter::new(db.clone()),
+    };
 
     loop {
         let readline = rl.readline(">> ");
@@ -27,5 +111,29 @@ fn main() -> Result<()> {
             }
         }
     }
+
+    drop(replica);
+    drop(shard);
+    drop(primary);
+    drop(interpreter);
+    drop(cluster);
+    drop(autosave);
+    shutdown(&db, &config);
     rl.save_history("history.txt")
 }
+
+/// Lets a running save finish, saves what changed since if saving is configured, and
+/// flushes the storage engines
+fn shutdown(db: &Database, config: &Config) {
+    SHUTDOWN.call_once(|| {
+        db.wait_for_save();
+        if config.persists() && db.dirty() > 0 {
+            if let Err(e) = db.store(&config.snapshot) {
+                eprintln!("Couldn't save before exiting: {e}");
+            }
+        }
+        if let Err(e) = db.flush() {
+            eprintln!("Couldn't flush before exiting: {e}");
+        }
+    });
+}
@@ -0,0 +1,263 @@
+#[allow(dead_code)]
+#[path = "../src/config.rs"]
+mod config;
+
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Parser;
+use config::{Config, Engine, Options};
+use db::{Cipher, Compression, DBTypes, SaveRule};
+
+fn options(args: &[&str]) -> Result<Options, clap::Error> {
+    Options::try_parse_from(std::iter::once("hoya").chain(args.iter().copied()))
+}
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoya-config-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `text` written as a config file in a directory of its own
+fn config_file(name: &str, text: &str) -> PathBuf {
+    let path = scratch(name).join("hoya.toml");
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn rule(after: u64, changes: u64) -> SaveRule {
+    SaveRule {
+        after: std::time::Duration::from_secs(after),
+        changes,
+    }
+}
+
+#[test]
+fn without_a_config_nothing_persists() {
+    let config = Config::from_options(options(&[]).unwrap()).unwrap();
+    assert_eq!(config.data_dir, PathBuf::from("."));
+    assert_eq!(config.engine, Engine::Memory);
+    assert_eq!(config.autoload, None);
+    assert_eq!(config.snapshot, "dump");
+    assert!(!config.persists());
+}
+
+#[test]
+fn flags_are_parsed() {
+    let config = Config::from_options(
+        options(&[
+            "--data-dir",
+            "/var/lib/hoya",
+            "--engine",
+            "lsm",
+            "--autoload",
+            "boot",
+            "--snapshot",
+            "main",
+            "--save",
+            "60:1000",
+            "--save",
+            " 300 : 1 ",
+        ])
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(config.data_dir, PathBuf::from("/var/lib/hoya"));
+    assert_eq!(config.engine, Engine::Lsm);
+    assert_eq!(config.autoload.as_deref(), Some("boot"));
+    assert_eq!(config.snapshot, "main");
+    assert_eq!(config.save_rules(), [rule(60, 1000), rule(300, 1)]);
+}
+
+#[test]
+fn malformed_flags_are_refused() {
+    for (args, message) in [
+        (
+            &["--save", "60"][..],
+            "Expected SECONDS:CHANGES, but found `60`",
+        ),
+        (
+            &["--save", "soon:1"],
+            "Expected SECONDS:CHANGES, but found `soon:1`",
+        ),
+        (
+            &["--engine", "disk"],
+            "Expected the engine memory, log, mmap or lsm, but found `disk`",
+        ),
+    ] {
+        let error = options(args).unwrap_err().to_string();
+        assert!(error.contains(message), "{args:?}: {error}");
+    }
+}
+
+#[test]
+fn the_config_file_is_read_and_flags_override_it() {
+    let path = config_file(
+        "file",
+        r#"
+            data_dir = "/srv/hoya"
+            engine = "log"
+            autoload = "dump"
+            compression = "zstd"
+            cipher = "chacha20-poly1305"
+            save = [{ after = 900, changes = 1 }, { after = 60, changes = 10000 }]
+        "#,
+    );
+    let config =
+        Config::from_options(options(&["--config", path.to_str().unwrap()]).unwrap()).unwrap();
+    assert_eq!(config.data_dir, PathBuf::from("/srv/hoya"));
+    assert_eq!(config.engine, Engine::Log);
+    assert_eq!(config.compression, Compression::Zstd);
+    assert_eq!(config.cipher, Cipher::ChaCha20Poly1305);
+    assert_eq!(config.autoload.as_deref(), Some("dump"));
+    assert_eq!(config.snapshot, "dump");
+    assert_eq!(config.save_rules(), [rule(900, 1), rule(60, 10000)]);
+
+    let overridden = Config::from_options(
+        options(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--engine",
+            "mmap",
+            "--save",
+            "5:5",
+        ])
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(overridden.data_dir, PathBuf::from("/srv/hoya"));
+    assert_eq!(overridden.engine, Engine::Mmap);
+    assert_eq!(overridden.save_rules(), [rule(5, 5)]);
+}
+
+#[test]
+fn malformed_config_files_are_refused() {
+    for (name, text, message) in [
+        ("unknown", "data_dirs = \"x\"", "unknown field `data_dirs`"),
+        (
+            "engine",
+            "engine = \"disk\"",
+            "Expected the engine memory, log, mmap or lsm, but found `disk`",
+        ),
+        ("rule", "save = [{ after = 60 }]", "missing field `changes`"),
+    ] {
+        let path = config_file(name, text);
+        let error = Config::from_options(options(&["--config", path.to_str().unwrap()]).unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with(&format!("In `{}`", path.display())),
+            "{error}"
+        );
+        assert!(error.contains(message), "{error}");
+    }
+
+    let missing = scratch("missing").join("hoya.toml");
+    let error = Config::from_options(options(&["--config", missing.to_str().unwrap()]).unwrap())
+        .unwrap_err()
+        .to_string();
+    assert!(error.starts_with(&format!("Couldn't read `{}`", missing.display())));
+}
+
+#[test]
+fn persisting_engines_keep_records_in_the_data_directory() {
+    for engine in ["log", "mmap", "lsm"] {
+        let dir = scratch(&format!("engine-{engine}"));
+        let config = Config::from_options(
+            options(&["--data-dir", dir.to_str().unwrap(), "--engine", engine]).unwrap(),
+        )
+        .unwrap();
+        {
+            let db = config.database().unwrap();
+            db.put(String::from("k"), DBTypes::Number(1)).unwrap();
+            db.flush().unwrap();
+        }
+        let db = config.database().unwrap();
+        assert_eq!(db.get("k"), Some(DBTypes::Number(1)), "{engine}");
+    }
+}
+
+#[test]
+fn key_files_hold_the_key_raw_or_as_hex() {
+    let dir = scratch("keys");
+    let raw = dir.join("raw.key");
+    fs::write(&raw, [7; 32]).unwrap();
+    let hex = dir.join("hex.key");
+    fs::write(&hex, format!("{}\n", "07".repeat(32))).unwrap();
+
+    for (cipher, key_file) in [("aes-256-gcm", &raw), ("chacha20-poly1305", &hex)] {
+        let config = Config::from_options(
+            options(&[
+                "--data-dir",
+                dir.to_str().unwrap(),
+                "--compression",
+                "lz4",
+                "--cipher",
+                cipher,
+                "--key-file",
+                key_file.to_str().unwrap(),
+            ])
+            .unwrap(),
+        )
+        .unwrap();
+        let db = config.database().unwrap();
+        db.put(String::from("k"), DBTypes::Number(1)).unwrap();
+        db.store(cipher).unwrap();
+
+        // Either form of the file is the same key
+        let other = if key_file == &raw { &hex } else { &raw };
+        let reader = Config::from_options(
+            options(&[
+                "--data-dir",
+                dir.to_str().unwrap(),
+                "--key-file",
+                other.to_str().unwrap(),
+            ])
+            .unwrap(),
+        )
+        .unwrap()
+        .database()
+        .unwrap();
+        reader.load(cipher).unwrap();
+        assert_eq!(reader.get("k"), Some(DBTypes::Number(1)), "{cipher}");
+
+        let without =
+            Config::from_options(options(&["--data-dir", dir.to_str().unwrap()]).unwrap()).unwrap();
+        let error = without.database().unwrap().load(cipher).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The snapshot is encrypted, but no key or passphrase was given"
+        );
+    }
+}
+
+#[test]
+fn malformed_key_files_are_refused() {
+    let dir = scratch("bad-keys");
+    for (name, contents) in [
+        ("short", vec![7; 31]),
+        ("odd", "07".repeat(31).into_bytes()),
+        ("digits", "zz".repeat(32).into_bytes()),
+    ] {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        let config =
+            Config::from_options(options(&["--key-file", path.to_str().unwrap()]).unwrap())
+                .unwrap();
+        assert_eq!(
+            config.database().unwrap_err().to_string(),
+            format!("`{}` doesn't hold a 32-byte key", path.display())
+        );
+    }
+
+    let missing = dir.join("missing");
+    let config =
+        Config::from_options(options(&["--key-file", missing.to_str().unwrap()]).unwrap()).unwrap();
+    assert!(config
+        .database()
+        .unwrap_err()
+        .to_string()
+        .starts_with(&format!("Couldn't read `{}`", missing.display())));
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoya-sharding-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    // The shell keeps its history next to where it runs
+    fs::write(dir.join("history.txt"), "").unwrap();
+    dir
+}
+
+/// `hoya_db` run in `dir`, reading its input from a pipe
+fn hoya(dir: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_hoya_db"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+/// Feeds `script` to `child`, then ends its input and returns what it printed for
+/// each line
+fn run(mut child: Child, script: &str) -> Vec<String> {
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(script.as_bytes()).unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    let mut lines = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    assert_eq!(lines.pop().as_deref(), Some("^D"));
+    lines
+}
+
+/// What a shell run with `args` in `dir` prints for each line of `script`
+fn shell(dir: &Path, args: &[&str], script: &str) -> Vec<String> {
+    run(hoya(dir, args), script)
+}
+
+/// A `hoya_db` process serving its records as a shard, in a directory of its own
+struct Shard {
+    addr: String,
+    process: Option<Child>,
+}
+
+impl Shard {
+    fn serve(name: &str) -> Self {
+        let dir = scratch(name);
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let process = hoya(&dir, &["--serve-shard", &addr]);
+        let started = Instant::now();
+        while TcpStream::connect(&addr).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "{addr} never served"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        Self {
+            addr,
+            process: Some(process),
+        }
+    }
+
+    /// What the shard's own shell prints for `script`, which stops the shard
+    fn shell(mut self, script: &str) -> Vec<String> {
+        run(self.process.take().unwrap(), script)
+    }
+}
+
+impl Drop for Shard {
+    fn drop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+/// How many of the keys starting with `key` the shard holds itself
+fn held(shard: Shard) -> usize {
+    shard.shell("(len (keys \"key\"))\n")[0].parse().unwrap()
+}
+
+#[test]
+fn separate_processes_route_and_reshard_keys_between_them() {
+    let shards = [0, 1, 2].map(|i| Shard::serve(&format!("shard{i}")));
+    let router = scratch("router");
+
+    // A shell routing to the first two writes the keys, then moves them onto the third
+    // and off the first
+    let mut script = (0..200)
+        .map(|i| format!("(put {i} \"key{i:03}\")\n"))
+        .collect::<String>();
+    script += &format!("(add-shard \"{}\")\n", shards[2].addr);
+    script += &format!("(remove-shard \"{}\")\n", shards[0].addr);
+    let printed = shell(
+        &router,
+        &["--shard", &shards[0].addr, "--shard", &shards[1].addr],
+        &script,
+    );
+    assert_eq!(printed.len(), 202);
+    assert!(
+        printed[..200].iter().all(|line| line == "nil"),
+        "{printed:?}"
+    );
+    for moved in &printed[200..] {
+        assert!(moved.parse::<u64>().unwrap() > 0, "{printed:?}");
+    }
+
+    // Another shell, knowing only one shard, learns the new ring from it
+    let printed = shell(
+        &router,
+        &["--shard", &shards[1].addr],
+        "(len (keys \"key\"))\n(get \"key000\")\n(get \"key123\")\n(get \"key199\")\n",
+    );
+    assert_eq!(printed, ["200", "0", "123", "199"]);
+
+    // Each process holds only its own share
+    let [first, second, third] = shards;
+    assert_eq!(held(first), 0);
+    let (second, third) = (held(second), held(third));
+    assert!(second > 0 && third > 0, "{second} and {third}");
+    assert_eq!(second + third, 200);
+}